@@ -123,4 +123,10 @@ class BluetoothGattCallback extends android.bluetooth.BluetoothGattCallback {
     }
     private native void native_onServiceChanged(long ptr, android.bluetooth.BluetoothGatt arg0);
 
+    // Hidden callback (API 26+), not annotated with `@Override` because it is absent in the SDK.
+    public void onConnectionUpdated(android.bluetooth.BluetoothGatt arg0, int arg1, int arg2, int arg3, int arg4) {
+        native_onConnectionUpdated(ptr, arg0, arg1, arg2, arg3, arg4);
+    }
+    private native void native_onConnectionUpdated(long ptr, android.bluetooth.BluetoothGatt arg0, int arg1, int arg2, int arg3, int arg4);
+
 }
@@ -3,6 +3,9 @@ package com.github.alexmoon.bluest.proxy.android.bluetooth.le;
 @SuppressWarnings("rawtypes")
 class ScanCallback extends android.bluetooth.le.ScanCallback {
     long ptr;
+    // Set through JNI by `ScanSettings::min_rssi`; weaker results are dropped without calling
+    // into native code.
+    volatile int minRssi = Integer.MIN_VALUE;
 
     private ScanCallback(long ptr) {
         this.ptr = ptr;
@@ -16,12 +19,28 @@ class ScanCallback extends android.bluetooth.le.ScanCallback {
 
     @Override
     public void onScanResult(int arg0, android.bluetooth.le.ScanResult arg1) {
+        if (arg1 != null && arg1.getRssi() < minRssi) {
+            return;
+        }
         native_onScanResult(ptr, arg0, arg1);
     }
     private native void native_onScanResult(long ptr, int arg0, android.bluetooth.le.ScanResult arg1);
 
     @Override
     public void onBatchScanResults(java.util.List arg0) {
+        int threshold = minRssi;
+        if (arg0 != null && threshold > Integer.MIN_VALUE) {
+            java.util.ArrayList filtered = new java.util.ArrayList();
+            for (Object result : arg0) {
+                if (((android.bluetooth.le.ScanResult) result).getRssi() >= threshold) {
+                    filtered.add(result);
+                }
+            }
+            if (filtered.isEmpty()) {
+                return;
+            }
+            arg0 = filtered;
+        }
         native_onBatchScanResults(ptr, arg0);
     }
     private native void native_onBatchScanResults(long ptr, java.util.List arg0);
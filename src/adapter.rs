@@ -1,39 +1,52 @@
 // Some portions of this code is orginally written by <https://github.com/Dirbaio>.
 
 use std::collections::HashMap;
-use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
 
+use async_lock::Semaphore;
 use futures_core::Stream;
-use futures_lite::{stream, StreamExt};
-use java_spaghetti::{ByteArray, Env, Global, Local, Null, Ref};
-use log::{debug, warn};
+use futures_lite::{stream, FutureExt, StreamExt};
+use java_spaghetti::sys::jvalue;
+use java_spaghetti::{AsArg, AsJValue, ByteArray, Env, Global, Local, Null, Ref};
+use log::{debug, warn, LevelFilter};
 use uuid::Uuid;
 
-use super::async_util::StreamUntil;
+use super::async_util::{StreamMerge, StreamUntil};
 use super::bindings::android::bluetooth::le::{
-    ScanCallback, ScanFilter_Builder, ScanResult, ScanSettings, ScanSettings_Builder,
+    BluetoothLeScanner, ScanCallback, ScanRecord, ScanResult, ScanSettings as JScanSettings,
 };
 use super::bindings::android::bluetooth::{
-    BluetoothAdapter, BluetoothDevice, BluetoothGattCallback, BluetoothManager, BluetoothProfile,
+    BluetoothAdapter, BluetoothDevice, BluetoothGatt, BluetoothGattCallback,
+    BluetoothGattCharacteristic, BluetoothGattDescriptor, BluetoothGattService, BluetoothManager,
+    BluetoothProfile,
 };
 use super::bindings::android::content::Context as AndroidContext;
 use super::bindings::android::os::ParcelUuid;
 use super::bindings::java::lang::String as JString;
 use super::bindings::java::util::Map_Entry;
 use super::bindings::java::{self};
+use super::callback::load_proxy_classes;
+use super::callback_log::{callback_panic_count, log_callback, set_callback_log_level};
+use super::callback_thread::CallbackThread;
 use super::device::Device;
 use super::error::ErrorKind;
-use super::event_receiver::{EventReceiver, GlobalEvent};
-use super::gatt_tree::{BluetoothGattCallbackProxy, CachedWeak, GattTree};
+use super::event_receiver::{EventKind, EventReceiver, GlobalEvent};
+use super::gatt_tree::{BluetoothGattCallbackProxy, CachedWeak, GattOptions, GattTree};
 use super::jni::{ByteArrayExt, Monitor, VM};
+use super::scan::{ScanEvent, ScanEvents, ScanFilter, ScanSettings};
+use super::timer::Delay;
+use super::trace::traced;
 use super::vm_context::{
-    android_api_level, android_context, android_has_permission, jni_get_vm, jni_set_vm,
-    jni_with_env,
+    android_api_level, android_check_initialized, android_context, android_has_permission,
+    android_system_property_int, jni_get_vm, jni_set_int_field, jni_set_vm, jni_with_env,
 };
-use crate::util::{defer, JavaIterator, OptionExt, UuidExt};
+use crate::util::{JavaIterator, OptionExt, UuidExt};
 use crate::{
-    AdapterEvent, AdvertisementData, AdvertisingDevice, ConnectionEvent, DeviceId, Error,
-    ManufacturerData, Result,
+    AdapterAvailability, AdapterEvent, AdvertisementData, AdvertisingDevice, ConnectionEvent,
+    DeviceId, Error, ExtendedAdvertisingInfo, ManufacturerData, OperationObserver, OverflowPolicy,
+    Phy, Result, ScanMode, SystemEvent,
 };
 
 /// The system’s Bluetooth adapter interface.
@@ -49,10 +62,121 @@ struct AdapterInner {
     global_event_receiver: Arc<EventReceiver>,
     request_mtu_on_connect: bool,
     allow_multiple_connections: bool,
+    pause_scans_while_connecting: bool,
+    gatt_options: GattOptions,
+    /// Limits connections made by this adapter; `None` means unlimited.
+    connection_slots: Option<Arc<Semaphore>>,
+    _live: LiveAdapter,
+}
+
+/// Count of `AdapterInner` instances which are not yet dropped.
+static LIVE_ADAPTERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Held by each `AdapterInner`; all connections are closed when the last one is dropped.
+struct LiveAdapter;
+
+impl LiveAdapter {
+    fn new() -> Self {
+        LIVE_ADAPTERS.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for LiveAdapter {
+    fn drop(&mut self) {
+        if LIVE_ADAPTERS.fetch_sub(1, Ordering::SeqCst) == 1 && GattTree::clear_connections() {
+            debug!("closed all connections as the last `Adapter` is dropped");
+        }
+    }
 }
 
 static CONN_MUTEX: async_lock::Mutex<()> = async_lock::Mutex::new(());
 
+/// Used in place of the connection timeout for background connections, which may take any time.
+const AUTO_CONNECT_TIMEOUT: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// Scans which are not yet stopped, for [Adapter::shutdown].
+static ACTIVE_SCANS: Mutex<Vec<Weak<ActiveScan>>> = Mutex::new(Vec::new());
+
+/// Count of [Adapter::pause_scanning] calls not yet balanced by [Adapter::resume_scanning].
+static SCAN_PAUSE_DEPTH: Mutex<usize> = Mutex::new(0);
+
+/// Count of scan results kept for a scan stream which is not polled fast enough.
+const SCAN_RESULT_CAPACITY: usize = 16;
+
+fn active_scans() -> Vec<Arc<ActiveScan>> {
+    ACTIVE_SCANS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+fn pause_active_scans() {
+    let mut depth = SCAN_PAUSE_DEPTH.lock().unwrap();
+    *depth += 1;
+    if *depth == 1 {
+        for scan in active_scans() {
+            scan.pause();
+        }
+    }
+}
+
+fn resume_active_scans() -> Result<()> {
+    let mut depth = SCAN_PAUSE_DEPTH.lock().unwrap();
+    if *depth == 0 {
+        return Ok(());
+    }
+    *depth -= 1;
+    if *depth > 0 {
+        return Ok(());
+    }
+    let mut result = Ok(());
+    for scan in active_scans() {
+        if let Err(e) = scan.resume() {
+            warn!("failed to resume scan: {e}");
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Pauses active scans until it is dropped, see [AdapterConfig::pause_scans_while_connecting].
+struct ScanPause;
+
+impl ScanPause {
+    fn new() -> Self {
+        pause_active_scans();
+        Self
+    }
+}
+
+impl Drop for ScanPause {
+    fn drop(&mut self) {
+        let _ = resume_active_scans();
+    }
+}
+
+/// Loads the class of `T` into the class cache of the bindings by resolving `getClass()`,
+/// which every class inherits from `java.lang.Object`.
+fn warm_up_class<T: java_spaghetti::JniType>(env: Env<'_>) {
+    T::static_with_jni_type(|class| unsafe {
+        env.require_class_method(class, "getClass\0", "()Ljava/lang/Class;\0");
+    })
+}
+
+static DEFAULT_CONFIG: Mutex<DefaultConfig> = Mutex::new(DefaultConfig {
+    config: None,
+    sealed: false,
+});
+
+struct DefaultConfig {
+    config: Option<AdapterConfig>,
+    /// Set when the first `Adapter` is created.
+    sealed: bool,
+}
+
 /// Configuration for creating an interface to the default Bluetooth adapter of the system.
 ///
 /// By deafult, [ndk-context](https://docs.rs/ndk-context/0.1.1/ndk_context) is used for
@@ -69,9 +193,16 @@ pub struct AdapterConfig {
 
     request_mtu_on_connect: bool,
     allow_multiple_connections: bool,
+    pause_scans_while_connecting: bool,
+    dedicated_callback_thread: bool,
+    gatt_options: GattOptions,
+    max_concurrent_connections: Option<usize>,
+    callback_log_level: Option<LevelFilter>,
 }
 
 unsafe impl Send for AdapterConfig {}
+// The raw pointers are only read when creating the adapter.
+unsafe impl Sync for AdapterConfig {}
 
 impl AdapterConfig {
     /// Creates a config for the default Bluetooth adapter for the system.
@@ -95,6 +226,11 @@ impl AdapterConfig {
             manager: bluetooth_manager,
             request_mtu_on_connect: true,
             allow_multiple_connections: true,
+            pause_scans_while_connecting: false,
+            dedicated_callback_thread: false,
+            gatt_options: GattOptions::default(),
+            max_concurrent_connections: None,
+            callback_log_level: None,
         }
     }
 
@@ -119,6 +255,158 @@ impl AdapterConfig {
         self.allow_multiple_connections = enabled;
         self
     }
+
+    /// If enabled, [Adapter::connect_device] pauses all active scans (see [Adapter::pause_scanning])
+    /// while the connection is being made, which improves the reliability of connecting on many
+    /// Android devices. Scans are resumed when the attempt completes or is cancelled; for a
+    /// background connection of [Adapter::connect_device_auto], they are resumed as soon as
+    /// `connectGatt` returns.
+    ///
+    /// This is disabled by default.
+    pub fn pause_scans_while_connecting(mut self, enabled: bool) -> Self {
+        self.pause_scans_while_connecting = enabled;
+        self
+    }
+
+    /// If enabled, the adapter starts a dedicated `android.os.HandlerThread`, and all
+    /// `BluetoothGattCallback` calls of connections made by it are delivered on that thread,
+    /// instead of Binder threads of the Android Bluetooth stack. This makes the order of callbacks
+    /// deterministic and keeps them away from Binder threads, which are shared by the whole
+    /// application. The thread is stopped when the adapter and all connections made by it
+    /// are dropped.
+    ///
+    /// This requires Android API level 26 or higher, and is ignored on older systems.
+    /// It is disabled by default.
+    pub fn dedicated_callback_thread(mut self, enabled: bool) -> Self {
+        self.dedicated_callback_thread = enabled;
+        self
+    }
+
+    /// If enabled, [crate::Characteristic::notify] writes the enable bits to the Client Characteristic
+    /// Configuration descriptor (CCCD) after enabling notifications locally for the first stream of the
+    /// characteristic, then reads it back; it returns an error of [ErrorKind::Other] if the enable bits
+    /// didn't stick. This catches devices that silently ignore the write. Unless other notification streams
+    /// of the characteristic exist, notifications are then disabled locally and in the CCCD before the error
+    /// is returned.
+    ///
+    /// This is disabled by default.
+    pub fn verify_cccd_after_enable(mut self, enabled: bool) -> Self {
+        self.gatt_options.verify_cccd_after_enable = enabled;
+        self
+    }
+
+    /// Sets the timeout for establishing a connection in [Adapter::connect_device]. The default value is 20 seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.gatt_options.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for service discovery. The default value is 10 seconds.
+    pub fn discovery_timeout(mut self, timeout: Duration) -> Self {
+        self.gatt_options.discovery_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for waiting for the result of other GATT operations, including reads and writes.
+    /// The default value is 5 seconds.
+    pub fn operation_timeout(mut self, timeout: Duration) -> Self {
+        self.gatt_options.operation_timeout = timeout;
+        self
+    }
+
+    /// Limits the amount of connections made by this adapter at the same time. When the limit is
+    /// reached, [Adapter::connect_device] waits until another connection is closed instead of failing.
+    ///
+    /// By default, this is [Adapter::max_connections] if it is known, otherwise unlimited.
+    /// [Adapter::with_config] returns an [ErrorKind::InvalidParameter] error if `max` is 0.
+    pub fn max_concurrent_connections(mut self, max: usize) -> Self {
+        self.max_concurrent_connections = Some(max);
+        self
+    }
+
+    /// Sets the observer receiving timing data of all GATT operations, for diagnosing slow
+    /// peripherals. No observer is set by default.
+    pub fn operation_observer(mut self, observer: Arc<dyn OperationObserver>) -> Self {
+        self.gatt_options.observer = Some(observer);
+        self
+    }
+
+    /// Sets the hook receiving raw `BluetoothGattCallback` calls of all connections, see
+    /// [crate::unstable_jni::RawGattCallbackHook]. No hook is set by default.
+    #[cfg(feature = "unstable-jni")]
+    pub fn raw_gatt_callback_hook(
+        mut self,
+        hook: Arc<dyn crate::unstable_jni::RawGattCallbackHook>,
+    ) -> Self {
+        self.gatt_options.raw_callback_hook = Some(hook);
+        self
+    }
+
+    /// Sets what to do with characteristic values received while the buffer of a
+    /// [crate::Characteristic::notify] stream is full (128 values). The default is
+    /// [OverflowPolicy::DropOldest].
+    pub fn notify_overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.gatt_options.notify_overflow = policy;
+        self
+    }
+
+    /// If set, a new [crate::Characteristic::notify] stream of a characteristic which already has
+    /// other subscribers yields the most recently received value at first, instead of only values
+    /// received after it is created. It is like a "behavior subject" rather than a plain broadcast.
+    /// Default: false.
+    ///
+    /// The replayed value doesn't take a place in the buffer, so it is never dropped by the
+    /// [AdapterConfig::notify_overflow] policy; it is the last value received from the device,
+    /// even if that value is dropped for other slow subscribers. Nothing is replayed when the
+    /// first subscriber enables the notification.
+    pub fn notify_replay_last(mut self, replay_last: bool) -> Self {
+        self.gatt_options.notify_replay_last = replay_last;
+        self
+    }
+
+    /// If enabled, a characteristic or descriptor read or write (with response) which fails with
+    /// the ATT error `DATABASE_OUT_OF_SYNC` (see [crate::Error::is_database_out_of_sync]) triggers
+    /// service discovery, then the operation is retried once. The retry fails with
+    /// [ErrorKind::ServiceChanged] if the attribute is no longer available after rediscovery.
+    ///
+    /// This is disabled by default.
+    pub fn auto_rediscover_on_out_of_sync(mut self, enabled: bool) -> Self {
+        self.gatt_options.auto_rediscover_on_out_of_sync = enabled;
+        self
+    }
+
+    /// Logs every callback received from the Android Bluetooth stack (`BluetoothGattCallback`,
+    /// `ScanCallback` and broadcast intents) with its raw arguments at the given level, through
+    /// the `log` facade with the `android_ble::callback` target. Callbacks ignored by this crate,
+    /// like those for devices that are no longer connected, are also logged. Values are truncated
+    /// to their first 16 bytes. Default: [LevelFilter::Off].
+    ///
+    /// This is for diagnosing issues in the field. The setting is process-wide; the adapter
+    /// created last decides it.
+    pub fn callback_log_level(mut self, level: LevelFilter) -> Self {
+        self.callback_log_level = Some(level);
+        self
+    }
+
+    /// Makes a copy of the config with a new global reference of the `BluetoothManager`.
+    fn duplicate(&self) -> Self {
+        let vm = unsafe { VM::from_raw(self.vm) };
+        let manager = vm.with_env(|env| {
+            let jnienv = env.as_raw();
+            unsafe { ((**jnienv).v1_2.NewGlobalRef)(jnienv, self.manager) }
+        });
+        Self {
+            vm: self.vm,
+            manager,
+            request_mtu_on_connect: self.request_mtu_on_connect,
+            allow_multiple_connections: self.allow_multiple_connections,
+            pause_scans_while_connecting: self.pause_scans_while_connecting,
+            dedicated_callback_thread: self.dedicated_callback_thread,
+            gatt_options: self.gatt_options.clone(),
+            max_concurrent_connections: self.max_concurrent_connections,
+            callback_log_level: self.callback_log_level,
+        }
+    }
 }
 
 impl Default for AdapterConfig {
@@ -139,6 +427,15 @@ impl Default for AdapterConfig {
     }
 }
 
+fn system_max_connections() -> Option<usize> {
+    static MAX_CONNECTIONS: OnceLock<Option<usize>> = OnceLock::new();
+    *MAX_CONNECTIONS.get_or_init(|| {
+        android_system_property_int("bluetooth.core.le.max_number_of_concurrent_connections")
+            .and_then(|max| usize::try_from(max).ok())
+            .filter(|&max| max > 0)
+    })
+}
+
 fn check_scan_permission() -> Result<(), crate::Error> {
     let has_perm = if android_api_level() >= 31 {
         if android_has_permission("android.permission.BLUETOOTH_SCAN") {
@@ -182,20 +479,100 @@ fn check_connection_permission() -> Result<(), crate::Error> {
     Ok(())
 }
 
+/// Calls the `connectGatt` overload taking a `Handler` (API level 26), with the transport and
+/// PHY values used by the overload without it.
+fn connect_gatt_with_handler<'env>(
+    device: &Local<'env, BluetoothDevice>,
+    context: impl AsArg<AndroidContext>,
+    auto_connect: bool,
+    callback: impl AsArg<BluetoothGattCallback>,
+    handler: &Global<java::lang::Object>,
+) -> std::result::Result<Option<Local<'env, BluetoothGatt>>, Local<'env, java::lang::Throwable>> {
+    let env = device.env();
+    unsafe {
+        let args = [
+            context.as_arg_jvalue(),
+            AsJValue::as_jvalue(&auto_connect),
+            callback.as_arg_jvalue(),
+            AsJValue::as_jvalue(&BluetoothDevice::TRANSPORT_AUTO),
+            AsJValue::as_jvalue(&BluetoothDevice::PHY_LE_1M_MASK),
+            jvalue {
+                l: handler.as_raw(),
+            },
+        ];
+        let (_, method) = env.require_class_method(
+            "android/bluetooth/BluetoothDevice\0",
+            "connectGatt\0",
+            "(Landroid/content/Context;ZLandroid/bluetooth/BluetoothGattCallback;IILandroid/os/Handler;)Landroid/bluetooth/BluetoothGatt;\0",
+        );
+        env.call_object_method_a(device.as_raw(), method, args.as_ptr())
+    }
+}
+
 impl Adapter {
     /// Creates an interface to a Bluetooth adapter using the default config.
+    ///
+    /// The config set by [Adapter::set_default_config] is used if it exists;
+    /// otherwise [AdapterConfig::default] is used.
     pub async fn default() -> Option<Self> {
-        Adapter::with_config(AdapterConfig::default()).await.ok()
+        let config = DEFAULT_CONFIG
+            .lock()
+            .unwrap()
+            .config
+            .as_ref()
+            .map(AdapterConfig::duplicate);
+        if config.is_none() {
+            if let Err(e) = android_check_initialized() {
+                warn!("{e}");
+                return None;
+            }
+        }
+        Adapter::with_config(config.unwrap_or_default()).await.ok()
+    }
+
+    /// Sets the config to be used by [Adapter::default]. It affects all operations
+    /// (e.g. [Adapter::connect_device]) of adapters created by [Adapter::default].
+    ///
+    /// This can be called from any thread, but only once, and only before any `Adapter`
+    /// is created; otherwise an error is returned.
+    pub fn set_default_config(config: AdapterConfig) -> Result<()> {
+        let mut default_config = DEFAULT_CONFIG.lock().unwrap();
+        if default_config.sealed || default_config.config.is_some() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                None,
+                "the default config can only be set once before the first adapter is created",
+            ));
+        }
+        default_config.config.replace(config);
+        Ok(())
     }
 
     /// Creates an interface to a Bluetooth adapter. The `vm` pointer will be ignored
     /// if this has been called previously.
     pub async fn with_config(config: AdapterConfig) -> Result<Self> {
+        if config.max_concurrent_connections == Some(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                None,
+                "`max_concurrent_connections` must not be 0",
+            ));
+        }
+        DEFAULT_CONFIG.lock().unwrap().sealed = true;
         unsafe {
             let vm = VM::from_raw(config.vm);
             let _ = jni_set_vm(vm);
+            android_check_initialized()?;
+            if let Some(level) = config.callback_log_level {
+                set_callback_log_level(level);
+            }
 
             let manager: Global<BluetoothManager> = Global::from_raw(vm.into(), config.manager);
+            let mut gatt_options = config.gatt_options.clone();
+            if config.dedicated_callback_thread && android_api_level() >= 26 {
+                let thread = CallbackThread::start("android-ble-gatt-callback")?;
+                gatt_options.callback_thread = Some(Arc::new(thread));
+            }
 
             jni_with_env(|env| {
                 let local_manager = manager.as_ref(env);
@@ -207,6 +584,13 @@ impl Adapter {
                         global_event_receiver: EventReceiver::build()?,
                         request_mtu_on_connect: config.request_mtu_on_connect,
                         allow_multiple_connections: config.allow_multiple_connections,
+                        pause_scans_while_connecting: config.pause_scans_while_connecting,
+                        gatt_options,
+                        connection_slots: config
+                            .max_concurrent_connections
+                            .or_else(system_max_connections)
+                            .map(|max| Arc::new(Semaphore::new(max))),
+                        _live: LiveAdapter::new(),
                     }),
                 })
             })
@@ -220,7 +604,7 @@ impl Adapter {
         Ok(self
             .inner
             .global_event_receiver
-            .subscribe()
+            .subscribe(EventKind::AdapterState)
             .await?
             .filter_map(|event| {
                 if let GlobalEvent::AdapterStateChanged(val) = event {
@@ -236,6 +620,49 @@ impl Adapter {
             .map(Ok))
     }
 
+    /// Gets the Bluetooth Classic scan mode (connectability and discoverability) of the adapter.
+    ///
+    /// This requires the `BLUETOOTH_SCAN` permission on API level 31 and above.
+    pub async fn scan_mode(&self) -> Result<ScanMode> {
+        jni_with_env(|env| {
+            let adapter = self.inner.adapter.as_ref(env);
+            Ok(ScanMode::from_java(adapter.getScanMode()?))
+        })
+    }
+
+    /// A stream of changes of the adapter's scan mode, see [Adapter::scan_mode].
+    pub async fn scan_mode_changes(
+        &self,
+    ) -> Result<impl Stream<Item = ScanMode> + Send + Unpin + '_> {
+        Ok(self
+            .inner
+            .global_event_receiver
+            .subscribe(EventKind::ScanMode)
+            .await?
+            .filter_map(|event| match event {
+                GlobalEvent::ScanModeChanged(val) => Some(ScanMode::from_java(val)),
+                _ => None,
+            }))
+    }
+
+    /// A stream of raw system-wide Bluetooth broadcasts: adapter state changes, ACL connections and
+    /// disconnections over the LE transport, bond state changes and pairing requests. This is an
+    /// advanced API; most applications need only [Adapter::events] and [Device::pair].
+    ///
+    /// Each stream has its own bounded queue; if it is not polled fast enough, its oldest
+    /// events are dropped without affecting other subscribers.
+    pub async fn system_events(
+        &self,
+    ) -> Result<impl Stream<Item = SystemEvent> + Send + Unpin + '_> {
+        let receiver = &self.inner.global_event_receiver;
+        let adapter_state = receiver.subscribe(EventKind::AdapterState).await?;
+        let acl = receiver.subscribe(EventKind::Acl).await?;
+        let bond = receiver.subscribe(EventKind::Bond).await?;
+        let pairing = receiver.subscribe(EventKind::Pairing).await?;
+        Ok(StreamMerge::new([adapter_state, acl, bond, pairing])
+            .filter_map(|event| event.to_system_event()))
+    }
+
     /// Asynchronously blocks until the adapter is available.
     pub async fn wait_available(&self) -> Result<()> {
         while !self.is_available().await? {
@@ -249,6 +676,59 @@ impl Adapter {
         Ok(())
     }
 
+    /// Loads the Java classes used by common GATT and scan operations, including the callback
+    /// proxy classes in the embedded dex, so that the first operation made by a latency-sensitive
+    /// application isn't slowed down by class loading in the JVM. It may be called at any time
+    /// after initialization; calling it more than once is harmless.
+    pub fn warm_up(&self) {
+        let started = Instant::now();
+        jni_with_env(|env| {
+            warm_up_class::<BluetoothDevice>(env);
+            warm_up_class::<BluetoothGatt>(env);
+            warm_up_class::<BluetoothGattService>(env);
+            warm_up_class::<BluetoothGattCharacteristic>(env);
+            warm_up_class::<BluetoothGattDescriptor>(env);
+            warm_up_class::<ScanResult>(env);
+            warm_up_class::<ScanRecord>(env);
+            warm_up_class::<ParcelUuid>(env);
+            warm_up_class::<java::util::UUID>(env);
+            warm_up_class::<java::util::ArrayList>(env);
+            load_proxy_classes(env);
+        });
+        debug!("warm_up finished in {:?}", started.elapsed());
+    }
+
+    /// Checks whether Bluetooth can be used, without creating an adapter: the Android context
+    /// must be initialized, and the `BluetoothManager` and the default adapter must exist.
+    /// This never panics, so it can be used for enabling Bluetooth features at startup.
+    pub fn availability() -> AdapterAvailability {
+        if android_check_initialized().is_err() {
+            return AdapterAvailability::NoContext;
+        }
+        let result = jni_with_env(|env| {
+            let context = android_context().as_local(env);
+            let service_name = JString::from_env_str(env, AndroidContext::BLUETOOTH_SERVICE);
+            let Some(manager) = context.getSystemService_String(service_name)? else {
+                return Ok(AdapterAvailability::NoManager);
+            };
+            let Ok(manager) = manager.cast::<BluetoothManager>() else {
+                return Ok(AdapterAvailability::NoManager);
+            };
+            let Some(adapter) = manager.getAdapter()? else {
+                return Ok(AdapterAvailability::NoAdapter);
+            };
+            Ok::<_, Error>(if adapter.isEnabled()? {
+                AdapterAvailability::Available
+            } else {
+                AdapterAvailability::Disabled
+            })
+        });
+        result.unwrap_or_else(|e| {
+            warn!("failed to check the availability of Bluetooth: {e}");
+            AdapterAvailability::NoManager
+        })
+    }
+
     /// Check if the adapter is available.
     pub async fn is_available(&self) -> Result<bool> {
         jni_with_env(|env| {
@@ -305,9 +785,12 @@ impl Adapter {
                 let devices = manager
                     .getConnectedDevices(BluetoothProfile::GATT)?
                     .non_null()?;
-                let iter_devices = JavaIterator(devices.iterator()?.non_null()?);
+                let iter_devices = JavaIterator::new(devices.iterator()?.non_null()?);
 
-                for device in iter_devices.filter_map(|dev| dev.cast::<BluetoothDevice>().ok()) {
+                for device in iter_devices {
+                    let Ok(device) = device?.cast::<BluetoothDevice>() else {
+                        continue;
+                    };
                     let id = DeviceId(
                         device
                             .getAddress()?
@@ -334,10 +817,49 @@ impl Adapter {
             }
             Ok(device_items)
         } else {
-            GattTree::registered_devices()
+            Ok(GattTree::registered_devices())
         }
     }
 
+    /// Lists devices currently connected in this library instance, regardless of
+    /// [AdapterConfig::allow_multiple_connections]. Unlike [Adapter::connected_devices],
+    /// this never asks the system for connected devices or creates new connections.
+    pub fn active_connections(&self) -> Vec<Device> {
+        GattTree::registered_devices()
+    }
+
+    /// Returns the maximum amount of simultaneous LE connections supported by the Bluetooth stack,
+    /// if it is configured by the system property `bluetooth.core.le.max_number_of_concurrent_connections`.
+    ///
+    /// Many Android devices don't set it and fall back to a build-time limit (often 7), in which
+    /// case `None` is returned. Note that the limit is shared with all other apps in the system.
+    pub fn max_connections(&self) -> Option<usize> {
+        system_max_connections()
+    }
+
+    /// Returns an error if [Adapter::max_connections] is known and reached by GATT connections
+    /// of all apps in the system.
+    fn check_connection_limit(&self) -> Result<()> {
+        let Some(max) = self.max_connections() else {
+            return Ok(());
+        };
+        let count = jni_with_env(|env| {
+            let manager = self.inner.manager.as_ref(env);
+            let devices = manager
+                .getConnectedDevices(BluetoothProfile::GATT)?
+                .non_null()?;
+            Ok::<_, crate::Error>(devices.size()?)
+        })?;
+        if usize::try_from(count).unwrap_or(0) >= max {
+            return Err(Error::new(
+                ErrorKind::NotReady,
+                None,
+                format!("the maximum of {max} simultaneous connections is reached"),
+            ));
+        }
+        Ok(())
+    }
+
     /// Finds all connected devices providing any service in `service_ids`.
     pub async fn connected_devices_with_services(
         &self,
@@ -369,75 +891,136 @@ impl Adapter {
         &'a self,
         service_ids: &'a [Uuid],
     ) -> Result<impl Stream<Item = AdvertisingDevice> + Send + Unpin + 'a> {
+        let filters: Vec<_> = service_ids
+            .iter()
+            .map(|&uuid| ScanFilter::new().service(uuid))
+            .collect();
+        self.scan_with_filters(&filters).await
+    }
+
+    /// Starts scanning for Bluetooth advertising packets like [Adapter::scan].
+    ///
+    /// If `filters` is not empty, returns advertisements matching any of the filters.
+    /// Otherwise returns all advertisements.
+    pub async fn scan_with_filters<'a>(
+        &'a self,
+        filters: &[ScanFilter],
+    ) -> Result<impl Stream<Item = AdvertisingDevice> + Send + Unpin + 'a> {
+        let stream = self
+            .scan_with_settings(filters, &ScanSettings::default())
+            .await?;
+        Ok(stream.filter_map(|event| match event {
+            ScanEvent::Found(adv_dev) => Some(adv_dev),
+            _ => None,
+        }))
+    }
+
+    /// Starts scanning for Bluetooth advertising packets with `settings`, returning a stream of [ScanEvent].
+    ///
+    /// If `filters` is not empty, returns advertisements matching any of the filters.
+    /// Otherwise returns all advertisements.
+    pub async fn scan_with_settings<'a>(
+        &'a self,
+        filters: &[ScanFilter],
+        settings: &ScanSettings,
+    ) -> Result<ScanEvents<'a>> {
         check_scan_permission()?;
-        let (start_receiver, stream) = jni_with_env(|env| {
+        let client_filters: Vec<ScanFilter> =
+            if filters.iter().any(|filter| filter.needs_client_side()) {
+                filters.to_vec()
+            } else {
+                Vec::new()
+            };
+        let (start_receiver, stream, guard) = jni_with_env(|env| {
             let (start_sender, start_receiver) = async_channel::bounded(1);
-            let (device_sender, device_receiver) = async_channel::bounded(16);
+            let (event_sender, event_receiver) = async_channel::unbounded();
 
-            let callback = ScanCallback::new_proxy(
-                env,
-                Arc::new(ScanCallbackProxy {
-                    device_sender,
-                    start_sender,
-                }),
-            )?;
+            let sink = Arc::new(ScanResultSink {
+                start_sender,
+                event_sender,
+                min_rssi: AtomicI32::new(i32::MIN),
+            });
+            let callback =
+                ScanCallback::new_proxy(env, Arc::new(ScanCallbackProxy { sink: sink.clone() }))?;
             let callback_global = callback.as_global();
 
             let adapter = self.inner.adapter.as_ref(env);
-            let adapter_global = adapter.as_global();
-            let adapter = Monitor::new(&adapter);
-            let scanner = adapter.getBluetoothLeScanner()?.non_null()?;
-            let scanner_global = scanner.as_global();
+            let scanner = Monitor::new(&adapter).getBluetoothLeScanner()?.non_null()?;
 
-            let settings_builder = ScanSettings_Builder::new(env)?;
-            settings_builder.setScanMode(ScanSettings::SCAN_MODE_LOW_LATENCY)?;
-            let settings = settings_builder.build()?.non_null()?;
+            // last reported advertisements for `ScanSettings::deduplicate`.
+            let mut reported = settings.is_deduplicated().then(HashMap::new);
+            let settings_min_rssi = settings.min_rssi_value();
+            let settings = settings.to_java(env, &adapter)?;
 
-            if !service_ids.is_empty() {
-                let filter_builder = ScanFilter_Builder::new(env)?;
+            let filter_list = if !filters.is_empty() {
                 let filter_list = java::util::ArrayList::new(env)?;
-                for uuid in service_ids {
-                    let uuid_string = JString::from_env_str(env, uuid.to_string());
-                    let parcel_uuid = ParcelUuid::fromString(env, uuid_string)?;
-                    filter_builder.setServiceUuid_ParcelUuid(parcel_uuid)?;
-                    let filter = filter_builder.build()?.non_null()?;
-                    filter_list.add_Object(filter)?;
+                for filter in filters {
+                    filter_list.add_Object(filter.to_java(env)?)?;
                 }
-                scanner.startScan_List_ScanSettings_ScanCallback(
-                    filter_list,
-                    settings,
-                    callback,
-                )?;
+                Some(filter_list.as_global())
             } else {
-                scanner.startScan_List_ScanSettings_ScanCallback(Null, settings, callback)?;
+                None
             };
 
-            let guard = defer(move || {
-                jni_with_env(|env| {
-                    let callback = callback_global.as_ref(env);
-                    let scanner = scanner_global.as_ref(env);
-                    if adapter_global.as_ref(env).isEnabled().unwrap_or(false) {
-                        match scanner.stopScan_ScanCallback(callback) {
-                            Ok(()) => debug!("stopped scan"),
-                            Err(e) => warn!("failed to stop scan: {:?}", e),
-                        };
-                    }
-                });
+            let guard = Arc::new(ActiveScan {
+                adapter: adapter.as_global(),
+                scanner: scanner.as_global(),
+                callback: callback_global,
+                filters: filter_list,
+                settings: settings.as_global(),
+                sink,
+                stopped: AtomicBool::new(false),
+                paused: AtomicBool::new(false),
             });
+            guard.set_min_rssi(env, settings_min_rssi);
+            guard.start(env)?;
+            let mut active_scans = ACTIVE_SCANS.lock().unwrap();
+            active_scans.retain(|scan| scan.strong_count() > 0);
+            active_scans.push(Arc::downgrade(&guard));
+            drop(active_scans);
+            if *SCAN_PAUSE_DEPTH.lock().unwrap() > 0 {
+                guard.pause();
+            }
 
             Ok::<_, crate::Error>((
                 start_receiver,
-                Box::pin(device_receiver).map(move |adv_dev| {
-                    let _guard = &guard;
-                    adv_dev
-                }),
+                Box::pin(event_receiver)
+                    .filter(move |event| match event {
+                        ScanEvent::Found(adv_dev) => {
+                            client_filters.is_empty()
+                                || client_filters.iter().any(|filter| filter.matches(adv_dev))
+                        }
+                        _ => true,
+                    })
+                    .filter(move |event| match (event, reported.as_mut()) {
+                        (ScanEvent::Found(adv_dev), Some(reported)) => {
+                            match reported.insert(adv_dev.device.id(), adv_dev.adv_data.clone()) {
+                                Some(last) => !last.significant_eq(&adv_dev.adv_data),
+                                None => true,
+                            }
+                        }
+                        (ScanEvent::Lost(dev_id), Some(reported)) => {
+                            reported.remove(dev_id);
+                            true
+                        }
+                        _ => true,
+                    }),
+                guard,
             ))
         })?;
 
         #[rustfmt::skip]
         let stream = StreamUntil::create(
             stream,
-            self.inner.global_event_receiver.subscribe().await?,
+            self.inner
+                .global_event_receiver
+                .subscribe(EventKind::Discovery)
+                .await?
+                .or(self
+                    .inner
+                    .global_event_receiver
+                    .subscribe(EventKind::AdapterState)
+                    .await?),
             |event| {
                 matches!(
                     event,
@@ -449,7 +1032,7 @@ impl Adapter {
 
         // Wait for scan started or failed.
         match start_receiver.recv().await {
-            Ok(Ok(())) => Ok(stream),
+            Ok(Ok(())) => Ok(ScanEvents::new(Box::pin(stream), guard)),
             Ok(Err(e)) => Err(e),
             Err(e) => Err(Error::new(
                 ErrorKind::Internal,
@@ -459,6 +1042,107 @@ impl Adapter {
         }
     }
 
+    /// Scans like [Adapter::scan_with_filters], but yields the whole list of devices found at most
+    /// once per `refresh` interval, for refreshing a device list in the UI without handling every
+    /// advertisement.
+    ///
+    /// Each device appears once in the list with its latest advertisement, in the order of being
+    /// found. A list is yielded only if it has changed since the last one. Returns an
+    /// [ErrorKind::InvalidParameter] error if `refresh` is zero.
+    pub async fn scan_throttled<'a>(
+        &'a self,
+        filters: &[ScanFilter],
+        refresh: Duration,
+    ) -> Result<impl Stream<Item = Vec<AdvertisingDevice>> + Send + Unpin + 'a> {
+        struct ThrottleState<S> {
+            stream: Option<S>,
+            devices: Vec<AdvertisingDevice>,
+            changed: bool,
+            next_emit: Instant,
+        }
+        if refresh.is_zero() {
+            return Err(Error::new(
+                ErrorKind::InvalidParameter,
+                None,
+                "the refresh interval of `scan_throttled` must not be zero",
+            ));
+        }
+        let scan = self
+            .scan_with_settings(filters, &ScanSettings::default())
+            .await?;
+        let state = ThrottleState {
+            stream: Some(scan),
+            devices: Vec::new(),
+            changed: false,
+            next_emit: Instant::now() + refresh,
+        };
+        Ok(Box::pin(stream::unfold(
+            state,
+            move |mut state| async move {
+                loop {
+                    let Some(scan) = state.stream.as_mut() else {
+                        return None;
+                    };
+                    let remaining = state.next_emit.saturating_duration_since(Instant::now());
+                    let event = async { Some(scan.next().await) }
+                        .or(async {
+                            Delay::new(remaining).await;
+                            None
+                        })
+                        .await;
+                    match event {
+                        Some(Some(ScanEvent::Found(adv_dev))) => {
+                            let id = adv_dev.device.id();
+                            match state.devices.iter_mut().find(|dev| dev.device.id() == id) {
+                                Some(existing) => *existing = adv_dev,
+                                None => state.devices.push(adv_dev),
+                            }
+                            state.changed = true;
+                        }
+                        Some(Some(ScanEvent::Lost(id))) => {
+                            state.devices.retain(|dev| dev.device.id() != id);
+                            state.changed = true;
+                        }
+                        Some(Some(_)) => (),
+                        Some(None) => {
+                            // the scan is stopped; yields pending changes before ending.
+                            state.stream = None;
+                            if state.changed {
+                                let devices = state.devices.clone();
+                                return Some((devices, state));
+                            }
+                        }
+                        None => {
+                            state.next_emit = Instant::now() + refresh;
+                            if state.changed {
+                                state.changed = false;
+                                let devices = state.devices.clone();
+                                return Some((devices, state));
+                            }
+                        }
+                    }
+                }
+            },
+        )))
+    }
+
+    /// Pauses all active scans of this crate: `stopScan` is called, but the scan streams stay
+    /// alive and yield [ScanEvent::Paused]. Scans started while paused are paused right away.
+    ///
+    /// Pauses are counted: scans are resumed after [Adapter::resume_scanning] is called as many
+    /// times as this method. This affects all `Adapter` handles.
+    pub fn pause_scanning(&self) {
+        pause_active_scans();
+        debug!("paused scans");
+    }
+
+    /// Resumes scans paused by [Adapter::pause_scanning] with their original filters and
+    /// settings; each resumed stream yields [ScanEvent::Resumed]. If any scan fails to restart,
+    /// the last error is returned, and other scans are still resumed.
+    pub fn resume_scanning(&self) -> Result<()> {
+        resume_active_scans()
+    }
+
     /// Finds Bluetooth devices providing any service in `services`.
     ///
     /// Returns a stream of [`Device`] structs with matching connected devices returned first. If the stream is not
@@ -483,10 +1167,33 @@ impl Adapter {
         Ok(connected.chain(advertising))
     }
 
-    /// Connects to the [`Device`].
+    /// Connects to the [`Device`] directly.
     pub async fn connect_device(&self, device: &Device) -> Result<()> {
+        self.connect_device_auto(device, false).await
+    }
+
+    /// Connects to the [`Device`], passing `auto_connect` to `BluetoothDevice.connectGatt()`.
+    ///
+    /// With `auto_connect` set to false, this is the same as [Adapter::connect_device]: the
+    /// connection is made quickly or fails after the connection timeout. With `auto_connect` set
+    /// to true, the Bluetooth stack connects in the background whenever the device becomes
+    /// available, which is slower but doesn't time out; this returns once the device is connected.
+    /// If the returned future is dropped, the background connection is still pending until
+    /// [Adapter::disconnect_device] is called.
+    pub async fn connect_device_auto(&self, device: &Device, auto_connect: bool) -> Result<()> {
+        let connect = self.connect_device_untraced(device, auto_connect);
+        traced("connect", &device.id(), None, connect).await
+    }
+
+    async fn connect_device_untraced(&self, device: &Device, auto_connect: bool) -> Result<()> {
         check_connection_permission()?;
-        let _conn_lock = CONN_MUTEX.lock().await;
+        let scan_pause = self.inner.pause_scans_while_connecting.then(ScanPause::new);
+        // Waiting for the permit must not hold `CONN_MUTEX`, which is needed by `disconnect_device`.
+        let connection_slot = match &self.inner.connection_slots {
+            Some(slots) if !device.is_connected().await => Some(slots.acquire_arc().await),
+            _ => None,
+        };
+        let conn_lock = CONN_MUTEX.lock().await;
         if device.is_connected().await {
             return Ok(());
         }
@@ -497,18 +1204,36 @@ impl Adapter {
                 "device is connected outside the current `android_ble` library",
             ));
         }
+        if connection_slot.is_none() {
+            // with a permit, the limit is already respected by waiting for it.
+            self.check_connection_limit()?;
+        }
         let callback_hdl = BluetoothGattCallbackProxy::new(device.id());
         jni_with_env(|env| {
             let adapter = self.inner.adapter.as_ref(env);
             let _lock = Monitor::new(&adapter);
             let device_obj = device.device.as_local(env);
-            let proxy = BluetoothGattCallback::new_proxy(env, callback_hdl.clone())?;
-            let gatt = device_obj
-                .connectGatt_Context_boolean_BluetoothGattCallback(
-                    android_context().as_ref(env),
-                    false,
+            let proxy = BluetoothGattCallback::new_proxy(
+                env,
+                callback_hdl.java_handler(&self.inner.gatt_options),
+            )?;
+            let context = android_context();
+            let context = context.as_ref(env);
+            let gatt = match &self.inner.gatt_options.callback_thread {
+                Some(thread) => connect_gatt_with_handler(
+                    &device_obj,
+                    context,
+                    auto_connect,
                     proxy,
-                )
+                    thread.handler(),
+                ),
+                None => device_obj.connectGatt_Context_boolean_BluetoothGattCallback(
+                    context,
+                    auto_connect,
+                    proxy,
+                ),
+            };
+            let gatt = gatt
                 .map_err(|e| {
                     Error::new(
                         ErrorKind::Internal,
@@ -520,17 +1245,49 @@ impl Adapter {
                 .as_global();
             GattTree::register_connection(
                 &device.id(),
+                device.device.clone(),
                 gatt,
                 &callback_hdl,
                 &self.inner.global_event_receiver,
+                self.inner.gatt_options.clone(),
+                connection_slot,
             );
             Ok::<_, crate::Error>(())
         })?;
+        if let Ok(conn) = GattTree::check_connection(&device.id()) {
+            // pending operations are failed if the bond is removed while connected; the bond
+            // state receiver is registered later if the device is bonded after connecting.
+            if device.is_paired().await.unwrap_or(false) {
+                conn.watch_bond(&device.id()).await;
+            }
+        }
+        if auto_connect {
+            // `disconnect_device` must be usable while waiting for the background connection,
+            // and scans must not be paused for as long as the device is away.
+            drop(conn_lock);
+            drop(scan_pause);
+        }
         if !self.is_actually_connected(&device.id())? {
-            GattTree::wait_connection_available(&device.id()).await?;
+            // The Android stack reports a generic error (133) if the connection limit is hit.
+            GattTree::wait_connection_available(
+                &device.id(),
+                auto_connect.then_some(AUTO_CONNECT_TIMEOUT),
+            )
+            .await
+            .map_err(|e| {
+                if !auto_connect {
+                    // closes the `BluetoothGatt` of the failed attempt, e.g. on timeout.
+                    GattTree::deregister_connection(&device.id());
+                }
+                match e.kind() {
+                    ErrorKind::NotConnected => self.check_connection_limit().err().unwrap_or(e),
+                    _ => e,
+                }
+            })?;
         }
         if self.inner.request_mtu_on_connect {
             let conn = GattTree::check_connection(&device.id())?;
+            let _sequence_guard = conn.sequence_guard().await;
             let mtu_lock = conn.mtu_changed_received.lock().await;
             jni_with_env(|env| {
                 let gatt = conn.gatt.as_ref(env);
@@ -548,6 +1305,21 @@ impl Adapter {
         Ok(())
     }
 
+    /// Returns the amount of `BluetoothGatt` client objects opened by this crate which are not
+    /// yet closed, for checking resource leaks in tests. Each of them is closed exactly once when
+    /// the device is disconnected by any means, the Bluetooth adapter is turned off, or the last
+    /// `Adapter` (including all of its clones) is dropped.
+    pub fn open_gatt_count(&self) -> usize {
+        GattTree::open_gatt_count()
+    }
+
+    /// Returns the amount of panics caught in callbacks from the Android Bluetooth stack since
+    /// the process started. Such a panic is logged and never unwinds into Java, but the event
+    /// being processed is lost, so the crate state may be inconsistent if this is not zero.
+    pub fn callback_panic_count(&self) -> usize {
+        callback_panic_count()
+    }
+
     /// Disconnects from the [`Device`].
     ///
     /// XXX: manage to call this internally when all API wrapper objects for the device are dropped.
@@ -574,6 +1346,26 @@ impl Adapter {
         Ok(())
     }
 
+    /// Releases all Bluetooth resources held by this crate, e.g. when the app goes to background:
+    /// stops all scans, closes all connections (which also disconnects them), and unregisters
+    /// the broadcast receivers. Existing scan streams, event streams and pending operations end
+    /// or fail; `Device` handles become disconnected.
+    ///
+    /// This affects all `Adapter` handles. They are still usable afterwards, and resources are
+    /// acquired again when they are needed.
+    pub async fn shutdown(&self) {
+        let scans: Vec<_> = ACTIVE_SCANS.lock().unwrap().drain(..).collect();
+        for scan in scans.iter().filter_map(Weak::upgrade) {
+            scan.stop();
+        }
+        // waits for a direct connection being made to be registered.
+        let _conn_lock = CONN_MUTEX.lock().await;
+        if GattTree::clear_connections() {
+            debug!("closed all connections in Adapter::shutdown");
+        }
+        self.inner.global_event_receiver.shutdown();
+    }
+
     /// Monitors a device for connection/disconnection events.
     ///
     /// This monitors only devices connected/disconnected in this library instance,
@@ -606,8 +1398,11 @@ impl Adapter {
             let devices = manager
                 .getConnectedDevices(BluetoothProfile::GATT)?
                 .non_null()?;
-            let iter_devices = JavaIterator(devices.iterator()?.non_null()?);
-            for device in iter_devices.filter_map(|dev| dev.cast::<BluetoothDevice>().ok()) {
+            let iter_devices = JavaIterator::new(devices.iterator()?.non_null()?);
+            for device in iter_devices {
+                let Ok(device) = device?.cast::<BluetoothDevice>() else {
+                    continue;
+                };
                 if dev_id.0 == device.getAddress()?.non_null()?.to_string_lossy().trim() {
                     return Ok(true);
                 }
@@ -635,19 +1430,188 @@ impl std::fmt::Debug for Adapter {
     }
 }
 
+/// Hooks for instrumented tests on Android, which feed synthetic events into the channels used by
+/// the Java callbacks, so that scanning and reconnecting logic can be exercised without a real
+/// peripheral.
+#[cfg(feature = "test-injection")]
+impl Adapter {
+    /// Delivers an advertisement of the device `id` to all active scans, as if it were received
+    /// by their `ScanCallback`s; it is dropped by scans with a higher RSSI threshold (see
+    /// [crate::ScanSettings::min_rssi]).
+    pub async fn inject_scan_result(
+        &self,
+        adv_data: AdvertisementData,
+        rssi: i16,
+        id: DeviceId,
+    ) -> Result<()> {
+        let device = self.open_device(&id).await?;
+        for scan in active_scans() {
+            scan.sink.deliver_found(rssi.into(), || {
+                Ok(AdvertisingDevice {
+                    device: device.clone(),
+                    adv_data: adv_data.clone(),
+                    rssi: Some(rssi),
+                    extended: None,
+                })
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reports a connection event of the device `id` to streams returned by
+    /// [Adapter::device_connection_events]. If [ConnectionEvent::Disconnected] is injected for a
+    /// device connected by this crate, its connection is closed like a real disconnection, so
+    /// pending operations fail with [ErrorKind::NotConnected].
+    pub fn inject_connection_event(&self, id: DeviceId, event: ConnectionEvent) {
+        GattTree::inject_connection_event(&id, event);
+    }
+
+    /// Delivers `value` to notification streams of `characteristic`, as if it were received by
+    /// `onCharacteristicChanged`. The characteristic must belong to a connected device.
+    pub fn inject_notification(
+        &self,
+        characteristic: &crate::Characteristic,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        characteristic.inject_notification(value)
+    }
+}
+
+/// Stops the scan when it is dropped, or by [Adapter::shutdown].
+pub(crate) struct ActiveScan {
+    adapter: Global<BluetoothAdapter>,
+    scanner: Global<BluetoothLeScanner>,
+    callback: Global<ScanCallback>,
+    /// Kept for restarting the scan after it is paused.
+    filters: Option<Global<java::util::ArrayList>>,
+    settings: Global<JScanSettings>,
+    /// Shared with `ScanCallbackProxy`.
+    sink: Arc<ScanResultSink>,
+    stopped: AtomicBool,
+    paused: AtomicBool,
+}
+
+impl ActiveScan {
+    /// Sets the threshold in the Java callback; it is also checked by `ScanCallbackProxy`, in case
+    /// the field is unavailable in an outdated copy of the proxy class.
+    pub(crate) fn set_min_rssi(&self, env: Env<'_>, min_rssi: Option<i16>) {
+        let value = min_rssi.map_or(i32::MIN, i32::from);
+        self.sink.min_rssi.store(value, Ordering::SeqCst);
+        if !jni_set_int_field(&self.callback.as_ref(env), "minRssi\0", value) {
+            warn!("`ScanCallback.minRssi` is unavailable, filtering by RSSI in native code");
+        }
+    }
+
+    fn start(&self, env: Env<'_>) -> Result<()> {
+        let adapter = self.adapter.as_ref(env);
+        let _adapter = Monitor::new(&adapter);
+        let scanner = self.scanner.as_ref(env);
+        let (settings, callback) = (self.settings.as_ref(env), self.callback.as_ref(env));
+        match &self.filters {
+            Some(filters) => scanner.startScan_List_ScanSettings_ScanCallback(
+                filters.as_ref(env),
+                settings,
+                callback,
+            )?,
+            None => scanner.startScan_List_ScanSettings_ScanCallback(Null, settings, callback)?,
+        }
+        Ok(())
+    }
+
+    fn stop(&self) {
+        if self.stopped.swap(true, Ordering::SeqCst) || self.paused.load(Ordering::SeqCst) {
+            return;
+        }
+        self.stop_scan();
+    }
+
+    fn pause(&self) {
+        if self.stopped.load(Ordering::SeqCst) || self.paused.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.stop_scan();
+        let _ = self.sink.event_sender.try_send(ScanEvent::Paused);
+    }
+
+    fn resume(&self) -> Result<()> {
+        if self.stopped.load(Ordering::SeqCst) || !self.paused.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        jni_with_env(|env| self.start(env))?;
+        debug!("resumed scan");
+        let _ = self.sink.event_sender.try_send(ScanEvent::Resumed);
+        Ok(())
+    }
+
+    fn stop_scan(&self) {
+        jni_with_env(|env| {
+            let callback = self.callback.as_ref(env);
+            let scanner = self.scanner.as_ref(env);
+            if self.adapter.as_ref(env).isEnabled().unwrap_or(false) {
+                match scanner.stopScan_ScanCallback(callback) {
+                    Ok(()) => debug!("stopped scan"),
+                    Err(e) => warn!("failed to stop scan: {:?}", e),
+                };
+            }
+        });
+    }
+}
+
+impl Drop for ActiveScan {
+    fn drop(&mut self) {
+        self.stop()
+    }
+}
+
 struct ScanCallbackProxy {
+    sink: Arc<ScanResultSink>,
+}
+
+/// Shared by `ScanCallbackProxy` and `ActiveScan`, so that injected scan results go through the
+/// same checks as those received by the Java callback.
+struct ScanResultSink {
     start_sender: async_channel::Sender<Result<()>>,
-    device_sender: async_channel::Sender<AdvertisingDevice>,
+    /// Unbounded, so that `Paused` and `Resumed` are never dropped; scan results are sent by
+    /// `send_result`, which keeps at most `SCAN_RESULT_CAPACITY` of them queued.
+    event_sender: async_channel::Sender<ScanEvent>,
+    /// `i32::MIN` means no threshold.
+    min_rssi: AtomicI32,
+}
+
+impl ScanResultSink {
+    /// Drops the advertisement if `rssi` is below the threshold, otherwise builds it with `found`
+    /// and delivers it; the first advertisement also confirms that the scan is started.
+    fn deliver_found(
+        &self,
+        rssi: i32,
+        found: impl FnOnce() -> Result<AdvertisingDevice>,
+    ) -> Result<()> {
+        if rssi < self.min_rssi.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let found = found()?;
+        self.start_sender.try_send(Ok(())).ok();
+        self.send_result(ScanEvent::Found(found));
+        Ok(())
+    }
+
+    /// Drops the scan result if the consumer cannot keep up.
+    fn send_result(&self, event: ScanEvent) {
+        if self.event_sender.len() < SCAN_RESULT_CAPACITY {
+            self.event_sender.try_send(event).ok();
+        }
+    }
 }
 
 impl super::callback::ScanCallbackProxy for ScanCallbackProxy {
     fn onScanFailed<'env>(&self, _env: Env<'env>, error_code: i32) {
+        log_callback!("onScanFailed: error_code {error_code}");
         let e = Error::new(
             ErrorKind::Internal,
             None,
             format!("Scan failed to start with error code {error_code}"),
         );
-        if let Err(e) = self.start_sender.try_send(Err(e)) {
+        if let Err(e) = self.sink.start_sender.try_send(Err(e)) {
             warn!("onScanFailed failed to send error: {e:?}");
         }
     }
@@ -657,6 +1621,10 @@ impl super::callback::ScanCallbackProxy for ScanCallbackProxy {
         env: Env<'env>,
         scan_results: Option<Ref<'env, super::bindings::java::util::List>>,
     ) {
+        log_callback!(
+            "onBatchScanResults: {:?} results",
+            scan_results.as_ref().and_then(|list| list.size().ok())
+        );
         let Some(scan_results) = scan_results else {
             warn!("onBatchScanResults: ignoring null scan_results");
             return;
@@ -670,14 +1638,27 @@ impl super::callback::ScanCallbackProxy for ScanCallbackProxy {
     fn onScanResult<'env>(
         &self,
         env: Env<'env>,
-        _callback_type: i32,
+        callback_type: i32,
         scan_result: Option<Ref<'env, ScanResult>>,
     ) {
+        log_callback!(
+            "onScanResult: callback_type {callback_type}, {:?}",
+            scan_result
+                .as_ref()
+                .and_then(|res| Some(res.toString().ok()??.to_string_lossy()))
+        );
         let Some(scan_result) = scan_result else {
             warn!("onScanResult: ignoring null scan_result");
             return;
         };
 
+        if callback_type == JScanSettings::CALLBACK_TYPE_MATCH_LOST {
+            if let Err(e) = self.on_match_lost(&scan_result) {
+                warn!("onScanResult failed for a lost match: {e:?}");
+            }
+            return;
+        }
+
         if let Err(e) = self.on_scan_result(env, &scan_result) {
             warn!("onScanResult failed: {e:?}");
         }
@@ -690,91 +1671,121 @@ impl ScanCallbackProxy {
         env: Env<'_>,
         scan_results: &Ref<super::bindings::java::util::List>,
     ) -> Result<()> {
-        for scan_result in JavaIterator(scan_results.iterator()?.non_null()?) {
-            let scan_result: Local<ScanResult> = scan_result.cast()?;
+        for scan_result in JavaIterator::new(scan_results.iterator()?.non_null()?) {
+            let scan_result: Local<ScanResult> = scan_result?.cast()?;
             self.on_scan_result(env, &scan_result.as_ref())?;
         }
         Ok(())
     }
 
     fn on_scan_result(&self, _env: Env<'_>, scan_result: &Ref<ScanResult>) -> Result<()> {
-        let scan_record = scan_result.getScanRecord()?.non_null()?;
+        let rssi = scan_result.getRssi()?;
+        self.sink
+            .deliver_found(rssi, || advertising_device(scan_result, rssi))
+    }
+
+    fn on_match_lost(&self, scan_result: &Ref<ScanResult>) -> Result<()> {
         let device = scan_result.getDevice()?.non_null()?;
+        let address = device.getAddress()?.non_null()?.to_string_lossy();
+        self.sink.start_sender.try_send(Ok(())).ok();
+        self.sink
+            .send_result(ScanEvent::Lost(DeviceId(address.trim().to_string())));
+        Ok(())
+    }
+}
 
-        let address = device
-            .getAddress()?
-            .non_null()?
-            .to_string_lossy()
-            .trim()
-            .to_string();
-        let rssi = scan_result.getRssi()?;
-        let is_connectable = if android_api_level() >= 26 {
-            scan_result.isConnectable()?
-        } else {
-            true // XXX: try to check `eventType` via `ScanResult.toString()`
-        };
-        let local_name = scan_record.getDeviceName()?.map(|s| s.to_string_lossy());
-        let tx_power_level = scan_record.getTxPowerLevel()?;
-
-        // Services
-        let mut services = Vec::new();
-        if let Some(uuids) = scan_record.getServiceUuids()? {
-            for uuid in JavaIterator(uuids.iterator()?.non_null()?) {
-                services.push(Uuid::from_andriod_parcel(uuid.cast()?)?)
-            }
-        }
+/// Builds the `AdvertisingDevice` reported by a `ScanResult` received with `rssi`.
+fn advertising_device(scan_result: &Ref<ScanResult>, rssi: i32) -> Result<AdvertisingDevice> {
+    let scan_record = scan_result.getScanRecord()?.non_null()?;
+    let device = scan_result.getDevice()?.non_null()?;
 
-        // Service data
-        let mut service_data = HashMap::new();
-        let sd = scan_record.getServiceData()?.non_null()?;
-        let sd = sd.entrySet()?.non_null()?;
-        for entry in JavaIterator(sd.iterator()?.non_null()?) {
-            let entry: Local<Map_Entry> = entry.cast()?;
-            let key: Local<ParcelUuid> = entry.getKey()?.non_null()?.cast()?;
-            let val: Local<ByteArray> = entry.getValue()?.non_null()?.cast()?;
-            service_data.insert(Uuid::from_andriod_parcel(key)?, val.as_vec_u8());
-        }
-
-        // Manufacturer data
-        let mut manufacturer_data = None;
-        let msd = scan_record.getManufacturerSpecificData()?.non_null()?;
-        // TODO: there can be multiple manufacturer data entries, but the API (compatible with bluest)
-        // only supports one. So grab just the first.
-        if msd.size()? != 0 {
-            let val: Local<'_, ByteArray> = msd.valueAt(0)?.non_null()?.cast()?;
-            manufacturer_data = Some(ManufacturerData {
-                company_id: msd.keyAt(0)? as _,
-                data: val.as_vec_u8(),
-            });
+    let address = device
+        .getAddress()?
+        .non_null()?
+        .to_string_lossy()
+        .trim()
+        .to_string();
+    let is_connectable = if android_api_level() >= 26 {
+        scan_result.isConnectable()?
+    } else {
+        true // unknown, see `AdvertisingDevice::is_connectable`.
+    };
+    let local_name = scan_record.getDeviceName()?.map(|s| s.to_string_lossy());
+    let tx_power_level = scan_record.getTxPowerLevel()?;
+
+    // Services
+    let mut services = Vec::new();
+    if let Some(uuids) = scan_record.getServiceUuids()? {
+        for uuid in JavaIterator::new(uuids.iterator()?.non_null()?) {
+            services.push(Uuid::from_android_parcel(uuid?.cast()?)?)
         }
+    }
 
-        let device_id = DeviceId(address);
+    // Service data
+    let mut service_data = HashMap::new();
+    let sd = scan_record.getServiceData()?.non_null()?;
+    let sd = sd.entrySet()?.non_null()?;
+    for entry in JavaIterator::new(sd.iterator()?.non_null()?) {
+        let entry: Local<Map_Entry> = entry?.cast()?;
+        let key: Local<ParcelUuid> = entry.getKey()?.non_null()?.cast()?;
+        let val: Local<ByteArray> = entry.getValue()?.non_null()?.cast()?;
+        service_data.insert(Uuid::from_android_parcel(key)?, val.as_vec_u8());
+    }
 
-        let d = AdvertisingDevice {
-            device: Device {
-                id: device_id.clone(),
-                device: device.as_global(),
-                connection: CachedWeak::new(),
-                once_connected: Arc::new(if GattTree::find_connection(&device_id).is_none() {
-                    OnceLock::new()
-                } else {
-                    OnceLock::from(()) // NOTE: this is unlikely to happen
-                }),
-            },
-            adv_data: AdvertisementData {
-                is_connectable,
-                local_name,
-                manufacturer_data, // TODO, SparseArray is cursed.
-                service_data,
-                services,
-                tx_power_level: Some(tx_power_level as _),
-            },
-            rssi: Some(rssi as _),
-        };
+    // Manufacturer data
+    let mut manufacturer_data = None;
+    let msd = scan_record.getManufacturerSpecificData()?.non_null()?;
+    // TODO: there can be multiple manufacturer data entries, but the API (compatible with bluest)
+    // only supports one. So grab just the first.
+    if msd.size()? != 0 {
+        let val: Local<'_, ByteArray> = msd.valueAt(0)?.non_null()?.cast()?;
+        manufacturer_data = Some(ManufacturerData {
+            company_id: msd.keyAt(0)? as _,
+            data: val.as_vec_u8(),
+        });
+    }
 
-        self.start_sender.try_send(Ok(())).ok();
-        self.device_sender.try_send(d).ok();
+    let extended = if android_api_level() >= 26 {
+        let interval = scan_result.getPeriodicAdvertisingInterval()?;
+        let sid = scan_result.getAdvertisingSid()?;
+        Some(ExtendedAdvertisingInfo {
+            is_legacy: scan_result.isLegacy()?,
+            primary_phy: Phy::from_java(scan_result.getPrimaryPhy()?),
+            secondary_phy: Phy::from_java(scan_result.getSecondaryPhy()?),
+            advertising_sid: (sid != ScanResult::SID_NOT_PRESENT)
+                .then(|| u8::try_from(sid).ok())
+                .flatten(),
+            // in units of 1.25 ms
+            periodic_advertising_interval: (interval != ScanResult::PERIODIC_INTERVAL_NOT_PRESENT)
+                .then(|| Duration::from_micros(interval as u64 * 1250)),
+            data_truncated: scan_result.getDataStatus()? == ScanResult::DATA_TRUNCATED,
+        })
+    } else {
+        None
+    };
 
-        Ok(())
-    }
+    let device_id = DeviceId(address);
+
+    Ok(AdvertisingDevice {
+        device: Device {
+            id: device_id.clone(),
+            device: device.as_global(),
+            connection: CachedWeak::new(),
+            once_connected: Arc::new(if GattTree::find_connection(&device_id).is_none() {
+                OnceLock::new()
+            } else {
+                OnceLock::from(()) // NOTE: this is unlikely to happen
+            }),
+        },
+        adv_data: AdvertisementData {
+            is_connectable,
+            local_name,
+            manufacturer_data, // TODO, SparseArray is cursed.
+            service_data,
+            services,
+            tx_power_level: Some(tx_power_level as _),
+        },
+        rssi: Some(rssi as _),
+        extended,
+    })
 }
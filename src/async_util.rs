@@ -1,38 +1,97 @@
-use std::marker::PhantomData;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::future::Future;
 use std::pin::Pin;
-use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
 use std::task;
 use std::time::{Duration, Instant};
 
-use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use async_broadcast::{InactiveReceiver, Receiver, Sender, TrySendError};
 use async_lock::{Mutex, MutexGuard, OnceCell};
 use futures_core::Stream;
-use futures_lite::{FutureExt, StreamExt};
-use futures_timer::Delay;
+use futures_lite::FutureExt;
+use log::debug;
+
+use crate::observer::{OperationKind, OperationObserver, OperationOutcome};
+use crate::timer::Delay;
+use crate::OverflowPolicy;
 
 /// Reusable exclusive register for `ResultWaiter`.
 pub struct Excluder<T: Send + Clone> {
-    inner: Mutex<Option<LockMark>>,
+    inner: Mutex<Option<LockMark<T>>>,
+    /// Makes sure the excluder is locked by tasks in the order of calling `lock`.
+    queue: FifoGate,
     last_val: Arc<Mutex<Option<T>>>,
     timeout: Duration,
+    /// Set by `abort_all`; the excluder cannot be used normally after that.
+    aborted: Arc<AtomicBool>,
+    /// Lock IDs of operations abandoned by their waiters after being started, with the expiry
+    /// time points; the next callbacks are late results of them.
+    stale: Arc<std::sync::Mutex<VecDeque<(usize, Instant)>>>,
+    observer: Option<(OperationKind, Arc<dyn OperationObserver>)>,
+}
+
+/// Android's GATT stack gives up an outstanding request after 30 seconds, then the link is dropped.
+const STALE_CALLBACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The reason why [ResultWaiter::wait_unlock] returns without a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The timeout value or the deadline is reached.
+    Timeout,
+    /// The `Excluder` is aborted or dropped, usually because of a disconnection.
+    Aborted,
 }
 
+/// Checks if a callback result belongs to the operation of a lock, see [ResultWaiter::expect].
+type ResultMatcher<T> = Arc<OnceLock<Box<dyn Fn(&T) -> bool + Send + Sync>>>;
+
+/// A result taken as the late result of an abandoned operation while a lock is held; it is
+/// given to the lock's waiter if no other result arrives before the timeout.
+type SwallowedResult<T> = Arc<std::sync::Mutex<Option<T>>>;
+
 /// Prevents other tasks from doing the same operation before the corresponding
 /// "foreign" callback is reiceived, or the timeout value is reached.
-struct LockMark {
+struct LockMark<T> {
     id: usize,
     callback_sender: Sender<()>,
     #[allow(unused)]
     sender_keeper: InactiveReceiver<()>,
     tp_timeout: Arc<OnceCell<Instant>>,
+    /// The timeout value of this lock, which may be different from that of the `Excluder`.
+    timeout: Duration,
+    matcher: ResultMatcher<T>,
+    swallowed: SwallowedResult<T>,
+}
+
+impl<T> LockMark<T> {
+    /// A lock is held until its waiter reaches the timeout; before `wait_unlock` is called,
+    /// the timeout is not yet determined.
+    fn is_held(&self) -> bool {
+        match self.tp_timeout.get() {
+            Some(&tp_timeout) => tp_timeout > Instant::now(),
+            None => true,
+        }
+    }
 }
 
 /// Makes waiting for the result of the "foreign" callback possible.
 pub struct ResultWaiter<T: Send + Clone> {
+    lock_id: usize,
     receiver: Receiver<()>,
     last_val: Weak<Mutex<Option<T>>>,
     tp_timeout: Arc<OnceCell<Instant>>,
     timeout: Duration,
+    matcher: ResultMatcher<T>,
+    swallowed: SwallowedResult<T>,
+    aborted: Arc<AtomicBool>,
+    stale: Arc<std::sync::Mutex<VecDeque<(usize, Instant)>>>,
+    /// Set when the result is received.
+    completed: bool,
+    observer: Option<(OperationKind, Arc<dyn OperationObserver>)>,
+    /// How long it took to get the lock.
+    queued: Duration,
+    locked_at: Instant,
 }
 
 impl<T: Send + Clone, E: Send + Clone> Excluder<Result<T, E>> {
@@ -42,7 +101,7 @@ impl<T: Send + Clone, E: Send + Clone> Excluder<Result<T, E>> {
     pub async fn obtain(&self, operation: impl FnOnce() -> Result<(), E>) -> Result<Option<T>, E> {
         let waiter = self.lock().await;
         operation()?;
-        if let Some(res) = waiter.wait_unlock().await {
+        if let Ok(res) = waiter.wait_unlock().await {
             Ok(Some(res?))
         } else {
             Ok(None)
@@ -51,15 +110,29 @@ impl<T: Send + Clone, E: Send + Clone> Excluder<Result<T, E>> {
 }
 
 impl<T: Send + Clone> Excluder<T> {
-    /// Creates a new unlocked `Excluder`.
-    pub fn new(callback_timeout: Duration) -> Self {
+    /// Creates a new unlocked `Excluder` with the default timeout value for its locks.
+    pub fn with_timeout(callback_timeout: Duration) -> Self {
         Self {
             inner: Mutex::new(None),
+            queue: FifoGate::default(),
             last_val: Arc::new(Mutex::new(None)),
             timeout: callback_timeout,
+            aborted: Arc::new(AtomicBool::new(false)),
+            stale: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            observer: None,
         }
     }
 
+    /// Reports timing data of operations done with this excluder to `observer`, if any.
+    pub fn observed(
+        mut self,
+        kind: OperationKind,
+        observer: Option<Arc<dyn OperationObserver>>,
+    ) -> Self {
+        self.observer = observer.map(|observer| (kind, observer));
+        self
+    }
+
     /// Clones and returns the last value returned by the "foreign" callback.
     pub fn last_value(&self) -> Option<T> {
         self.last_val.lock_blocking().clone()
@@ -71,11 +144,36 @@ impl<T: Send + Clone> Excluder<T> {
     /// after calling that method, call [ResultWaiter::wait_unlock] in the same task.
     /// Otherwise, the lock will become invalid when the returned `ResultWaiter` is dropped;
     /// even if it is not dropped, another task that tries to lock this excluder will sleep
-    /// for the general timeout value and then invalidate this lock with a new lock.
+    /// for the timeout value of this lock and then invalidate this lock with a new lock.
     pub async fn lock(&self) -> ResultWaiter<T> {
+        self.lock_with_timeout(self.timeout).await
+    }
+
+    /// Waits until the excluder is unlocked and locks the excluder like [Excluder::lock],
+    /// but overrides the timeout value for this lock.
+    pub async fn lock_with_timeout(&self, timeout: Duration) -> ResultWaiter<T> {
+        self.lock_before(timeout, None)
+            .await
+            .expect("`lock_before` returns a waiter if no deadline is given")
+    }
+
+    /// Locks the excluder with `timeout`, which is limited by the time left before `deadline` at
+    /// the moment the lock is taken. Returns `None` if `deadline` is reached before that.
+    async fn lock_before(
+        &self,
+        timeout: Duration,
+        deadline: Option<Instant>,
+    ) -> Option<ResultWaiter<T>> {
+        let t_start = Instant::now();
+        // only the task at the head of the queue waits for the current lock to be released.
+        let _turn = self.queue.enter().await;
         let mut waited_without_tp_timeout = None;
         let mut guard_inner = loop {
             let guard_inner = self.inner.lock().await;
+            if self.aborted.load(Ordering::SeqCst) {
+                // the returned waiter will return `WaitError::Aborted` immediately.
+                break guard_inner;
+            }
             if let Some(lock_mark) = guard_inner.as_ref() {
                 if let Some(prev_id) = waited_without_tp_timeout.as_ref() {
                     if prev_id != &lock_mark.id {
@@ -89,14 +187,22 @@ impl<T: Send + Clone> Excluder<T> {
                         break guard_inner;
                     }
                 } else if waited_without_tp_timeout.is_none() {
+                    // `wait_unlock` is not called yet; waits for the timeout value of the lock
+                    // holder instead of ours, which may be overridden.
                     waited_without_tp_timeout.replace(lock_mark.id);
-                    self.timeout
+                    lock_mark.timeout
                 } else {
                     break guard_inner;
                 };
                 if dur_wait.is_zero() {
                     break guard_inner;
                 }
+                let dur_wait = match deadline {
+                    Some(deadline) => {
+                        dur_wait.min(deadline.checked_duration_since(Instant::now())?)
+                    }
+                    None => dur_wait,
+                };
                 let mut receiver = lock_mark.callback_sender.new_receiver();
                 let fut = receiver.recv().or(async {
                     Delay::new(dur_wait).await;
@@ -108,88 +214,223 @@ impl<T: Send + Clone> Excluder<T> {
                 break guard_inner;
             }
         };
-        self.unchecked_set_lock(&mut guard_inner)
+        let timeout = match deadline {
+            Some(deadline) => timeout.min(deadline.checked_duration_since(Instant::now())?),
+            None => timeout,
+        };
+        let mut waiter = self.unchecked_set_lock(&mut guard_inner, timeout);
+        waiter.queued = t_start.elapsed();
+        Some(waiter)
+    }
+
+    /// Waits until the excluder is unlocked and locks the excluder like [Excluder::lock]; if `deadline`
+    /// is given, the lock's timeout value is limited by it, and `None` is returned if it is reached
+    /// before the excluder is locked.
+    pub async fn lock_until(&self, deadline: Option<Instant>) -> Option<ResultWaiter<T>> {
+        let Some(deadline) = deadline else {
+            return Some(self.lock().await);
+        };
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        // the deadline is checked again whenever the lock holder is waited for, and the timeout
+        // of the new lock is the time left when it is taken, not when this is called.
+        let lock = self.lock_before(self.timeout, Some(deadline));
+        lock.or(async {
+            Delay::new(remaining).await;
+            None
+        })
+        .await
     }
 
     /// Locks the excluder if it is previously unlocked.
     pub fn try_lock(&self) -> Option<ResultWaiter<T>> {
+        if !self.queue.is_idle() {
+            return None;
+        }
         let mut guard_inner = self.inner.lock_blocking();
-        if let Some(lock_mark) = guard_inner.as_ref() {
-            if let Some(&tp_timeout) = lock_mark.tp_timeout.get() {
-                if tp_timeout > Instant::now() {
-                    return None;
-                }
-            } else {
-                return None;
-            }
+        if guard_inner.as_ref().is_some_and(LockMark::is_held) {
+            return None;
         }
-        Some(self.unchecked_set_lock(&mut guard_inner))
+        Some(self.unchecked_set_lock(&mut guard_inner, self.timeout))
+    }
+
+    /// Checks if the excluder is locked or waited for, in which case [Excluder::try_lock]
+    /// would return `None`. The excluder is not locked by this check.
+    pub fn is_locked(&self) -> bool {
+        !self.queue.is_idle()
+            || self
+                .inner
+                .lock_blocking()
+                .as_ref()
+                .is_some_and(LockMark::is_held)
     }
 
     fn unchecked_set_lock(
         &self,
-        guard_inner: &mut MutexGuard<Option<LockMark>>,
+        guard_inner: &mut MutexGuard<Option<LockMark<T>>>,
+        timeout: Duration,
     ) -> ResultWaiter<T> {
         use std::sync::atomic::{AtomicUsize, Ordering};
         static NEXT_LOCK_ID: AtomicUsize = AtomicUsize::new(0);
 
         let (sender, receiver) = async_broadcast::broadcast(2);
         let tp_timeout = Arc::new(OnceCell::new());
+        let matcher = Arc::new(OnceLock::new());
+        let swallowed = Arc::new(std::sync::Mutex::new(None));
+        let lock_id = NEXT_LOCK_ID.fetch_add(1, Ordering::SeqCst);
         let mark = LockMark {
-            id: NEXT_LOCK_ID.fetch_add(1, Ordering::SeqCst),
+            id: lock_id,
             callback_sender: sender,
             sender_keeper: receiver.clone().deactivate(),
             tp_timeout: tp_timeout.clone(),
+            timeout,
+            matcher: matcher.clone(),
+            swallowed: swallowed.clone(),
         };
         guard_inner.replace(mark);
 
         ResultWaiter {
+            lock_id,
             receiver,
             last_val: Arc::downgrade(&self.last_val),
             tp_timeout,
-            timeout: self.timeout,
+            timeout,
+            matcher,
+            swallowed,
+            aborted: self.aborted.clone(),
+            stale: self.stale.clone(),
+            completed: false,
+            observer: self.observer.clone(),
+            queued: Duration::ZERO,
+            locked_at: Instant::now(),
         }
     }
 
-    /// Sends the "completed" (unlock) signal from the "foreign" callback.
-    pub fn unlock(&self, result: T) {
-        // XXX: this may be changed to disallow update of "last value" storage if `self`
-        // is not locked by an operation.
+    /// Makes current and future waiters of this excluder return [WaitError::Aborted] immediately.
+    /// This is called for all excluders of a connection when it is closed.
+    pub fn abort_all(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.stale.lock().unwrap().clear();
+        let mut guard_inner = self.inner.lock_blocking();
+        if let Some(lock_mark) = guard_inner.take() {
+            drop(guard_inner);
+            let _ = lock_mark.callback_sender.try_broadcast(());
+        }
+    }
+
+    /// Completes the current lock with `result` on behalf of the "foreign" callback, which is
+    /// still expected and will be ignored as a late result. Returns `false` if it is not locked.
+    pub fn fail_current(&self, result: T) -> bool {
+        let mut guard_inner = self.inner.lock_blocking();
+        let Some(lock_mark) = guard_inner.take_if(|mark| mark.is_held()) else {
+            return false;
+        };
+        let expiry = Instant::now() + STALE_CALLBACK_TIMEOUT;
+        self.stale.lock().unwrap().push_back((lock_mark.id, expiry));
         self.last_val.lock_blocking().replace(result);
+        drop(guard_inner);
+        let _ = lock_mark.callback_sender.try_broadcast(());
+        true
+    }
 
+    /// Sends the "completed" (unlock) signal from the "foreign" callback.
+    ///
+    /// If the current lock has a matcher set by [ResultWaiter::expect], `result` is attributed
+    /// to it only if it matches, and is ignored otherwise.
+    ///
+    /// Without a matcher, Android doesn't tell which request a callback belongs to, but requests
+    /// of a connection are processed in order; so if some operations were abandoned by their
+    /// waiters (timed out or cancelled), the same amount of upcoming results are not attributed
+    /// to newer locks. Such late results are dropped without becoming the last value; but if the
+    /// current lock gets no other result before its timeout, the abandoned operation's callback
+    /// is assumed to be lost, and the dropped result is given to the current lock instead.
+    pub fn unlock(&self, result: T) {
         let mut guard_inner = self.inner.lock_blocking();
+        let current = guard_inner.as_ref().filter(|mark| mark.is_held());
+        if let Some(matcher) = current.and_then(|mark| mark.matcher.get()) {
+            if !matcher(&result) {
+                debug!("ignored a callback result not matching the current operation");
+                return;
+            }
+        } else {
+            let stale_id = {
+                let mut stale = self.stale.lock().unwrap();
+                let now = Instant::now();
+                while stale.front().is_some_and(|&(_, expiry)| expiry <= now) {
+                    let _ = stale.pop_front();
+                }
+                stale.pop_front().map(|(id, _)| id)
+            };
+            if let Some(stale_id) = stale_id {
+                if !matches!(guard_inner.as_ref(), Some(mark) if mark.id == stale_id) {
+                    debug!("ignored a late callback result of an abandoned operation");
+                    if let Some(mark) = current {
+                        mark.swallowed.lock().unwrap().replace(result);
+                    }
+                    return;
+                }
+            }
+        }
+        // A waiter reads the "last value" storage only after its own signal.
+        self.last_val.lock_blocking().replace(result);
         if let Some(lock_mark) = guard_inner.take() {
             drop(guard_inner);
-            let _ = lock_mark.callback_sender.broadcast_blocking(());
+            // the waiter may be gone, then `broadcast_blocking` would wait for an active receiver.
+            let _ = lock_mark.callback_sender.try_broadcast(());
         }
     }
 }
 
 impl<T: Send + Clone> Default for Excluder<T> {
     fn default() -> Self {
-        Self::new(Duration::from_secs(5))
+        Self::with_timeout(Duration::from_secs(5))
     }
 }
 
 impl<T: Send + Clone> Drop for Excluder<T> {
     fn drop(&mut self) {
-        // makes sure `ResultWaiter::wait_unlock` return `None`.
+        // makes sure `ResultWaiter::wait_unlock` return `WaitError::Aborted`.
         let _ = self.last_val.lock_blocking().take();
-
-        let mut guard_inner = self.inner.lock_blocking();
-        if let Some(lock_mark) = guard_inner.take() {
-            drop(guard_inner);
-            let _ = lock_mark.callback_sender.broadcast_blocking(());
-        }
+        self.abort_all();
     }
 }
 
 impl<T: Send + Clone> ResultWaiter<T> {
+    /// Makes the excluder attribute only callback results accepted by `matcher` to this lock,
+    /// for callbacks carrying enough information to tell the operation they belong to; other
+    /// results (late or spontaneous) are ignored instead of completing this lock.
+    pub fn expect(&self, matcher: impl Fn(&T) -> bool + Send + Sync + 'static) {
+        let _ = self.matcher.set(Box::new(matcher));
+    }
+
     /// Waits until the unlock signal is sent from the "foreign" callback or the timeout
-    /// is reached. Returns `None` when timeout or when the corresponding `Excluder` is dropped.
-    pub async fn wait_unlock(mut self) -> Option<T> {
-        let tp_timeout = Instant::now() + self.timeout;
+    /// is reached. Returns an error when timeout or when the corresponding `Excluder` is
+    /// aborted or dropped.
+    pub async fn wait_unlock(self) -> Result<T, WaitError> {
+        let deadline = Instant::now() + self.timeout;
+        self.wait_unlock_until(deadline).await
+    }
+
+    /// Waits like [ResultWaiter::wait_unlock], but with an absolute deadline instead of
+    /// the timeout value of the lock.
+    pub async fn wait_unlock_until(mut self, deadline: Instant) -> Result<T, WaitError> {
+        let result = self.wait_result(deadline).await;
+        if let Some((kind, observer)) = &self.observer {
+            let outcome = match &result {
+                Ok(_) => OperationOutcome::Completed,
+                Err(WaitError::Timeout) => OperationOutcome::TimedOut,
+                Err(WaitError::Aborted) => OperationOutcome::Aborted,
+            };
+            observer.on_operation(*kind, self.queued, self.locked_at.elapsed(), outcome);
+        }
+        result
+    }
+
+    async fn wait_result(&mut self, deadline: Instant) -> Result<T, WaitError> {
+        let tp_timeout = deadline;
         let _ = self.tp_timeout.set_blocking(tp_timeout);
+        if self.aborted.load(Ordering::SeqCst) {
+            return Err(WaitError::Aborted);
+        }
         let dur_wait = tp_timeout
             .checked_duration_since(Instant::now())
             .unwrap_or(Duration::from_millis(1));
@@ -201,10 +442,27 @@ impl<T: Send + Clone> ResultWaiter<T> {
                 Err(async_broadcast::RecvError::Closed)
             })
             .await;
-        res.ok()?;
-        let last_val = self.last_val.upgrade()?;
+        if self.aborted.load(Ordering::SeqCst) {
+            return Err(WaitError::Aborted);
+        }
+        if res.is_err() {
+            let swallowed = self.swallowed.lock().unwrap().take();
+            let Some(val) = swallowed else {
+                return Err(WaitError::Timeout);
+            };
+            // the callback of the abandoned operation is lost; taking this result also keeps
+            // the timed out operation from being expected as another late result.
+            debug!("took the result ignored as a late one after the timeout");
+            self.completed = true;
+            if let Some(last_val) = self.last_val.upgrade() {
+                last_val.lock().await.replace(val.clone());
+            }
+            return Ok(val);
+        }
+        self.completed = true;
+        let last_val = self.last_val.upgrade().ok_or(WaitError::Aborted)?;
         let val = last_val.lock().await.as_ref().cloned();
-        val
+        val.ok_or(WaitError::Aborted)
     }
 }
 
@@ -214,6 +472,84 @@ impl<T: Send + Clone> Drop for ResultWaiter<T> {
         // before dropping; in this case, just invalidate the registered lock immediately:
         if self.tp_timeout.set_blocking(Instant::now()).is_ok() {
             let _ = self.receiver.new_sender().broadcast_blocking(());
+        } else if !self.completed
+            && !self.aborted.load(Ordering::SeqCst)
+            && self.matcher.get().is_none()
+        {
+            // The operation is started but its result is not received: expect its late callback,
+            // unless it can be told apart by the matcher.
+            let expiry = Instant::now() + STALE_CALLBACK_TIMEOUT;
+            self.stale.lock().unwrap().push_back((self.lock_id, expiry));
+        }
+    }
+}
+
+/// A FIFO queue of tasks: each task gets a ticket on entering, and tickets are served in order.
+#[derive(Default)]
+struct FifoGate {
+    state: std::sync::Mutex<FifoGateState>,
+}
+
+#[derive(Default)]
+struct FifoGateState {
+    next_ticket: u64,
+    serving: u64,
+    /// Tickets dropped before being served.
+    abandoned: BTreeSet<u64>,
+    wakers: HashMap<u64, task::Waker>,
+}
+
+/// Holds the turn of a task in `FifoGate`; the next task is served when it is dropped.
+struct FifoTurn<'a> {
+    gate: &'a FifoGate,
+    ticket: u64,
+}
+
+impl FifoGate {
+    /// Waits until all tasks entered before are done.
+    async fn enter(&self) -> FifoTurn<'_> {
+        let ticket = {
+            let mut state = self.state.lock().unwrap();
+            state.next_ticket += 1;
+            state.next_ticket - 1
+        };
+        // if this future is dropped before being served, the ticket will be abandoned.
+        let turn = FifoTurn { gate: self, ticket };
+        std::future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.serving == ticket {
+                task::Poll::Ready(())
+            } else {
+                state.wakers.insert(ticket, cx.waker().clone());
+                task::Poll::Pending
+            }
+        })
+        .await;
+        turn
+    }
+
+    fn is_idle(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.serving == state.next_ticket
+    }
+}
+
+impl Drop for FifoTurn<'_> {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().unwrap();
+        let _ = state.wakers.remove(&self.ticket);
+        if state.serving != self.ticket {
+            state.abandoned.insert(self.ticket);
+            return;
+        }
+        let mut serving = self.ticket + 1;
+        while state.abandoned.remove(&serving) {
+            serving += 1;
+        }
+        state.serving = serving;
+        if let Some(waker) = state.wakers.remove(&serving) {
+            drop(state);
+            waker.wake();
         }
     }
 }
@@ -221,28 +557,129 @@ impl<T: Send + Clone> Drop for ResultWaiter<T> {
 /// Sends notifications from "foreign" callbacks if there is any existing `NotifierReceiver`.
 pub struct Notifier<T: Send + Clone> {
     capacity: usize,
+    policy: OverflowPolicy,
+    /// Creates the item sent in place of lost values under [OverflowPolicy::Error].
+    overflow_marker: Option<fn() -> T>,
+    /// Makes a new receiver of an active notifier receive the last value at first.
+    replay_last: bool,
     inner: Mutex<Weak<NotifierInner<T>>>,
 }
 
 struct NotifierInner<T: Send + Clone> {
-    sender: Sender<Option<T>>,
+    /// Shared by receivers created by [Notifier::subscribe].
+    channel: NotifierChannel<T>,
+    /// Keeps `channel` open while all receivers are created by [Notifier::subscribe_with].
+    _channel_keeper: InactiveReceiver<Option<T>>,
+    /// Channels of receivers created by [Notifier::subscribe_with], one for each receiver.
+    own_channels: std::sync::Mutex<Vec<NotifierChannel<T>>>,
+    /// The last value sent while the notifier is active, kept only if `replay_last` is set.
+    /// It is locked while sending, so that a new receiver either gets a value replayed or
+    /// from the channel, but not both.
+    last: std::sync::Mutex<Option<T>>,
     on_stop: Box<dyn Fn() + Send + Sync + 'static>,
+    /// Set when `on_stop` is called, which may happen before dropping in [Notifier::close].
+    stopped: AtomicBool,
+}
+
+struct NotifierChannel<T: Send + Clone> {
+    sender: Sender<Option<T>>,
+    policy: OverflowPolicy,
+    /// Set under [OverflowPolicy::Error] when a value is lost and the marker is not yet sent.
+    overflowed: AtomicBool,
+}
+
+impl<T: Send + Clone> NotifierChannel<T> {
+    fn new(capacity: usize, policy: OverflowPolicy) -> (Self, Receiver<Option<T>>) {
+        let (mut sender, receiver) = async_broadcast::broadcast(capacity);
+        sender.set_overflow(policy == OverflowPolicy::DropOldest);
+        let channel = Self {
+            sender,
+            policy,
+            overflowed: AtomicBool::new(false),
+        };
+        (channel, receiver)
+    }
+
+    fn send(&self, value: T, overflow_marker: Option<fn() -> T>) {
+        // The channel drops the oldest value by itself under `DropOldest`.
+        match (self.policy, overflow_marker) {
+            (OverflowPolicy::Error, Some(marker)) => {
+                if self.overflowed.load(Ordering::Acquire) {
+                    if self.sender.try_broadcast(Some(marker())).is_err() {
+                        return; // still full, this value is lost as well
+                    }
+                    self.overflowed.store(false, Ordering::Release);
+                }
+                if let Err(TrySendError::Full(_)) = self.sender.try_broadcast(Some(value)) {
+                    self.overflowed.store(true, Ordering::Release);
+                }
+            }
+            _ => {
+                let _ = self.sender.try_broadcast(Some(value));
+            }
+        }
+    }
+}
+
+impl<T: Send + Clone> NotifierInner<T> {
+    fn stop(&self) {
+        if !self.stopped.swap(true, Ordering::SeqCst) {
+            (self.on_stop)()
+        }
+    }
+
+    /// Closes the shared channel and all channels of [Notifier::subscribe_with] receivers.
+    fn close_channels(&self) {
+        self.channel.sender.close();
+        for channel in self.own_channels.lock().unwrap().iter() {
+            channel.sender.close();
+        }
+    }
 }
 
 pub struct NotifierReceiver<T: Send + Clone> {
     holder: Option<Arc<NotifierInner<T>>>,
+    /// Yielded before values from the channel.
+    replay: Option<T>,
     receiver: Receiver<Option<T>>,
 }
 
 impl<T: Send + Clone> Notifier<T> {
-    /// Creates a new inactive `Notifier`.
-    pub const fn new(capacity: usize) -> Self {
+    /// Creates a new inactive `Notifier`. `capacity` is the maximum count of values kept for
+    /// each receiver, `policy` decides what happens when a receiver cannot keep up.
+    pub const fn new(capacity: usize, policy: OverflowPolicy) -> Self {
         Self {
             capacity,
+            policy,
+            overflow_marker: None,
+            replay_last: false,
             inner: Mutex::new(Weak::new()),
         }
     }
 
+    /// Makes a new receiver of an active notifier receive the last sent value at first.
+    pub fn with_replay_last(mut self, replay_last: bool) -> Self {
+        self.replay_last = replay_last;
+        self
+    }
+
+    /// Sets the item to be received in place of lost values under [OverflowPolicy::Error].
+    /// Without it, that policy behaves like [OverflowPolicy::DropNewest].
+    pub fn with_overflow_marker(mut self, marker: fn() -> T) -> Self {
+        self.overflow_marker = Some(marker);
+        self
+    }
+
+    /// Deactivates the notifier immediately: existing receivers are ended, and `on_stop` is
+    /// called without waiting for them to be dropped. It can be activated again later.
+    pub fn close(&self) {
+        let inner = std::mem::take(&mut *self.inner.lock_blocking()).upgrade();
+        if let Some(inner) = inner {
+            inner.close_channels();
+            inner.stop();
+        }
+    }
+
     /// Checks if the notifier is active.
     pub fn is_notifying(&self) -> bool {
         // Don't call it in this module
@@ -257,45 +694,92 @@ impl<T: Send + Clone> Notifier<T> {
         &self,
         on_start: impl FnOnce() -> Result<(), E>,
         on_stop: impl Fn() + Send + Sync + 'static,
+    ) -> Result<NotifierReceiver<T>, E> {
+        self.subscribe_with(on_start, on_stop, None).await
+    }
+
+    /// Like [Notifier::subscribe]; with `Some((capacity, policy))` as `own_channel`, the receiver
+    /// has a channel of its own with that capacity and overflow policy instead of those of the
+    /// notifier.
+    pub async fn subscribe_with<E>(
+        &self,
+        on_start: impl FnOnce() -> Result<(), E>,
+        on_stop: impl Fn() + Send + Sync + 'static,
+        own_channel: Option<(usize, OverflowPolicy)>,
     ) -> Result<NotifierReceiver<T>, E> {
         let mut guard_inner = self.inner.lock().await;
-        if let Some(inner) = guard_inner.upgrade() {
-            let receiver = inner.sender.new_receiver();
-            Ok(NotifierReceiver {
-                holder: Some(inner),
-                receiver,
-            })
+        let inner = if let Some(inner) = guard_inner.upgrade() {
+            inner
         } else {
             on_start()?;
-            let (mut sender, receiver) = async_broadcast::broadcast(self.capacity);
-            sender.set_overflow(true);
+            let (channel, receiver) = NotifierChannel::new(self.capacity, self.policy);
             let new_inner = Arc::new(NotifierInner {
-                sender,
+                channel,
+                _channel_keeper: receiver.clone().deactivate(),
+                own_channels: std::sync::Mutex::new(Vec::new()),
+                last: std::sync::Mutex::new(None),
                 on_stop: Box::new(on_stop),
+                stopped: AtomicBool::new(false),
             });
             *guard_inner = Arc::downgrade(&new_inner);
-            Ok(NotifierReceiver {
-                holder: Some(new_inner),
-                receiver,
-            })
-        }
+            if own_channel.is_none() {
+                return Ok(NotifierReceiver {
+                    holder: Some(new_inner),
+                    replay: None,
+                    receiver,
+                });
+            }
+            new_inner
+        };
+        let last = inner.last.lock().unwrap();
+        let replay = last.clone();
+        let receiver = if let Some((capacity, policy)) = own_channel {
+            let (channel, receiver) = NotifierChannel::new(capacity, policy);
+            let mut own_channels = inner.own_channels.lock().unwrap();
+            own_channels.retain(|channel| channel.sender.receiver_count() > 0);
+            own_channels.push(channel);
+            receiver
+        } else {
+            inner.channel.sender.new_receiver()
+        };
+        drop(last);
+        Ok(NotifierReceiver {
+            holder: Some(inner),
+            replay,
+            receiver,
+        })
     }
 
     /// Sends a notifcation value from the "foreign" callback.
     pub fn notify(&self, value: T) {
-        let inner = self.inner.lock_blocking().upgrade();
-        if let Some(inner) = inner {
-            let _ = inner.sender.broadcast_blocking(Some(value));
+        let Some(inner) = self.inner.lock_blocking().upgrade() else {
+            return;
+        };
+        let mut last = inner.last.lock().unwrap();
+        if self.replay_last {
+            *last = Some(value.clone());
+        }
+        let mut own_channels = inner.own_channels.lock().unwrap();
+        own_channels.retain(|channel| channel.sender.receiver_count() > 0);
+        for channel in own_channels.iter() {
+            channel.send(value.clone(), self.overflow_marker);
         }
+        drop(own_channels);
+        inner.channel.send(value, self.overflow_marker);
     }
 }
 
+// the replayed value is never pinned.
+impl<T: Send + Clone> Unpin for NotifierReceiver<T> {}
+
 impl<T: Send + Clone> futures_core::Stream for NotifierReceiver<T> {
     type Item = T;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> task::Poll<Option<T>> {
         if self.holder.is_none() {
             task::Poll::Ready(None)
+        } else if let Some(value) = self.replay.take() {
+            task::Poll::Ready(Some(value))
         } else if let task::Poll::Ready(result) = std::pin::pin!(&mut self.receiver).poll_next(cx) {
             if let Some(value) = result.flatten() {
                 task::Poll::Ready(Some(value))
@@ -309,7 +793,9 @@ impl<T: Send + Clone> futures_core::Stream for NotifierReceiver<T> {
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.receiver.size_hint()
+        let (lower, upper) = self.receiver.size_hint();
+        let replay = usize::from(self.replay.is_some());
+        (lower + replay, upper.map(|n| n + replay))
     }
 }
 
@@ -317,71 +803,436 @@ impl<T: Send + Clone> Drop for Notifier<T> {
     fn drop(&mut self) {
         let inner = self.inner.lock_blocking().upgrade();
         if let Some(inner) = inner {
-            let _ = inner.sender.broadcast_blocking(None);
+            // Closing instead of sending `None`, which may block if the channel is full.
+            inner.close_channels();
         }
     }
 }
 
 impl<T: Send + Clone> Drop for NotifierInner<T> {
     fn drop(&mut self) {
-        (self.on_stop)()
+        self.stop()
     }
 }
 
 /// Wraps the main stream and also checks an event stream; ends and fuses the main stream when
 /// the event stream ends or the checker returns true for a received event item.
-pub struct StreamUntil<T, E, S, F>
+pub struct StreamUntil<M, E, S, F>
 where
-    T: Send + Unpin,
-    E: Send,
-    S: Stream<Item = E> + Send + Unpin,
-    F: Fn(&E) -> bool + Send + Sync + Unpin + 'static,
+    M: Stream + Unpin,
+    S: Stream<Item = E> + Unpin,
+    F: Fn(&E) -> bool + Unpin,
 {
-    stream: S,
+    stream: M,
+    /// Becomes `None` when the main stream should be ended.
+    event_stream: Option<S>,
     event_checker: F,
-    ph: PhantomData<T>,
 }
 
-impl<T, E, S, F> StreamUntil<T, E, S, F>
+impl<M, E, S, F> StreamUntil<M, E, S, F>
 where
-    T: Send + Unpin,
-    E: Send,
-    S: Stream<Item = E> + Send + Unpin,
-    F: Fn(&E) -> bool + Send + Sync + Unpin + 'static,
+    M: Stream + Unpin,
+    S: Stream<Item = E> + Unpin,
+    F: Fn(&E) -> bool + Unpin,
 {
     /// Creates the `StreamUntil`.
-    pub fn create(
-        stream: impl Stream<Item = T>,
-        event_stream: S,
-        event_checker: F,
-    ) -> impl Stream<Item = T> {
-        stream
-            .or(StreamUntil {
-                stream: event_stream,
-                event_checker,
-                ph: PhantomData,
-            })
-            .fuse()
+    pub fn create(stream: M, event_stream: S, event_checker: F) -> Self {
+        Self {
+            stream,
+            event_stream: Some(event_stream),
+            event_checker,
+        }
     }
 }
 
-impl<T, E, S, F> futures_core::Stream for StreamUntil<T, E, S, F>
+impl<M, E, S, F> futures_core::Stream for StreamUntil<M, E, S, F>
 where
-    T: Send + Unpin,
-    E: Send,
-    S: Stream<Item = E> + Send + Unpin,
-    F: Fn(&E) -> bool + Send + Sync + Unpin + 'static,
+    M: Stream + Unpin,
+    S: Stream<Item = E> + Unpin,
+    F: Fn(&E) -> bool + Unpin,
 {
-    type Item = T;
+    type Item = M::Item;
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<Option<M::Item>> {
+        let this = &mut *self;
+        // Consumes all pending events, so that the waker is registered for the event stream
+        // before polling the main stream.
+        while let Some(event_stream) = this.event_stream.as_mut() {
+            match Pin::new(event_stream).poll_next(cx) {
+                task::Poll::Ready(Some(event)) if !(this.event_checker)(&event) => continue,
+                task::Poll::Ready(_) => this.event_stream = None,
+                task::Poll::Pending => break,
+            }
+        }
+        if this.event_stream.is_none() {
+            return task::Poll::Ready(None);
+        }
+        match Pin::new(&mut this.stream).poll_next(cx) {
+            task::Poll::Ready(None) => {
+                this.event_stream = None;
+                task::Poll::Ready(None)
+            }
+            poll => poll,
+        }
+    }
+}
+
+/// Wraps the main stream; yields the item produced by `on_idle` whenever the main stream stays
+/// silent for `max_gap`, then keeps forwarding items of the main stream if it resumes.
+pub struct StreamIdleTimeout<S, F>
+where
+    S: Stream + Unpin,
+    F: Fn() -> S::Item + Unpin,
+{
+    stream: S,
+    max_gap: Duration,
+    delay: Delay,
+    on_idle: F,
+}
+
+impl<S, F> StreamIdleTimeout<S, F>
+where
+    S: Stream + Unpin,
+    F: Fn() -> S::Item + Unpin,
+{
+    /// Creates the `StreamIdleTimeout`. The idle timer starts immediately.
+    pub fn new(stream: S, max_gap: Duration, on_idle: F) -> Self {
+        Self {
+            stream,
+            max_gap,
+            delay: Delay::new(max_gap),
+            on_idle,
+        }
+    }
+}
+
+impl<S, F> futures_core::Stream for StreamIdleTimeout<S, F>
+where
+    S: Stream + Unpin,
+    F: Fn() -> S::Item + Unpin,
+{
+    type Item = S::Item;
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<Option<S::Item>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.stream).poll_next(cx) {
+            task::Poll::Ready(Some(item)) => {
+                this.delay.reset(this.max_gap);
+                task::Poll::Ready(Some(item))
+            }
+            task::Poll::Ready(None) => task::Poll::Ready(None),
+            task::Poll::Pending => {
+                if Pin::new(&mut this.delay).poll(cx).is_ready() {
+                    this.delay.reset(this.max_gap);
+                    task::Poll::Ready(Some((this.on_idle)()))
+                } else {
+                    task::Poll::Pending
+                }
+            }
+        }
+    }
+}
+
+/// Merges streams of the same type; unlike `StreamExt::or`, it ends only after all of them have
+/// ended. The streams are polled in rotation, so that a busy stream doesn't starve the others.
+pub struct StreamMerge<S: Stream + Unpin> {
+    /// Ended streams are replaced by `None`.
+    streams: Vec<Option<S>>,
+    next: usize,
+}
+
+impl<S: Stream + Unpin> StreamMerge<S> {
+    pub fn new(streams: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            streams: streams.into_iter().map(Some).collect(),
+            next: 0,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> futures_core::Stream for StreamMerge<S> {
+    type Item = S::Item;
     fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
-        use futures_core::task::Poll;
-        match self.stream.poll_next(cx) {
-            Poll::Ready(Some(event)) if (self.event_checker)(&event) => Poll::Ready(None),
-            Poll::Ready(None) => Poll::Ready(None),
-            _ => Poll::Pending,
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<Option<S::Item>> {
+        let this = &mut *self;
+        let count = this.streams.len();
+        for i in 0..count {
+            let index = (this.next + i) % count;
+            let Some(stream) = this.streams[index].as_mut() else {
+                continue;
+            };
+            match Pin::new(stream).poll_next(cx) {
+                task::Poll::Ready(Some(item)) => {
+                    this.next = (index + 1) % count;
+                    return task::Poll::Ready(Some(item));
+                }
+                task::Poll::Ready(None) => this.streams[index] = None,
+                task::Poll::Pending => (),
+            }
         }
+        if this.streams.iter().all(Option::is_none) {
+            task::Poll::Ready(None)
+        } else {
+            task::Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn late_callback_before_fresh_operation() {
+        let excluder = Excluder::<i32>::with_timeout(TIMEOUT);
+        block_on(async {
+            let waiter = excluder.lock().await;
+            assert_eq!(waiter.wait_unlock().await, Err(WaitError::Timeout));
+            excluder.unlock(1);
+            let waiter = excluder.lock().await;
+            excluder.unlock(2);
+            assert_eq!(waiter.wait_unlock().await, Ok(2));
+        });
+    }
+
+    #[test]
+    fn late_callback_during_fresh_operation() {
+        let excluder = Excluder::<i32>::with_timeout(TIMEOUT);
+        block_on(async {
+            let waiter = excluder.lock().await;
+            assert_eq!(waiter.wait_unlock().await, Err(WaitError::Timeout));
+            let waiter = excluder.lock().await;
+            excluder.unlock(1);
+            assert_eq!(excluder.last_value(), None);
+            excluder.unlock(2);
+            assert_eq!(waiter.wait_unlock().await, Ok(2));
+            assert_eq!(excluder.last_value(), Some(2));
+        });
+    }
+
+    #[test]
+    fn matcher_ignores_other_results() {
+        let excluder = Excluder::<i32>::with_timeout(TIMEOUT);
+        block_on(async {
+            let waiter = excluder.lock().await;
+            assert_eq!(waiter.wait_unlock().await, Err(WaitError::Timeout));
+            let waiter = excluder.lock().await;
+            waiter.expect(|&v| v == 3);
+            excluder.unlock(2);
+            excluder.unlock(3);
+            assert_eq!(waiter.wait_unlock().await, Ok(3));
+            // the stale entry is kept for the late result of the abandoned operation.
+            let waiter = excluder.lock().await;
+            excluder.unlock(1);
+            excluder.unlock(4);
+            assert_eq!(waiter.wait_unlock().await, Ok(4));
+        });
+    }
+
+    #[test]
+    fn fresh_operation_after_timeout_without_late_callback() {
+        let excluder = Excluder::<i32>::with_timeout(TIMEOUT);
+        block_on(async {
+            let waiter = excluder.lock().await;
+            assert_eq!(waiter.wait_unlock().await, Err(WaitError::Timeout));
+            let waiter = excluder.lock().await;
+            excluder.unlock(2);
+            // attributed to the abandoned operation by arrival order at first, then taken by
+            // the fresh operation at its timeout, which is not expected as a late one.
+            assert_eq!(waiter.wait_unlock().await, Ok(2));
+            assert_eq!(excluder.last_value(), Some(2));
+            let waiter = excluder.lock().await;
+            excluder.unlock(3);
+            assert_eq!(waiter.wait_unlock().await, Ok(3));
+        });
+    }
+
+    #[test]
+    fn fifo_gate_serves_in_arrival_order() {
+        use futures_lite::future::poll_once;
+
+        let gate = FifoGate::default();
+        block_on(async {
+            let mut entering: VecDeque<_> = (0..8).map(|_| Box::pin(gate.enter())).collect();
+            let mut turn = None;
+            for (i, fut) in entering.iter_mut().enumerate() {
+                let polled = poll_once(fut).await;
+                assert_eq!(polled.is_some(), i == 0);
+                turn = turn.or(polled);
+            }
+            entering.pop_front();
+            // the task with ticket 3 gives up before being served.
+            drop(entering.remove(2));
+            let mut served = Vec::new();
+            while let Some(current) = turn.take() {
+                served.push(current.ticket);
+                drop(current);
+                for (i, fut) in entering.iter_mut().enumerate() {
+                    let polled = poll_once(fut).await;
+                    assert_eq!(polled.is_some(), i == 0);
+                    turn = turn.or(polled);
+                }
+                if turn.is_some() {
+                    entering.pop_front();
+                }
+            }
+            assert_eq!(served, [0, 1, 2, 4, 5, 6, 7]);
+            assert!(gate.is_idle());
+        });
+    }
+
+    // Tasks on several threads keep locking the same excluder; none of them may be starved.
+    #[test]
+    fn excluder_fifo_stress() {
+        const TASKS: usize = 8;
+        const ROUNDS: usize = 50;
+        let excluder = Excluder::<usize>::with_timeout(Duration::from_secs(5));
+        let max_wait = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..TASKS)
+                .map(|task| {
+                    let excluder = &excluder;
+                    scope.spawn(move || {
+                        let mut max_wait = Duration::ZERO;
+                        for _ in 0..ROUNDS {
+                            let t_start = Instant::now();
+                            let waiter = block_on(excluder.lock());
+                            max_wait = max_wait.max(t_start.elapsed());
+                            excluder.unlock(task);
+                            assert!(block_on(waiter.wait_unlock()).is_ok());
+                        }
+                        max_wait
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .max()
+                .unwrap()
+        });
+        // far below the timeout value, which would be reached by a starved task.
+        assert!(max_wait < Duration::from_secs(2), "waited {max_wait:?}");
+    }
+
+    #[test]
+    fn default_locker_waits_for_long_override() {
+        let excluder = Excluder::<i32>::with_timeout(TIMEOUT);
+        let long = Duration::from_millis(300);
+        block_on(async {
+            let t_start = Instant::now();
+            let _holder = excluder.lock_with_timeout(long).await;
+            // the holder's timeout applies before it calls `wait_unlock`, not the default one.
+            let waiter = excluder.lock().await;
+            assert!(t_start.elapsed() >= long);
+            // the new lock has the default timeout, not the override of the previous holder.
+            let t_locked = Instant::now();
+            assert_eq!(waiter.wait_unlock().await, Err(WaitError::Timeout));
+            assert!(t_locked.elapsed() < long);
+        });
+    }
+
+    #[test]
+    fn long_override_waits_for_default_holder() {
+        let excluder = Excluder::<i32>::with_timeout(TIMEOUT);
+        let long = Duration::from_millis(300);
+        block_on(async {
+            let t_start = Instant::now();
+            let _holder = excluder.lock().await;
+            let waiter = excluder.lock_with_timeout(long).await;
+            let waited = t_start.elapsed();
+            assert!(waited >= TIMEOUT && waited < long);
+            let t_locked = Instant::now();
+            assert_eq!(waiter.wait_unlock().await, Err(WaitError::Timeout));
+            assert!(t_locked.elapsed() >= long);
+        });
+    }
+
+    #[test]
+    fn lock_until_gives_up_at_deadline() {
+        let excluder = Excluder::<i32>::with_timeout(Duration::from_secs(5));
+        block_on(async {
+            let _holder = excluder.lock().await;
+            let t_start = Instant::now();
+            assert!(excluder.lock_until(Some(t_start + TIMEOUT)).await.is_none());
+            assert!(t_start.elapsed() < Duration::from_secs(1));
+            assert!(excluder.lock_until(Some(t_start)).await.is_none());
+        });
+    }
+
+    #[test]
+    fn subscribe_with_own_channel() {
+        use std::sync::atomic::AtomicUsize;
+
+        use futures_lite::StreamExt;
+
+        let notifier = Notifier::<i32>::new(8, OverflowPolicy::DropOldest);
+        let stops = Arc::new(AtomicUsize::new(0));
+        block_on(async {
+            let stops_2 = stops.clone();
+            let on_stop = move || {
+                stops_2.fetch_add(1, Ordering::SeqCst);
+            };
+            let own_channel = Some((2, OverflowPolicy::DropNewest));
+            let mut own = notifier
+                .subscribe_with(|| Ok::<_, ()>(()), on_stop.clone(), own_channel)
+                .await
+                .unwrap();
+            let mut shared = notifier
+                .subscribe(|| Ok::<_, ()>(()), on_stop)
+                .await
+                .unwrap();
+            for value in 1..=3 {
+                notifier.notify(value);
+            }
+            assert_eq!(own.next().await, Some(1));
+            assert_eq!(own.next().await, Some(2));
+            for value in 1..=3 {
+                assert_eq!(shared.next().await, Some(value));
+            }
+            drop(own);
+            notifier.notify(4);
+            assert_eq!(shared.next().await, Some(4));
+            assert_eq!(stops.load(Ordering::SeqCst), 0);
+            drop(shared);
+            assert_eq!(stops.load(Ordering::SeqCst), 1);
+            assert!(!notifier.is_notifying());
+        });
+    }
+
+    #[test]
+    fn merged_stream_ends_after_all_streams() {
+        use futures_lite::future::poll_once;
+        use futures_lite::StreamExt;
+
+        let notifiers = [1, 2].map(|_| Notifier::<i32>::new(8, OverflowPolicy::DropOldest));
+        block_on(async {
+            let mut receivers = Vec::new();
+            for notifier in &notifiers {
+                receivers.push(notifier.subscribe(|| Ok::<_, ()>(()), || ()).await.unwrap());
+            }
+            let mut merged = StreamMerge::new(receivers);
+            notifiers[0].notify(1);
+            notifiers[1].notify(2);
+            let mut values = [merged.next().await, merged.next().await];
+            values.sort();
+            assert_eq!(values, [Some(1), Some(2)]);
+
+            notifiers[0].close();
+            assert_eq!(poll_once(merged.next()).await, None);
+            notifiers[1].notify(3);
+            assert_eq!(merged.next().await, Some(3));
+            notifiers[1].close();
+            assert_eq!(merged.next().await, None);
+        });
     }
 }
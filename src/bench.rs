@@ -0,0 +1,273 @@
+//! On-device throughput measurement, for comparing phone models and versions of this crate.
+//!
+//! The functions here drive a connected peripheral for a given duration and report packet
+//! rates, byte rates and latency percentiles. They are ordinary async functions, so they can
+//! be embedded in a QA application.
+//!
+//! ```ignore
+//! let config = BenchConfig::new(SERVICE_UUID, CHAR_UUID).duration(Duration::from_secs(5));
+//! let results = android_ble::bench::run(&device, &config).await?;
+//! log::info!("write: {:?}", results.write);
+//! ```
+
+use std::time::{Duration, Instant};
+
+use futures_lite::{AsyncWriteExt, FutureExt, StreamExt};
+
+use super::error::ErrorKind;
+use super::timer::Delay;
+use super::{Characteristic, Device, L2capChannel, Result, Uuid};
+
+/// Parameters of [run].
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    service: Uuid,
+    characteristic: Uuid,
+    duration: Duration,
+    payload_len: Option<usize>,
+    l2cap_psm: Option<(u16, bool)>,
+}
+
+impl BenchConfig {
+    /// Measures with the characteristic `characteristic` in the service `service`, which should
+    /// support write without response and notification. The default duration of each
+    /// measurement is 10 seconds.
+    pub fn new(service: Uuid, characteristic: Uuid) -> Self {
+        Self {
+            service,
+            characteristic,
+            duration: Duration::from_secs(10),
+            payload_len: None,
+            l2cap_psm: None,
+        }
+    }
+
+    /// Sets the duration of each measurement.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Sets the length of written packets. By default, [Characteristic::max_write_len] is used.
+    pub fn payload_len(mut self, len: usize) -> Self {
+        self.payload_len = Some(len);
+        self
+    }
+
+    /// Also measures L2CAP throughput by opening a channel on `psm`.
+    pub fn l2cap(mut self, psm: u16, secure: bool) -> Self {
+        self.l2cap_psm = Some((psm, secure));
+        self
+    }
+}
+
+/// Results of [run]; failed measurements keep their errors.
+#[derive(Debug)]
+pub struct BenchResults {
+    /// See [write_without_response_throughput].
+    pub write: Result<ThroughputReport>,
+    /// See [notification_rate].
+    pub notify: Result<ThroughputReport>,
+    /// See [l2cap_throughput]; `None` if it is not configured.
+    pub l2cap: Option<Result<ThroughputReport>>,
+}
+
+/// Result of a single measurement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThroughputReport {
+    /// Count of packets sent or received.
+    pub packets: u64,
+    /// Count of payload bytes sent or received.
+    pub bytes: u64,
+    /// Actual duration of the measurement.
+    pub elapsed: Duration,
+    /// Packets per second.
+    pub packets_per_sec: f64,
+    /// Payload bytes per second.
+    pub bytes_per_sec: f64,
+    /// Latencies of writes, or intervals between received notifications; `None` if there are
+    /// no samples.
+    pub latency: Option<LatencyPercentiles>,
+}
+
+/// Percentiles of latency samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    /// The median.
+    pub p50: Duration,
+    /// The 90th percentile.
+    pub p90: Duration,
+    /// The 99th percentile.
+    pub p99: Duration,
+    /// The maximum.
+    pub max: Duration,
+}
+
+impl LatencyPercentiles {
+    fn from_samples(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let at = |percent: usize| samples[(samples.len() - 1) * percent / 100];
+        Some(Self {
+            p50: at(50),
+            p90: at(90),
+            p99: at(99),
+            max: at(100),
+        })
+    }
+}
+
+impl ThroughputReport {
+    fn new(packets: u64, bytes: u64, elapsed: Duration, samples: Vec<Duration>) -> Self {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        Self {
+            packets,
+            bytes,
+            elapsed,
+            packets_per_sec: packets as f64 / secs,
+            bytes_per_sec: bytes as f64 / secs,
+            latency: LatencyPercentiles::from_samples(samples),
+        }
+    }
+}
+
+/// Runs all configured measurements one after another on the connected `device`.
+pub async fn run(device: &Device, config: &BenchConfig) -> Result<BenchResults> {
+    let characteristic = find_characteristic(device, config).await?;
+    let write = match config.payload_len {
+        Some(len) => Ok(len),
+        None => characteristic.max_write_len(),
+    };
+    let write = match write {
+        Ok(len) => write_without_response_throughput(&characteristic, len, config.duration).await,
+        Err(e) => Err(e),
+    };
+    let notify = notification_rate(&characteristic, config.duration).await;
+    let l2cap = match config.l2cap_psm {
+        Some((psm, secure)) => Some(match device.open_l2cap_channel(psm, secure).await {
+            Ok(mut channel) => {
+                let len = config.payload_len.unwrap_or(1024);
+                l2cap_throughput(&mut channel, len, config.duration).await
+            }
+            Err(e) => Err(e),
+        }),
+        None => None,
+    };
+    Ok(BenchResults {
+        write,
+        notify,
+        l2cap,
+    })
+}
+
+/// Writes packets of `payload_len` bytes without response as fast as the Android Bluetooth
+/// stack accepts them for `duration`. Latencies are the time taken by each write.
+pub async fn write_without_response_throughput(
+    characteristic: &Characteristic,
+    payload_len: usize,
+    duration: Duration,
+) -> Result<ThroughputReport> {
+    let payload: Vec<u8> = (0..payload_len).map(|i| i as u8).collect();
+    let (mut packets, mut samples) = (0, Vec::new());
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let t = Instant::now();
+        characteristic.write_without_response(&payload).await?;
+        samples.push(t.elapsed());
+        packets += 1;
+    }
+    let bytes = packets * payload_len as u64;
+    Ok(ThroughputReport::new(
+        packets,
+        bytes,
+        started.elapsed(),
+        samples,
+    ))
+}
+
+/// Subscribes to the characteristic and counts received notifications for `duration`.
+/// Latencies are intervals between received notifications.
+pub async fn notification_rate(
+    characteristic: &Characteristic,
+    duration: Duration,
+) -> Result<ThroughputReport> {
+    let mut stream = characteristic.notify().await?;
+    let (mut packets, mut bytes, mut samples) = (0, 0, Vec::new());
+    let started = Instant::now();
+    let mut last_received = None;
+    loop {
+        let Some(remaining) = duration.checked_sub(started.elapsed()) else {
+            break;
+        };
+        let item = async { Some(stream.next().await) }
+            .or(async {
+                Delay::new(remaining).await;
+                None
+            })
+            .await;
+        let value = match item {
+            Some(Some(value)) => value?,
+            Some(None) => return Err(ErrorKind::NotConnected.into()),
+            None => break,
+        };
+        let now = Instant::now();
+        if let Some(last) = last_received.replace(now) {
+            samples.push(now - last);
+        }
+        packets += 1;
+        bytes += value.len() as u64;
+    }
+    Ok(ThroughputReport::new(
+        packets,
+        bytes,
+        started.elapsed(),
+        samples,
+    ))
+}
+
+/// Writes packets of `payload_len` bytes to the channel for `duration`. Latencies are the time
+/// taken by each write, which includes waiting for room in the send buffer.
+pub async fn l2cap_throughput(
+    channel: &mut L2capChannel,
+    payload_len: usize,
+    duration: Duration,
+) -> Result<ThroughputReport> {
+    let payload: Vec<u8> = (0..payload_len).map(|i| i as u8).collect();
+    let (mut packets, mut samples) = (0, Vec::new());
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let t = Instant::now();
+        channel
+            .write_all(&payload)
+            .await
+            .map_err(crate::Error::from_io)?;
+        samples.push(t.elapsed());
+        packets += 1;
+    }
+    let _ = channel.flush().await;
+    let bytes = packets * payload_len as u64;
+    Ok(ThroughputReport::new(
+        packets,
+        bytes,
+        started.elapsed(),
+        samples,
+    ))
+}
+
+async fn find_characteristic(device: &Device, config: &BenchConfig) -> Result<Characteristic> {
+    for service in device.discover_services_with_uuid(config.service).await? {
+        let chars = service
+            .discover_characteristics_with_uuid(config.characteristic)
+            .await?;
+        if let Some(characteristic) = chars.into_iter().next() {
+            return Ok(characteristic);
+        }
+    }
+    Err(crate::Error::new(
+        ErrorKind::NotFound,
+        None,
+        "the characteristic for benchmarking is not found",
+    ))
+}
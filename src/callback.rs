@@ -15,6 +15,7 @@ use super::bindings::android::content::{BroadcastReceiver, Context, Intent};
 use super::bindings::java::lang::Class;
 use super::bindings::java::lang::{ClassLoader, Throwable};
 use super::bindings::java::{self};
+use super::callback_log::catch_callback_panic;
 use super::vm_context::android_load_dex;
 use super::vm_context::jni_load_class_with;
 
@@ -27,6 +28,13 @@ const DEX_DATA: &[u8] = &[]; // dummy data
 static DEX_CLASS_LOADER: LazyLock<Global<ClassLoader>> =
     LazyLock::new(|| android_load_dex(DEX_DATA));
 
+/// Loads the embedded dex and every proxy class in it, see [crate::Adapter::warm_up].
+pub(crate) fn load_proxy_classes(env: Env<'_>) {
+    BluetoothGattCallback::proxy_class(env);
+    ScanCallback::proxy_class(env);
+    BroadcastReceiver::proxy_class(env);
+}
+
 // NOTE: code below is ported back from the nightly `java-spaghetti` and the proxy code
 // generated by nightly `java-spaghetti-gen`.
 
@@ -42,6 +50,14 @@ trait EnvExt<'env> {
         descriptor: &CStr,
         fn_ptr: *mut c_void,
     );
+
+    unsafe fn try_register_native_method(
+        &'env self,
+        class: &Ref<'env, Class>,
+        method: &CStr,
+        descriptor: &CStr,
+        fn_ptr: *mut c_void,
+    ) -> bool;
 }
 
 impl<'env> EnvExt<'env> for Env<'env> {
@@ -81,6 +97,43 @@ impl<'env> EnvExt<'env> for Env<'env> {
             "error ocurred while calling JNI RegisterNatives"
         );
     }
+
+    /// Like `register_native_method`, but clears the `NoSuchMethodError` and returns `false`
+    /// if the native method is not declared by `class`. This is used for methods which may be
+    /// missing in an outdated copy of the proxy class.
+    ///
+    /// # Safety
+    ///
+    /// See `register_native_method`.
+    unsafe fn try_register_native_method(
+        &self,
+        class: &Ref<'env, Class>,
+        method: &CStr,
+        descriptor: &CStr,
+        fn_ptr: *mut c_void,
+    ) -> bool {
+        let (method, descriptor) = (method.to_owned(), descriptor.to_owned());
+        let mut native_methods = [JNINativeMethod {
+            name: method.as_ptr() as *mut c_char,
+            signature: descriptor.as_ptr() as *mut c_char,
+            fnPtr: fn_ptr,
+        }];
+        let jnienv = self.as_raw();
+        let res = ((**jnienv).v1_2.RegisterNatives)(
+            jnienv,
+            class.as_raw(),
+            native_methods.as_mut_ptr(),
+            1,
+        );
+
+        let exception = unsafe { ((**jnienv).v1_2.ExceptionOccurred)(jnienv) };
+        if !exception.is_null() {
+            ((**jnienv).v1_2.ExceptionClear)(jnienv);
+            ((**jnienv).v1_2.DeleteLocalRef)(jnienv, exception);
+            return false;
+        }
+        res >= 0
+    }
 }
 
 pub trait BroadcastReceiverProxy: ::std::marker::Send + ::std::marker::Sync + 'static {
@@ -92,12 +145,10 @@ pub trait BroadcastReceiverProxy: ::std::marker::Send + ::std::marker::Sync + 's
     ) -> ();
 }
 impl BroadcastReceiver {
-    pub fn new_proxy<'env>(
-        env: ::java_spaghetti::Env<'env>,
-        proxy: ::std::sync::Arc<dyn BroadcastReceiverProxy>,
-    ) -> Result<::java_spaghetti::Local<'env, Self>, ::java_spaghetti::Local<'env, Throwable>> {
+    /// Loads the proxy class from the embedded dex and registers its native methods once.
+    pub(crate) fn proxy_class(env: ::java_spaghetti::Env<'_>) -> &'static Global<Class> {
         static __CLASS: ::std::sync::OnceLock<Global<Class>> = ::std::sync::OnceLock::new();
-        let __jni_class = if let Some(cached) = __CLASS.get() {
+        if let Some(cached) = __CLASS.get() {
             cached
         } else {
             let loaded = {
@@ -111,7 +162,13 @@ impl BroadcastReceiver {
             Self::register_proxy_methods(env, &loaded.as_ref(env));
             __CLASS.get_or_init(|| loaded)
         }
-        .as_raw();
+    }
+
+    pub fn new_proxy<'env>(
+        env: ::java_spaghetti::Env<'env>,
+        proxy: ::std::sync::Arc<dyn BroadcastReceiverProxy>,
+    ) -> Result<::java_spaghetti::Local<'env, Self>, ::java_spaghetti::Local<'env, Throwable>> {
+        let __jni_class = Self::proxy_class(env).as_raw();
         let b = ::std::boxed::Box::new(proxy);
         let ptr = ::std::boxed::Box::into_raw(b);
         unsafe {
@@ -152,7 +209,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_content_Broadca
 ) {
     let ptr: *mut ::std::sync::Arc<dyn BroadcastReceiverProxy> =
         ::std::ptr::with_exposed_provenance_mut(ptr as usize);
-    let _ = unsafe { Box::from_raw(ptr) };
+    catch_callback_panic("BroadcastReceiver.finalize", || {
+        let _ = unsafe { Box::from_raw(ptr) };
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_content_BroadcastReceiver_native_1onReceive__JLandroid_content_Context_2Landroid_content_Intent_2<
@@ -166,13 +225,13 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_content_Broadca
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BroadcastReceiverProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
+    catch_callback_panic("BroadcastReceiver.onReceive", || unsafe {
         (*ptr).onReceive(
             __jni_env,
             arg0.into_ref(__jni_env),
             arg1.into_ref(__jni_env),
         )
-    }
+    });
 }
 
 pub trait ScanCallbackProxy: ::std::marker::Send + ::std::marker::Sync + 'static {
@@ -191,12 +250,10 @@ pub trait ScanCallbackProxy: ::std::marker::Send + ::std::marker::Sync + 'static
 }
 
 impl ScanCallback {
-    pub fn new_proxy<'env>(
-        env: ::java_spaghetti::Env<'env>,
-        proxy: ::std::sync::Arc<dyn ScanCallbackProxy>,
-    ) -> Result<::java_spaghetti::Local<'env, Self>, ::java_spaghetti::Local<'env, Throwable>> {
+    /// Loads the proxy class from the embedded dex and registers its native methods once.
+    pub(crate) fn proxy_class(env: ::java_spaghetti::Env<'_>) -> &'static Global<Class> {
         static __CLASS: ::std::sync::OnceLock<Global<Class>> = ::std::sync::OnceLock::new();
-        let __jni_class = if let Some(cached) = __CLASS.get() {
+        if let Some(cached) = __CLASS.get() {
             cached
         } else {
             let loaded = {
@@ -210,7 +267,13 @@ impl ScanCallback {
             Self::register_proxy_methods(env, &loaded.as_ref(env));
             __CLASS.get_or_init(|| loaded)
         }
-        .as_raw();
+    }
+
+    pub fn new_proxy<'env>(
+        env: ::java_spaghetti::Env<'env>,
+        proxy: ::std::sync::Arc<dyn ScanCallbackProxy>,
+    ) -> Result<::java_spaghetti::Local<'env, Self>, ::java_spaghetti::Local<'env, Throwable>> {
+        let __jni_class = Self::proxy_class(env).as_raw();
         let b = ::std::boxed::Box::new(proxy);
         let ptr = ::std::boxed::Box::into_raw(b);
         unsafe {
@@ -265,7 +328,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_le_Sc
 ) {
     let ptr: *mut ::std::sync::Arc<dyn ScanCallbackProxy> =
         ::std::ptr::with_exposed_provenance_mut(ptr as usize);
-    let _ = unsafe { Box::from_raw(ptr) };
+    catch_callback_panic("ScanCallback.finalize", || {
+        let _ = unsafe { Box::from_raw(ptr) };
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -280,7 +345,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_le_Sc
 ) -> () {
     let ptr: *const std::sync::Arc<dyn ScanCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onScanResult(__jni_env, arg0, arg1.into_ref(__jni_env)) }
+    catch_callback_panic("ScanCallback.onScanResult", || unsafe {
+        (*ptr).onScanResult(__jni_env, arg0, arg1.into_ref(__jni_env))
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -294,7 +361,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_le_Sc
 ) -> () {
     let ptr: *const std::sync::Arc<dyn ScanCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onBatchScanResults(__jni_env, arg0.into_ref(__jni_env)) }
+    catch_callback_panic("ScanCallback.onBatchScanResults", || unsafe {
+        (*ptr).onBatchScanResults(__jni_env, arg0.into_ref(__jni_env))
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -308,7 +377,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_le_Sc
 ) -> () {
     let ptr: *const std::sync::Arc<dyn ScanCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onScanFailed(__jni_env, arg0) }
+    catch_callback_panic("ScanCallback.onScanFailed", || unsafe {
+        (*ptr).onScanFailed(__jni_env, arg0)
+    });
 }
 
 pub trait BluetoothGattCallbackProxy: ::std::marker::Send + ::std::marker::Sync + 'static {
@@ -423,15 +494,22 @@ pub trait BluetoothGattCallbackProxy: ::std::marker::Send + ::std::marker::Sync
         env: ::java_spaghetti::Env<'env>,
         arg0: ::std::option::Option<::java_spaghetti::Ref<'env, BluetoothGatt>>,
     ) -> ();
+    fn onConnectionUpdated<'env>(
+        &self,
+        env: ::java_spaghetti::Env<'env>,
+        arg0: ::std::option::Option<::java_spaghetti::Ref<'env, BluetoothGatt>>,
+        arg1: i32,
+        arg2: i32,
+        arg3: i32,
+        arg4: i32,
+    ) -> ();
 }
 
 impl BluetoothGattCallback {
-    pub fn new_proxy<'env>(
-        env: ::java_spaghetti::Env<'env>,
-        proxy: ::std::sync::Arc<dyn BluetoothGattCallbackProxy>,
-    ) -> Result<::java_spaghetti::Local<'env, Self>, ::java_spaghetti::Local<'env, Throwable>> {
+    /// Loads the proxy class from the embedded dex and registers its native methods once.
+    pub(crate) fn proxy_class(env: ::java_spaghetti::Env<'_>) -> &'static Global<Class> {
         static __CLASS: ::std::sync::OnceLock<Global<Class>> = ::std::sync::OnceLock::new();
-        let __jni_class = if let Some(cached) = __CLASS.get() {
+        if let Some(cached) = __CLASS.get() {
             cached
         } else {
             let loaded = {
@@ -445,7 +523,13 @@ impl BluetoothGattCallback {
             Self::register_proxy_methods(env, &loaded.as_ref(env));
             __CLASS.get_or_init(|| loaded)
         }
-        .as_raw();
+    }
+
+    pub fn new_proxy<'env>(
+        env: ::java_spaghetti::Env<'env>,
+        proxy: ::std::sync::Arc<dyn BluetoothGattCallbackProxy>,
+    ) -> Result<::java_spaghetti::Local<'env, Self>, ::java_spaghetti::Local<'env, Throwable>> {
+        let __jni_class = Self::proxy_class(env).as_raw();
         let b = ::std::boxed::Box::new(proxy);
         let ptr = ::std::boxed::Box::into_raw(b);
         unsafe {
@@ -563,6 +647,19 @@ impl BluetoothGattCallback {
                 let fn_ptr = Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onServiceChanged__JLandroid_bluetooth_BluetoothGatt_2 as * mut _ ;
                 let _ = env.register_native_method(proxy_class, method_name, descriptor, fn_ptr);
             }
+            {
+                let method_name = c"native_onConnectionUpdated";
+                let descriptor = c"(JLandroid/bluetooth/BluetoothGatt;IIII)V";
+                let fn_ptr = Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onConnectionUpdated__JLandroid_bluetooth_BluetoothGatt_2IIII as * mut _ ;
+                // not declared by an outdated proxy class (e.g. one loaded from another copy of
+                // the dex); connection updates are then detected by the fallback timeout.
+                if !env.try_register_native_method(proxy_class, method_name, descriptor, fn_ptr) {
+                    log::warn!(
+                        "`native_onConnectionUpdated` is missing in the loaded proxy class, \
+                        connection update events will not be received"
+                    );
+                }
+            }
             {
                 let method_name = c"native_finalize";
                 let descriptor = c"(J)V";
@@ -583,7 +680,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) {
     let ptr: *mut ::std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance_mut(ptr as usize);
-    let _ = unsafe { Box::from_raw(ptr) };
+    catch_callback_panic("BluetoothGattCallback.finalize", || {
+        let _ = unsafe { Box::from_raw(ptr) };
+    });
 }
 
 #[unsafe(no_mangle)]
@@ -600,7 +699,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onPhyUpdate(__jni_env, arg0.into_ref(__jni_env), arg1, arg2, arg3) }
+    catch_callback_panic("BluetoothGattCallback.onPhyUpdate", || unsafe {
+        (*ptr).onPhyUpdate(__jni_env, arg0.into_ref(__jni_env), arg1, arg2, arg3)
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onPhyRead__JLandroid_bluetooth_BluetoothGatt_2III<
@@ -616,7 +717,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onPhyRead(__jni_env, arg0.into_ref(__jni_env), arg1, arg2, arg3) }
+    catch_callback_panic("BluetoothGattCallback.onPhyRead", || unsafe {
+        (*ptr).onPhyRead(__jni_env, arg0.into_ref(__jni_env), arg1, arg2, arg3)
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onConnectionStateChange__JLandroid_bluetooth_BluetoothGatt_2II<
@@ -631,7 +734,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onConnectionStateChange(__jni_env, arg0.into_ref(__jni_env), arg1, arg2) }
+    catch_callback_panic("BluetoothGattCallback.onConnectionStateChange", || unsafe {
+        (*ptr).onConnectionStateChange(__jni_env, arg0.into_ref(__jni_env), arg1, arg2)
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onServicesDiscovered__JLandroid_bluetooth_BluetoothGatt_2I<
@@ -645,7 +750,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onServicesDiscovered(__jni_env, arg0.into_ref(__jni_env), arg1) }
+    catch_callback_panic("BluetoothGattCallback.onServicesDiscovered", || unsafe {
+        (*ptr).onServicesDiscovered(__jni_env, arg0.into_ref(__jni_env), arg1)
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onCharacteristicRead__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattCharacteristic_2I<
@@ -660,14 +767,17 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
-        (*ptr).onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_int(
-            __jni_env,
-            arg0.into_ref(__jni_env),
-            arg1.into_ref(__jni_env),
-            arg2,
-        )
-    }
+    catch_callback_panic(
+        "BluetoothGattCallback.onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_int",
+        || unsafe {
+            (*ptr).onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_int(
+                __jni_env,
+                arg0.into_ref(__jni_env),
+                arg1.into_ref(__jni_env),
+                arg2,
+            )
+        },
+    );
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onCharacteristicRead__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattCharacteristic_2_3BI<
@@ -683,15 +793,17 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
-        (*ptr).onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_byte_array_int(
-            __jni_env,
-            arg0.into_ref(__jni_env),
-            arg1.into_ref(__jni_env),
-            arg2.into_ref(__jni_env),
-            arg3,
-        )
-    }
+    catch_callback_panic("BluetoothGattCallback.onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_byte_array_int", || {
+        unsafe {
+            (*ptr).onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_byte_array_int(
+                __jni_env,
+                arg0.into_ref(__jni_env),
+                arg1.into_ref(__jni_env),
+                arg2.into_ref(__jni_env),
+                arg3,
+            )
+        }
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onCharacteristicWrite__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattCharacteristic_2I<
@@ -706,14 +818,14 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
+    catch_callback_panic("BluetoothGattCallback.onCharacteristicWrite", || unsafe {
         (*ptr).onCharacteristicWrite(
             __jni_env,
             arg0.into_ref(__jni_env),
             arg1.into_ref(__jni_env),
             arg2,
         )
-    }
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onCharacteristicChanged__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattCharacteristic_2<
@@ -727,13 +839,16 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
-        (*ptr).onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic(
-            __jni_env,
-            arg0.into_ref(__jni_env),
-            arg1.into_ref(__jni_env),
-        )
-    }
+    catch_callback_panic(
+        "BluetoothGattCallback.onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic",
+        || unsafe {
+            (*ptr).onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic(
+                __jni_env,
+                arg0.into_ref(__jni_env),
+                arg1.into_ref(__jni_env),
+            )
+        },
+    );
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onCharacteristicChanged__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattCharacteristic_2_3B<
@@ -748,14 +863,16 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
-        (*ptr).onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic_byte_array(
-            __jni_env,
-            arg0.into_ref(__jni_env),
-            arg1.into_ref(__jni_env),
-            arg2.into_ref(__jni_env),
-        )
-    }
+    catch_callback_panic("BluetoothGattCallback.onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic_byte_array", || {
+        unsafe {
+            (*ptr).onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic_byte_array(
+                __jni_env,
+                arg0.into_ref(__jni_env),
+                arg1.into_ref(__jni_env),
+                arg2.into_ref(__jni_env),
+            )
+        }
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onDescriptorRead__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattDescriptor_2I<
@@ -770,14 +887,17 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
-        (*ptr).onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int(
-            __jni_env,
-            arg0.into_ref(__jni_env),
-            arg1.into_ref(__jni_env),
-            arg2,
-        )
-    }
+    catch_callback_panic(
+        "BluetoothGattCallback.onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int",
+        || unsafe {
+            (*ptr).onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int(
+                __jni_env,
+                arg0.into_ref(__jni_env),
+                arg1.into_ref(__jni_env),
+                arg2,
+            )
+        },
+    );
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onDescriptorRead__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattDescriptor_2I_3B<
@@ -793,15 +913,17 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
-        (*ptr).onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int_byte_array(
-            __jni_env,
-            arg0.into_ref(__jni_env),
-            arg1.into_ref(__jni_env),
-            arg2,
-            arg3.into_ref(__jni_env),
-        )
-    }
+    catch_callback_panic("BluetoothGattCallback.onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int_byte_array", || {
+        unsafe {
+            (*ptr).onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int_byte_array(
+                __jni_env,
+                arg0.into_ref(__jni_env),
+                arg1.into_ref(__jni_env),
+                arg2,
+                arg3.into_ref(__jni_env),
+            )
+        }
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onDescriptorWrite__JLandroid_bluetooth_BluetoothGatt_2Landroid_bluetooth_BluetoothGattDescriptor_2I<
@@ -816,14 +938,14 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe {
+    catch_callback_panic("BluetoothGattCallback.onDescriptorWrite", || unsafe {
         (*ptr).onDescriptorWrite(
             __jni_env,
             arg0.into_ref(__jni_env),
             arg1.into_ref(__jni_env),
             arg2,
         )
-    }
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onReliableWriteCompleted__JLandroid_bluetooth_BluetoothGatt_2I<
@@ -837,7 +959,10 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onReliableWriteCompleted(__jni_env, arg0.into_ref(__jni_env), arg1) }
+    catch_callback_panic(
+        "BluetoothGattCallback.onReliableWriteCompleted",
+        || unsafe { (*ptr).onReliableWriteCompleted(__jni_env, arg0.into_ref(__jni_env), arg1) },
+    );
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onReadRemoteRssi__JLandroid_bluetooth_BluetoothGatt_2II<
@@ -852,7 +977,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onReadRemoteRssi(__jni_env, arg0.into_ref(__jni_env), arg1, arg2) }
+    catch_callback_panic("BluetoothGattCallback.onReadRemoteRssi", || unsafe {
+        (*ptr).onReadRemoteRssi(__jni_env, arg0.into_ref(__jni_env), arg1, arg2)
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onMtuChanged__JLandroid_bluetooth_BluetoothGatt_2II<
@@ -867,7 +994,9 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onMtuChanged(__jni_env, arg0.into_ref(__jni_env), arg1, arg2) }
+    catch_callback_panic("BluetoothGattCallback.onMtuChanged", || unsafe {
+        (*ptr).onMtuChanged(__jni_env, arg0.into_ref(__jni_env), arg1, arg2)
+    });
 }
 #[unsafe(no_mangle)]
 extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onServiceChanged__JLandroid_bluetooth_BluetoothGatt_2<
@@ -880,5 +1009,26 @@ extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_Bluet
 ) -> () {
     let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
         ::std::ptr::with_exposed_provenance(ptr as usize);
-    unsafe { (*ptr).onServiceChanged(__jni_env, arg0.into_ref(__jni_env)) }
+    catch_callback_panic("BluetoothGattCallback.onServiceChanged", || unsafe {
+        (*ptr).onServiceChanged(__jni_env, arg0.into_ref(__jni_env))
+    });
+}
+#[unsafe(no_mangle)]
+extern "system" fn Java_com_github_alexmoon_bluest_proxy_android_bluetooth_BluetoothGattCallback_native_1onConnectionUpdated__JLandroid_bluetooth_BluetoothGatt_2IIII<
+    'env,
+>(
+    __jni_env: ::java_spaghetti::Env<'env>,
+    _class: *mut (),
+    ptr: i64,
+    arg0: ::java_spaghetti::Arg<BluetoothGatt>,
+    arg1: i32,
+    arg2: i32,
+    arg3: i32,
+    arg4: i32,
+) -> () {
+    let ptr: *const std::sync::Arc<dyn BluetoothGattCallbackProxy> =
+        ::std::ptr::with_exposed_provenance(ptr as usize);
+    catch_callback_panic("BluetoothGattCallback.onConnectionUpdated", || unsafe {
+        (*ptr).onConnectionUpdated(__jni_env, arg0.into_ref(__jni_env), arg1, arg2, arg3, arg4)
+    });
 }
@@ -0,0 +1,152 @@
+//! Optional logging of all callbacks from the Android Bluetooth stack, enabled by
+//! `AdapterConfig::callback_log_level`, and the panic guard of native callback entry points.
+
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use java_spaghetti::{ByteArray, Ref};
+use log::{Level, LevelFilter};
+
+use super::bindings::android::bluetooth::{BluetoothGattCharacteristic, BluetoothGattDescriptor};
+use super::jni::ByteArrayExt;
+use super::util::UuidExt;
+use super::Uuid;
+
+/// Values longer than this are truncated in the log.
+const MAX_LOGGED_VALUE_LEN: usize = 16;
+
+/// Stores `LevelFilter as usize`; `LevelFilter::Off` is 0.
+static CALLBACK_LOG_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Count of panics caught in native callback entry points.
+static CALLBACK_PANICS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_callback_log_level(level: LevelFilter) {
+    CALLBACK_LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Returns `None` if callback logging is disabled.
+pub fn callback_log_level() -> Option<Level> {
+    match CALLBACK_LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => None,
+        1 => Some(Level::Error),
+        2 => Some(Level::Warn),
+        3 => Some(Level::Info),
+        4 => Some(Level::Debug),
+        _ => Some(Level::Trace),
+    }
+}
+
+/// Runs the body of a native callback entry point. Unwinding into the JVM is undefined behavior,
+/// so a panic is caught here, logged and counted instead; the callback is then treated as if it
+/// has returned normally.
+pub fn catch_callback_panic(callback: &str, f: impl FnOnce()) {
+    let Err(payload) = catch_unwind(AssertUnwindSafe(f)) else {
+        return;
+    };
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+        .unwrap_or("(unknown panic payload)");
+    CALLBACK_PANICS.fetch_add(1, Ordering::Relaxed);
+    log::error!("panic caught in the native callback `{callback}`: {message}");
+}
+
+/// Returns the count of panics caught by [catch_callback_panic].
+pub fn callback_panic_count() -> usize {
+    CALLBACK_PANICS.load(Ordering::Relaxed)
+}
+
+/// Logs a callback with the `android_ble::callback` target and the current thread, if enabled.
+/// The arguments are not evaluated if it is disabled.
+macro_rules! log_callback {
+    ($($arg:tt)+) => {
+        if let Some(level) = $crate::callback_log::callback_log_level() {
+            let thread = std::thread::current();
+            log::log!(
+                target: "android_ble::callback",
+                level,
+                "[{}] {}",
+                thread.name().unwrap_or("unnamed"),
+                format_args!($($arg)+)
+            );
+        }
+    };
+}
+pub(crate) use log_callback;
+
+/// Formats a received value as hexadecimal bytes, truncated to [MAX_LOGGED_VALUE_LEN] bytes.
+pub struct LoggedValue(pub Option<Vec<u8>>);
+
+impl LoggedValue {
+    pub fn from_java(value: &Option<Ref<'_, ByteArray>>) -> Self {
+        Self(value.as_ref().map(|arr| arr.as_vec_u8()))
+    }
+}
+
+impl fmt::Display for LoggedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(value) = &self.0 else {
+            return f.write_str("null");
+        };
+        write!(f, "{} bytes [", value.len())?;
+        for (i, byte) in value.iter().take(MAX_LOGGED_VALUE_LEN).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        if value.len() > MAX_LOGGED_VALUE_LEN {
+            f.write_str(" ...")?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Describes the characteristic by its UUID and instance ID.
+pub fn logged_char(char: &Option<Ref<'_, BluetoothGattCharacteristic>>) -> String {
+    let Some(char) = char else {
+        return "null".into();
+    };
+    let uuid = char
+        .getUuid()
+        .ok()
+        .flatten()
+        .and_then(|uuid| Uuid::from_java(uuid.as_ref()).ok());
+    match (uuid, char.getInstanceId()) {
+        (Some(uuid), Ok(instance_id)) => format!("{uuid} (instance {instance_id})"),
+        _ => "unknown".into(),
+    }
+}
+
+/// Describes the descriptor by its UUID.
+pub fn logged_desc(desc: &Option<Ref<'_, BluetoothGattDescriptor>>) -> String {
+    let Some(desc) = desc else {
+        return "null".into();
+    };
+    desc.getUuid()
+        .ok()
+        .flatten()
+        .and_then(|uuid| Uuid::from_java(uuid.as_ref()).ok())
+        .map_or_else(|| "unknown".into(), |uuid| uuid.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_is_caught_and_counted() {
+        let before = callback_panic_count();
+        let mut reached = false;
+        catch_callback_panic("onTest", || reached = true);
+        assert!(reached);
+        assert_eq!(callback_panic_count(), before);
+
+        catch_callback_panic("onTest", || panic!("handler failed"));
+        catch_callback_panic("onTest", || panic!("{}", String::from("formatted payload")));
+        assert_eq!(callback_panic_count(), before + 2);
+    }
+}
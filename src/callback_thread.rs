@@ -0,0 +1,91 @@
+//! A dedicated `android.os.HandlerThread` on which GATT callbacks are delivered, see
+//! [crate::AdapterConfig::dedicated_callback_thread].
+//!
+//! `Handler`, `HandlerThread` and `Looper` are not included in the generated bindings, so
+//! they are accessed with raw JNI calls here.
+
+use std::ptr::null;
+
+use java_spaghetti::sys::jvalue;
+use java_spaghetti::{Global, Local};
+use log::{debug, warn};
+
+use super::bindings::java::lang::{Object, String as JString, Throwable};
+use super::error::ErrorKind;
+use super::vm_context::jni_with_env;
+
+/// Quits the looper when dropped; it is shared by the adapter and all connections made by it.
+pub(crate) struct CallbackThread {
+    thread: Global<Object>,
+    handler: Global<Object>,
+}
+
+impl CallbackThread {
+    /// Starts the thread and creates a `Handler` for its looper.
+    pub fn start(name: &str) -> Result<Self, crate::Error> {
+        jni_with_env(|env| unsafe {
+            let (class, method) = env.require_class_method(
+                "android/os/HandlerThread\0",
+                "<init>\0",
+                "(Ljava/lang/String;)V\0",
+            );
+            let name = JString::from_env_str(env, name);
+            let args = [jvalue { l: name.as_raw() }];
+            let thread: Local<Object> =
+                env.new_object_a::<Object, Throwable>(class, method, args.as_ptr())?;
+
+            let (_, method) = env.require_class_method("java/lang/Thread\0", "start\0", "()V\0");
+            env.call_void_method_a::<Throwable>(thread.as_raw(), method, null())?;
+
+            // blocks until the looper of the new thread is prepared.
+            let (_, method) = env.require_class_method(
+                "android/os/HandlerThread\0",
+                "getLooper\0",
+                "()Landroid/os/Looper;\0",
+            );
+            let looper = env
+                .call_object_method_a::<Object, Throwable>(thread.as_raw(), method, null())?
+                .ok_or_else(|| {
+                    crate::Error::new(
+                        ErrorKind::Internal,
+                        None,
+                        "`HandlerThread.getLooper()` returned null",
+                    )
+                })?;
+
+            let (class, method) = env.require_class_method(
+                "android/os/Handler\0",
+                "<init>\0",
+                "(Landroid/os/Looper;)V\0",
+            );
+            let args = [jvalue { l: looper.as_raw() }];
+            let handler: Local<Object> =
+                env.new_object_a::<Object, Throwable>(class, method, args.as_ptr())?;
+            debug!("started the GATT callback thread");
+            Ok(Self {
+                thread: thread.as_global(),
+                handler: handler.as_global(),
+            })
+        })
+    }
+
+    /// The `android.os.Handler` to be passed to `BluetoothDevice.connectGatt()`.
+    pub fn handler(&self) -> &Global<Object> {
+        &self.handler
+    }
+}
+
+impl Drop for CallbackThread {
+    fn drop(&mut self) {
+        jni_with_env(|env| unsafe {
+            // callbacks already posted are still delivered.
+            let (_, method) =
+                env.require_class_method("android/os/HandlerThread\0", "quitSafely\0", "()Z\0");
+            let thread = self.thread.as_ref(env);
+            match env.call_boolean_method_a::<Throwable>(thread.as_raw(), method, null()) {
+                Ok(_) => debug!("stopped the GATT callback thread"),
+                Err(e) => warn!("`HandlerThread.quitSafely()` threw: {e:?}"),
+            }
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use futures_lite::FutureExt;
+
+use super::error::ErrorKind;
+use super::Result;
+
+/// A shared signal for cancelling operations like [crate::Characteristic::read_cancellable].
+///
+/// Clones of the token share the same state; once [CancellationToken::cancel] is called,
+/// all pending and future operations taking the token return an [ErrorKind::Other] error.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    inner: Arc<CancellationInner>,
+}
+
+#[derive(Debug)]
+struct CancellationInner {
+    // no message is ever sent; closing the channel wakes up all receivers.
+    sender: async_channel::Sender<()>,
+    receiver: async_channel::Receiver<()>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        let (sender, receiver) = async_channel::bounded(1);
+        Self {
+            inner: Arc::new(CancellationInner { sender, receiver }),
+        }
+    }
+
+    /// Cancels all operations taking this token.
+    pub fn cancel(&self) {
+        self.inner.sender.close();
+    }
+
+    /// Checks if [CancellationToken::cancel] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.sender.is_closed()
+    }
+
+    /// Waits until the token is cancelled.
+    pub async fn cancelled(&self) {
+        let _ = self.inner.receiver.recv().await;
+    }
+
+    /// Runs `fut` until it is completed or the token is cancelled.
+    pub(crate) async fn run<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        fut.or(async {
+            self.cancelled().await;
+            Err(cancelled_error())
+        })
+        .await
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn cancelled_error() -> crate::Error {
+    crate::Error::new(ErrorKind::Other, None, "cancelled")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use futures_lite::future::{block_on, yield_now, zip};
+
+    use super::*;
+    use crate::async_util::Excluder;
+
+    fn is_cancelled_error<T>(result: &Result<T>) -> bool {
+        matches!(result, Err(e) if e.kind() == ErrorKind::Other)
+    }
+
+    #[test]
+    fn cancelled_token_skips_operation() {
+        let token = CancellationToken::new();
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        let started = AtomicBool::new(false);
+        let result = block_on(token.run(async {
+            started.store(true, Ordering::SeqCst);
+            Ok(())
+        }));
+        assert!(is_cancelled_error(&result));
+        assert!(!started.load(Ordering::SeqCst));
+    }
+
+    // the excluder is held by another operation, so the cancelled one is still queued.
+    #[test]
+    fn cancel_while_waiting_for_lock() {
+        let excluder = Excluder::<i32>::with_timeout(Duration::from_secs(5));
+        let token = CancellationToken::new();
+        block_on(async {
+            let holder = excluder.lock().await;
+            let operation = token.run(async {
+                let waiter = excluder.lock().await;
+                excluder.unlock(1);
+                waiter
+                    .wait_unlock()
+                    .await
+                    .map_err(|_| ErrorKind::Timeout.into())
+            });
+            let (result, ()) = zip(operation, async {
+                yield_now().await;
+                token.cancel();
+            })
+            .await;
+            assert!(is_cancelled_error(&result));
+
+            excluder.unlock(2);
+            assert_eq!(holder.wait_unlock().await, Ok(2));
+            let waiter = excluder
+                .try_lock()
+                .expect("the excluder should be released");
+            excluder.unlock(3);
+            assert_eq!(waiter.wait_unlock().await, Ok(3));
+        });
+    }
+
+    // the request is sent to the device, so the excluder is held until its callback arrives.
+    #[test]
+    fn cancel_while_waiting_for_callback() {
+        let excluder = Excluder::<i32>::with_timeout(Duration::from_secs(5));
+        let token = CancellationToken::new();
+        block_on(async {
+            let operation = token.run(async {
+                let waiter = excluder.lock().await;
+                waiter
+                    .wait_unlock()
+                    .await
+                    .map_err(|_| ErrorKind::Timeout.into())
+            });
+            let (result, ()) = zip(operation, async {
+                yield_now().await;
+                token.cancel();
+            })
+            .await;
+            assert!(is_cancelled_error(&result));
+            assert!(excluder.is_locked());
+
+            // the late callback releases the excluder, and is not taken as the next result.
+            excluder.unlock(1);
+            assert!(!excluder.is_locked());
+            let waiter = excluder.lock().await;
+            excluder.unlock(2);
+            assert_eq!(waiter.wait_unlock().await, Ok(2));
+            assert_eq!(excluder.last_value(), Some(2));
+        });
+    }
+}
@@ -1,24 +1,40 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures_core::Stream;
+use futures_lite::FutureExt;
 use java_spaghetti::ByteArray;
+use log::warn;
 use uuid::Uuid;
 
+use super::async_util::{NotifierReceiver, StreamIdleTimeout};
 use super::bindings::android::bluetooth::BluetoothGattCharacteristic;
+use super::btuuid::descriptors;
 use super::descriptor::Descriptor;
-use super::error::ErrorKind;
-use super::gatt_tree::{CachedWeak, CharacteristicInner, GattTree};
+use super::device::Device;
+use super::error::{AttError, ErrorKind};
+use super::gatt_tree::{CachedWeak, CharacteristicInner, GattTree, ReadValue};
 use super::jni::{ByteArrayExt, Monitor};
-use super::util::{BoolExt, IntExt, OptionExt};
+use super::resilient::{persistent_notify, PersistentKey};
+use super::service::Service;
+use super::timer::Delay;
+use super::trace::traced;
+use super::util::{deadline_error, BoolExt, IntExt, OptionExt, WaitResultExt};
 use super::vm_context::{android_api_level, jni_with_env};
-use super::{CharacteristicProperties, DeviceId, Result};
+use super::{
+    CancellationToken, CharacteristicProperties, DeviceId, ExtendedProperties, NotifyEvent,
+    OverflowPolicy, Result,
+};
 
 /// A Bluetooth GATT characteristic.
 #[derive(Debug, Clone)]
 pub struct Characteristic {
     dev_id: DeviceId,
     service_id: Uuid,
+    service_instance: i32,
     char_id: Uuid,
+    instance_id: i32,
     inner: CachedWeak<CharacteristicInner>,
 }
 
@@ -26,7 +42,9 @@ impl PartialEq for Characteristic {
     fn eq(&self, other: &Self) -> bool {
         self.dev_id == other.dev_id
             && self.service_id == other.service_id
+            && self.service_instance == other.service_instance
             && self.char_id == other.char_id
+            && self.instance_id == other.instance_id
     }
 }
 
@@ -36,16 +54,26 @@ impl std::hash::Hash for Characteristic {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.dev_id.hash(state);
         self.service_id.hash(state);
+        self.service_instance.hash(state);
         self.char_id.hash(state);
+        self.instance_id.hash(state);
     }
 }
 
 impl Characteristic {
-    pub(crate) fn new(dev_id: DeviceId, service_id: Uuid, char_id: Uuid) -> Self {
+    pub(crate) fn new(
+        dev_id: DeviceId,
+        service_id: Uuid,
+        service_instance: i32,
+        char_id: Uuid,
+        instance_id: i32,
+    ) -> Self {
         Self {
             dev_id,
             service_id,
+            service_instance,
             char_id,
+            instance_id,
             inner: CachedWeak::new(),
         }
     }
@@ -60,15 +88,77 @@ impl Characteristic {
         Ok(self.char_id)
     }
 
+    /// The instance ID of this characteristic, from `BluetoothGattCharacteristic.getInstanceId()`.
+    /// It tells apart characteristics of the same UUID in the service.
+    pub fn instance_id(&self) -> i32 {
+        self.instance_id
+    }
+
+    /// The device of this characteristic. It is available even if the device is disconnected, so
+    /// device-level operations like [Device::is_connected] can be called on it.
+    pub fn device(&self) -> Result<Device> {
+        Device::from_known_id(&self.dev_id)
+    }
+
+    /// Checks if `other` belongs to the same device as this characteristic, unlike `==` which
+    /// also compares the service and the characteristic. This doesn't call any Java method.
+    pub fn same_device(&self, other: &Characteristic) -> bool {
+        self.dev_id == other.dev_id
+    }
+
+    /// The service containing this characteristic. This doesn't call any Java method.
+    pub fn service(&self) -> Service {
+        Service::new(self.dev_id.clone(), self.service_id, self.service_instance)
+    }
+
     /// The properties of this this GATT characteristic.
     ///
     /// Characteristic properties indicate which operations (e.g. read, write, notify, etc)
     /// may be performed on this characteristic.
     pub async fn properties(&self) -> Result<CharacteristicProperties> {
-        jni_with_env(|env| {
-            let val = self.get_inner()?.char.as_ref(env).getProperties()?;
-            Ok(CharacteristicProperties::from_bits(val.cast_unsigned()))
-        })
+        Ok(self.get_inner()?.properties)
+    }
+
+    /// Reads and decodes the Characteristic Extended Properties descriptor (0x2900).
+    ///
+    /// Returns an [ErrorKind::NotFound] error if the characteristic doesn't have this descriptor.
+    pub async fn extended_properties(&self) -> Result<ExtendedProperties> {
+        let desc = self
+            .descriptors()
+            .await?
+            .into_iter()
+            .find(|desc| desc.uuid() == descriptors::CHARACTERISTIC_EXTENDED_PROPERTIES)
+            .ok_or_else(|| {
+                crate::Error::new(
+                    ErrorKind::NotFound,
+                    None,
+                    "the characteristic has no extended properties descriptor",
+                )
+            })?;
+        let value = desc.read().await?;
+        let [lo, hi, ..] = value[..] else {
+            return Err(crate::Error::new(
+                ErrorKind::Other,
+                None,
+                format!("invalid extended properties value {value:02x?}"),
+            ));
+        };
+        Ok(ExtendedProperties::from_bits(u16::from_le_bytes([lo, hi])))
+    }
+
+    /// Checks if the characteristic supports reliable writes (queued writes executed atomically).
+    ///
+    /// This reads the Characteristic Extended Properties descriptor if the extended properties
+    /// bit is set; a characteristic without the descriptor doesn't support reliable writes.
+    pub async fn supports_reliable_write(&self) -> Result<bool> {
+        if !self.properties().await?.extended_properties {
+            return Ok(false);
+        }
+        match self.extended_properties().await {
+            Ok(ext_props) => Ok(ext_props.reliable_write),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
     /// The cached value of this characteristic. Returns an error if the value has not yet been read.
@@ -81,6 +171,7 @@ impl Characteristic {
                 None,
                 "please call `Characteristic::read` at first",
             ))?
+            .map(|value| value.to_vec())
     }
 
     // NOTE: the sequence of gaining read lock and write lock should be the same
@@ -91,22 +182,145 @@ impl Characteristic {
 
     /// Read the value of this characteristic from the device.
     pub async fn read(&self) -> Result<Vec<u8>> {
+        self.read_internal(None).await
+    }
+
+    /// Read the value of this characteristic from the device like [Characteristic::read], but returns
+    /// an [ErrorKind::Timeout] error if it is not completed before `deadline`.
+    pub async fn read_until(&self, deadline: Instant) -> Result<Vec<u8>> {
+        self.read_internal(Some(deadline)).await
+    }
+
+    /// Returns the value read within `max_age` by any read of this characteristic, otherwise reads
+    /// it from the device like [Characteristic::read]. Callers arriving while the value is being
+    /// read wait for that read instead of starting another one.
+    ///
+    /// Unlike [Characteristic::value], the cached value is never a failed result, and notified
+    /// values are not taken into account. The cached value is discarded when the characteristic
+    /// is written by this library, and when services are discovered again, either by
+    /// [Device::discover_services] or after a Service Changed indication.
+    pub async fn read_cached(&self, max_age: Duration) -> Result<Vec<u8>> {
+        let inner = self.get_inner()?;
+        let fresh = || {
+            inner
+                .read_cache
+                .lock()
+                .unwrap()
+                .as_ref()
+                .filter(|(read_at, _)| read_at.elapsed() <= max_age)
+                .map(|(_, value)| value.to_vec())
+        };
+        if let Some(value) = fresh() {
+            return Ok(value);
+        }
+        let _refresh = inner.read_refresh.lock_arc().await;
+        if let Some(value) = fresh() {
+            return Ok(value); // read by the previous holder of the lock
+        }
+        drop(inner);
+        self.read().await
+    }
+
+    /// Read the value of this characteristic from the device like [Characteristic::read], but copies
+    /// it from the Java array into `buf` directly and returns its length, without allocating a
+    /// `Vec<u8>` for it.
+    ///
+    /// Returns an [ErrorKind::InvalidParameter] error if `buf` is too small for the value.
+    pub async fn read_value_into(&self, buf: &mut [u8]) -> Result<usize> {
+        let value = self.read_array(None).await?;
+        value.copy_to_slice(buf).ok_or_else(|| {
+            crate::Error::new(
+                ErrorKind::InvalidParameter,
+                None,
+                format!(
+                    "buffer of {} bytes is too small for the {}-byte value",
+                    buf.len(),
+                    value.len()
+                ),
+            )
+        })
+    }
+
+    /// Read the value of this characteristic from the device like [Characteristic::read], but only
+    /// returns its length, without copying the value out of the Java array. The value is kept, so
+    /// [Characteristic::value] can be called afterwards without reading it again.
+    pub async fn value_len(&self) -> Result<usize> {
+        self.read_array(None).await.map(|value| value.len())
+    }
+
+    /// Read the value of this characteristic from the device like [Characteristic::read], but returns
+    /// an [ErrorKind::Other] error once `token` is cancelled.
+    pub async fn read_cancellable(&self, token: &CancellationToken) -> Result<Vec<u8>> {
+        token.run(self.read()).await
+    }
+
+    async fn read_internal(&self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        self.read_array(deadline).await.map(|value| value.to_vec())
+    }
+
+    async fn read_array(&self, deadline: Option<Instant>) -> Result<ReadValue> {
+        let _sequence_guard = GattTree::check_connection(&self.dev_id)?
+            .sequence_guard()
+            .await;
+        let result = match self.read_array_in_sequence(deadline).await {
+            Err(e) if Device::rediscover_if_out_of_sync(&self.dev_id, &e).await => {
+                self.read_array_in_sequence(deadline).await
+            }
+            result => result,
+        };
+        Device::watch_bond_on_failure(&self.dev_id, result).await
+    }
+
+    /// Reads the value without taking the sequence lock of the connection, which should be
+    /// held by the caller.
+    pub(crate) async fn read_in_sequence(&self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        let value = self.read_array_in_sequence(deadline).await?;
+        Ok(value.to_vec())
+    }
+
+    async fn read_array_in_sequence(&self, deadline: Option<Instant>) -> Result<ReadValue> {
+        let read = self.read_in_sequence_untraced(deadline);
+        traced(
+            "read_characteristic",
+            &self.dev_id,
+            Some(self.char_id),
+            read,
+        )
+        .await
+    }
+
+    async fn read_in_sequence_untraced(&self, deadline: Option<Instant>) -> Result<ReadValue> {
+        const OPERATION: &str = "characteristic read";
         let conn = GattTree::check_connection(&self.dev_id)?;
+        conn.check_not_discovering()?;
         let inner = self.get_inner()?;
-        let read_lock = inner.read.lock().await;
-        let _write_lock = inner.write.lock().await;
+        let read_lock = inner
+            .read
+            .lock_until(deadline)
+            .await
+            .ok_or_else(|| deadline_error(OPERATION))?;
+        let _write_lock = inner
+            .write
+            .lock_until(deadline)
+            .await
+            .ok_or_else(|| deadline_error(OPERATION))?;
         jni_with_env(|env| {
             let gatt = &conn.gatt.as_ref(env);
             let gatt = Monitor::new(gatt);
-            gatt.readCharacteristic(inner.char.as_ref(env))
+            gatt.readCharacteristic(inner.char_obj().as_ref(env))
                 .map_err(|e| e.into())
                 .and_then(|b| b.non_false())
         })?;
         drop((conn, inner));
-        read_lock
-            .wait_unlock()
-            .await
-            .ok_or_check_conn(&self.dev_id)?
+        let value = match deadline {
+            Some(deadline) => read_lock.wait_unlock_until(deadline).await,
+            None => read_lock.wait_unlock().await,
+        }
+        .check_wait(&self.dev_id, OPERATION)??;
+        if let Ok(inner) = self.get_inner() {
+            *inner.read_cache.lock().unwrap() = Some((Instant::now(), value.clone()));
+        }
+        Ok(value)
     }
 
     /// Write `value` to this characteristic on the device and request the device to return a response
@@ -114,11 +328,57 @@ impl Characteristic {
     pub async fn write(&self, value: &[u8]) -> Result<()> {
         // NOTE: It is tested that `AttError::INVALID_ATTRIBUTE_VALUE_LENGTH` is returned if the data length
         // is too long; a successful write means it is not truncated. Is this really guaranteed?
-        self.write_internal(value, true).await
+        self.write_internal(value, true, None).await
+    }
+
+    /// Write `value` to this characteristic on the device like [Characteristic::write], but returns
+    /// an [ErrorKind::Timeout] error if it is not completed before `deadline`.
+    pub async fn write_until(&self, value: &[u8], deadline: Instant) -> Result<()> {
+        self.write_internal(value, true, Some(deadline)).await
     }
 
     /// Write `value` to this characteristic on the device without requesting a response.
     pub async fn write_without_response(&self, value: &[u8]) -> Result<()> {
+        self.check_write_without_response_len(value)?;
+        self.write_internal(value, false, None).await
+    }
+
+    /// Writes `value` without response like [Characteristic::write_without_response], with flow
+    /// control for high-throughput transfers like OTA updates.
+    ///
+    /// On API level 33 and above, if `writeCharacteristic` reports that the Bluetooth stack is busy
+    /// (`ERROR_GATT_WRITE_REQUEST_BUSY`), this waits for the callback of the pending request of the
+    /// connection, then issues the write again, until the operation timeout is reached. Below API
+    /// level 33, the reason of a rejected write is unknown, so it fails immediately.
+    pub async fn write_without_response_awaiting_credit(&self, value: &[u8]) -> Result<()> {
+        self.check_write_without_response_len(value)?;
+        let timeout = GattTree::check_connection(&self.dev_id)?
+            .options
+            .operation_timeout;
+        let deadline = Instant::now() + timeout;
+        loop {
+            // subscribed before the write, so that the callback cannot be missed.
+            let mut request_done =
+                GattTree::check_connection(&self.dev_id)?.request_done_receiver();
+            match self.write_internal(value, false, None).await {
+                Err(e) if e.is_write_busy() => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let signalled = async { request_done.recv().await.is_ok() }
+                        .or(async {
+                            Delay::new(remaining).await;
+                            false
+                        })
+                        .await;
+                    if !signalled {
+                        return Err(e);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn check_write_without_response_len(&self, value: &[u8]) -> Result<()> {
         // NOTE: It is tested that writing *without response* may never cause an error from the Android API
         // even if the write length is horrible.
         //
@@ -126,7 +386,7 @@ impl Characteristic {
         // When performing a write request operation (write without response), the data sent is truncated
         // to the MTU size.
         if value.len() <= self.max_write_len()? {
-            self.write_internal(value, false).await
+            Ok(())
         } else {
             Err(crate::Error::new(
                 ErrorKind::InvalidParameter,
@@ -136,15 +396,75 @@ impl Characteristic {
         }
     }
 
-    async fn write_internal(&self, value: &[u8], with_response: bool) -> Result<()> {
+    /// Write `value` to this characteristic on the device like [Characteristic::write], but returns
+    /// an [ErrorKind::Other] error once `token` is cancelled.
+    pub async fn write_cancellable(&self, value: &[u8], token: &CancellationToken) -> Result<()> {
+        token.run(self.write(value)).await
+    }
+
+    async fn write_internal(
+        &self,
+        value: &[u8],
+        with_response: bool,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        let _sequence_guard = GattTree::check_connection(&self.dev_id)?
+            .sequence_guard()
+            .await;
+        let result = match self.write_in_sequence(value, with_response, deadline).await {
+            Err(e) if Device::rediscover_if_out_of_sync(&self.dev_id, &e).await => {
+                self.write_in_sequence(value, with_response, deadline).await
+            }
+            result => result,
+        };
+        Device::watch_bond_on_failure(&self.dev_id, result).await
+    }
+
+    /// Writes the value without taking the sequence lock of the connection, which should be
+    /// held by the caller.
+    pub(crate) async fn write_in_sequence(
+        &self,
+        value: &[u8],
+        with_response: bool,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        let write = self.write_in_sequence_untraced(value, with_response, deadline);
+        traced(
+            "write_characteristic",
+            &self.dev_id,
+            Some(self.char_id),
+            write,
+        )
+        .await
+    }
+
+    async fn write_in_sequence_untraced(
+        &self,
+        value: &[u8],
+        with_response: bool,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        const OPERATION: &str = "characteristic write";
         let conn = GattTree::check_connection(&self.dev_id)?;
+        conn.check_not_discovering()?;
         let inner = self.get_inner()?;
-        let _read_lock = inner.read.lock().await;
-        let write_lock = inner.write.lock().await;
+        let _read_lock = inner
+            .read
+            .lock_until(deadline)
+            .await
+            .ok_or_else(|| deadline_error(OPERATION))?;
+        // the value may be changed even if the write fails.
+        inner.read_cache.lock().unwrap().take();
+        let write_lock = inner
+            .write
+            .lock_until(deadline)
+            .await
+            .ok_or_else(|| deadline_error(OPERATION))?;
         jni_with_env(|env| {
             let gatt = conn.gatt.as_ref(env);
             let gatt = Monitor::new(&gatt);
-            let char = inner.char.as_ref(env);
+            let char = inner.char_obj();
+            let char = char.as_ref(env);
             let array = ByteArray::from_slice(env, value);
             let write_type = if with_response {
                 BluetoothGattCharacteristic::WRITE_TYPE_DEFAULT
@@ -167,10 +487,21 @@ impl Characteristic {
             }
         })?;
         drop((conn, inner));
-        write_lock
-            .wait_unlock()
-            .await
-            .ok_or_check_conn(&self.dev_id)?
+        match deadline {
+            Some(deadline) => write_lock.wait_unlock_until(deadline).await,
+            None => write_lock.wait_unlock().await,
+        }
+        .check_wait(&self.dev_id, OPERATION)?
+    }
+
+    /// Writes `value` to this characteristic with response, then reads the value back. No other
+    /// GATT operation of the same connection started by this library can happen between them.
+    pub async fn write_then_read(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let _sequence_guard = GattTree::check_connection(&self.dev_id)?
+            .exclusive_sequence_guard()
+            .await;
+        self.write_in_sequence(value, true, None).await?;
+        self.read_in_sequence(None).await
     }
 
     /// Get the maximum amount of data that can be written in a single packet for this characteristic.
@@ -185,6 +516,20 @@ impl Characteristic {
         Ok(mtu - 5)
     }
 
+    /// Like [Characteristic::max_write_len], but returns an [ErrorKind::NotReady] error instead of
+    /// falling back to the default MTU (23) if no MTU exchange has succeeded.
+    pub fn max_write_len_strict(&self) -> Result<usize> {
+        let conn = GattTree::check_connection(&self.dev_id)?;
+        match conn.mtu_changed_received.last_value() {
+            Some(mtu) if conn.mtu_negotiated.load(Ordering::SeqCst) => Ok(mtu - 5),
+            _ => Err(crate::Error::new(
+                ErrorKind::NotReady,
+                None,
+                "no MTU exchange has succeeded, see `AdapterConfig::request_mtu_on_connect`",
+            )),
+        }
+    }
+
     /// This method is kept for compatibility with `bluest`.
     pub async fn max_write_len_async(&self) -> Result<usize> {
         self.max_write_len()
@@ -193,20 +538,64 @@ impl Characteristic {
     /// Enables notification of value changes for this GATT characteristic.
     ///
     /// Returns a stream of values for the characteristic sent from the device.
+    ///
+    /// If [crate::AdapterConfig::verify_cccd_after_enable] is enabled, the CCCD is written and then read back
+    /// to verify the configuration when notification is enabled by the first subscriber; it is not verified
+    /// again for streams created while notification is already enabled.
     pub async fn notify(&self) -> Result<impl Stream<Item = Result<Vec<u8>>> + Send + Unpin + '_> {
+        self.notify_receiver().await
+    }
+
+    /// Enables notification of value changes like [Characteristic::notify], but the returned
+    /// stream keeps at most `capacity` values of its own, and `policy` decides what happens
+    /// when it is full, instead of [crate::AdapterConfig::notify_overflow].
+    ///
+    /// Returns an [ErrorKind::InvalidParameter] error if `capacity` is zero.
+    pub async fn notify_with(
+        &self,
+        policy: OverflowPolicy,
+        capacity: usize,
+    ) -> Result<impl Stream<Item = Result<Vec<u8>>> + Send + Unpin + '_> {
+        if capacity == 0 {
+            return Err(crate::Error::new(
+                ErrorKind::InvalidParameter,
+                None,
+                "the capacity of a notification stream must not be zero",
+            ));
+        }
+        let subscribe = self.notify_receiver_untraced(Some((capacity, policy)), false);
+        traced("subscribe", &self.dev_id, Some(self.char_id), subscribe).await
+    }
+
+    pub(crate) async fn notify_receiver(&self) -> Result<NotifierReceiver<Result<Vec<u8>>>> {
+        let subscribe = self.notify_receiver_untraced(None, false);
+        traced("subscribe", &self.dev_id, Some(self.char_id), subscribe).await
+    }
+
+    /// The CCCD is written (and verified if configured) if this subscription enables notification,
+    /// or if `write_cccd` is set.
+    async fn notify_receiver_untraced(
+        &self,
+        own_channel: Option<(usize, OverflowPolicy)>,
+        write_cccd: bool,
+    ) -> Result<NotifierReceiver<Result<Vec<u8>>>> {
         let conn = GattTree::check_connection(&self.dev_id)?;
+        let verify_cccd = conn.options.verify_cccd_after_enable;
         let inner = self.get_inner()?;
         let inner_2 = inner.clone();
-        let (gatt_for_stop, char_for_stop) = (conn.gatt.clone(), inner.char.clone());
-        inner
+        let (gatt_for_stop, char_for_stop) = (conn.gatt.clone(), inner.char_obj());
+        let started = AtomicBool::new(false);
+        let started_ref = &started;
+        let receiver = inner
             .notify
-            .subscribe(
+            .subscribe_with(
                 move || {
+                    started_ref.store(true, Ordering::Relaxed);
                     jni_with_env(|env| {
                         let gatt = conn.gatt.as_ref(env);
                         let gatt = Monitor::new(&gatt);
-                        let result =
-                            gatt.setCharacteristicNotification(inner_2.char.as_ref(env), true)?;
+                        let result = gatt
+                            .setCharacteristicNotification(inner_2.char_obj().as_ref(env), true)?;
                         result.non_false()
                     })
                 },
@@ -218,8 +607,152 @@ impl Characteristic {
                             gatt.setCharacteristicNotification(char_for_stop.as_ref(env), false);
                     })
                 },
+                own_channel,
             )
-            .await
+            .await?;
+        if write_cccd || (verify_cccd && started.load(Ordering::Relaxed)) {
+            if let Err(e) = self.enable_cccd(verify_cccd).await {
+                // dropping the only receiver calls `setCharacteristicNotification(false)`.
+                drop(receiver);
+                if !inner.notify.is_notifying() {
+                    if let Err(e) = self.disable_cccd().await {
+                        warn!("failed to disable the CCCD of {}: {e}", self.char_id);
+                    }
+                }
+                return Err(e);
+            }
+        }
+        Ok(receiver)
+    }
+
+    /// Subscribes like [Characteristic::notify_receiver], and makes sure the CCCD is written,
+    /// which is needed for restoring a subscription after reconnecting.
+    pub(crate) async fn notify_receiver_with_cccd(
+        &self,
+    ) -> Result<NotifierReceiver<Result<Vec<u8>>>> {
+        let subscribe = self.notify_receiver_untraced(None, true);
+        traced("subscribe", &self.dev_id, Some(self.char_id), subscribe).await
+    }
+
+    /// Writes the CCCD for enabling notification or indication, then reads it back for
+    /// verification if `verify` is set.
+    async fn enable_cccd(&self, verify: bool) -> Result<()> {
+        let properties = self.properties().await?;
+        let enable_bits: u8 = if properties.notify {
+            0x01
+        } else if properties.indicate {
+            0x02
+        } else {
+            return Err(crate::Error::new(
+                ErrorKind::NotSupported,
+                None,
+                "the characteristic supports neither notification nor indication",
+            ));
+        };
+        let cccd = self.cccd().await?;
+        cccd.write(&[enable_bits, 0x00]).await?;
+        if !verify {
+            return Ok(());
+        }
+        let value = cccd.read().await?;
+        if value.first().is_some_and(|&b| b & enable_bits != 0) {
+            Ok(())
+        } else {
+            Err(crate::Error::new(
+                ErrorKind::Other,
+                Some(AttError::CCCD_IMPROPERLY_CONFIGURED.into()),
+                format!("CCCD value is {value:02x?} after enabling"),
+            ))
+        }
+    }
+
+    /// Writes zero to the CCCD, which disables both notification and indication.
+    async fn disable_cccd(&self) -> Result<()> {
+        self.cccd().await?.write(&[0x00, 0x00]).await
+    }
+
+    async fn cccd(&self) -> Result<Descriptor> {
+        self.descriptors()
+            .await?
+            .into_iter()
+            .find(|desc| desc.uuid() == descriptors::CLIENT_CHARACTERISTIC_CONFIGURATION)
+            .ok_or_else(|| {
+                crate::Error::new(
+                    ErrorKind::Other,
+                    Some(AttError::CCCD_IMPROPERLY_CONFIGURED.into()),
+                    "the characteristic has no CCCD",
+                )
+            })
+    }
+
+    /// Enables notification of value changes like [Characteristic::notify], but the returned stream
+    /// also yields an [ErrorKind::Timeout] error whenever no notification arrives within `max_gap`.
+    ///
+    /// The stream is not ended by such an error; it forwards values again if the device resumes sending.
+    pub async fn notify_with_heartbeat(
+        &self,
+        max_gap: Duration,
+    ) -> Result<impl Stream<Item = Result<Vec<u8>>> + Send + Unpin + '_> {
+        let stream = self.notify().await?;
+        Ok(StreamIdleTimeout::new(stream, max_gap, move || {
+            Err(crate::Error::new(
+                ErrorKind::Timeout,
+                None,
+                format!("no notification received within {max_gap:?}"),
+            ))
+        }))
+    }
+
+    /// Enables notification like [Characteristic::notify], but keeps the subscription across
+    /// reconnections: the returned stream doesn't end on disconnection. After the device is
+    /// connected again (by the application or any reconnection utility), services are discovered
+    /// if needed and notification is enabled again, with the CCCD written; then
+    /// [NotifyEvent::Reconnected] is yielded, followed by new values.
+    ///
+    /// Subscriptions of the same device made by this method are restored in the order they were
+    /// made. If the subscription fails to be restored, the error is yielded, and it is tried
+    /// again after the next reconnection. The characteristic is found again by the UUIDs and
+    /// instance IDs of the service and the characteristic.
+    pub async fn notify_persistent(
+        &self,
+    ) -> Result<impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static> {
+        self.notify_persistent_inner(false).await
+    }
+
+    /// Like [Characteristic::notify_persistent], but also yields [NotifyEvent::Lost] when the
+    /// connection is dropped, so the application can show that the subscription is interrupted
+    /// until [NotifyEvent::Reconnected] is yielded.
+    ///
+    /// Note that Android doesn't report a reset of the CCCD by the peripheral itself; such a
+    /// subscription silently stops delivering values until the next reconnection.
+    pub async fn notify_with_status(
+        &self,
+    ) -> Result<impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static> {
+        self.notify_persistent_inner(true).await
+    }
+
+    async fn notify_persistent_inner(
+        &self,
+        report_lost: bool,
+    ) -> Result<impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static> {
+        // subscribes to connection events at first, so that no reconnection can be missed.
+        let events = GattTree::connection_events().await;
+        let receiver = self.notify_receiver().await?;
+        let device =
+            Device::from_connection(&self.dev_id, &GattTree::check_connection(&self.dev_id)?);
+        let key = PersistentKey {
+            service: self.service_id,
+            service_instance: self.service_instance,
+            characteristic: self.char_id,
+            instance_id: self.instance_id,
+        };
+        Ok(persistent_notify(
+            device,
+            key,
+            receiver,
+            events,
+            report_lost,
+        ))
     }
 
     /// Is the device currently sending notifications for this characteristic?
@@ -237,15 +770,60 @@ impl Characteristic {
         Ok(self
             .get_inner()?
             .descs
-            .keys()
-            .map(|id| Descriptor::new(self.dev_id.clone(), self.service_id, self.char_id, *id))
+            .iter()
+            .enumerate()
+            .map(|(index, desc)| {
+                Descriptor::new(
+                    self.dev_id.clone(),
+                    self.service_id,
+                    self.service_instance,
+                    self.char_id,
+                    self.instance_id,
+                    desc.uuid,
+                    index,
+                )
+            })
             .collect())
     }
 
+    /// See `Adapter::inject_notification`.
+    #[cfg(feature = "test-injection")]
+    pub(crate) fn inject_notification(&self, value: Vec<u8>) -> Result<()> {
+        self.get_inner()?.notify.notify(Ok(value));
+        Ok(())
+    }
+
     fn get_inner(&self) -> Result<Arc<CharacteristicInner>, crate::Error> {
-        self.inner.get_or_find(|| {
-            GattTree::find_characteristic(&self.dev_id, self.service_id, self.char_id)
+        self.inner.get_or_find(&self.dev_id, || {
+            GattTree::find_characteristic(&self.dev_id, self.service_instance, self.instance_id)
+                .filter(|ch| ch.uuid == self.char_id)
                 .ok_or_check_conn(&self.dev_id)
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn characteristics_of_same_uuid_are_distinct() {
+        let dev_id = DeviceId("00:00:00:00:00:01".into());
+        let (service, char) = (Uuid::from_u128(0x180f), Uuid::from_u128(0x2a19));
+        let make = |service_instance, instance_id| {
+            Characteristic::new(dev_id.clone(), service, service_instance, char, instance_id)
+        };
+        // duplicates in one service, and the same instance ID in another service.
+        let chars = [make(10, 11), make(10, 13), make(20, 11)];
+        let set: HashSet<_> = chars.iter().cloned().chain([make(10, 11)]).collect();
+        assert_eq!(set.len(), 3);
+        for (i, a) in chars.iter().enumerate() {
+            for (j, b) in chars.iter().enumerate() {
+                assert_eq!(i == j, a == b);
+            }
+        }
+        assert_eq!(chars[1].instance_id(), 13);
+    }
+}
@@ -2,10 +2,14 @@ use std::sync::Arc;
 
 use java_spaghetti::ByteArray;
 
+use super::characteristic::Characteristic;
+use super::device::Device;
 use super::error::ErrorKind;
 use super::gatt_tree::{CachedWeak, DescriptorInner, GattTree};
 use super::jni::{ByteArrayExt, Monitor};
-use super::util::{BoolExt, IntExt, OptionExt};
+use super::service::Service;
+use super::trace::traced;
+use super::util::{BoolExt, IntExt, OptionExt, WaitResultExt};
 use super::vm_context::{android_api_level, jni_with_env};
 use super::{DeviceId, Result, Uuid};
 
@@ -14,8 +18,11 @@ use super::{DeviceId, Result, Uuid};
 pub struct Descriptor {
     dev_id: DeviceId,
     service_id: Uuid,
+    service_instance: i32,
     char_id: Uuid,
+    char_instance: i32,
     desc_id: Uuid,
+    index: usize,
     inner: CachedWeak<DescriptorInner>,
 }
 
@@ -23,20 +30,46 @@ impl PartialEq for Descriptor {
     fn eq(&self, other: &Self) -> bool {
         self.dev_id == other.dev_id
             && self.service_id == other.service_id
+            && self.service_instance == other.service_instance
             && self.char_id == other.char_id
+            && self.char_instance == other.char_instance
             && self.desc_id == other.desc_id
+            && self.index == other.index
     }
 }
 
 impl Eq for Descriptor {}
 
+impl std::hash::Hash for Descriptor {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.dev_id.hash(state);
+        self.service_id.hash(state);
+        self.service_instance.hash(state);
+        self.char_id.hash(state);
+        self.char_instance.hash(state);
+        self.desc_id.hash(state);
+        self.index.hash(state);
+    }
+}
+
 impl Descriptor {
-    pub(crate) fn new(dev_id: DeviceId, service_id: Uuid, char_id: Uuid, desc_id: Uuid) -> Self {
+    pub(crate) fn new(
+        dev_id: DeviceId,
+        service_id: Uuid,
+        service_instance: i32,
+        char_id: Uuid,
+        char_instance: i32,
+        desc_id: Uuid,
+        index: usize,
+    ) -> Self {
         Self {
             dev_id,
             service_id,
+            service_instance,
             char_id,
+            char_instance,
             desc_id,
+            index,
             inner: CachedWeak::new(),
         }
     }
@@ -51,6 +84,28 @@ impl Descriptor {
         Ok(self.desc_id)
     }
 
+    /// The device of this descriptor. It is available even if the device is disconnected, so
+    /// device-level operations like [Device::is_connected] can be called on it.
+    pub fn device(&self) -> Result<Device> {
+        Device::from_known_id(&self.dev_id)
+    }
+
+    /// The characteristic containing this descriptor. This doesn't call any Java method.
+    pub fn characteristic(&self) -> Characteristic {
+        Characteristic::new(
+            self.dev_id.clone(),
+            self.service_id,
+            self.service_instance,
+            self.char_id,
+            self.char_instance,
+        )
+    }
+
+    /// The service containing this descriptor. This doesn't call any Java method.
+    pub fn service(&self) -> Service {
+        Service::new(self.dev_id.clone(), self.service_id, self.service_instance)
+    }
+
     /// The cached value of this descriptor. Returns an error if the value has not yet been read.
     pub async fn value(&self) -> Result<Vec<u8>> {
         self.get_inner()?
@@ -68,14 +123,35 @@ impl Descriptor {
 
     /// Read the value of this descriptor from the device.
     pub async fn read(&self) -> Result<Vec<u8>> {
+        let _sequence_guard = GattTree::check_connection(&self.dev_id)?
+            .sequence_guard()
+            .await;
+        let result = match self.read_in_sequence().await {
+            Err(e) if Device::rediscover_if_out_of_sync(&self.dev_id, &e).await => {
+                self.read_in_sequence().await
+            }
+            result => result,
+        };
+        Device::watch_bond_on_failure(&self.dev_id, result).await
+    }
+
+    /// Reads the value without taking the sequence lock of the connection, which should be
+    /// held by the caller.
+    pub(crate) async fn read_in_sequence(&self) -> Result<Vec<u8>> {
+        let read = self.read_in_sequence_untraced();
+        traced("read_descriptor", &self.dev_id, Some(self.desc_id), read).await
+    }
+
+    async fn read_in_sequence_untraced(&self) -> Result<Vec<u8>> {
         let conn = GattTree::check_connection(&self.dev_id)?;
+        conn.check_not_discovering()?;
         let inner = self.get_inner()?;
         let read_lock = inner.read.lock().await;
         let _write_lock = inner.write.lock().await;
         jni_with_env(|env| {
             let gatt = &conn.gatt.as_ref(env);
             let gatt = Monitor::new(gatt);
-            gatt.readDescriptor(inner.desc.as_ref(env))
+            gatt.readDescriptor(inner.desc_obj().as_ref(env))
                 .map_err(|e| e.into())
                 .and_then(|b| b.non_false())
         })?;
@@ -83,19 +159,41 @@ impl Descriptor {
         read_lock
             .wait_unlock()
             .await
-            .ok_or_check_conn(&self.dev_id)?
+            .check_wait(&self.dev_id, "descriptor read")?
     }
 
     /// Write the `value` to this descriptor on the device.
     pub async fn write(&self, value: &[u8]) -> Result<()> {
+        let _sequence_guard = GattTree::check_connection(&self.dev_id)?
+            .sequence_guard()
+            .await;
+        let result = match self.write_in_sequence(value).await {
+            Err(e) if Device::rediscover_if_out_of_sync(&self.dev_id, &e).await => {
+                self.write_in_sequence(value).await
+            }
+            result => result,
+        };
+        Device::watch_bond_on_failure(&self.dev_id, result).await
+    }
+
+    /// Writes the value without taking the sequence lock of the connection, which should be
+    /// held by the caller.
+    pub(crate) async fn write_in_sequence(&self, value: &[u8]) -> Result<()> {
+        let write = self.write_in_sequence_untraced(value);
+        traced("write_descriptor", &self.dev_id, Some(self.desc_id), write).await
+    }
+
+    async fn write_in_sequence_untraced(&self, value: &[u8]) -> Result<()> {
         let conn = GattTree::check_connection(&self.dev_id)?;
+        conn.check_not_discovering()?;
         let inner = self.get_inner()?;
         let _read_lock = inner.read.lock().await;
         let write_lock = inner.write.lock().await;
         jni_with_env(|env| {
             let gatt = conn.gatt.as_ref(env);
             let gatt = Monitor::new(&gatt);
-            let desc = inner.desc.as_ref(env);
+            let desc = inner.desc_obj();
+            let desc = desc.as_ref(env);
             let array = ByteArray::from_slice(env, value);
             if android_api_level() >= 33 {
                 gatt.writeDescriptor_BluetoothGattDescriptor_byte_array(desc, array)?
@@ -113,13 +211,65 @@ impl Descriptor {
         write_lock
             .wait_unlock()
             .await
-            .ok_or_check_conn(&self.dev_id)?
+            .check_wait(&self.dev_id, "descriptor write")?
+    }
+
+    /// Writes the `value` to this descriptor, then reads it back and compares it with `value`.
+    ///
+    /// Returns an [ErrorKind::Other] error including both values if they mismatch, which
+    /// indicates the device doesn't really accept the write.
+    pub async fn write_verify(&self, value: &[u8]) -> Result<()> {
+        self.write(value).await?;
+        let read_back = self.read().await?;
+        if read_back == value {
+            Ok(())
+        } else {
+            Err(crate::Error::new(
+                ErrorKind::Other,
+                None,
+                format!(
+                    "descriptor value mismatch: written {value:02x?}, read back {read_back:02x?}"
+                ),
+            ))
+        }
     }
 
     fn get_inner(&self) -> Result<Arc<DescriptorInner>, crate::Error> {
-        self.inner.get_or_find(|| {
-            GattTree::find_descriptor(&self.dev_id, self.service_id, self.char_id, self.desc_id)
-                .ok_or_check_conn(&self.dev_id)
+        self.inner.get_or_find(&self.dev_id, || {
+            GattTree::find_descriptor(
+                &self.dev_id,
+                self.service_instance,
+                self.char_instance,
+                self.index,
+            )
+            .filter(|desc| desc.uuid == self.desc_id)
+            .ok_or_check_conn(&self.dev_id)
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors_distinguished_by_index_and_parents() {
+        let dev_id = DeviceId("00:00:00:00:00:01".into());
+        let (service, char) = (Uuid::from_u128(0x180f), Uuid::from_u128(0x2a19));
+        let desc = Uuid::from_u128(0x2901);
+        let make = |char_instance, index| {
+            Descriptor::new(
+                dev_id.clone(),
+                service,
+                10,
+                char,
+                char_instance,
+                desc,
+                index,
+            )
+        };
+        assert_eq!(make(11, 0), make(11, 0));
+        assert_ne!(make(11, 0), make(11, 1));
+        assert_ne!(make(11, 0), make(13, 0));
+    }
+}
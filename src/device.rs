@@ -1,20 +1,34 @@
+use std::future::Future;
+use std::pin::pin;
+use std::ptr::null;
 use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use futures_core::Stream;
-use futures_lite::StreamExt;
+use futures_lite::{future, stream, FutureExt, StreamExt};
+use java_spaghetti::sys::jvalue;
 use java_spaghetti::Global;
 use log::info;
 use uuid::Uuid;
 
-use super::bindings::android::bluetooth::BluetoothDevice;
-use super::error::ErrorKind;
-use super::event_receiver::GlobalEvent;
+use super::bindings::android::bluetooth::{BluetoothAdapter, BluetoothDevice, BluetoothGatt};
+use super::bindings::java::lang::{String as JString, Throwable};
+use super::btuuid::{characteristics, services};
+use super::error::{AttError, ErrorKind};
+use super::event_receiver::{EventKind, GlobalEvent};
 use super::gatt_tree::{CachedWeak, GattConnection, GattTree};
 use super::jni::Monitor;
+use super::scan::ScanFilter;
 use super::service::Service;
-use super::util::{BoolExt, OptionExt};
-use super::vm_context::{android_api_level, jni_with_env};
-use super::{DeviceId, Result};
+use super::timer::Delay;
+use super::trace::traced;
+use super::transaction::Transaction;
+use super::util::{deadline_error, BoolExt, OptionExt, WaitResultExt};
+use super::vm_context::{android_api_level, jni_find_method, jni_with_env};
+use super::{
+    Adapter, ConnectionInfo, ConnectionParameters, ConnectionStats, DeviceId, DeviceSnapshot,
+    DisconnectReason, GattSnapshot, NotifyEvent, Phy, Result, RetryPolicy,
+};
 
 /// A Bluetooth LE device.
 #[derive(Clone)]
@@ -95,13 +109,33 @@ impl Device {
         })
     }
 
+    /// Checks if the link with this device is currently encrypted.
+    ///
+    /// This calls the hidden `BluetoothDevice.isEncrypted()` method; an error of [ErrorKind::NotSupported]
+    /// is returned if it is unavailable or blocked on the current system.
+    pub async fn is_encrypted(&self) -> Result<bool> {
+        jni_with_env(|env| {
+            let device = self.device.as_ref(env);
+            let Some(method) = jni_find_method(&device, "isEncrypted\0", "()Z\0") else {
+                return Err(crate::Error::new(
+                    ErrorKind::NotSupported,
+                    None,
+                    "`BluetoothDevice.isEncrypted()` is inaccessible",
+                ));
+            };
+            unsafe { env.call_boolean_method_a::<Throwable>(device.as_raw(), method, null()) }
+                .map_err(|e| e.into())
+        })
+    }
+
     /// Attempt to pair this device using the system default pairing UI.
     pub async fn pair(&self) -> Result<()> {
         let conn = self.get_connection()?;
+        conn.watch_bond(&self.id).await;
         let mut receiver = self
             .get_connection()?
             .global_event_receiver
-            .subscribe()
+            .subscribe(EventKind::Bond)
             .await?;
 
         let bond_state = jni_with_env(|env| {
@@ -152,8 +186,38 @@ impl Device {
 
     /// Discover the primary services of this device.
     pub async fn discover_services(&self) -> Result<Vec<Service>> {
+        self.discover_services_internal(None).await
+    }
+
+    /// Discover the primary services of this device like [Device::discover_services], but returns
+    /// an [ErrorKind::Timeout] error if it is not completed before `deadline`.
+    pub async fn discover_services_until(&self, deadline: Instant) -> Result<Vec<Service>> {
+        self.discover_services_internal(Some(deadline)).await
+    }
+
+    async fn discover_services_internal(&self, deadline: Option<Instant>) -> Result<Vec<Service>> {
+        let discover = self.discover_services_untraced(deadline);
+        traced("discover_services", &self.id, None, discover).await
+    }
+
+    async fn discover_services_untraced(&self, deadline: Option<Instant>) -> Result<Vec<Service>> {
+        let _sequence_guard = self.get_connection()?.sequence_guard().await;
+        self.discover_services_in_sequence(deadline).await
+    }
+
+    /// Discovers services without taking the sequence lock of the connection, which should be
+    /// held by the caller.
+    async fn discover_services_in_sequence(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<Service>> {
+        const OPERATION: &str = "service discovery";
         let conn = self.get_connection()?;
-        let disc_lock = conn.discover_services.lock().await;
+        let disc_lock = conn
+            .discover_services
+            .lock_until(deadline)
+            .await
+            .ok_or_else(|| deadline_error(OPERATION))?;
         jni_with_env(|env| {
             let gatt = conn.gatt.as_ref(env);
             let gatt = Monitor::new(&gatt);
@@ -161,26 +225,32 @@ impl Device {
             Ok::<_, crate::Error>(())
         })?;
         drop(conn);
-        disc_lock.wait_unlock().await.ok_or_check_conn(&self.id)??;
+        match deadline {
+            Some(deadline) => disc_lock.wait_unlock_until(deadline).await,
+            None => disc_lock.wait_unlock().await,
+        }
+        .check_wait(&self.id, OPERATION)??;
         self.collect_discovered_services()
     }
 
     /// Discover the primary service(s) of this device with the given [Uuid].
     pub async fn discover_services_with_uuid(&self, uuid: Uuid) -> Result<Vec<Service>> {
-        Ok(self
-            .discover_services()
-            .await?
+        let _ = self.discover_services().await?;
+        Ok(GattTree::find_services_by_uuid(&self.id, uuid)
             .into_iter()
-            .filter(|serv| serv.uuid() == uuid)
+            .map(|serv| Service::new(self.id.clone(), serv.uuid, serv.instance_id))
             .collect())
     }
 
     /// Get previously discovered services.
     ///
-    /// If no services have been discovered yet, this method will perform service discovery.
+    /// If no services have been discovered yet, or the previously discovered services are invalidated
+    /// by a service changed indication, this method will perform service discovery.
     pub async fn services(&self) -> Result<Vec<Service>> {
         let conn = self.get_connection()?;
-        if conn.discover_services.last_value().is_some() {
+        if matches!(conn.discover_services.last_value(), Some(Ok(())))
+            && !conn.services.lock().unwrap().is_empty()
+        {
             self.collect_discovered_services()
         } else {
             self.discover_services().await
@@ -188,44 +258,265 @@ impl Device {
     }
 
     fn collect_discovered_services(&self) -> Result<Vec<Service>> {
-        Ok(self
-            .get_connection()?
-            .services
-            .lock()
-            .unwrap()
-            .keys()
-            .map(|&service_id| Service::new(self.id.clone(), service_id))
+        let conn = self.get_connection()?;
+        let services = conn.services.lock().unwrap();
+        let mut services: Vec<_> = services.values().filter(|serv| serv.is_primary).collect();
+        services.sort_by_key(|serv| serv.instance_id);
+        Ok(services
+            .into_iter()
+            .map(|serv| Service::new(self.id.clone(), serv.uuid, serv.instance_id))
             .collect())
     }
 
+    /// Returns a copy of the currently discovered GATT tree for diagnostics.
+    ///
+    /// This reads only the cached tree without calling any Java method. It will be empty
+    /// if the services have not been discovered yet.
+    pub fn gatt_snapshot(&self) -> Result<GattSnapshot> {
+        Ok(GattSnapshot::from_connection(
+            &self.id,
+            &self.get_connection()?,
+        ))
+    }
+
+    /// Discovers services, then reads every readable characteristic and every descriptor of the
+    /// device, for bug reports or comparing the state of different firmware versions.
+    ///
+    /// Attributes are read one by one; other GATT operations started by this library on the
+    /// connection wait until all of them are read. A failed read is recorded in the snapshot
+    /// instead of failing the whole walk; characteristics without the read property are skipped.
+    pub async fn snapshot(&self) -> Result<DeviceSnapshot> {
+        self.discover_services().await?;
+        let tree = self.gatt_snapshot()?;
+        let _sequence_guard = self.get_connection()?.exclusive_sequence_guard().await;
+        Ok(DeviceSnapshot::read_in_sequence(self, tree).await)
+    }
+
+    /// Returns a snapshot of state values of the current connection.
+    ///
+    /// This reads only the values stored on this side without calling any Java method.
+    pub fn connection_info(&self) -> Result<ConnectionInfo> {
+        let conn = self.get_connection()?;
+        Ok(ConnectionInfo {
+            mtu: conn.mtu_changed_received.last_value(),
+            rssi: conn.read_rssi.last_value().and_then(|res| res.ok()),
+            connected_at: conn.connected_at.get().copied(),
+            phy: *conn.current_phy.lock().unwrap(),
+        })
+    }
+
+    /// Returns latencies of reads and writes on the current connection, for tuning the
+    /// connection priority and the MTU.
+    pub fn stats(&self) -> Result<ConnectionStats> {
+        Ok(self.get_connection()?.latency.stats())
+    }
+
     /// **(Experimental)** Monitors the device for service changed indications.
     ///
-    /// This requires Android API level 31 or higher.
+    /// On Android API level 31 or higher, this relies on `BluetoothGattCallback.onServiceChanged()`.
+    /// Below that, this subscribes to the Service Changed characteristic (0x2A05) of the Generic
+    /// Attribute service internally, and services are rediscovered on each indication; an error of
+    /// [ErrorKind::NotSupported] is returned if the device doesn't have that characteristic.
+    /// The internal subscription is shared with subscriptions made by the application.
     pub async fn service_changed_indications(
         &self,
     ) -> Result<impl Stream<Item = Result<ServicesChanged>> + Send + Unpin + '_> {
-        if android_api_level() < 31 {
-            return Err(crate::Error::new(
-                ErrorKind::NotSupported,
-                None,
-                "this requires BluetoothGattCallback.onServiceChanged() introduced in API level 31",
-            ));
-        }
         let receiver = self
             .get_connection()?
             .services_changes
             .subscribe(|| Ok::<_, crate::Error>(()), || ())
             .await?;
-        Ok(receiver.map(|_| {
+        let indications = if android_api_level() < 31 {
+            Some(
+                self.service_changed_characteristic()
+                    .await?
+                    .notify_receiver_with_cccd()
+                    .await?,
+            )
+        } else {
+            None
+        };
+        let changes = receiver.map(|diff| {
             Ok(ServicesChanged {
                 dev_id: self.id.clone(),
+                invalidated: diff.invalidated,
+                added: diff.added,
+                generation: diff.generation,
+                handle_range: diff.handle_range,
+            })
+        });
+        // the resulting `ServicesChanged` item is yielded by `changes` after the rediscovery.
+        let rediscoveries = stream::iter(indications)
+            .flatten()
+            .then(move |value| async move {
+                let range = value?;
+                if let [a, b, c, d] = range[..] {
+                    let (start, end) = (u16::from_le_bytes([a, b]), u16::from_le_bytes([c, d]));
+                    info!(
+                        "service changed indication of {}: handles {start:#06x}..={end:#06x}",
+                        self.id
+                    );
+                    self.get_connection()?
+                        .changed_handle_range
+                        .lock()
+                        .unwrap()
+                        .replace((start, end));
+                }
+                self.discover_services().await.map(|_| ())
             })
-        }))
+            .filter_map(|result| result.err().map(Err));
+        // ends with `changes`, which ends on disconnection.
+        Ok(Box::pin(stream::unfold(
+            (changes, Box::pin(rediscoveries)),
+            |(mut changes, mut rediscoveries)| async move {
+                let item = changes
+                    .next()
+                    .or(async {
+                        match rediscoveries.next().await {
+                            Some(item) => Some(item),
+                            None => future::pending().await,
+                        }
+                    })
+                    .await?;
+                Some((item, (changes, rediscoveries)))
+            },
+        )))
+    }
+
+    async fn service_changed_characteristic(&self) -> Result<crate::Characteristic> {
+        for service in self
+            .services()
+            .await?
+            .into_iter()
+            .filter(|serv| serv.uuid() == services::GENERIC_ATTRIBUTE)
+        {
+            let chars = service
+                .discover_characteristics_with_uuid(characteristics::SERVICE_CHANGED)
+                .await?;
+            if let Some(characteristic) = chars.into_iter().next() {
+                return Ok(characteristic);
+            }
+        }
+        Err(crate::Error::new(
+            ErrorKind::NotSupported,
+            None,
+            "the Service Changed characteristic is not found, and BluetoothGattCallback.onServiceChanged() requires API level 31",
+        ))
+    }
+
+    /// Enables notification of value changes for the characteristic specified by UUIDs like
+    /// [crate::Characteristic::notify], but the returned stream never ends until it is dropped.
+    ///
+    /// On disconnection, it reconnects to the device with `adapter` according to `policy`, then
+    /// discovers services and subscribes again with the CCCD written, yielding
+    /// [NotifyEvent::Reconnected]. If all attempts
+    /// of a round of retries fail, the last error is yielded before the next round begins.
+    pub fn resilient_notify(
+        &self,
+        adapter: &Adapter,
+        service: Uuid,
+        characteristic: Uuid,
+        policy: RetryPolicy,
+    ) -> impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static {
+        super::resilient::resilient_notify(adapter, self, service, characteristic, policy)
+    }
+
+    /// Scans for advertisements from this device with `adapter`, and returns when one is received.
+    ///
+    /// Returns an [ErrorKind::Timeout] error if the device is not found within `timeout`. This is useful
+    /// for avoiding a connection attempt to an absent device.
+    pub async fn wait_in_range(&self, adapter: &Adapter, timeout: Duration) -> Result<()> {
+        let filter = ScanFilter::new().device_id(self.id.clone());
+        let scan = async {
+            let mut stream = adapter.scan_with_filters(&[filter]).await?;
+            match stream.next().await {
+                Some(_) => Ok(()),
+                None => Err(crate::Error::new(
+                    ErrorKind::Other,
+                    None,
+                    "scanning stopped before the device is found",
+                )),
+            }
+        };
+        let timer = async {
+            Delay::new(timeout).await;
+            Err(crate::Error::new(
+                ErrorKind::Timeout,
+                None,
+                format!("device {} is not found within {timeout:?}", self.id),
+            ))
+        };
+        scan.or(timer).await
+    }
+
+    /// Waits until the device is connected and its services have been discovered at least once,
+    /// which is useful after [Adapter::connect_device_auto] with `auto_connect` set, whose
+    /// connection is made in the background. Services are discovered here if nobody else did.
+    ///
+    /// Returns an [ErrorKind::NotConnected] error if no connection with the device is pending or
+    /// the attempt fails, or an [ErrorKind::Timeout] error if it is not ready within `timeout`.
+    pub async fn await_ready(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let ready = async {
+            GattTree::wait_connection_available(&self.id, Some(timeout)).await?;
+            let conn = self.get_connection()?;
+            let discovered = matches!(conn.discover_services.last_value(), Some(Ok(())));
+            drop(conn);
+            if !discovered {
+                self.discover_services_until(deadline).await?;
+            }
+            Ok(())
+        };
+        let timer = async {
+            Delay::new(timeout).await;
+            Err(crate::Error::new(
+                ErrorKind::Timeout,
+                None,
+                format!("device {} is not ready within {timeout:?}", self.id),
+            ))
+        };
+        ready.or(timer).await
+    }
+
+    /// Waits until the connection with the device is dropped, and returns the reason.
+    ///
+    /// Android notices a device going out of range once the supervision timeout of the connection
+    /// (negotiated with the device, usually a few seconds) elapses without receiving any packet;
+    /// this resolves right after that, without issuing any GATT operation. It resolves with
+    /// [DisconnectReason::Unknown] immediately if the device is not connected.
+    pub fn wait_for_disconnect(&self) -> impl Future<Output = DisconnectReason> + Send + 'static {
+        let dev_id = self.id.clone();
+        let reason = self
+            .get_connection()
+            .ok()
+            .map(|conn| conn.disconnect_reason.clone());
+        async move {
+            let Some(reason) = reason else {
+                return DisconnectReason::Unknown;
+            };
+            let mut events = pin!(GattTree::connection_events().await);
+            loop {
+                if let Some(reason) = reason.get() {
+                    return *reason;
+                }
+                // the reason is set before the disconnection event is sent.
+                if events.next().await.is_none() {
+                    return DisconnectReason::Unknown;
+                }
+            }
+        }
+    }
+
+    /// Creates a [Transaction] builder for a sequence of GATT operations on this device which
+    /// must not be interrupted by operations from other tasks.
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction::new(self)
     }
 
     /// Get the current signal strength from the device in dBm.
     pub async fn rssi(&self) -> Result<i16> {
         let conn = self.get_connection()?;
+        let _sequence_guard = conn.sequence_guard().await;
         let read_rssi_lock = conn.read_rssi.lock().await;
         jni_with_env(|env| {
             let gatt = conn.gatt.as_ref(env);
@@ -237,7 +528,153 @@ impl Device {
         read_rssi_lock
             .wait_unlock()
             .await
-            .ok_or_check_conn(&self.id)?
+            .check_wait(&self.id, "RSSI read")?
+    }
+
+    /// Reads the current transmitter and receiver PHYs of the connection from the Bluetooth stack.
+    ///
+    /// This requires Android API level 26 or higher.
+    pub async fn read_phy(&self) -> Result<(Phy, Phy)> {
+        check_phy_api_level()?;
+        let conn = self.get_connection()?;
+        let _sequence_guard = conn.sequence_guard().await;
+        let read_phy_lock = conn.read_phy.lock().await;
+        jni_with_env(|env| {
+            let gatt = conn.gatt.as_ref(env);
+            let gatt = Monitor::new(&gatt);
+            gatt.readPhy()?;
+            Ok::<_, crate::Error>(())
+        })?;
+        drop(conn);
+        read_phy_lock
+            .wait_unlock()
+            .await
+            .check_wait(&self.id, "PHY read")?
+    }
+
+    /// Requests the Bluetooth stack to use the given transmitter and receiver PHYs for the connection.
+    /// The request may be ignored; use [Device::current_phy] to check the actual PHYs after the update.
+    ///
+    /// This requires Android API level 26 or higher.
+    pub fn set_preferred_phy(&self, tx_phy: Phy, rx_phy: Phy) -> Result<()> {
+        check_phy_api_level()?;
+        let conn = self.get_connection()?;
+        jni_with_env(|env| {
+            let gatt = conn.gatt.as_ref(env);
+            let gatt = Monitor::new(&gatt);
+            gatt.setPreferredPhy(
+                tx_phy.to_java_mask(),
+                rx_phy.to_java_mask(),
+                BluetoothDevice::PHY_OPTION_NO_PREFERRED,
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Returns the transmitter and receiver PHYs last reported by the Bluetooth stack, either from
+    /// [Device::read_phy] or from a PHY update. This doesn't call any Java method.
+    ///
+    /// Returns `None` if the device is not connected or the PHYs have never been reported.
+    pub fn current_phy(&self) -> Option<(Phy, Phy)> {
+        *self.get_connection().ok()?.current_phy.lock().unwrap()
+    }
+
+    /// Requests the exact connection parameters through the hidden
+    /// `BluetoothGatt.requestLeConnectionUpdate()`, and returns the parameters reported by the
+    /// Bluetooth stack once they match `params`. In the result, `min_interval` and `max_interval`
+    /// are both the actual interval.
+    ///
+    /// If the hidden method is inaccessible, or no matching update is reported within a few
+    /// seconds (e.g. the peripheral rejected the parameters), this falls back to
+    /// `BluetoothGatt.requestConnectionPriority()` with the preset closest to `params`;
+    /// if the update is not reported in that case, the nominal parameters of the preset are
+    /// returned.
+    pub async fn request_connection_update(
+        &self,
+        params: ConnectionParameters,
+    ) -> Result<ConnectionParameters> {
+        if params.min_interval > params.max_interval
+            || !(6..=3200).contains(&params.min_interval)
+            || !(6..=3200).contains(&params.max_interval)
+            || params.latency > 499
+            || !(10..=3200).contains(&params.supervision_timeout)
+        {
+            return Err(crate::Error::new(
+                ErrorKind::InvalidParameter,
+                None,
+                format!("invalid connection parameters {params:?}"),
+            ));
+        }
+        let conn = self.get_connection()?;
+        let update_lock = conn
+            .connection_update
+            .lock_with_timeout(CONNECTION_UPDATE_TIMEOUT)
+            .await;
+        // updates initiated by the peripheral or requested before are not the result.
+        update_lock.expect(move |result| {
+            result.as_ref().map_or(true, |reported| {
+                (params.min_interval..=params.max_interval).contains(&reported.min_interval)
+                    && reported.latency == params.latency
+                    && reported.supervision_timeout == params.supervision_timeout
+            })
+        });
+        let requested = jni_with_env(|env| {
+            let gatt = conn.gatt.as_ref(env);
+            let gatt = Monitor::new(&gatt);
+            // The variant without connection event lengths exists before API 26; the JVM only
+            // reads as many arguments as the signature declares.
+            let Some(method) = jni_find_method(&gatt, "requestLeConnectionUpdate\0", "(IIIIII)Z\0")
+                .or_else(|| jni_find_method(&gatt, "requestLeConnectionUpdate\0", "(IIII)Z\0"))
+            else {
+                return Ok::<_, crate::Error>(false);
+            };
+            let args = [
+                params.min_interval as i32,
+                params.max_interval as i32,
+                params.latency as i32,
+                params.supervision_timeout as i32,
+                0,
+                0,
+            ]
+            .map(|i| jvalue { i });
+            unsafe {
+                env.call_boolean_method_a::<Throwable>(gatt.as_raw(), method, args.as_ptr())
+            }?
+            .non_false()?;
+            Ok(true)
+        })?;
+        if requested {
+            match update_lock
+                .wait_unlock()
+                .await
+                .check_wait(&self.id, "connection update")
+            {
+                Err(e) if e.kind() == ErrorKind::Timeout => (),
+                result => return result?,
+            }
+        } else {
+            drop(update_lock);
+        }
+
+        let (priority, nominal) = connection_priority_for(&params);
+        let update_lock = conn
+            .connection_update
+            .lock_with_timeout(CONNECTION_UPDATE_TIMEOUT)
+            .await;
+        jni_with_env(|env| {
+            let gatt = conn.gatt.as_ref(env);
+            let gatt = Monitor::new(&gatt);
+            gatt.requestConnectionPriority(priority)?.non_false()
+        })?;
+        drop(conn);
+        match update_lock
+            .wait_unlock()
+            .await
+            .check_wait(&self.id, "connection update")
+        {
+            Err(e) if e.kind() == ErrorKind::Timeout => Ok(nominal),
+            result => result?,
+        }
     }
 
     /// Open an L2CAP connection-oriented channel (CoC) to this device.
@@ -247,31 +684,286 @@ impl Device {
         &self,
         psm: u16,
         secure: bool,
+    ) -> Result<super::l2cap_channel::L2capChannel> {
+        self.open_l2cap_channel_internal(psm, secure, None).await
+    }
+
+    /// Open an L2CAP connection-oriented channel (CoC) to this device like
+    /// [Device::open_l2cap_channel], but closes the socket and returns an [ErrorKind::Timeout]
+    /// error if it is not connected within `timeout`, e.g. when nothing listens on the PSM.
+    pub async fn open_l2cap_channel_timeout(
+        &self,
+        psm: u16,
+        secure: bool,
+        timeout: Duration,
+    ) -> Result<super::l2cap_channel::L2capChannel> {
+        self.open_l2cap_channel_internal(psm, secure, Some(timeout))
+            .await
+    }
+
+    /// Reads the PSM from the characteristic `psm_char` of the service `service` as a
+    /// little-endian `u16`, then opens an L2CAP channel on it like [Device::open_l2cap_channel].
+    ///
+    /// The device must be connected for reading the PSM characteristic.
+    pub async fn open_l2cap_from_characteristic(
+        &self,
+        service: Uuid,
+        psm_char: Uuid,
+        secure: bool,
+    ) -> Result<super::l2cap_channel::L2capChannel> {
+        let mut characteristic = None;
+        for service in self.discover_services_with_uuid(service).await? {
+            let chars = service.discover_characteristics_with_uuid(psm_char).await?;
+            if let Some(found) = chars.into_iter().next() {
+                characteristic.replace(found);
+                break;
+            }
+        }
+        let characteristic = characteristic.ok_or_else(|| {
+            crate::Error::new(
+                ErrorKind::NotFound,
+                None,
+                "the PSM characteristic is not found",
+            )
+        })?;
+        let value = characteristic.read().await?;
+        let psm = match value[..] {
+            [low, high, ..] => u16::from_le_bytes([low, high]),
+            _ => {
+                return Err(crate::Error::new(
+                    ErrorKind::Other,
+                    None,
+                    format!("invalid PSM characteristic value {value:02x?}"),
+                ))
+            }
+        };
+        info!("read L2CAP PSM {psm:#06x} from {psm_char} of {}", self.id);
+        self.open_l2cap_channel(psm, secure).await
+    }
+
+    async fn open_l2cap_channel_internal(
+        &self,
+        psm: u16,
+        secure: bool,
+        timeout: Option<Duration>,
     ) -> Result<super::l2cap_channel::L2capChannel> {
         use log::warn;
         if self.get_connection().is_ok() {
             warn!("trying to open L2CAP channel while there is a GATT connection.");
         }
         let (reader, writer) =
-            super::l2cap_channel::open_l2cap_channel(self.device.clone(), psm, secure)?;
+            super::l2cap_channel::open_l2cap_channel(self.device.clone(), psm, secure, timeout)
+                .await?;
         Ok(super::l2cap_channel::L2capChannel { reader, writer })
     }
 
+    /// Rediscovers services if `error` is [crate::Error::is_database_out_of_sync] and the
+    /// connection is configured with [crate::AdapterConfig::auto_rediscover_on_out_of_sync].
+    /// Returns true if the failed operation should be retried.
+    ///
+    /// The caller should hold the sequence lock of the connection.
+    pub(crate) async fn rediscover_if_out_of_sync(dev_id: &DeviceId, error: &crate::Error) -> bool {
+        if !error.is_database_out_of_sync() {
+            return false;
+        }
+        let Ok(conn) = GattTree::check_connection(dev_id) else {
+            return false;
+        };
+        if !conn.options.auto_rediscover_on_out_of_sync {
+            return false;
+        }
+        let device = Device::from_connection(dev_id, &conn);
+        drop(conn);
+        info!("rediscovering services of {dev_id} for the out-of-sync GATT database");
+        match device.discover_services_in_sequence(None).await {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("failed to rediscover services of {dev_id}: {e}");
+                false
+            }
+        }
+    }
+
+    /// Registers the bond state receiver of the connection if `result` failed for insufficient
+    /// authentication or encryption, after which Android may bond with the device. See
+    /// `GattTree::on_bond_lost`.
+    pub(crate) async fn watch_bond_on_failure<T>(
+        dev_id: &DeviceId,
+        result: Result<T>,
+    ) -> Result<T> {
+        let Err(e) = &result else {
+            return result;
+        };
+        let ErrorKind::Protocol(att_error) = e.kind() else {
+            return result;
+        };
+        let needs_bonding = [
+            AttError::INSUFFICIENT_AUTHENTICATION,
+            AttError::INSUFFICIENT_ENCRYPTION,
+            AttError::INSUFFICIENT_ENCRYPTION_KEY_SIZE,
+        ];
+        if needs_bonding.contains(&att_error) {
+            if let Ok(conn) = GattTree::check_connection(dev_id) {
+                conn.watch_bond(dev_id).await;
+            }
+        }
+        result
+    }
+
+    /// Creates a `Device` for the device of `conn`, which keeps working after reconnection.
+    pub(crate) fn from_connection(dev_id: &DeviceId, conn: &GattConnection) -> Self {
+        Device {
+            id: dev_id.clone(),
+            device: conn.device.clone(),
+            connection: CachedWeak::new(),
+            once_connected: Arc::new(OnceLock::from(())),
+        }
+    }
+
+    /// Creates a `Device` for the device `dev_id`, which has been connected by this library;
+    /// it doesn't need to be connected currently. If the `BluetoothDevice` is no longer kept
+    /// by the library, it is obtained from `BluetoothAdapter.getRemoteDevice()`.
+    pub(crate) fn from_known_id(dev_id: &DeviceId) -> Result<Self> {
+        if let Ok(conn) = GattTree::check_connection(dev_id) {
+            let mut device = Self::from_connection(dev_id, &conn);
+            device.connection = CachedWeak::from_arc(dev_id, &conn);
+            return Ok(device);
+        }
+        let device = match GattTree::known_device(dev_id) {
+            Some(device) => device,
+            None => remote_device(dev_id)?,
+        };
+        Ok(Device {
+            id: dev_id.clone(),
+            device,
+            connection: CachedWeak::new(),
+            once_connected: Arc::new(OnceLock::from(())),
+        })
+    }
+
     pub(crate) fn get_connection(&self) -> Result<Arc<GattConnection>, crate::Error> {
         self.connection
-            .get_or_find(|| GattTree::check_connection(&self.id))
+            .get_or_find(&self.id, || GattTree::check_connection(&self.id))
     }
 }
 
-/// A services changed notification.
+/// Gets the `BluetoothDevice` of `dev_id` from the default `BluetoothAdapter`.
+fn remote_device(dev_id: &DeviceId) -> Result<Global<BluetoothDevice>> {
+    jni_with_env(|env| {
+        #[allow(deprecated)]
+        let adapter = BluetoothAdapter::getDefaultAdapter(env)?.non_null()?;
+        let device = adapter
+            .getRemoteDevice_String(JString::from_env_str(env, &dev_id.0))?
+            .non_null()?;
+        Ok(device.as_global())
+    })
+}
+
+/// A services changed notification, carrying a snapshot of the changes made by the rediscovery.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ServicesChanged {
-    dev_id: DeviceId, // XXX: this is not enough for a unique hash value
+    dev_id: DeviceId,
+    invalidated: Vec<(Uuid, i32)>,
+    added: Vec<(Uuid, i32)>,
+    generation: u64,
+    handle_range: Option<(u16, u16)>,
 }
 
 impl ServicesChanged {
-    /// Check if `service` is currently invalidated.
+    /// Check if `service` was invalidated by this change. The answer comes from the snapshot
+    /// taken by the rediscovery, so it doesn't depend on later changes of the GATT tree.
     pub fn was_invalidated(&self, service: &Service) -> bool {
-        GattTree::find_service(&self.dev_id, service.uuid()).is_none()
+        self.invalidated_services().contains(service)
+    }
+
+    /// Generation of the service tree built by the rediscovery. It increases monotonically for
+    /// the device, so a handler can ignore events older than the last one it handled.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The attribute handle range (start and end, inclusive) affected by the change, if it is
+    /// carried by a Service Changed indication received below API level 31.
+    pub fn handle_range(&self) -> Option<(u16, u16)> {
+        self.handle_range
+    }
+
+    /// Services that were removed or changed by the rediscovery. Notification subscriptions and cached
+    /// values of their characteristics are lost; handles of unchanged characteristics, which fail with
+    /// [ErrorKind::ServiceChanged] during the rediscovery, are valid again.
+    pub fn invalidated_services(&self) -> Vec<Service> {
+        self.invalidated
+            .iter()
+            .map(|&(uuid, instance_id)| Service::new(self.dev_id.clone(), uuid, instance_id))
+            .collect()
+    }
+
+    /// Services that were newly added by the rediscovery.
+    pub fn added_services(&self) -> Vec<Service> {
+        self.added
+            .iter()
+            .map(|&(uuid, instance_id)| Service::new(self.dev_id.clone(), uuid, instance_id))
+            .collect()
+    }
+}
+
+/// How long to wait for the connection update to be reported, before falling back to
+/// `BluetoothGatt.requestConnectionPriority()` or returning the nominal parameters.
+const CONNECTION_UPDATE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Maps `params` to the closest `BluetoothGatt.CONNECTION_PRIORITY_*` preset, and returns it
+/// with the nominal parameters of the preset in AOSP.
+fn connection_priority_for(params: &ConnectionParameters) -> (i32, ConnectionParameters) {
+    if params.max_interval <= 24 {
+        (
+            BluetoothGatt::CONNECTION_PRIORITY_HIGH,
+            ConnectionParameters::new(9, 12, 0, 500),
+        )
+    } else if params.min_interval >= 80 {
+        (
+            BluetoothGatt::CONNECTION_PRIORITY_LOW_POWER,
+            ConnectionParameters::new(80, 100, 2, 500),
+        )
+    } else {
+        (
+            BluetoothGatt::CONNECTION_PRIORITY_BALANCED,
+            ConnectionParameters::new(24, 40, 0, 500),
+        )
+    }
+}
+
+fn check_phy_api_level() -> Result<()> {
+    if android_api_level() < 26 {
+        return Err(crate::Error::new(
+            ErrorKind::NotSupported,
+            None,
+            "PHY operations require API level 26",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services_changed_by_instance() {
+        let dev_id = DeviceId("00:00:00:00:00:01".into());
+        let uuid = Uuid::from_u128(0x180f);
+        let changed = ServicesChanged {
+            dev_id: dev_id.clone(),
+            invalidated: vec![(uuid, 10)],
+            added: vec![(uuid, 30)],
+            generation: 1,
+            handle_range: None,
+        };
+        assert!(changed.was_invalidated(&Service::new(dev_id.clone(), uuid, 10)));
+        // another service of the same UUID is kept.
+        assert!(!changed.was_invalidated(&Service::new(dev_id.clone(), uuid, 20)));
+        assert_eq!(
+            changed.added_services(),
+            vec![Service::new(dev_id, uuid, 30)]
+        );
     }
 }
@@ -1,12 +1,15 @@
 //! Defines error types.
 
 use std::fmt::Debug;
+use std::io;
 use std::num::NonZeroI32;
+use std::sync::Arc;
 
 use super::bindings::{android::bluetooth::BluetoothStatusCodes, java::lang::Throwable};
 use super::vm_context::jni_with_env;
 
 /// Internal error type, not compatible with `bluest::Error`.
+#[non_exhaustive]
 #[derive(Clone)]
 pub enum NativeError {
     GattError(AttError),
@@ -15,6 +18,7 @@ pub enum NativeError {
     JavaCastError,
     JavaNullResult,
     JavaCallReturnedFalse,
+    Io(Arc<io::Error>),
 }
 
 impl std::error::Error for NativeError {}
@@ -31,6 +35,7 @@ impl std::fmt::Debug for NativeError {
             Self::JavaCastError => write!(f, "JavaCastError"),
             Self::JavaNullResult => write!(f, "JavaNullResult"),
             Self::JavaCallReturnedFalse => write!(f, "JavaCallReturnedFalse"),
+            Self::Io(err) => write!(f, "Io({err:?})"),
         }
     }
 }
@@ -47,6 +52,7 @@ impl std::fmt::Display for NativeError {
             Self::JavaCastError => write!(f, "Java object cast failed"),
             Self::JavaNullResult => write!(f, "Java call unexpectedly returned null"),
             Self::JavaCallReturnedFalse => write!(f, "Java call unexpectedly returned false"),
+            Self::Io(err) => write!(f, "I/O error: {err}"),
         }
     }
 }
@@ -87,6 +93,24 @@ impl From<AttError> for crate::Error {
     }
 }
 
+/// The original error is kept as the source of the I/O error.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match (&err.source, err.kind) {
+            (Some(NativeError::Io(io_err)), _) => io_err.kind(),
+            (_, ErrorKind::Timeout) => io::ErrorKind::TimedOut,
+            (_, ErrorKind::NotConnected) => io::ErrorKind::NotConnected,
+            (_, ErrorKind::ConnectionFailed) => io::ErrorKind::ConnectionRefused,
+            (_, ErrorKind::NotAuthorized) => io::ErrorKind::PermissionDenied,
+            (_, ErrorKind::NotFound) => io::ErrorKind::NotFound,
+            (_, ErrorKind::InvalidParameter) => io::ErrorKind::InvalidInput,
+            (_, ErrorKind::NotSupported) => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
         Error {
@@ -118,6 +142,20 @@ impl From<NativeError> for Error {
             | NativeError::JavaCastError
             | NativeError::JavaNullResult
             | NativeError::JavaCallReturnedFalse => ErrorKind::Internal,
+            NativeError::Io(err) => match err.kind() {
+                io::ErrorKind::TimedOut => ErrorKind::Timeout,
+                io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::UnexpectedEof => ErrorKind::NotConnected,
+                io::ErrorKind::ConnectionRefused => ErrorKind::ConnectionFailed,
+                io::ErrorKind::PermissionDenied => ErrorKind::NotAuthorized,
+                io::ErrorKind::NotFound => ErrorKind::NotFound,
+                io::ErrorKind::InvalidInput => ErrorKind::InvalidParameter,
+                io::ErrorKind::Unsupported => ErrorKind::NotSupported,
+                _ => ErrorKind::Other,
+            },
         };
         let msg = err.to_string();
         Error::new(kind, Some(err), msg)
@@ -211,6 +249,12 @@ impl Error {
         }
     }
 
+    /// Converts an I/O error, e.g. of an [crate::L2capChannel], keeping it as the source. The
+    /// kind is mapped from [io::ErrorKind], like [io::ErrorKind::TimedOut] to [ErrorKind::Timeout].
+    pub fn from_io(err: io::Error) -> Self {
+        NativeError::Io(Arc::new(err)).into()
+    }
+
     /// Returns the corresponding [`ErrorKind`] for this error.
     pub fn kind(&self) -> ErrorKind {
         self.kind
@@ -220,6 +264,26 @@ impl Error {
     pub fn message(&self) -> &str {
         &self.message
     }
+
+    /// Checks if the operation failed with the ATT error `DATABASE_OUT_OF_SYNC` (0x12), which means
+    /// the GATT database cached locally is stale and services should be rediscovered.
+    ///
+    /// See [crate::AdapterConfig::auto_rediscover_on_out_of_sync].
+    pub fn is_database_out_of_sync(&self) -> bool {
+        self.kind == ErrorKind::Protocol(AttError::DATABASE_OUT_OF_SYNC)
+    }
+
+    /// Checks if a write is rejected because the Bluetooth stack is busy, which is only told by
+    /// `ERROR_GATT_WRITE_REQUEST_BUSY` on API level 33 and above; below that, `writeCharacteristic`
+    /// returns false for any failure.
+    pub(crate) fn is_write_busy(&self) -> bool {
+        matches!(
+            self.source,
+            Some(NativeError::BluetoothStatusCode(
+                BluetoothStatusCode::GattWriteBusy
+            ))
+        )
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -242,6 +306,9 @@ impl std::error::Error for Error {
 }
 
 /// A list of general categories of Bluetooth error.
+///
+/// The variants correspond one-to-one to those of `bluest::error::ErrorKind`. Conversions are
+/// not provided by this crate, because the Android backend of `bluest` depends on it.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ErrorKind {
@@ -414,3 +481,51 @@ impl From<AttError> for u8 {
         val.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IO_KIND_TABLE: &[(ErrorKind, io::ErrorKind)] = &[
+        (ErrorKind::Timeout, io::ErrorKind::TimedOut),
+        (ErrorKind::NotConnected, io::ErrorKind::NotConnected),
+        (
+            ErrorKind::ConnectionFailed,
+            io::ErrorKind::ConnectionRefused,
+        ),
+        (ErrorKind::NotAuthorized, io::ErrorKind::PermissionDenied),
+        (ErrorKind::NotFound, io::ErrorKind::NotFound),
+        (ErrorKind::InvalidParameter, io::ErrorKind::InvalidInput),
+        (ErrorKind::NotSupported, io::ErrorKind::Unsupported),
+        (ErrorKind::Internal, io::ErrorKind::Other),
+        (ErrorKind::NotReady, io::ErrorKind::Other),
+        (ErrorKind::Other, io::ErrorKind::Other),
+    ];
+
+    #[test]
+    fn error_to_io_error() {
+        for &(kind, io_kind) in IO_KIND_TABLE {
+            let err = io::Error::from(Error::new(kind, None, "test"));
+            assert_eq!(err.kind(), io_kind, "{kind:?}");
+            let source = err.get_ref().unwrap().downcast_ref::<Error>().unwrap();
+            assert_eq!(source.kind(), kind);
+            assert_eq!(source.message(), "test");
+        }
+    }
+
+    #[test]
+    fn io_error_round_trip() {
+        for &(kind, io_kind) in IO_KIND_TABLE {
+            if io_kind == io::ErrorKind::Other {
+                continue;
+            }
+            let err = Error::from_io(io::Error::new(io_kind, "test"));
+            assert_eq!(err.kind(), kind, "{io_kind:?}");
+            // the kind of the original I/O error is kept.
+            assert_eq!(io::Error::from(err).kind(), io_kind);
+        }
+        let err = Error::from_io(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::BrokenPipe);
+    }
+}
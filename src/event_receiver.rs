@@ -1,4 +1,4 @@
-use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::sync::{Arc, Mutex};
 
 use java_spaghetti::{Env, Global, Ref};
 use log::{error, info};
@@ -7,9 +7,10 @@ use super::async_util::{Notifier, NotifierReceiver};
 use super::bindings::android::bluetooth::{BluetoothAdapter, BluetoothDevice};
 use super::bindings::android::content::{BroadcastReceiver, Context, Intent, IntentFilter};
 use super::bindings::java::lang::{Class, String as JString};
+use super::callback_log::log_callback;
 use super::gatt_tree::GattTree;
 use super::vm_context::{android_api_level, android_context, jni_with_env};
-use super::{util::OptionExt, DeviceId};
+use super::{util::OptionExt, AdapterState, BondState, DeviceId, OverflowPolicy, SystemEvent};
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug)]
@@ -19,76 +20,149 @@ pub enum GlobalEvent {
     /// `Adapter::scan` should return when this event is received
     DiscoveryFinished,
     /// contains device address
-    #[allow(unused)] // NOTE: this may not be received; this can be removed.
     AclConnectionStateChanged(DeviceId, bool),
     /// contains device address, EXTRA_PREVIOUS_BOND_STATE, and EXTRA_BOND_STATE
     BondStateChanged(DeviceId, i32, i32),
+    /// contains device address, EXTRA_PAIRING_VARIANT, and EXTRA_PAIRING_KEY if present
+    PairingRequest(DeviceId, i32, Option<i32>),
+    /// contains EXTRA_SCAN_MODE
+    ScanModeChanged(i32),
 }
 
-static GLOBAL_RECEIVER: Mutex<Weak<EventReceiver>> = Mutex::new(Weak::new());
+/// Groups of intent actions; each group is registered with its own `BroadcastReceiver` while
+/// it has at least one subscriber, so unused groups never show up in the app's receivers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// [GlobalEvent::AdapterStateChanged]
+    AdapterState,
+    /// [GlobalEvent::DiscoveryFinished]
+    Discovery,
+    /// [GlobalEvent::AclConnectionStateChanged]
+    Acl,
+    /// [GlobalEvent::BondStateChanged]
+    Bond,
+    /// [GlobalEvent::PairingRequest]
+    Pairing,
+    /// [GlobalEvent::ScanModeChanged]
+    ScanMode,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 6] = [
+        EventKind::AdapterState,
+        EventKind::Discovery,
+        EventKind::Acl,
+        EventKind::Bond,
+        EventKind::Pairing,
+        EventKind::ScanMode,
+    ];
+
+    fn actions(self) -> &'static [&'static str] {
+        match self {
+            EventKind::AdapterState => &[BluetoothAdapter::ACTION_STATE_CHANGED],
+            EventKind::Discovery => &[BluetoothAdapter::ACTION_DISCOVERY_FINISHED],
+            EventKind::Acl => &[
+                BluetoothDevice::ACTION_ACL_CONNECTED,
+                BluetoothDevice::ACTION_ACL_DISCONNECTED,
+            ],
+            EventKind::Bond => &[BluetoothDevice::ACTION_BOND_STATE_CHANGED],
+            EventKind::Pairing => &[BluetoothDevice::ACTION_PAIRING_REQUEST],
+            EventKind::ScanMode => &[BluetoothAdapter::ACTION_SCAN_MODE_CHANGED],
+        }
+    }
+}
+
+// It is kept for the whole process, so that subscriptions don't depend on the `Adapter`
+// which has created it. Java receivers are only registered while being subscribed.
+static GLOBAL_RECEIVER: Mutex<Option<Arc<EventReceiver>>> = Mutex::new(None);
 
 pub struct EventReceiver {
-    notifier: Notifier<GlobalEvent>,
-    java_receiver: OnceLock<Global<BroadcastReceiver>>,
+    channels: Vec<EventChannel>,
+}
+
+struct EventChannel {
+    kind: EventKind,
+    notifier: Arc<Notifier<GlobalEvent>>,
+    java_receiver: Global<BroadcastReceiver>,
 }
 
 impl EventReceiver {
     pub fn build() -> Result<Arc<Self>, crate::Error> {
         let mut global_rec = GLOBAL_RECEIVER.lock().unwrap();
-        if let Some(rec) = global_rec.upgrade() {
-            return Ok(rec);
+        if let Some(rec) = global_rec.as_ref() {
+            return Ok(rec.clone());
         }
-        let event_receiver = Arc::new(Self {
-            notifier: Notifier::new(128),
-            java_receiver: OnceLock::new(),
-        });
-        let event_receiver_weak = Arc::downgrade(&event_receiver);
-        let proxy = Arc::new(BroadcastReceiverProxy {
-            rec_hdl: event_receiver_weak,
-        });
-        let java_receiver = jni_with_env(|env| {
-            Ok::<_, crate::Error>(BroadcastReceiver::new_proxy(env, proxy)?.as_global())
+        let channels = jni_with_env(|env| {
+            let mut channels = Vec::with_capacity(EventKind::ALL.len());
+            for kind in EventKind::ALL {
+                let notifier = Arc::new(Notifier::new(128, OverflowPolicy::DropOldest));
+                let proxy = Arc::new(BroadcastReceiverProxy {
+                    notifier: notifier.clone(),
+                });
+                channels.push(EventChannel {
+                    kind,
+                    notifier,
+                    java_receiver: BroadcastReceiver::new_proxy(env, proxy)?.as_global(),
+                });
+            }
+            Ok::<_, crate::Error>(channels)
         })?;
-        let _ = event_receiver.java_receiver.set(java_receiver);
-        *global_rec = Arc::downgrade(&event_receiver);
+        let event_receiver = Arc::new(Self { channels });
+        global_rec.replace(event_receiver.clone());
         Ok(event_receiver)
     }
 
-    pub async fn subscribe(&self) -> Result<NotifierReceiver<GlobalEvent>, crate::Error> {
-        let java_receiver = self.java_receiver.get().unwrap().clone();
-        let java_receiver_2 = self.java_receiver.get().unwrap().clone();
-        self.notifier
+    /// Ends all subscriptions and unregisters all Java receivers. Later subscriptions register
+    /// them again.
+    pub fn shutdown(&self) {
+        for channel in &self.channels {
+            channel.notifier.close();
+        }
+    }
+
+    /// Receives events of `kind`. The corresponding Java receiver is registered on the first
+    /// subscription, and unregistered after the last `NotifierReceiver` of `kind` is dropped.
+    pub async fn subscribe(
+        &self,
+        kind: EventKind,
+    ) -> Result<NotifierReceiver<GlobalEvent>, crate::Error> {
+        let channel = self.channels.iter().find(|ch| ch.kind == kind).unwrap();
+        let java_receiver = channel.java_receiver.clone();
+        let java_receiver_2 = channel.java_receiver.clone();
+        channel
+            .notifier
             .subscribe(
                 move || {
                     jni_with_env(|env| {
                         let filter = IntentFilter::new(env)?;
-                        for action in [
-                            BluetoothAdapter::ACTION_STATE_CHANGED,
-                            BluetoothAdapter::ACTION_DISCOVERY_FINISHED,
-                            BluetoothDevice::ACTION_ACL_CONNECTED,
-                            BluetoothDevice::ACTION_ACL_DISCONNECTED,
-                            BluetoothDevice::ACTION_BOND_STATE_CHANGED,
-                        ] {
+                        for action in kind.actions() {
                             let action_jstring = JString::from_env_str(env, action);
                             filter.addAction(&action_jstring)?;
                         }
-                        info!("registering the global bluetooth event broadcast receiver.");
+                        info!("registering the broadcast receiver for {kind:?} events.");
                         android_context()
                             .as_ref(env)
                             .registerReceiver_BroadcastReceiver_IntentFilter(
                                 java_receiver.as_ref(env),
                                 &filter,
                             )
-                            .map_err(|e| e.into())
+                            .map_err(|e| {
+                                error!("failed to register the receiver for {kind:?} events");
+                                e.into()
+                            })
                             .map(|_| ())
                     })
                 },
                 move || {
                     jni_with_env(|env| {
-                        info!("deregistering the global bluetooth event broadcast receiver.");
-                        let _ = android_context()
+                        info!("deregistering the broadcast receiver for {kind:?} events.");
+                        if let Err(e) = android_context()
                             .as_ref(env)
-                            .unregisterReceiver(java_receiver_2.as_ref(env));
+                            .unregisterReceiver(java_receiver_2.as_ref(env))
+                        {
+                            let e: crate::Error = e.into();
+                            error!("failed to deregister the receiver for {kind:?} events: {e}");
+                        }
                     })
                 },
             )
@@ -96,8 +170,36 @@ impl EventReceiver {
     }
 }
 
+impl GlobalEvent {
+    /// Converts to the public event type; events of unknown states are skipped.
+    pub fn to_system_event(&self) -> Option<SystemEvent> {
+        Some(match self {
+            GlobalEvent::AdapterStateChanged(val) => {
+                SystemEvent::AdapterStateChanged(AdapterState::from_java(*val)?)
+            }
+            GlobalEvent::AclConnectionStateChanged(dev_id, true) => {
+                SystemEvent::AclConnected(dev_id.clone())
+            }
+            GlobalEvent::AclConnectionStateChanged(dev_id, false) => {
+                SystemEvent::AclDisconnected(dev_id.clone())
+            }
+            GlobalEvent::BondStateChanged(dev_id, prev_st, st) => SystemEvent::BondStateChanged {
+                device: dev_id.clone(),
+                previous: BondState::from_java(*prev_st)?,
+                current: BondState::from_java(*st)?,
+            },
+            GlobalEvent::PairingRequest(dev_id, variant, key) => SystemEvent::PairingRequest {
+                device: dev_id.clone(),
+                variant: *variant,
+                key: *key,
+            },
+            GlobalEvent::DiscoveryFinished | GlobalEvent::ScanModeChanged(_) => return None,
+        })
+    }
+}
+
 struct BroadcastReceiverProxy {
-    rec_hdl: Weak<EventReceiver>,
+    notifier: Arc<Notifier<GlobalEvent>>,
 }
 
 impl super::callback::BroadcastReceiverProxy for BroadcastReceiverProxy {
@@ -108,9 +210,12 @@ impl super::callback::BroadcastReceiverProxy for BroadcastReceiverProxy {
         _context: Option<Ref<'env, Context>>,
         intent: Option<Ref<'env, Intent>>,
     ) {
-        let Some(rec_hdl) = self.rec_hdl.upgrade() else {
-            return;
-        };
+        log_callback!(
+            "BroadcastReceiver.onReceive: {:?}",
+            intent
+                .as_ref()
+                .and_then(|intent| Some(intent.toString().ok()??.to_string_lossy()))
+        );
         let Some(intent) = intent else {
             return;
         };
@@ -131,13 +236,11 @@ impl super::callback::BroadcastReceiverProxy for BroadcastReceiverProxy {
                         info!("deregistered all connections in BroadcastReceiverProxy");
                     }
                 }
-                rec_hdl
-                    .notifier
-                    .notify(GlobalEvent::AdapterStateChanged(val));
+                self.notifier.notify(GlobalEvent::AdapterStateChanged(val));
                 Ok::<_, crate::Error>(())
             }
             BluetoothAdapter::ACTION_DISCOVERY_FINISHED => {
-                rec_hdl.notifier.notify(GlobalEvent::DiscoveryFinished);
+                self.notifier.notify(GlobalEvent::DiscoveryFinished);
                 Ok::<_, crate::Error>(())
             }
             BluetoothDevice::ACTION_ACL_CONNECTED => {
@@ -145,8 +248,7 @@ impl super::callback::BroadcastReceiverProxy for BroadcastReceiverProxy {
                 let transport = intent.getIntExtra(&extra_transport, 0)?;
                 if transport == BluetoothDevice::TRANSPORT_LE {
                     let dev_id = get_extra_device_id(&intent)?;
-                    rec_hdl
-                        .notifier
+                    self.notifier
                         .notify(GlobalEvent::AclConnectionStateChanged(dev_id, true));
                 }
                 Ok(())
@@ -159,8 +261,7 @@ impl super::callback::BroadcastReceiverProxy for BroadcastReceiverProxy {
                     if GattTree::deregister_connection(&dev_id) {
                         info!("deregistered connection with {dev_id} in BroadcastReceiverProxy");
                     }
-                    rec_hdl
-                        .notifier
+                    self.notifier
                         .notify(GlobalEvent::AclConnectionStateChanged(dev_id, false));
                 }
                 Ok(())
@@ -173,13 +274,38 @@ impl super::callback::BroadcastReceiverProxy for BroadcastReceiverProxy {
                 let extra_bond_state =
                     JString::from_env_str(env, BluetoothDevice::EXTRA_BOND_STATE);
                 let bond_state = intent.getIntExtra(&extra_bond_state, 0)?;
-                rec_hdl.notifier.notify(GlobalEvent::BondStateChanged(
+                if prev_bond_state == BluetoothDevice::BOND_BONDED
+                    && bond_state == BluetoothDevice::BOND_NONE
+                {
+                    GattTree::on_bond_lost(&dev_id);
+                }
+                self.notifier.notify(GlobalEvent::BondStateChanged(
                     dev_id,
                     prev_bond_state,
                     bond_state,
                 ));
                 Ok(())
             }
+            BluetoothDevice::ACTION_PAIRING_REQUEST => {
+                let dev_id = get_extra_device_id(&intent)?;
+                let extra_variant =
+                    JString::from_env_str(env, BluetoothDevice::EXTRA_PAIRING_VARIANT);
+                let variant = intent.getIntExtra(&extra_variant, -1)?;
+                let extra_key = JString::from_env_str(env, BluetoothDevice::EXTRA_PAIRING_KEY);
+                let key = intent.getIntExtra(&extra_key, -1)?;
+                self.notifier.notify(GlobalEvent::PairingRequest(
+                    dev_id,
+                    variant,
+                    (key >= 0).then_some(key),
+                ));
+                Ok(())
+            }
+            BluetoothAdapter::ACTION_SCAN_MODE_CHANGED => {
+                let extra_scan_mode = JString::from_env_str(env, BluetoothAdapter::EXTRA_SCAN_MODE);
+                let val = intent.getIntExtra(&extra_scan_mode, 0)?;
+                self.notifier.notify(GlobalEvent::ScanModeChanged(val));
+                Ok(())
+            }
             _ => Ok(()),
         };
         if let Err(e) = process_intent() {
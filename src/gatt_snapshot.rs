@@ -0,0 +1,301 @@
+use std::fmt;
+
+use super::gatt_tree::GattConnection;
+use super::{Characteristic, CharacteristicProperties, Descriptor, Device, DeviceId, Result, Uuid};
+
+/// A plain copy of the GATT tree discovered on a connected device, got by [crate::Device::gatt_snapshot].
+///
+/// It is built from the cached tree without calling any Java method, which makes it suitable
+/// for diagnostics in error paths. Use the [fmt::Display] implementation for a readable listing.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GattSnapshot {
+    /// The device which is holding the GATT database.
+    pub device_id: DeviceId,
+    /// The MTU value received in the latest `onMtuChanged` callback, if any.
+    pub mtu: Option<usize>,
+    /// Discovered services sorted by instance IDs.
+    pub services: Vec<ServiceSnapshot>,
+}
+
+/// A service in the [GattSnapshot].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceSnapshot {
+    /// The [Uuid] identifying the type of the service.
+    pub uuid: Uuid,
+    /// The Android instance ID of the service.
+    pub instance_id: i32,
+    /// Whether this is a primary service.
+    pub is_primary: bool,
+    /// Characteristics of the service sorted by instance IDs.
+    pub characteristics: Vec<CharacteristicSnapshot>,
+}
+
+/// A characteristic in the [GattSnapshot].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharacteristicSnapshot {
+    /// The [Uuid] identifying the type of the characteristic.
+    pub uuid: Uuid,
+    /// The Android instance ID of the characteristic.
+    pub instance_id: i32,
+    /// Properties of the characteristic.
+    pub properties: CharacteristicProperties,
+    /// UUIDs of descriptors in their original order.
+    pub descriptors: Vec<Uuid>,
+}
+
+impl GattSnapshot {
+    pub(crate) fn from_connection(dev_id: &DeviceId, conn: &GattConnection) -> Self {
+        let mut services: Vec<_> = conn
+            .services
+            .lock()
+            .unwrap()
+            .values()
+            .map(|serv| {
+                let mut characteristics: Vec<_> = serv
+                    .chars
+                    .values()
+                    .map(|ch| CharacteristicSnapshot {
+                        uuid: ch.uuid,
+                        instance_id: ch.instance_id,
+                        properties: ch.properties,
+                        descriptors: ch.descs.iter().map(|desc| desc.uuid).collect(),
+                    })
+                    .collect();
+                characteristics.sort_by_key(|ch| ch.instance_id);
+                ServiceSnapshot {
+                    uuid: serv.uuid,
+                    instance_id: serv.instance_id,
+                    is_primary: serv.is_primary,
+                    characteristics,
+                }
+            })
+            .collect();
+        services.sort_by_key(|serv| serv.instance_id);
+        Self {
+            device_id: dev_id.clone(),
+            mtu: conn.mtu_changed_received.last_value(),
+            services,
+        }
+    }
+}
+
+/// Values of all readable attributes of a device, got by [crate::Device::snapshot].
+///
+/// Use the [fmt::Display] implementation for a readable listing, e.g. in bug reports.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct DeviceSnapshot {
+    /// The device which is holding the GATT database.
+    pub device_id: DeviceId,
+    /// The MTU value received in the latest `onMtuChanged` callback, if any.
+    pub mtu: Option<usize>,
+    /// Discovered services sorted by instance IDs.
+    pub services: Vec<ServiceValues>,
+}
+
+/// A service in the [DeviceSnapshot].
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ServiceValues {
+    /// The [Uuid] identifying the type of the service.
+    pub uuid: Uuid,
+    /// The Android instance ID of the service.
+    pub instance_id: i32,
+    /// Whether this is a primary service.
+    pub is_primary: bool,
+    /// Characteristics of the service sorted by instance IDs.
+    pub characteristics: Vec<CharacteristicValues>,
+}
+
+/// A characteristic in the [DeviceSnapshot].
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct CharacteristicValues {
+    /// The [Uuid] identifying the type of the characteristic.
+    pub uuid: Uuid,
+    /// The Android instance ID of the characteristic.
+    pub instance_id: i32,
+    /// Properties of the characteristic.
+    pub properties: CharacteristicProperties,
+    /// The result of reading the value; `None` if the characteristic is not readable.
+    pub value: Option<Result<Vec<u8>>>,
+    /// Descriptors in their original order.
+    pub descriptors: Vec<DescriptorValue>,
+}
+
+/// A descriptor in the [DeviceSnapshot].
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct DescriptorValue {
+    /// The [Uuid] identifying the type of the descriptor.
+    pub uuid: Uuid,
+    /// The result of reading the value.
+    pub value: Result<Vec<u8>>,
+}
+
+impl DeviceSnapshot {
+    /// Reads attributes of the tree in `tree` one by one. The caller should hold the write guard
+    /// of the sequence lock of the connection.
+    pub(crate) async fn read_in_sequence(device: &Device, tree: GattSnapshot) -> Self {
+        let dev_id = device.id();
+        let mut services = Vec::with_capacity(tree.services.len());
+        for serv in tree.services {
+            let mut characteristics = Vec::with_capacity(serv.characteristics.len());
+            for ch in serv.characteristics {
+                let handle = Characteristic::new(
+                    dev_id.clone(),
+                    serv.uuid,
+                    serv.instance_id,
+                    ch.uuid,
+                    ch.instance_id,
+                );
+                let value = if ch.properties.read {
+                    Some(handle.read_in_sequence(None).await)
+                } else {
+                    None
+                };
+                let mut descriptors = Vec::with_capacity(ch.descriptors.len());
+                for (index, uuid) in ch.descriptors.into_iter().enumerate() {
+                    let desc = Descriptor::new(
+                        dev_id.clone(),
+                        serv.uuid,
+                        serv.instance_id,
+                        ch.uuid,
+                        ch.instance_id,
+                        uuid,
+                        index,
+                    );
+                    let value = desc.read_in_sequence().await;
+                    descriptors.push(DescriptorValue { uuid, value });
+                }
+                characteristics.push(CharacteristicValues {
+                    uuid: ch.uuid,
+                    instance_id: ch.instance_id,
+                    properties: ch.properties,
+                    value,
+                    descriptors,
+                });
+            }
+            services.push(ServiceValues {
+                uuid: serv.uuid,
+                instance_id: serv.instance_id,
+                is_primary: serv.is_primary,
+                characteristics,
+            });
+        }
+        Self {
+            device_id: tree.device_id,
+            mtu: tree.mtu,
+            services,
+        }
+    }
+}
+
+impl fmt::Display for DeviceSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GATT values of {}", self.device_id)?;
+        if let Some(mtu) = self.mtu {
+            write!(f, " (MTU {mtu})")?;
+        }
+        writeln!(f)?;
+        for serv in &self.services {
+            let kind = if serv.is_primary {
+                "service"
+            } else {
+                "secondary service"
+            };
+            writeln!(f, "  {kind} {} (instance {})", serv.uuid, serv.instance_id)?;
+            for ch in &serv.characteristics {
+                write!(
+                    f,
+                    "    characteristic {} (instance {}) [",
+                    ch.uuid, ch.instance_id
+                )?;
+                write_properties(f, &ch.properties)?;
+                write!(f, "]")?;
+                if let Some(value) = &ch.value {
+                    write!(f, ": ")?;
+                    write_value(f, value)?;
+                }
+                writeln!(f)?;
+                for desc in &ch.descriptors {
+                    write!(f, "      descriptor {}: ", desc.uuid)?;
+                    write_value(f, &desc.value)?;
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_value(f: &mut fmt::Formatter<'_>, value: &Result<Vec<u8>>) -> fmt::Result {
+    match value {
+        Ok(value) => write!(f, "{value:02x?}"),
+        Err(e) => write!(f, "error: {e}"),
+    }
+}
+
+impl fmt::Display for GattSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GATT database of {}", self.device_id)?;
+        if let Some(mtu) = self.mtu {
+            write!(f, " (MTU {mtu})")?;
+        }
+        writeln!(f)?;
+        for serv in &self.services {
+            let kind = if serv.is_primary {
+                "service"
+            } else {
+                "secondary service"
+            };
+            writeln!(f, "  {kind} {} (instance {})", serv.uuid, serv.instance_id)?;
+            for ch in &serv.characteristics {
+                write!(
+                    f,
+                    "    characteristic {} (instance {}) [",
+                    ch.uuid, ch.instance_id
+                )?;
+                write_properties(f, &ch.properties)?;
+                writeln!(f, "]")?;
+                for desc in &ch.descriptors {
+                    writeln!(f, "      descriptor {desc}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_properties(f: &mut fmt::Formatter<'_>, props: &CharacteristicProperties) -> fmt::Result {
+    let names = [
+        (props.broadcast, "broadcast"),
+        (props.read, "read"),
+        (props.write_without_response, "write_without_response"),
+        (props.write, "write"),
+        (props.notify, "notify"),
+        (props.indicate, "indicate"),
+        (
+            props.authenticated_signed_writes,
+            "authenticated_signed_writes",
+        ),
+        (props.extended_properties, "extended_properties"),
+        (props.reliable_write, "reliable_write"),
+        (props.writable_auxiliaries, "writable_auxiliaries"),
+    ];
+    let mut first = true;
+    for (_, name) in names.iter().filter(|(set, _)| *set) {
+        if !first {
+            f.write_str(", ")?;
+        }
+        f.write_str(name)?;
+        first = false;
+    }
+    Ok(())
+}
@@ -1,39 +1,122 @@
-// XXX: have adjustable timeout values in `AdapterConfig`.
-
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, LazyLock, Mutex, OnceLock, Weak};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use async_lock::{RwLock, RwLockReadGuardArc, RwLockWriteGuardArc, SemaphoreGuardArc};
 use futures_core::Stream;
 use java_spaghetti::{ByteArray, Env, Global, Ref};
-use log::{error, info};
+use log::{debug, error, info, warn};
 
-use super::async_util::{Excluder, Notifier, ResultWaiter};
+use super::async_util::{Excluder, Notifier, NotifierReceiver, ResultWaiter};
 use super::bindings::android::bluetooth::{
-    BluetoothGatt, BluetoothGattCharacteristic, BluetoothGattDescriptor, BluetoothGattService,
-    BluetoothProfile,
+    BluetoothDevice, BluetoothGatt, BluetoothGattCharacteristic, BluetoothGattDescriptor,
+    BluetoothGattService, BluetoothProfile,
 };
+use super::callback_log::{log_callback, logged_char, logged_desc, LoggedValue};
+use super::callback_thread::CallbackThread;
 use super::device::Device;
-use super::error::{AttError, Error, NativeError};
-use super::event_receiver::EventReceiver;
-use super::jni::{ByteArrayExt, Monitor};
-use super::util::{BoolExt, JavaIterator, OptionExt, UuidExt};
+use super::error::{AttError, Error, ErrorKind, NativeError};
+use super::event_receiver::{EventKind, EventReceiver, GlobalEvent};
+use super::jni::{is_same_object, ByteArrayExt, Monitor};
+use super::observer::{LatencyRecorder, OperationKind, OperationObserver};
+use super::util::{java_list_items, BoolExt, OptionExt, UuidExt, WaitResultExt};
 use super::vm_context::{android_api_level, jni_with_env};
-use super::{ConnectionEvent, DeviceId, Uuid};
+use super::{
+    CharacteristicProperties, ConnectionEvent, ConnectionParameters, DeviceId, DisconnectReason,
+    OverflowPolicy, Phy, Uuid,
+};
 
 static GATT_CONNECTIONS: LazyLock<Mutex<HashMap<DeviceId, Arc<GattConnection>>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
-static CONNECTION_EVENTS: Notifier<(DeviceId, ConnectionEvent)> = Notifier::new(32);
+/// `BluetoothDevice` objects of devices registered before, kept after disconnection. Entries of
+/// disconnected devices are evicted when there are more than [KNOWN_DEVICES_LIMIT] of them.
+static KNOWN_DEVICES: LazyLock<Mutex<HashMap<DeviceId, Global<BluetoothDevice>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+const KNOWN_DEVICES_LIMIT: usize = 64;
+
+static CONNECTION_EVENTS: Notifier<(DeviceId, ConnectionEvent)> =
+    Notifier::new(32, OverflowPolicy::DropOldest);
+
+/// Count of `BluetoothGatt` objects registered and not yet closed.
+static OPEN_GATT_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Accounts a `BluetoothGatt` object in [OPEN_GATT_CLIENTS] until it is released.
+struct OpenGattClient {
+    released: AtomicBool,
+}
+
+impl OpenGattClient {
+    fn new() -> Self {
+        OPEN_GATT_CLIENTS.fetch_add(1, Ordering::SeqCst);
+        Self {
+            released: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true only for the first call, which should close the `BluetoothGatt` object.
+    fn release(&self) -> bool {
+        if self.released.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        OPEN_GATT_CLIENTS.fetch_sub(1, Ordering::SeqCst);
+        true
+    }
+}
+
+/// Source of GATT tree generations, which are unique and increasing across all devices.
+static NEXT_TREE_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// Generation counters of the GATT trees of devices, kept alive by the connection and the
+/// `CachedWeak` items of each device. A counter is bumped whenever a connection of the device
+/// is registered or closed, or its service tree is rebuilt.
+static TREE_GENERATIONS: LazyLock<Mutex<HashMap<DeviceId, Weak<AtomicU64>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the generation counter of the GATT tree of `dev_id`.
+fn tree_generation(dev_id: &DeviceId) -> Arc<AtomicU64> {
+    let mut generations = TREE_GENERATIONS.lock().unwrap();
+    if let Some(generation) = generations.get(dev_id).and_then(Weak::upgrade) {
+        return generation;
+    }
+    generations.retain(|_, generation| generation.strong_count() > 0);
+    // starts from the latest generation, so that generations of the device keep increasing.
+    let generation = Arc::new(AtomicU64::new(NEXT_TREE_GENERATION.load(Ordering::SeqCst)));
+    generations.insert(dev_id.clone(), Arc::downgrade(&generation));
+    generation
+}
+
+/// Bumps the generation counter of a device and returns the new generation.
+fn bump_tree_generation(generation: &AtomicU64) -> u64 {
+    let new_generation = NEXT_TREE_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    generation.fetch_max(new_generation, Ordering::SeqCst);
+    new_generation
+}
 
 pub(crate) use cached_weak::CachedWeak;
 mod cached_weak {
     use std::fmt::Debug;
-    use std::sync::atomic::{AtomicPtr, Ordering};
-    use std::sync::{Arc, Weak};
+    use std::mem::ManuallyDrop;
+    use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, OnceLock, Weak};
+
+    use super::tree_generation;
+    use crate::DeviceId;
 
+    /// The cached item is considered outdated if the GATT tree of the device is modified after
+    /// it is cached, because an item removed from the tree may still be upgradable while it's
+    /// referenced elsewhere.
     pub struct CachedWeak<T> {
         ptr: AtomicPtr<T>,
+        /// Count of threads cloning the stored weak; a replaced weak is released after it
+        /// drops to zero, because those threads may still use the old pointer.
+        readers: AtomicUsize,
+        generation: AtomicU64,
+        /// Generation counter of the device, taken on the first access.
+        tree_generation: OnceLock<Arc<AtomicU64>>,
     }
 
     impl<T> CachedWeak<T> {
@@ -41,42 +124,93 @@ mod cached_weak {
             self.ptr.load(Ordering::SeqCst)
         }
         fn get_weak(&self) -> Weak<T> {
-            // Safety: the raw pointer is got from `Weak::into_raw`
-            let weak = unsafe { Weak::from_raw(self.get_raw()) };
-            let weak_cloned = weak.clone();
-            let _ = weak.into_raw(); // preserve the ownership of the stored weak
+            self.readers.fetch_add(1, Ordering::SeqCst);
+            // Safety: the raw pointer is got from `Weak::into_raw`, and it is not released
+            // by `replace` before `readers` is decreased. It is not dropped here to preserve
+            // the ownership of the stored weak.
+            let weak = ManuallyDrop::new(unsafe { Weak::from_raw(self.get_raw()) });
+            let weak_cloned = Weak::clone(&weak);
+            self.readers.fetch_sub(1, Ordering::SeqCst);
             weak_cloned
         }
+        fn replace(&self, weak: Weak<T>) {
+            let old = self.ptr.swap(weak.into_raw().cast_mut(), Ordering::SeqCst);
+            // readers which have loaded the old pointer finish in a few instructions.
+            while self.readers.load(Ordering::SeqCst) != 0 {
+                std::hint::spin_loop();
+            }
+            // Safety: the old pointer is got from `Weak::into_raw`, and it is no longer
+            // reachable from `self`.
+            drop(unsafe { Weak::from_raw(old) });
+        }
+        fn tree_generation(&self, dev_id: &DeviceId) -> &AtomicU64 {
+            self.tree_generation.get_or_init(|| tree_generation(dev_id))
+        }
         pub fn new() -> Self {
             Self {
                 ptr: AtomicPtr::new(Weak::<T>::new().into_raw().cast_mut()),
+                readers: AtomicUsize::new(0),
+                generation: AtomicU64::new(0),
+                tree_generation: OnceLock::new(),
+            }
+        }
+        /// Note: `arc` must be taken from the current GATT tree of `dev_id`.
+        pub fn from_arc(dev_id: &DeviceId, arc: &Arc<T>) -> Self {
+            let tree_generation = tree_generation(dev_id);
+            Self {
+                ptr: AtomicPtr::new(Arc::downgrade(arc).into_raw().cast_mut()),
+                readers: AtomicUsize::new(0),
+                generation: AtomicU64::new(tree_generation.load(Ordering::SeqCst)),
+                tree_generation: OnceLock::from(tree_generation),
             }
         }
-        pub fn get(&self) -> Option<Arc<T>> {
+        /// Returns the cached item if it is still alive and not outdated. `dev_id` must be the
+        /// same device in all calls.
+        pub fn get(&self, dev_id: &DeviceId) -> Option<Arc<T>> {
+            // the generation must be loaded before the pointer; see `get_or_find`.
+            let current = self.tree_generation(dev_id).load(Ordering::SeqCst);
+            if self.generation.load(Ordering::SeqCst) != current {
+                return None;
+            }
             self.get_weak().upgrade()
         }
         pub fn get_or_find<E>(
             &self,
+            dev_id: &DeviceId,
             finder: impl FnOnce() -> Result<Arc<T>, E>,
         ) -> Result<Arc<T>, E> {
-            if let Some(arc) = self.get() {
+            if let Some(arc) = self.get(dev_id) {
                 return Ok(arc);
             }
+            // if the tree is modified during `finder`, the old generation is stored and
+            // the item will be found again on the next access.
+            let generation = self.tree_generation(dev_id).load(Ordering::SeqCst);
             let arc = finder()?;
-            self.ptr
-                .store(Arc::downgrade(&arc).into_raw().cast_mut(), Ordering::SeqCst);
+            self.replace(Arc::downgrade(&arc));
+            self.generation.store(generation, Ordering::SeqCst);
             Ok(arc)
         }
     }
 
     impl<T> Clone for CachedWeak<T> {
         fn clone(&self) -> Self {
+            let generation = self.generation.load(Ordering::SeqCst);
             Self {
                 ptr: AtomicPtr::new(self.get_weak().into_raw().cast_mut()),
+                readers: AtomicUsize::new(0),
+                generation: AtomicU64::new(generation),
+                tree_generation: self.tree_generation.clone(),
             }
         }
     }
 
+    impl<T> Drop for CachedWeak<T> {
+        fn drop(&mut self) {
+            // Safety: the raw pointer is got from `Weak::into_raw`
+            drop(unsafe { Weak::from_raw(*self.ptr.get_mut()) });
+        }
+    }
+
     impl<T> Debug for CachedWeak<T> {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             f.write_fmt(format_args!("CachedWeak {{ ptr: {:?} }}", self.get_raw()))
@@ -84,97 +218,325 @@ mod cached_weak {
     }
 }
 
+/// Options taken from `AdapterConfig` which are needed by operations on a GATT connection.
+#[derive(Clone)]
+pub(crate) struct GattOptions {
+    pub verify_cccd_after_enable: bool,
+    pub connect_timeout: Duration,
+    pub discovery_timeout: Duration,
+    /// Timeout for other operations including reads and writes.
+    pub operation_timeout: Duration,
+    pub notify_overflow: OverflowPolicy,
+    pub notify_replay_last: bool,
+    pub auto_rediscover_on_out_of_sync: bool,
+    pub observer: Option<Arc<dyn OperationObserver>>,
+    /// Set by `Adapter::with_config` if `AdapterConfig::dedicated_callback_thread` is enabled;
+    /// each connection keeps it alive.
+    pub callback_thread: Option<Arc<CallbackThread>>,
+    #[cfg(feature = "unstable-jni")]
+    pub raw_callback_hook: Option<Arc<dyn super::unstable_jni::RawGattCallbackHook>>,
+}
+
+impl Default for GattOptions {
+    fn default() -> Self {
+        Self {
+            verify_cccd_after_enable: false,
+            // Inspired by `CONNECTION_TIMEOUT_THRESHOLD` in `Android-BLE-Library`.
+            connect_timeout: Duration::from_secs(20),
+            discovery_timeout: Duration::from_secs(10),
+            operation_timeout: Duration::from_secs(5),
+            notify_overflow: OverflowPolicy::DropOldest,
+            notify_replay_last: false,
+            auto_rediscover_on_out_of_sync: false,
+            observer: None,
+            callback_thread: None,
+            #[cfg(feature = "unstable-jni")]
+            raw_callback_hook: None,
+        }
+    }
+}
+
+impl GattOptions {
+    /// Creates the excluder for operations of `kind` with the configured timeout and observer.
+    fn excluder<T: Send + Clone>(&self, kind: OperationKind) -> Excluder<T> {
+        let timeout = match kind {
+            OperationKind::Connect => self.connect_timeout,
+            OperationKind::DiscoverServices => self.discovery_timeout,
+            _ => self.operation_timeout,
+        };
+        Excluder::with_timeout(timeout).observed(kind, self.observer.clone())
+    }
+}
+
 pub(crate) struct GattConnection {
+    pub(super) device: Global<BluetoothDevice>,
     pub(super) gatt: Global<BluetoothGatt>,
+    pub(super) options: GattOptions,
     pub(super) callback_hdl_weak: Weak<BluetoothGattCallbackProxy>,
     pub(super) gatt_connect: Excluder<()>,
     pub(super) global_event_receiver: Arc<EventReceiver>,
-    pub(super) services: Mutex<HashMap<Uuid, Arc<ServiceInner>>>,
+    /// Keyed by service instance IDs.
+    pub(super) services: Mutex<HashMap<i32, Arc<ServiceInner>>>,
+    /// Entries taken out of `services` by `onServiceChanged`, which are compared with the
+    /// rediscovered tree in `refresh_services()`.
+    pub(super) services_before_change: Mutex<Option<HashMap<i32, Arc<ServiceInner>>>>,
     pub(super) discover_services: Excluder<Result<(), Error>>,
     pub(super) read_rssi: Excluder<Result<i16, Error>>,
-    pub(super) services_changes: Notifier<()>,
+    pub(super) read_phy: Excluder<Result<(Phy, Phy), Error>>,
+    /// Updated by both `onPhyRead` and `onPhyUpdate`.
+    pub(super) current_phy: Mutex<Option<(Phy, Phy)>>,
+    pub(super) services_changes: Notifier<ServicesDiff>,
+    /// Set when a Service Changed indication is received, taken by the following rebuild.
+    pub(super) changed_handle_range: Mutex<Option<(u16, u16)>>,
     pub(super) mtu_changed_received: Excluder<usize>,
+    pub(super) connection_update: Excluder<Result<ConnectionParameters, Error>>,
+    /// Set when an MTU exchange succeeds.
+    pub(super) mtu_negotiated: AtomicBool,
+    /// Set when the connection is established.
+    pub(super) connected_at: OnceLock<SystemTime>,
+    /// Ordinary GATT operations hold the read guard; sequences of operations which must not be
+    /// interleaved by other tasks (like `Characteristic::write_then_read`) hold the write guard.
+    sequence_lock: Arc<RwLock<()>>,
+    /// Signalled by callbacks of requests which make `BluetoothGatt` busy (reads and writes),
+    /// for `Characteristic::write_without_response_awaiting_credit`.
+    request_done: (Sender<()>, InactiveReceiver<()>),
+    /// Permit of `AdapterConfig::max_concurrent_connections`, released when the connection is closed.
+    connection_slot: Mutex<Option<SemaphoreGuardArc>>,
+    /// Keeps the bond state receiver registered while connected, see `GattTree::on_bond_lost`.
+    /// It is set by [GattConnection::watch_bond] only when the device may be bonded.
+    pub(super) bond_watch: Mutex<Option<NotifierReceiver<GlobalEvent>>>,
+    /// Set before the connection is deregistered.
+    pub(super) disconnect_reason: Arc<OnceLock<DisconnectReason>>,
+    /// Released by `close_connection`, making sure `BluetoothGatt.close()` is called only once.
+    open_client: OpenGattClient,
+    /// Generation counter of the GATT tree of the device.
+    tree_generation: Arc<AtomicU64>,
+    /// Also set as the observer of all excluders of this connection.
+    pub(super) latency: Arc<LatencyRecorder>,
 }
 
 pub(crate) struct ServiceInner {
     pub(super) service: Global<BluetoothGattService>,
-    pub(super) chars: HashMap<Uuid, Arc<CharacteristicInner>>,
+    pub(super) uuid: Uuid,
+    pub(super) instance_id: i32,
+    /// Secondary services are kept in the tree for being reachable from including services.
+    pub(super) is_primary: bool,
+    /// UUIDs and instance IDs of included services, captured during discovery.
+    pub(super) includes: Vec<(Uuid, i32)>,
+    /// Keyed by characteristic instance IDs.
+    pub(super) chars: HashMap<i32, Arc<CharacteristicInner>>,
+}
+
+/// Value of a characteristic read, kept as the Java array so that it can be copied into a buffer
+/// of the caller without an intermediate `Vec`.
+#[derive(Clone)]
+pub(crate) struct ReadValue(Arc<Global<ByteArray>>);
+
+impl ReadValue {
+    fn new(array: &Ref<'_, ByteArray>) -> Self {
+        Self(Arc::new(array.as_global()))
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        jni_with_env(|env| self.0.as_ref(env).as_vec_u8())
+    }
+
+    pub fn len(&self) -> usize {
+        jni_with_env(|env| self.0.as_ref(env).len())
+    }
+
+    /// See [ByteArrayExt::copy_to_slice].
+    pub fn copy_to_slice(&self, buf: &mut [u8]) -> Option<usize> {
+        jni_with_env(|env| self.0.as_ref(env).copy_to_slice(buf))
+    }
 }
 
 pub(crate) struct CharacteristicInner {
-    pub(super) char: Global<BluetoothGattCharacteristic>,
-    pub(super) descs: HashMap<Uuid, Arc<DescriptorInner>>,
+    /// Replaced with the new Java object if the characteristic survives a rediscovery.
+    char: Mutex<Global<BluetoothGattCharacteristic>>,
+    pub(super) uuid: Uuid,
+    pub(super) instance_id: i32,
+    pub(super) properties: CharacteristicProperties,
+    /// Android doesn't expose descriptor instance IDs; they are identified by positional indexes.
+    pub(super) descs: Vec<Arc<DescriptorInner>>,
     pub(super) notify: Notifier<Result<Vec<u8>, Error>>,
-    pub(super) read: Excluder<Result<Vec<u8>, Error>>,
+    pub(super) read: Excluder<Result<ReadValue, Error>>,
     pub(super) write: Excluder<Result<(), Error>>,
+    /// The last value read successfully, with the time point of receiving it. It is cleared by
+    /// writes and by service rediscovery.
+    pub(super) read_cache: Mutex<Option<(Instant, ReadValue)>>,
+    /// Held by `Characteristic::read_cached` while refreshing the value, so that simultaneous
+    /// callers share one read.
+    pub(super) read_refresh: Arc<async_lock::Mutex<()>>,
 }
 
 pub(crate) struct DescriptorInner {
-    pub(super) desc: Global<BluetoothGattDescriptor>,
+    /// Replaced with the new Java object if the descriptor survives a rediscovery.
+    desc: Mutex<Global<BluetoothGattDescriptor>>,
+    pub(super) uuid: Uuid,
     pub(super) read: Excluder<Result<Vec<u8>, Error>>,
     pub(super) write: Excluder<Result<(), Error>>,
 }
 
+impl CharacteristicInner {
+    pub fn char_obj(&self) -> Global<BluetoothGattCharacteristic> {
+        self.char.lock().unwrap().clone()
+    }
+}
+
+impl DescriptorInner {
+    pub fn desc_obj(&self) -> Global<BluetoothGattDescriptor> {
+        self.desc.lock().unwrap().clone()
+    }
+}
+
+/// Services (UUIDs and instance IDs) affected by a rebuild of the GATT tree.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ServicesDiff {
+    /// Services that are removed, or have lost any characteristic or descriptor.
+    pub invalidated: Vec<(Uuid, i32)>,
+    /// Services that are newly added.
+    pub added: Vec<(Uuid, i32)>,
+    /// Generation of the GATT tree after the rebuild.
+    pub generation: u64,
+    /// Handle range carried by the Service Changed indication which caused the rebuild.
+    pub handle_range: Option<(u16, u16)>,
+}
+
 /// Manages all existing GATT connections handled by this crate.
 pub(crate) struct GattTree;
 
 impl GattTree {
     /// Gets all devices registered here.
-    pub fn registered_devices() -> Result<Vec<crate::Device>, crate::Error> {
-        let connections = GATT_CONNECTIONS.lock().unwrap();
-        let mut devices = Vec::with_capacity(connections.len());
-        jni_with_env(|env| {
-            for (id, conn) in connections.iter() {
-                let cached_weak = CachedWeak::new();
-                let _ = cached_weak.get_or_find(|| Ok::<_, ()>(conn.clone()));
-                devices.push(Device {
-                    id: id.clone(),
-                    device: conn.gatt.as_ref(env).getDevice()?.non_null()?.as_global(),
-                    connection: cached_weak,
-                    once_connected: Arc::new(OnceLock::from(())),
-                });
-            }
-            Ok(devices)
-        })
+    pub fn registered_devices() -> Vec<crate::Device> {
+        GATT_CONNECTIONS
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, conn)| Device {
+                id: id.clone(),
+                device: conn.device.clone(),
+                connection: CachedWeak::from_arc(id, conn),
+                once_connected: Arc::new(OnceLock::from(())),
+            })
+            .collect()
     }
 
     /// Called from `Adapter::connect_device`.
+    ///
+    /// Stale entries found in the registry are deregistered here, and an existing
+    /// connection registered for the same device is closed before being replaced.
     pub fn register_connection(
         dev_id: &DeviceId,
+        device: Global<BluetoothDevice>,
         gatt: Global<BluetoothGatt>,
         callback_hdl: &Arc<BluetoothGattCallbackProxy>,
         event_receiver: &Arc<EventReceiver>,
+        mut options: GattOptions,
+        connection_slot: Option<SemaphoreGuardArc>,
     ) {
-        let _ = GATT_CONNECTIONS.lock().unwrap().insert(
+        Self::sweep_stale_connections();
+        // the recorder forwards operations to the user's observer.
+        let latency = Arc::new(LatencyRecorder::new(options.observer.take()));
+        options.observer = Some(latency.clone());
+        Self::remember_device(dev_id, &device);
+        let replaced = GATT_CONNECTIONS.lock().unwrap().insert(
             dev_id.clone(),
             Arc::new(GattConnection {
+                device,
                 gatt,
                 callback_hdl_weak: Arc::downgrade(callback_hdl),
-                // Inspired by `CONNECTION_TIMEOUT_THRESHOLD` in `Android-BLE-Library`.
-                gatt_connect: Excluder::new(Duration::from_secs(20)),
+                gatt_connect: options.excluder(OperationKind::Connect),
                 global_event_receiver: event_receiver.clone(),
                 services: Mutex::new(HashMap::new()),
-                discover_services: Excluder::new(Duration::from_secs(10)),
-                read_rssi: Excluder::default(),
-                services_changes: Notifier::new(16),
-                mtu_changed_received: Excluder::default(),
+                services_before_change: Mutex::new(None),
+                discover_services: options.excluder(OperationKind::DiscoverServices),
+                read_rssi: options.excluder(OperationKind::ReadRssi),
+                read_phy: options.excluder(OperationKind::ReadPhy),
+                current_phy: Mutex::new(None),
+                services_changes: Notifier::new(16, OverflowPolicy::DropOldest),
+                changed_handle_range: Mutex::new(None),
+                mtu_changed_received: options.excluder(OperationKind::RequestMtu),
+                connection_update: options.excluder(OperationKind::ConnectionUpdate),
+                mtu_negotiated: AtomicBool::new(false),
+                connected_at: OnceLock::new(),
+                sequence_lock: Arc::new(RwLock::new(())),
+                request_done: {
+                    let (mut sender, receiver) = async_broadcast::broadcast(1);
+                    sender.set_overflow(true);
+                    (sender, receiver.deactivate())
+                },
+                connection_slot: Mutex::new(connection_slot),
+                bond_watch: Mutex::new(None),
+                disconnect_reason: Arc::new(OnceLock::new()),
+                open_client: OpenGattClient::new(),
+                tree_generation: tree_generation(dev_id),
+                latency,
+                options,
             }),
         );
+        if let Some(conn) = GATT_CONNECTIONS.lock().unwrap().get(dev_id) {
+            bump_tree_generation(&conn.tree_generation);
+        }
+        if let Some(conn) = replaced {
+            warn!("replacing the registered connection with {dev_id}");
+            close_connection(dev_id, conn);
+        }
+        debug!(
+            "registered connection with {dev_id}, {} connection(s) in total",
+            Self::connection_count()
+        );
+    }
+
+    /// Returns the amount of `BluetoothGatt` objects which are not yet closed.
+    pub fn open_gatt_count() -> usize {
+        OPEN_GATT_CLIENTS.load(Ordering::SeqCst)
+    }
+
+    /// Returns the amount of connections currently registered.
+    pub fn connection_count() -> usize {
+        GATT_CONNECTIONS.lock().unwrap().len()
+    }
+
+    /// Deregisters all connections whose callback handler has been dropped,
+    /// which means no callback can ever be received for them.
+    pub fn sweep_stale_connections() {
+        let stale_conns: Vec<_> = {
+            let mut conns = GATT_CONNECTIONS.lock().unwrap();
+            let stale_ids: Vec<_> = conns
+                .iter()
+                .filter(|(_, conn)| conn.callback_hdl_weak.strong_count() == 0)
+                .map(|(id, _)| id.clone())
+                .collect();
+            stale_ids
+                .into_iter()
+                .filter_map(|id| conns.remove(&id).map(|conn| (id, conn)))
+                .collect()
+        };
+        for (dev_id, conn) in stale_conns {
+            info!("deregistered stale connection with {dev_id}");
+            close_connection(&dev_id, conn);
+        }
     }
 
     /// Call it *once* right after calling `register_connection`.
     /// Returns `None` if it's still disconnected.
-    pub async fn wait_connection_available(dev_id: &DeviceId) -> Result<(), crate::Error> {
+    /// Waits for `onConnectionStateChange`; `timeout` overrides the connection timeout.
+    pub async fn wait_connection_available(
+        dev_id: &DeviceId,
+        timeout: Option<Duration>,
+    ) -> Result<(), crate::Error> {
         let conn = Self::check_connection(dev_id)?;
-        let connect_lock = conn.gatt_connect.lock().await;
+        let connect_lock = match timeout {
+            Some(timeout) => conn.gatt_connect.lock_with_timeout(timeout).await,
+            None => conn.gatt_connect.lock().await,
+        };
         if conn.gatt_connect.last_value().is_none() {
             drop(conn);
-            if connect_lock.wait_unlock().await.is_some() {
-                Ok(())
-            } else {
-                let _ = Self::check_connection(dev_id)?;
-                Err(crate::Error::from(crate::error::ErrorKind::Timeout))
-            }
+            connect_lock
+                .wait_unlock()
+                .await
+                .check_wait(dev_id, "connection")
         } else {
             Ok(())
         }
@@ -184,16 +546,51 @@ impl GattTree {
     pub fn deregister_connection(dev_id: &DeviceId) -> bool {
         let deregistered = GATT_CONNECTIONS.lock().unwrap().remove(dev_id);
         if let Some(conn) = deregistered {
-            jni_with_env(|env| {
-                let _ = conn.gatt.as_ref(env).close(); // releases resources
-            });
-            CONNECTION_EVENTS.notify((dev_id.clone(), ConnectionEvent::Disconnected));
+            close_connection(dev_id, conn);
             true
         } else {
             false
         }
     }
 
+    /// Called when the bond with a device is removed. Pending characteristic and descriptor
+    /// operations of its connection are failed immediately, because those requiring encryption
+    /// would otherwise wait for a response that never comes.
+    pub fn on_bond_lost(dev_id: &DeviceId) {
+        let Some(conn) = Self::find_connection(dev_id) else {
+            return;
+        };
+        let error = || {
+            Error::new(
+                ErrorKind::NotAuthorized,
+                None,
+                format!("the bond with {dev_id} was removed while the operation was pending"),
+            )
+        };
+        let mut failed = 0;
+        for serv in conn.services.lock().unwrap().values() {
+            for char in serv.chars.values() {
+                failed += char.read.fail_current(Err(error())) as usize;
+                failed += char.write.fail_current(Err(error())) as usize;
+                for desc in &char.descs {
+                    failed += desc.read.fail_current(Err(error())) as usize;
+                    failed += desc.write.fail_current(Err(error())) as usize;
+                }
+            }
+        }
+        warn!("the bond with connected device {dev_id} is removed, failed {failed} operation(s)");
+    }
+
+    /// See `Adapter::inject_connection_event`.
+    #[cfg(feature = "test-injection")]
+    pub fn inject_connection_event(dev_id: &DeviceId, event: ConnectionEvent) {
+        if event == ConnectionEvent::Disconnected && Self::deregister_connection(dev_id) {
+            info!("deregistered connection with {dev_id} for an injected disconnection");
+            return; // the event is sent by `close_connection`
+        }
+        CONNECTION_EVENTS.notify((dev_id.clone(), event));
+    }
+
     pub async fn connection_events() -> impl Stream<Item = (DeviceId, ConnectionEvent)> {
         CONNECTION_EVENTS
             .subscribe(|| Ok::<_, ()>(()), || ())
@@ -203,13 +600,29 @@ impl GattTree {
 
     /// Call this on adapter disabling event.
     pub fn clear_connections() -> bool {
-        let mut conns = GATT_CONNECTIONS.lock().unwrap();
-        if !conns.is_empty() {
-            conns.clear();
-            true
-        } else {
-            false
+        let conns: Vec<_> = GATT_CONNECTIONS.lock().unwrap().drain().collect();
+        let cleared = !conns.is_empty();
+        for (dev_id, conn) in conns {
+            close_connection(&dev_id, conn);
         }
+        cleared
+    }
+
+    /// Keeps the `BluetoothDevice` of a registered device, evicting disconnected devices if there
+    /// are too many of them.
+    fn remember_device(dev_id: &DeviceId, device: &Global<BluetoothDevice>) {
+        let mut known = KNOWN_DEVICES.lock().unwrap();
+        if known.len() >= KNOWN_DEVICES_LIMIT && !known.contains_key(dev_id) {
+            let connections = GATT_CONNECTIONS.lock().unwrap();
+            known.retain(|id, _| connections.contains_key(id));
+        }
+        known.insert(dev_id.clone(), device.clone());
+    }
+
+    /// Returns the `BluetoothDevice` of a device which has been registered, even if it is
+    /// disconnected now. It may be `None` for a disconnected device evicted from the list.
+    pub fn known_device(dev_id: &DeviceId) -> Option<Global<BluetoothDevice>> {
+        KNOWN_DEVICES.lock().unwrap().get(dev_id).cloned()
     }
 
     pub fn check_connection(dev_id: &DeviceId) -> Result<Arc<GattConnection>, crate::Error> {
@@ -227,119 +640,405 @@ impl GattTree {
         }
     }
 
-    pub fn find_service(dev_id: &DeviceId, service_id: Uuid) -> Option<Arc<ServiceInner>> {
-        Self::find_connection(dev_id)
-            .and_then(|conn| conn.services.lock().unwrap().get(&service_id).cloned())
+    pub fn find_service(dev_id: &DeviceId, service_instance: i32) -> Option<Arc<ServiceInner>> {
+        Self::find_connection(dev_id).and_then(|conn| {
+            conn.services
+                .lock()
+                .unwrap()
+                .get(&service_instance)
+                .cloned()
+        })
+    }
+
+    /// Secondary lookup of primary services by UUID. The result is sorted by instance IDs.
+    pub fn find_services_by_uuid(dev_id: &DeviceId, service_id: Uuid) -> Vec<Arc<ServiceInner>> {
+        let Some(conn) = Self::find_connection(dev_id) else {
+            return Vec::new();
+        };
+        let mut services: Vec<_> = conn
+            .services
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|serv| serv.is_primary && serv.uuid == service_id)
+            .cloned()
+            .collect();
+        services.sort_by_key(|serv| serv.instance_id);
+        services
     }
 
     pub fn find_characteristic(
         dev_id: &DeviceId,
-        service_id: Uuid,
-        char_id: Uuid,
+        service_instance: i32,
+        char_instance: i32,
     ) -> Option<Arc<CharacteristicInner>> {
-        Self::find_service(dev_id, service_id)
-            .and_then(|service| service.chars.get(&char_id).cloned())
+        Self::find_service(dev_id, service_instance)
+            .and_then(|service| service.chars.get(&char_instance).cloned())
     }
 
     pub fn find_descriptor(
         dev_id: &DeviceId,
-        service_id: Uuid,
-        char_id: Uuid,
-        desc_id: Uuid,
+        service_instance: i32,
+        char_instance: i32,
+        desc_index: usize,
     ) -> Option<Arc<DescriptorInner>> {
-        Self::find_characteristic(dev_id, service_id, char_id)
-            .and_then(|char| char.descs.get(&desc_id).cloned())
+        Self::find_characteristic(dev_id, service_instance, char_instance)
+            .and_then(|char| char.descs.get(desc_index).cloned())
     }
 }
 
+/// Invariant: every connection removed from the registry must be passed here. It closes the
+/// `BluetoothGatt` object (which holds one of the limited GATT client slots) and drops all
+/// inner items of the GATT tree, even if the `GattConnection` is still referenced somewhere.
+fn close_connection(dev_id: &DeviceId, conn: Arc<GattConnection>) {
+    if !conn.open_client.release() {
+        return;
+    }
+    bump_tree_generation(&conn.tree_generation);
+    conn.abort_all_operations();
+    jni_with_env(|env| {
+        let _ = conn.gatt.as_ref(env).close(); // releases resources
+    });
+    conn.services.lock().unwrap().clear();
+    if let Some(callback_hdl) = conn.callback_hdl_weak.upgrade() {
+        callback_hdl.notify_routes.clear();
+    }
+    let _ = conn.connection_slot.lock().unwrap().take();
+    let _ = conn.disconnect_reason.set(DisconnectReason::Closed);
+    CONNECTION_EVENTS.notify((dev_id.clone(), ConnectionEvent::Disconnected));
+}
+
 impl GattConnection {
+    /// Registers the bond state receiver for `GattTree::on_bond_lost` if it is not registered
+    /// yet. It is called when the device is bonded on connection, when a bonding API is used,
+    /// or when an operation fails for insufficient authentication or encryption.
+    pub(super) async fn watch_bond(&self, dev_id: &DeviceId) {
+        if self.bond_watch.lock().unwrap().is_some() {
+            return;
+        }
+        match self.global_event_receiver.subscribe(EventKind::Bond).await {
+            Ok(receiver) => {
+                self.bond_watch.lock().unwrap().get_or_insert(receiver);
+            }
+            Err(e) => warn!("failed to watch bond state changes of {dev_id}: {e}"),
+        }
+    }
+
+    /// Waits for the shared guard of the sequence lock, which is held by every single GATT
+    /// operation. It must not be taken again by the holder, otherwise it may deadlock with a
+    /// task waiting for [GattConnection::exclusive_sequence_guard].
+    pub(super) async fn sequence_guard(&self) -> RwLockReadGuardArc<()> {
+        self.sequence_lock.read_arc().await
+    }
+
+    /// Waits for the exclusive guard of the sequence lock, held by sequences of operations which
+    /// must not be interleaved by other tasks. The holder calls the `*_in_sequence` methods.
+    pub(super) async fn exclusive_sequence_guard(&self) -> RwLockWriteGuardArc<()> {
+        self.sequence_lock.write_arc().await
+    }
+
+    /// Receives signals of `BluetoothGatt` becoming able to accept another request, sent after
+    /// this is called.
+    pub(super) fn request_done_receiver(&self) -> Receiver<()> {
+        self.request_done.1.activate_cloned()
+    }
+
+    /// Returns an `ErrorKind::NotReady` error if a service discovery is in progress, instead of
+    /// letting the operation wait behind it and possibly fail with an unrelated error.
+    pub(super) fn check_not_discovering(&self) -> Result<(), Error> {
+        if self.discover_services.is_locked() {
+            return Err(Error::new(
+                ErrorKind::NotReady,
+                None,
+                "service discovery is in progress",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Makes all pending and future operations on this connection fail immediately.
+    fn abort_all_operations(&self) {
+        self.gatt_connect.abort_all();
+        self.discover_services.abort_all();
+        self.read_rssi.abort_all();
+        self.read_phy.abort_all();
+        self.mtu_changed_received.abort_all();
+        self.connection_update.abort_all();
+        for serv in self.services.lock().unwrap().values() {
+            for char in serv.chars.values() {
+                char.read.abort_all();
+                char.write.abort_all();
+                for desc in &char.descs {
+                    desc.read.abort_all();
+                    desc.write.abort_all();
+                }
+            }
+        }
+    }
+
     /// Refresh available services according to the result of `BluetoothGatt.getServices()`.
     /// This does not perform real device discovering.
-    pub fn refresh_services(&self) -> Result<(), crate::Error> {
+    ///
+    /// The new layout is compared with the existing tree: characteristics whose identity
+    /// (instance IDs and UUIDs, including those of descriptors) is unchanged are kept, so
+    /// their subscriptions and pending operations survive. The tree is left untouched on error.
+    pub fn refresh_services(&self) -> Result<ServicesDiff, crate::Error> {
         let mut services = self.services.lock().unwrap();
-        let mut current_services_ids = Vec::new();
+        if let Some(previous) = self.services_before_change.lock().unwrap().take() {
+            *services = previous;
+        }
+        let mut new_services = HashMap::new();
+        let mut diff = ServicesDiff::default();
+        let started = Instant::now();
+        let mut attr_count = 0;
         jni_with_env(|env| {
             let gatt = self.gatt.as_ref(env);
             let services_obj = gatt.getServices()?.non_null()?;
-            let iter = JavaIterator(services_obj.iterator()?.non_null()?);
-            for service_obj in iter.filter_map(|o| o.cast::<BluetoothGattService>().ok()) {
+            for service_obj in java_list_items(&services_obj)?
+                .into_iter()
+                .filter_map(|o| o.cast::<BluetoothGattService>().ok())
+            {
+                let instance_id = service_obj.getInstanceId()?;
                 let service_id = Uuid::from_java(service_obj.getUuid()?.non_null()?.as_ref())?;
-                current_services_ids.push(service_id);
-                if services.get(&service_id).is_none() {
-                    services.insert(
-                        service_id,
-                        Arc::new(construct_service_tree(&service_obj.as_ref())?),
-                    );
+                // the same instance ID may be reused by another service after a service change.
+                let old = services
+                    .get(&instance_id)
+                    .filter(|serv| serv.uuid == service_id)
+                    .map(|serv| serv.as_ref());
+                let (serv, unchanged) = construct_service_tree(
+                    &service_obj.as_ref(),
+                    (service_id, instance_id),
+                    old,
+                    &self.options,
+                )?;
+                attr_count += 1 + serv
+                    .chars
+                    .values()
+                    .map(|ch| 1 + ch.descs.len())
+                    .sum::<usize>();
+                match old {
+                    None => diff.added.push((service_id, instance_id)),
+                    Some(_) if !unchanged => diff.invalidated.push((service_id, instance_id)),
+                    Some(_) => (),
                 }
+                new_services.insert(instance_id, Arc::new(serv));
             }
-            services.retain(|id, _| current_services_ids.contains(id));
-            Ok(())
-        })
+            Ok::<_, crate::Error>(())
+        })?;
+        for (instance_id, serv) in services.iter() {
+            if !matches!(new_services.get(instance_id), Some(new) if new.uuid == serv.uuid) {
+                diff.invalidated.push((serv.uuid, *instance_id));
+            }
+        }
+        // values may be changed along with the services, even if the characteristic is kept.
+        for char in new_services.values().flat_map(|serv| serv.chars.values()) {
+            char.read_cache.lock().unwrap().take();
+        }
+        // re-enable notifications with new Java objects for subscriptions that are kept.
+        jni_with_env(|env| {
+            let gatt = self.gatt.as_ref(env);
+            let gatt = Monitor::new(&gatt);
+            for serv in new_services.values() {
+                for char in serv.chars.values().filter(|ch| ch.notify.is_notifying()) {
+                    let res = gatt.setCharacteristicNotification(char.char_obj().as_ref(env), true);
+                    if !matches!(res, Ok(true)) {
+                        warn!(
+                            "failed to re-enable notification for characteristic {}",
+                            char.uuid
+                        );
+                    }
+                }
+            }
+        });
+        *services = new_services;
+        diff.generation = bump_tree_generation(&self.tree_generation);
+        diff.handle_range = self.changed_handle_range.lock().unwrap().take();
+        debug!(
+            "built the service tree of {attr_count} attributes in {:?}",
+            started.elapsed()
+        );
+        Ok(diff)
     }
 }
 
+/// Constructs the service entry from `service_obj`, reusing characteristic entries of `old`
+/// if their identities are unchanged. Returns the entry and whether it is identical to `old`.
 fn construct_service_tree<'env>(
     service_obj: &Ref<'env, BluetoothGattService>,
-) -> Result<ServiceInner, crate::Error> {
+    (service_id, instance_id): (Uuid, i32),
+    old: Option<&ServiceInner>,
+    options: &GattOptions,
+) -> Result<(ServiceInner, bool), crate::Error> {
     let chars_obj = service_obj.getCharacteristics()?.non_null()?;
-    let iter = JavaIterator(chars_obj.iterator()?.non_null()?);
     let mut chars = HashMap::new();
-    for char_obj in iter.filter_map(|o| o.cast::<BluetoothGattCharacteristic>().ok()) {
+    let mut reused_count = 0;
+    for char_obj in java_list_items(&chars_obj)?
+        .into_iter()
+        .filter_map(|o| o.cast::<BluetoothGattCharacteristic>().ok())
+    {
         let char_id = Uuid::from_java(char_obj.getUuid()?.non_null()?.as_ref())?;
+        let char_instance = char_obj.getInstanceId()?;
         let descs_obj = char_obj.getDescriptors()?.non_null()?;
-        let iter = JavaIterator(descs_obj.iterator()?.non_null()?);
-        let mut descs = HashMap::new();
-        for desc_obj in iter.filter_map(|o| o.cast::<BluetoothGattDescriptor>().ok()) {
+        let mut desc_objs = Vec::new();
+        for desc_obj in java_list_items(&descs_obj)?
+            .into_iter()
+            .filter_map(|o| o.cast::<BluetoothGattDescriptor>().ok())
+        {
             let desc_id = Uuid::from_java(desc_obj.getUuid()?.non_null()?.as_ref())?;
-            descs.insert(
-                desc_id,
-                Arc::new(DescriptorInner {
-                    desc: desc_obj.as_global(),
-                    read: Excluder::default(),
-                    write: Excluder::default(),
-                }),
-            );
+            desc_objs.push((desc_id, desc_obj));
+        }
+
+        let old_char = old
+            .and_then(|serv| serv.chars.get(&char_instance))
+            .filter(|ch| {
+                ch.uuid == char_id
+                    && ch.descs.len() == desc_objs.len()
+                    && ch
+                        .descs
+                        .iter()
+                        .zip(&desc_objs)
+                        .all(|(d, (id, _))| d.uuid == *id)
+            });
+        if let Some(old_char) = old_char {
+            *old_char.char.lock().unwrap() = char_obj.as_global();
+            for (desc, (_, desc_obj)) in old_char.descs.iter().zip(&desc_objs) {
+                *desc.desc.lock().unwrap() = desc_obj.as_global();
+            }
+            chars.insert(char_instance, old_char.clone());
+            reused_count += 1;
+            continue;
         }
+
+        let descs = desc_objs
+            .into_iter()
+            .map(|(desc_id, desc_obj)| {
+                Arc::new(DescriptorInner {
+                    desc: Mutex::new(desc_obj.as_global()),
+                    uuid: desc_id,
+                    read: options.excluder(OperationKind::ReadDescriptor),
+                    write: options.excluder(OperationKind::WriteDescriptor),
+                })
+            })
+            .collect();
         chars.insert(
-            char_id,
+            char_instance,
             Arc::new(CharacteristicInner {
-                char: char_obj.as_global(),
+                char: Mutex::new(char_obj.as_global()),
+                uuid: char_id,
+                instance_id: char_instance,
+                properties: CharacteristicProperties::from_bits(char_obj.getProperties()? as u32),
                 descs,
-                notify: Notifier::new(128),
-                read: Excluder::default(),
-                write: Excluder::default(),
+                notify: Notifier::new(128, options.notify_overflow)
+                    .with_overflow_marker(notify_overflow_error)
+                    .with_replay_last(options.notify_replay_last),
+                read: options.excluder(OperationKind::ReadCharacteristic),
+                write: options.excluder(OperationKind::WriteCharacteristic),
+                read_cache: Mutex::new(None),
+                read_refresh: Arc::new(async_lock::Mutex::new(())),
             }),
         );
     }
-    Ok(ServiceInner {
+    let includes_obj = service_obj.getIncludedServices()?.non_null()?;
+    let mut includes = Vec::new();
+    for incl_obj in java_list_items(&includes_obj)?
+        .into_iter()
+        .filter_map(|o| o.cast::<BluetoothGattService>().ok())
+    {
+        let incl_id = Uuid::from_java(incl_obj.getUuid()?.non_null()?.as_ref())?;
+        includes.push((incl_id, incl_obj.getInstanceId()?));
+    }
+    let serv = ServiceInner {
         service: service_obj.as_global(),
+        uuid: service_id,
+        instance_id,
+        is_primary: service_obj.getType()? == BluetoothGattService::SERVICE_TYPE_PRIMARY,
+        includes,
         chars,
-    })
+    };
+    let unchanged = old.is_some_and(|old| {
+        reused_count == old.chars.len()
+            && reused_count == serv.chars.len()
+            && old.is_primary == serv.is_primary
+            && old.includes == serv.includes
+    });
+    Ok((serv, unchanged))
+}
+
+/// The item received in place of lost notification values under [OverflowPolicy::Error].
+fn notify_overflow_error() -> Result<Vec<u8>, Error> {
+    Err(Error::new(
+        crate::error::ErrorKind::Other,
+        None,
+        "notification values were lost because the receiver could not keep up",
+    ))
 }
 
 fn callback_find_char(
     dev_id: &DeviceId,
     char: &Option<Ref<'_, BluetoothGattCharacteristic>>,
 ) -> Option<Arc<CharacteristicInner>> {
-    let service_id =
-        Uuid::from_java(char.as_ref()?.getService().ok()??.getUuid().ok()??.as_ref()).ok()?;
-    let char_id = Uuid::from_java(char.as_ref()?.getUuid().ok()??.as_ref()).ok()?;
-    GattTree::find_characteristic(dev_id, service_id, char_id)
+    let service_instance = char.as_ref()?.getService().ok()??.getInstanceId().ok()?;
+    let char_instance = char.as_ref()?.getInstanceId().ok()?;
+    GattTree::find_characteristic(dev_id, service_instance, char_instance)
 }
 
 fn callback_find_desc(
     dev_id: &DeviceId,
     desc: &Option<Ref<'_, BluetoothGattDescriptor>>,
 ) -> Option<Arc<DescriptorInner>> {
-    let char = desc.as_ref()?.getCharacteristic().ok()??;
+    let desc = desc.as_ref()?;
+    let char = desc.getCharacteristic().ok()??;
     let char = callback_find_char(dev_id, &Some(char.as_ref()))?;
-    let desc_id = Uuid::from_java(desc.as_ref()?.getUuid().ok()??.as_ref()).ok()?;
-    char.descs.get(&desc_id).cloned()
+    // Android passes the same descriptor objects as those got from `BluetoothGatt.getServices()`.
+    if let Some(desc_item) = char
+        .descs
+        .iter()
+        .find(|item| is_same_object(&item.desc_obj().as_ref(desc.env()), desc))
+    {
+        return Some(desc_item.clone());
+    }
+    let desc_id = Uuid::from_java(desc.getUuid().ok()??.as_ref()).ok()?;
+    char.descs.iter().find(|item| item.uuid == desc_id).cloned()
 }
 
 pub struct BluetoothGattCallbackProxy {
     dev_id: DeviceId,
     discover_services_on_change: Mutex<Option<ResultWaiter<Result<(), Error>>>>,
+    notify_routes: NotifyRoutes<CharacteristicInner>,
+}
+
+/// Characteristics found in notification callbacks, keyed by characteristic instance IDs
+/// (attribute handles, unique within the device); this avoids `getService()` calls.
+struct NotifyRoutes<T> {
+    routes: Mutex<HashMap<i32, CachedWeak<T>>>,
+}
+
+impl<T> NotifyRoutes<T> {
+    fn new() -> Self {
+        Self {
+            routes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached item of `char_instance`; `finder` is called only if it is not cached
+    /// or outdated by a change of the GATT tree.
+    fn find(
+        &self,
+        dev_id: &DeviceId,
+        char_instance: i32,
+        finder: impl FnOnce() -> Option<Arc<T>>,
+    ) -> Option<Arc<T>> {
+        let mut routes = self.routes.lock().unwrap();
+        routes
+            .entry(char_instance)
+            .or_insert_with(CachedWeak::new)
+            .get_or_find(dev_id, || finder().ok_or(()))
+            .ok()
+    }
+
+    fn clear(&self) {
+        self.routes.lock().unwrap().clear();
+    }
 }
 
 impl BluetoothGattCallbackProxy {
@@ -347,6 +1046,42 @@ impl BluetoothGattCallbackProxy {
         Arc::new(Self {
             dev_id,
             discover_services_on_change: Mutex::new(None),
+            notify_routes: NotifyRoutes::new(),
+        })
+    }
+
+    /// Returns the handler to be passed to the Java proxy, which also calls the raw callback
+    /// hook if it is set in `options`.
+    #[cfg_attr(not(feature = "unstable-jni"), allow(unused_variables))]
+    pub fn java_handler(
+        self: &Arc<Self>,
+        options: &GattOptions,
+    ) -> Arc<dyn super::callback::BluetoothGattCallbackProxy> {
+        #[cfg(feature = "unstable-jni")]
+        if let Some(hook) = options.raw_callback_hook.clone() {
+            return Arc::new(super::unstable_jni::HookedGattCallback {
+                inner: self.clone(),
+                hook,
+            });
+        }
+        self.clone()
+    }
+
+    /// Called by callbacks of reads and writes, after which `BluetoothGatt` accepts another request.
+    fn on_request_done(&self) {
+        if let Some(conn) = GattTree::find_connection(&self.dev_id) {
+            let _ = conn.request_done.0.try_broadcast(());
+        }
+    }
+
+    /// Finds the characteristic for notification callbacks, which may be very frequent.
+    fn find_notifying_char(
+        &self,
+        char: &Option<Ref<'_, BluetoothGattCharacteristic>>,
+    ) -> Option<Arc<CharacteristicInner>> {
+        let char_instance = char.as_ref()?.getInstanceId().ok()?;
+        self.notify_routes.find(&self.dev_id, char_instance, || {
+            callback_find_char(&self.dev_id, char)
         })
     }
 }
@@ -354,37 +1089,97 @@ impl BluetoothGattCallbackProxy {
 impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy {
     fn onPhyUpdate<'env>(
         &self,
-        _: Env<'env>,
-        _: Option<Ref<'env, BluetoothGatt>>,
-        _: i32,
-        _: i32,
-        _: i32,
+        _env: Env<'env>,
+        _gatt: Option<Ref<'env, BluetoothGatt>>,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
     ) {
+        log_callback!(
+            "onPhyUpdate of {}: tx_phy {tx_phy}, rx_phy {rx_phy}, status {status}",
+            self.dev_id
+        );
+        let Some(conn) = GattTree::find_connection(&self.dev_id) else {
+            return;
+        };
+        if let Ok(phy) = phy_result_check(tx_phy, rx_phy, status) {
+            conn.current_phy.lock().unwrap().replace(phy);
+        }
     }
+
     fn onPhyRead<'env>(
         &self,
-        _: Env<'env>,
-        _: Option<Ref<'env, BluetoothGatt>>,
-        _: i32,
-        _: i32,
-        _: i32,
+        _env: Env<'env>,
+        _gatt: Option<Ref<'env, BluetoothGatt>>,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
     ) {
+        log_callback!(
+            "onPhyRead of {}: tx_phy {tx_phy}, rx_phy {rx_phy}, status {status}",
+            self.dev_id
+        );
+        let Some(conn) = GattTree::find_connection(&self.dev_id) else {
+            return;
+        };
+        let result = phy_result_check(tx_phy, rx_phy, status);
+        if let Ok(phy) = result {
+            conn.current_phy.lock().unwrap().replace(phy);
+        }
+        conn.read_phy.unlock(result);
+    }
+
+    fn onConnectionUpdated<'env>(
+        &self,
+        _env: Env<'env>,
+        _gatt: Option<Ref<'env, BluetoothGatt>>,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    ) {
+        log_callback!(
+            "onConnectionUpdated of {}: interval {interval}, latency {latency}, timeout {timeout}, status {status}",
+            self.dev_id
+        );
+        let Some(conn) = GattTree::find_connection(&self.dev_id) else {
+            return;
+        };
+        let result = gatt_error_check(status).map(|_| {
+            ConnectionParameters::new(
+                interval as u16,
+                interval as u16,
+                latency as u16,
+                timeout as u16,
+            )
+        });
+        conn.connection_update.unlock(result);
     }
 
     fn onConnectionStateChange<'env>(
         &self,
         _env: Env<'env>,
         _gatt: Option<Ref<'env, BluetoothGatt>>,
-        _status: i32,
+        status: i32,
         new_state: i32,
     ) {
+        log_callback!(
+            "onConnectionStateChange of {}: status {status}, new_state {new_state}",
+            self.dev_id
+        );
         #[allow(clippy::collapsible_if)]
         if new_state == BluetoothProfile::STATE_CONNECTED {
             CONNECTION_EVENTS.notify((self.dev_id.clone(), ConnectionEvent::Connected));
             if let Some(conn) = GattTree::find_connection(&self.dev_id) {
+                let _ = conn.connected_at.set(SystemTime::now());
                 conn.gatt_connect.unlock(());
             }
         } else if new_state == BluetoothProfile::STATE_DISCONNECTED {
+            if let Some(conn) = GattTree::find_connection(&self.dev_id) {
+                let _ = conn
+                    .disconnect_reason
+                    .set(DisconnectReason::from_status(status));
+            }
             if GattTree::deregister_connection(&self.dev_id) {
                 info!(
                     "deregistered connection with {} in onConnectionStateChange()",
@@ -400,22 +1195,34 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         _gatt: Option<Ref<'env, BluetoothGatt>>,
         status: i32,
     ) {
+        log_callback!("onServicesDiscovered of {}: status {status}", self.dev_id);
         info!("onServicesDiscovered of {}, status {status}", self.dev_id);
         let Some(conn) = GattTree::find_connection(&self.dev_id) else {
             return;
         };
-        if let Err(e) = conn.refresh_services() {
-            error!("refresh_services failed during onServicesDiscovered(): {e}");
-        }
+        // The tree provided by Android may be incomplete if the discovery failed; keep the
+        // previous tree in that case.
         let status = gatt_error_check(status);
-        if let Err(e) = &status {
-            error!("onServicesDiscovered() with error status: {e}");
-        }
+        let diff = match &status {
+            Ok(()) => Some(conn.refresh_services().unwrap_or_else(|e| {
+                error!("refresh_services failed during onServicesDiscovered(): {e}");
+                ServicesDiff {
+                    generation: conn.tree_generation.load(Ordering::SeqCst),
+                    ..Default::default()
+                }
+            })),
+            Err(e) => {
+                error!("onServicesDiscovered() with error status: {e}");
+                None
+            }
+        };
         conn.discover_services.unlock(status);
 
         // see onServiceChanged().
         let _ = self.discover_services_on_change.lock().unwrap().take();
-        conn.services_changes.notify(());
+        if let Some(diff) = diff {
+            conn.services_changes.notify(diff);
+        }
     }
 
     fn onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_int<'env>(
@@ -425,9 +1232,15 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         char: Option<Ref<'env, BluetoothGattCharacteristic>>,
         status: i32,
     ) {
+        log_callback!(
+            "onCharacteristicRead of {}: characteristic {}, status {status}",
+            self.dev_id,
+            logged_char(&char)
+        );
         if android_api_level() >= 33 {
             return;
         }
+        self.on_request_done();
 
         let Some(char_item) = callback_find_char(&self.dev_id, &char) else {
             return;
@@ -443,7 +1256,7 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
                 .non_null()?
                 .getValue()?
                 .non_null()
-                .map(|val| val.as_vec_u8())
+                .map(|val| ReadValue::new(&val))
         };
         char_item.read.unlock(get_data());
     }
@@ -456,6 +1269,13 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         data: Option<Ref<'env, ByteArray>>,
         status: i32,
     ) {
+        log_callback!(
+            "onCharacteristicRead of {}: characteristic {}, value {}, status {status}",
+            self.dev_id,
+            logged_char(&char),
+            LoggedValue::from_java(&data)
+        );
+        self.on_request_done();
         let Some(char_item) = callback_find_char(&self.dev_id, &char) else {
             return;
         };
@@ -467,7 +1287,7 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         };
         char_item
             .read
-            .unlock(gatt_error_check(status).map(|_| jarr.as_vec_u8()));
+            .unlock(gatt_error_check(status).map(|_| ReadValue::new(&jarr)));
     }
 
     fn onCharacteristicWrite<'env>(
@@ -477,6 +1297,12 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         char: Option<Ref<'env, BluetoothGattCharacteristic>>,
         status: i32,
     ) {
+        log_callback!(
+            "onCharacteristicWrite of {}: characteristic {}, status {status}",
+            self.dev_id,
+            logged_char(&char)
+        );
+        self.on_request_done();
         let Some(char_item) = callback_find_char(&self.dev_id, &char) else {
             return;
         };
@@ -489,11 +1315,16 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         _gatt: Option<Ref<'env, BluetoothGatt>>,
         char: Option<Ref<'env, BluetoothGattCharacteristic>>,
     ) {
+        log_callback!(
+            "onCharacteristicChanged of {}: characteristic {}",
+            self.dev_id,
+            logged_char(&char)
+        );
         if android_api_level() >= 33 {
             return;
         }
 
-        let Some(char_item) = callback_find_char(&self.dev_id, &char) else {
+        let Some(char_item) = self.find_notifying_char(&char) else {
             return;
         };
         // XXX: is this thread-safe?
@@ -515,7 +1346,13 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         char: Option<Ref<'env, BluetoothGattCharacteristic>>,
         data: Option<Ref<'env, ByteArray>>,
     ) {
-        let Some(char_item) = callback_find_char(&self.dev_id, &char) else {
+        log_callback!(
+            "onCharacteristicChanged of {}: characteristic {}, value {}",
+            self.dev_id,
+            logged_char(&char),
+            LoggedValue::from_java(&data)
+        );
+        let Some(char_item) = self.find_notifying_char(&char) else {
             return;
         };
         let result = data.non_null().map(|jarr| jarr.as_vec_u8());
@@ -529,9 +1366,15 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         desc: Option<Ref<'env, BluetoothGattDescriptor>>,
         status: i32,
     ) {
+        log_callback!(
+            "onDescriptorRead of {}: descriptor {}, status {status}",
+            self.dev_id,
+            logged_desc(&desc)
+        );
         if android_api_level() >= 33 {
             return;
         }
+        self.on_request_done();
 
         let Some(desc_item) = callback_find_desc(&self.dev_id, &desc) else {
             return;
@@ -560,6 +1403,13 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         status: i32,
         data: Option<Ref<'env, ByteArray>>,
     ) {
+        log_callback!(
+            "onDescriptorRead of {}: descriptor {}, value {}, status {status}",
+            self.dev_id,
+            logged_desc(&desc),
+            LoggedValue::from_java(&data)
+        );
+        self.on_request_done();
         let Some(desc_item) = callback_find_desc(&self.dev_id, &desc) else {
             return;
         };
@@ -581,6 +1431,12 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         desc: Option<Ref<'env, BluetoothGattDescriptor>>,
         status: i32,
     ) {
+        log_callback!(
+            "onDescriptorWrite of {}: descriptor {}, status {status}",
+            self.dev_id,
+            logged_desc(&desc)
+        );
+        self.on_request_done();
         let Some(desc_item) = callback_find_desc(&self.dev_id, &desc) else {
             return;
         };
@@ -590,9 +1446,14 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
     fn onReliableWriteCompleted<'env>(
         &self,
         _env: Env<'env>,
-        _arg0: Option<Ref<'env, BluetoothGatt>>,
-        _arg1: i32,
+        _gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
     ) {
+        log_callback!(
+            "onReliableWriteCompleted of {}: status {status}",
+            self.dev_id
+        );
+        self.on_request_done();
     }
 
     fn onReadRemoteRssi<'env>(
@@ -602,6 +1463,10 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         rssi: i32,
         status: i32,
     ) {
+        log_callback!(
+            "onReadRemoteRssi of {}: rssi {rssi}, status {status}",
+            self.dev_id
+        );
         let Some(conn) = GattTree::find_connection(&self.dev_id) else {
             return;
         };
@@ -614,23 +1479,43 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
         _env: Env<'env>,
         _gatt: Option<Ref<'env, BluetoothGatt>>,
         mtu: i32,
-        _status: i32,
+        status: i32,
     ) {
+        log_callback!(
+            "onMtuChanged of {}: mtu {mtu}, status {status}",
+            self.dev_id
+        );
         let Some(conn) = GattTree::find_connection(&self.dev_id) else {
             return;
         };
         // this should be true
         if let Ok(mtu) = usize::try_from(mtu) {
-            info!("onMtuChanged of {}, mtu is {mtu}", self.dev_id);
+            info!(
+                "onMtuChanged of {}, mtu is {mtu}, status {status}",
+                self.dev_id
+            );
+            if gatt_error_check(status).is_ok() {
+                conn.mtu_negotiated.store(true, Ordering::SeqCst);
+            }
             conn.mtu_changed_received.unlock(mtu);
         }
     }
 
     fn onServiceChanged<'env>(&self, _env: Env<'env>, gatt: Option<Ref<'env, BluetoothGatt>>) {
+        log_callback!("onServiceChanged of {}", self.dev_id);
         let Some(conn) = GattTree::find_connection(&self.dev_id) else {
             return;
         };
         info!("onServiceChanged of {}", self.dev_id);
+        // Drops all entries at once, so that existing handles fail with `ErrorKind::ServiceChanged`
+        // instead of operating on stale Java objects until the rediscovery is completed; then
+        // entries that are unchanged are restored by the diffing rebuild in `refresh_services()`.
+        let previous = std::mem::take(&mut *conn.services.lock().unwrap());
+        let _ = conn
+            .services_before_change
+            .lock()
+            .unwrap()
+            .get_or_insert(previous);
         if let Some(disc_lock) = conn.discover_services.try_lock() {
             let gatt = Monitor::new(gatt.as_ref().unwrap());
             if let Err(e) = gatt
@@ -650,6 +1535,14 @@ impl super::callback::BluetoothGattCallbackProxy for BluetoothGattCallbackProxy
     }
 }
 
+fn phy_result_check(tx_phy: i32, rx_phy: i32, status: i32) -> Result<(Phy, Phy), Error> {
+    gatt_error_check(status)?;
+    match (Phy::from_java(tx_phy), Phy::from_java(rx_phy)) {
+        (Some(tx), Some(rx)) => Ok((tx, rx)),
+        _ => Err(AttError::UNLIKELY_ERROR.into()),
+    }
+}
+
 fn gatt_error_check(status: i32) -> Result<(), Error> {
     if status == AttError::SUCCESS.as_u8() as i32 {
         Ok(())
@@ -659,3 +1552,159 @@ fn gatt_error_check(status: i32) -> Result<(), Error> {
         Err(AttError::UNLIKELY_ERROR.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Simulates many connect/disconnect cycles in which an explicit disconnection, the
+    // disconnection callback and the adapter being turned off race to close the same client.
+    #[test]
+    fn open_client_soak() {
+        let before = GattTree::open_gatt_count();
+        for _ in 0..500 {
+            let client = Arc::new(OpenGattClient::new());
+            assert_eq!(GattTree::open_gatt_count(), before + 1);
+            let closers: Vec<_> = (0..3)
+                .map(|_| {
+                    let client = client.clone();
+                    std::thread::spawn(move || client.release())
+                })
+                .collect();
+            let closed = closers
+                .into_iter()
+                .map(|closer| closer.join().unwrap())
+                .filter(|&closed| closed)
+                .count();
+            assert_eq!(closed, 1);
+            assert!(!client.release());
+            assert_eq!(GattTree::open_gatt_count(), before);
+        }
+    }
+
+    // Simulates connections of many devices being registered and deregistered: handles cached
+    // during a connection are outdated by the deregistration, and no per-device state is left
+    // once the connections and their handles are gone.
+    #[test]
+    fn registry_soak_leaves_no_state() {
+        let ids: Vec<_> = (0..20)
+            .map(|i| DeviceId(format!("00:00:00:00:A0:{i:02X}")))
+            .collect();
+        for cycle in 0..200 {
+            let dev_id = &ids[cycle % ids.len()];
+            // registration
+            let generation = tree_generation(dev_id);
+            bump_tree_generation(&generation);
+            let inner = Arc::new(cycle);
+            let cached = CachedWeak::from_arc(dev_id, &inner);
+            assert_eq!(cached.get(dev_id), Some(inner.clone()));
+            // deregistration
+            bump_tree_generation(&generation);
+            assert_eq!(cached.get(dev_id), None);
+            drop((generation, inner, cached));
+        }
+        // creating another entry prunes the entries of dropped connections.
+        let _other = tree_generation(&DeviceId("00:00:00:00:A1:00".into()));
+        let generations = TREE_GENERATIONS.lock().unwrap();
+        assert!(ids.iter().all(|id| !generations.contains_key(id)));
+    }
+
+    #[test]
+    fn cached_weak_outdated_by_reconnection() {
+        let dev_id = DeviceId("00:11:22:33:44:55".into());
+        let other_id = DeviceId("66:77:88:99:AA:BB".into());
+        // held by the registered connection.
+        let generation = tree_generation(&dev_id);
+        let old_item = Arc::new(1);
+        let cached = CachedWeak::new();
+        let found = cached.get_or_find(&dev_id, || Ok::<_, ()>(old_item.clone()));
+        assert_eq!(found, Ok(old_item.clone()));
+
+        // changes of another device don't outdate the item.
+        bump_tree_generation(&tree_generation(&other_id));
+        assert_eq!(cached.get(&dev_id), Some(old_item.clone()));
+
+        // deregistered, accessed while the old item is still referenced, then registered again.
+        bump_tree_generation(&generation);
+        assert_eq!(cached.get(&dev_id), None);
+        bump_tree_generation(&generation);
+        let new_item = Arc::new(2);
+        let found = cached.get_or_find(&dev_id, || Ok::<_, ()>(new_item.clone()));
+        assert_eq!(found, Ok(new_item.clone()));
+        assert_eq!(cached.clone().get(&dev_id), Some(new_item.clone()));
+
+        // modified while the item is being found.
+        let cached = CachedWeak::new();
+        let found = cached.get_or_find(&dev_id, || {
+            bump_tree_generation(&generation);
+            Ok::<_, ()>(new_item.clone())
+        });
+        assert_eq!(found, Ok(new_item));
+        assert_eq!(cached.get(&dev_id), None);
+    }
+
+    #[test]
+    fn cached_weak_releases_replaced_items() {
+        let dev_id = DeviceId("00:11:22:33:44:66".into());
+        let generation = tree_generation(&dev_id);
+        let (old_item, new_item) = (Arc::new(1), Arc::new(2));
+        let cached = CachedWeak::new();
+        let _ = cached.get_or_find(&dev_id, || Ok::<_, ()>(old_item.clone()));
+        let cloned = cached.clone();
+        assert_eq!(Arc::weak_count(&old_item), 2);
+
+        for _ in 0..3 {
+            bump_tree_generation(&generation);
+            let _ = cached.get_or_find(&dev_id, || Ok::<_, ()>(new_item.clone()));
+        }
+        assert_eq!(Arc::weak_count(&old_item), 1);
+        assert_eq!(Arc::weak_count(&new_item), 1);
+        drop((cached, cloned));
+        assert_eq!(Arc::weak_count(&old_item), 0);
+        assert_eq!(Arc::weak_count(&new_item), 0);
+    }
+
+    // The slow lookup through `getService()` is done once per characteristic and tree generation.
+    #[test]
+    fn notify_routes_find_once() {
+        use std::cell::Cell;
+
+        let dev_id = DeviceId("00:11:22:33:44:77".into());
+        let generation = tree_generation(&dev_id);
+        let routes = NotifyRoutes::new();
+        let (first, second) = (Arc::new(1), Arc::new(2));
+        let lookups = Cell::new(0);
+        let find = |instance, item: &Arc<i32>| {
+            routes.find(&dev_id, instance, || {
+                lookups.set(lookups.get() + 1);
+                Some(item.clone())
+            })
+        };
+        for _ in 0..100 {
+            assert_eq!(find(11, &first), Some(first.clone()));
+            assert_eq!(find(13, &second), Some(second.clone()));
+        }
+        assert_eq!(lookups.get(), 2);
+
+        bump_tree_generation(&generation);
+        assert_eq!(find(11, &first), Some(first.clone()));
+        assert_eq!(lookups.get(), 3);
+        routes.clear();
+        assert_eq!(find(11, &first), Some(first));
+        assert_eq!(lookups.get(), 4);
+    }
+
+    #[test]
+    fn tree_generation_increases() {
+        let dev_id = DeviceId("00:11:22:33:44:66".into());
+        let generation = tree_generation(&dev_id);
+        let first = bump_tree_generation(&generation);
+        bump_tree_generation(&tree_generation(&DeviceId("66:77:88:99:AA:CC".into())));
+        let second = bump_tree_generation(&generation);
+        assert!(second > first);
+        assert_eq!(generation.load(Ordering::SeqCst), second);
+        drop(generation);
+        // a counter created again doesn't go back.
+        assert!(tree_generation(&dev_id).load(Ordering::SeqCst) >= second);
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::cell::{Cell, OnceCell, RefCell};
 use std::ptr::null_mut;
-use std::slice::from_raw_parts;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
 
 use java_spaghetti::sys::*;
 use java_spaghetti::{ByteArray, Env, Local, PrimitiveArray, Ref, ReferenceType};
@@ -34,6 +34,14 @@ impl VM {
     where
         F: for<'env> FnOnce(Env<'env>) -> R,
     {
+        // nested calls reuse the env and the local frame of the outermost call;
+        // threads attached by this module keep the env until the thread exits.
+        if let Some(env) = get_cached_env(self.0) {
+            let env = unsafe { Env::from_raw(env) };
+            let _guard = NestGuard::enter(env, false, self.0);
+            return callback(env);
+        }
+
         let mut env = null_mut();
         let just_attached = match unsafe {
             ((**self.0).v1_2.GetEnv)(self.0, &mut env, JNI_VERSION_1_2)
@@ -46,7 +54,7 @@ impl VM {
                     panic!("AttachCurrentThread returned unknown error: {ret}")
                 }
                 if !get_thread_exit_flag() {
-                    set_thread_attach_flag(self.0);
+                    set_thread_attach_flag(self.0, env as _);
                 }
                 true
             }
@@ -56,16 +64,37 @@ impl VM {
 
         let env = unsafe { Env::from_raw(env as _) };
         // pushing/popping local frame is a workaround for the local reference leakage bug in `java-spaghetti` 0.2.0.
+        // the guard pops the frame (and detaches if needed) even if `callback` panics.
+        let _guard = NestGuard::enter(env, just_attached, self.0);
+        callback(env)
+    }
+}
+
+/// Keeps the nest level of `with_env` balanced, even if the callback unwinds.
+struct NestGuard<'env> {
+    env: Env<'env>,
+    just_attached: bool,
+    raw_vm: *mut JavaVM,
+}
+
+impl<'env> NestGuard<'env> {
+    fn enter(env: Env<'env>, just_attached: bool, raw_vm: *mut JavaVM) -> Self {
         increase_nest_level(env);
-        let result = callback(env);
-        decrease_nest_level(env);
+        Self {
+            env,
+            just_attached,
+            raw_vm,
+        }
+    }
+}
 
-        if just_attached && get_thread_exit_flag() {
+impl Drop for NestGuard<'_> {
+    fn drop(&mut self) {
+        decrease_nest_level(self.env);
+        if self.just_attached && get_thread_exit_flag() {
             // this is needed in case of `with_env` is used on dropping some thread-local instance.
-            unsafe { ((**self.0).v1_2.DetachCurrentThread)(self.0) };
+            unsafe { ((**self.raw_vm).v1_2.DetachCurrentThread)(self.raw_vm) };
         }
-
-        result
     }
 }
 
@@ -81,11 +110,13 @@ impl From<VM> for java_spaghetti::VM {
 thread_local! {
     static THREAD_ATTACH_FLAG: RefCell<Option<AttachFlag>> = const { RefCell::new(None) };
     static WITH_ENV_NEST_LEVEL: Cell<usize> = const { Cell::new(0) };
+    static OUTER_ENV: Cell<*mut JNIEnv> = const { Cell::new(null_mut()) };
     static THREAD_EXIT_FLAG: OnceCell<()> = const { OnceCell::new() };
 }
 
 struct AttachFlag {
     raw_vm: *mut JavaVM,
+    env: *mut JNIEnv,
 }
 
 impl Drop for AttachFlag {
@@ -96,8 +127,41 @@ impl Drop for AttachFlag {
     }
 }
 
-fn set_thread_attach_flag(raw_vm: *mut JavaVM) {
-    THREAD_ATTACH_FLAG.replace(Some(AttachFlag { raw_vm }));
+fn set_thread_attach_flag(raw_vm: *mut JavaVM, env: *mut JNIEnv) {
+    THREAD_ATTACH_FLAG.replace(Some(AttachFlag { raw_vm, env }));
+}
+
+/// Returns the env of the outer `with_env` call, or the env of the current thread if it is
+/// attached by this module (it stays attached until the thread exits). Threads attached by
+/// others (e.g. the Java main thread) are not cached outside of `with_env`, because they may be
+/// detached by their owners at any time.
+fn get_cached_env(raw_vm: *mut JavaVM) -> Option<*mut JNIEnv> {
+    let env = OUTER_ENV
+        .try_with(|env| env.get())
+        .ok()
+        .filter(|env| !env.is_null())
+        .or_else(|| {
+            THREAD_ATTACH_FLAG
+                .try_with(|flag| {
+                    flag.try_borrow()
+                        .ok()?
+                        .as_ref()
+                        .filter(|flag| flag.raw_vm == raw_vm)
+                        .map(|flag| flag.env)
+                })
+                .ok()
+                .flatten()
+        })?;
+    debug_assert_eq!(
+        {
+            let mut current = null_mut();
+            unsafe { ((**raw_vm).v1_2.GetEnv)(raw_vm, &mut current, JNI_VERSION_1_2) };
+            current as *mut JNIEnv
+        },
+        env,
+        "cached `JNIEnv` doesn't belong to the current thread"
+    );
+    Some(env)
 }
 
 fn get_thread_exit_flag() -> bool {
@@ -120,6 +184,7 @@ fn increase_nest_level<'env>(env: Env<'env>) {
         let jnienv = env.as_raw();
         let result = unsafe { ((**jnienv).v1_2.PushLocalFrame)(jnienv, LOCAL_FRAME_SIZE) };
         assert_eq!(result, JNI_OK);
+        let _ = OUTER_ENV.try_with(|env| env.set(jnienv));
     }
     WITH_ENV_NEST_LEVEL.replace(level + 1);
 }
@@ -131,6 +196,7 @@ fn decrease_nest_level<'env>(env: Env<'env>) {
     if level == 1 {
         let jnienv = env.as_raw();
         let _ = unsafe { ((**jnienv).v1_2.PopLocalFrame)(jnienv, null_mut()) };
+        let _ = OUTER_ENV.try_with(|env| env.set(null_mut()));
     }
     if level > 0 {
         WITH_ENV_NEST_LEVEL.replace(level - 1);
@@ -190,9 +256,21 @@ impl<'env, T: ReferenceType> Drop for Monitor<'env, T> {
     }
 }
 
+/// Checks if two references refer to the same Java object.
+pub fn is_same_object<'env, T: ReferenceType, U: ReferenceType>(
+    a: &Ref<'env, T>,
+    b: &Ref<'env, U>,
+) -> bool {
+    let jnienv = a.env().as_raw();
+    unsafe { ((**jnienv).v1_2.IsSameObject)(jnienv, a.as_raw(), b.as_raw()) == JNI_TRUE }
+}
+
 pub trait ByteArrayExt {
     fn from_slice<'env>(env: Env<'env>, data: &[u8]) -> Local<'env, ByteArray>;
     fn as_vec_u8(&self) -> Vec<u8>;
+    /// Copies the array into the beginning of `buf` and returns its length, or returns `None`
+    /// if `buf` is too small.
+    fn copy_to_slice(&self, buf: &mut [u8]) -> Option<usize>;
 }
 
 impl ByteArrayExt for ByteArray {
@@ -204,7 +282,18 @@ impl ByteArrayExt for ByteArray {
         arr
     }
     fn as_vec_u8(&self) -> Vec<u8> {
-        // unsafe { std::mem::transmute(self.as_vec()) }
-        self.as_vec().iter().map(|&i| i as u8).collect()
+        // copies into the final buffer directly, avoiding an intermediate `Vec<i8>`.
+        let mut vec = vec![0u8; self.len()];
+        self.get_region(0, unsafe {
+            from_raw_parts_mut(vec.as_mut_ptr().cast(), vec.len())
+        });
+        vec
+    }
+    fn copy_to_slice(&self, buf: &mut [u8]) -> Option<usize> {
+        let dest = buf.get_mut(..self.len())?;
+        self.get_region(0, unsafe {
+            from_raw_parts_mut(dest.as_mut_ptr().cast(), dest.len())
+        });
+        Some(dest.len())
     }
 }
@@ -1,16 +1,19 @@
 // This code is orginally written by <https://github.com/Dirbaio>.
 
-use std::io::{Read, Write};
-use std::sync::Arc;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use std::{fmt, pin, slice, thread};
 
 use futures_lite::io::{AsyncRead, AsyncWrite, BlockOn};
+use futures_lite::FutureExt;
 use java_spaghetti::{ByteArray, Global, Local, PrimitiveArray};
 use log::{debug, trace, warn};
 
 use super::bindings::android::bluetooth::{BluetoothDevice, BluetoothSocket};
-use super::error::ErrorKind;
+use super::error::{ErrorKind, NativeError};
+use super::timer::Delay;
 use super::util::OptionExt;
 use super::vm_context::{android_api_level, jni_with_env};
 
@@ -71,10 +74,13 @@ macro_rules! derive_async_write {
     };
 }
 
-pub fn open_l2cap_channel(
+/// Opens the channel; if `timeout` is reached before it is connected, the socket is closed,
+/// which also makes the blocking `connect()` call in the background thread return.
+pub async fn open_l2cap_channel(
     device: Global<BluetoothDevice>,
     psm: u16,
     secure: bool,
+    timeout: Option<Duration>,
 ) -> std::prelude::v1::Result<(L2capChannelReader, L2capChannelWriter), crate::Error> {
     if android_api_level() < 29 {
         return Err(crate::Error::new(
@@ -83,23 +89,60 @@ pub fn open_l2cap_channel(
             "creating L2CAP channel requires Android API level 29 or higher",
         ));
     }
-    jni_with_env(|env| {
+    let channel = jni_with_env(|env| {
         let device = device.as_local(env);
-
         let channel = if secure {
             device.createL2capChannel(psm as _)?.non_null()?
         } else {
             device.createInsecureL2capChannel(psm as _)?.non_null()?
         };
-
-        channel.connect()?;
-
-        // The L2capCloser closes the l2cap channel when dropped.
-        // We put it in an Arc held by both the reader and writer, so it gets dropped
-        // when
-        let closer = Arc::new(L2capCloser {
-            channel: channel.as_global(),
+        Ok::<_, crate::Error>(channel.as_global())
+    })?;
+
+    // The L2capCloser closes the l2cap channel when dropped.
+    // We put it in an Arc held by both the reader and writer, so it gets dropped
+    // when both of them are dropped, or right here if the connection fails.
+    let closer = Arc::new(L2capCloser {
+        channel: channel.clone(),
+        error: Mutex::new(None),
+    });
+
+    // `BluetoothSocket.connect()` is blocking, so it is called in a background thread.
+    let (result_sender, result_receiver) = async_channel::bounded(1);
+    thread::spawn(move || {
+        let result = jni_with_env(|env| {
+            channel.as_local(env).connect()?;
+            Ok::<_, crate::Error>(())
         });
+        let _ = result_sender.try_send(result);
+    });
+    let connect = async {
+        result_receiver.recv().await.unwrap_or_else(|_| {
+            Err(crate::Error::new(
+                ErrorKind::Internal,
+                None,
+                "L2CAP connect thread exited unexpectedly",
+            ))
+        })
+    };
+    match timeout {
+        Some(timeout) => {
+            connect
+                .or(async {
+                    Delay::new(timeout).await;
+                    Err(crate::Error::new(
+                        ErrorKind::Timeout,
+                        None,
+                        "timed out while opening the L2CAP channel",
+                    ))
+                })
+                .await
+        }
+        None => connect.await,
+    }?;
+
+    jni_with_env(|env| {
+        let channel = closer.channel.as_local(env);
 
         let (read_receiver, read_sender) = piper::pipe(PIPE_CAPACITY);
         let (write_receiver, write_sender) = piper::pipe(PIPE_CAPACITY);
@@ -111,7 +154,10 @@ pub fn open_l2cap_channel(
         // with the async Rust world via async channels.
         //
         // The loops stop when either Android returns an error (for example if the channel is closed), or the
-        // async channel gets closed because the user dropped the reader or writer structs.
+        // async channel gets closed because the user dropped the reader or writer structs. The threads
+        // only hold weak references to the closer, so that they don't keep the channel open; an error
+        // stopping a thread is stored in the closer for the reader and writer.
+        let read_closer = Arc::downgrade(&closer);
         thread::spawn(move || {
             debug!("l2cap read thread running!");
             let mut read_sender = BlockOn::new(read_sender);
@@ -119,6 +165,7 @@ pub fn open_l2cap_channel(
             jni_with_env(|env| {
                 let stream = input_stream.as_local(env);
                 let arr: Local<ByteArray> = ByteArray::new(env, 1024);
+                let mut buf = [0u8; 1024];
 
                 loop {
                     match stream.read_byte_array(&arr) {
@@ -128,13 +175,15 @@ pub fn open_l2cap_channel(
                         }
                         Err(e) => {
                             warn!("failed to read from l2cap channel: {:?}", e);
+                            if let Some(closer) = read_closer.upgrade() {
+                                closer.set_error(e.into(), "failed to read from the L2CAP channel");
+                            }
                             break;
                         }
                         Ok(n) => {
-                            let n = n as usize;
-                            let mut buf = vec![0u8; n];
-                            arr.get_region(0, u8toi8_mut(&mut buf));
-                            if let Err(e) = read_sender.write_all(&buf) {
+                            let n = (n as usize).min(buf.len());
+                            arr.get_region(0, u8toi8_mut(&mut buf[..n]));
+                            if let Err(e) = read_sender.write_all(&buf[..n]) {
                                 warn!("failed to enqueue received l2cap packet: {:?}", e);
                                 break;
                             }
@@ -146,6 +195,7 @@ pub fn open_l2cap_channel(
             debug!("l2cap read thread exiting!");
         });
 
+        let write_closer = Arc::downgrade(&closer);
         thread::spawn(move || {
             debug!("l2cap write thread running!");
             let mut write_receiver = BlockOn::new(write_receiver);
@@ -167,6 +217,12 @@ pub fn open_l2cap_channel(
                             let b = ByteArray::new_from(env, u8toi8(&buf[..packet]));
                             if let Err(e) = stream.write_byte_array(b) {
                                 warn!("failed to write to l2cap channel: {:?}", e);
+                                if let Some(closer) = write_closer.upgrade() {
+                                    closer.set_error(
+                                        e.into(),
+                                        "failed to write to the L2CAP channel",
+                                    );
+                                }
                                 break;
                             };
                         }
@@ -179,23 +235,49 @@ pub fn open_l2cap_channel(
 
         Ok((
             L2capChannelReader {
-                _closer: closer.clone(),
+                closer: closer.clone(),
                 stream: read_receiver,
             },
             L2capChannelWriter {
-                _closer: closer,
+                closer,
                 stream: write_sender,
             },
         ))
     })
 }
 
-/// Utility struct to close the channel on drop.
+/// Utility struct to close the channel on drop. It also keeps the first error that stopped
+/// one of the background threads, which is returned by the reader and writer after that.
 pub(super) struct L2capCloser {
     channel: Global<BluetoothSocket>,
+    error: Mutex<Option<crate::Error>>,
 }
 
 impl L2capCloser {
+    fn set_error(&self, source: NativeError, msg: &str) {
+        let mut error = self.error.lock().unwrap();
+        if error.is_none() {
+            *error = Some(crate::Error::new(
+                ErrorKind::NotConnected,
+                Some(source),
+                msg,
+            ));
+        }
+    }
+
+    /// Converts the stored error, or returns an error of `ErrorKind::NotConnected` if the
+    /// background thread stopped at the end of the stream.
+    fn io_error(&self) -> io::Error {
+        self.error
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| {
+                crate::Error::new(ErrorKind::NotConnected, None, "the L2CAP channel is closed")
+            })
+            .into()
+    }
+
     fn close(&self) {
         jni_with_env(|env| {
             let channel = self.channel.as_local(env);
@@ -232,10 +314,28 @@ derive_async_write!(L2capChannel, writer);
 /// Reader half of a L2CAP Connection-oriented Channel (CoC).
 pub struct L2capChannelReader {
     stream: piper::Reader,
-    _closer: Arc<L2capCloser>,
+    closer: Arc<L2capCloser>,
 }
 
-derive_async_read!(L2capChannelReader, stream);
+impl AsyncRead for L2capChannelReader {
+    fn poll_read(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let reader = pin::pin!(&mut self.stream);
+        match reader.poll_read(cx, buf) {
+            // the end of the stream is reported as is, unless the read thread has failed.
+            Poll::Ready(Ok(0)) if !buf.is_empty() => {
+                match self.closer.error.lock().unwrap().clone() {
+                    Some(err) => Poll::Ready(Err(err.into())),
+                    None => Poll::Ready(Ok(0)),
+                }
+            }
+            poll => poll,
+        }
+    }
+}
 
 impl fmt::Debug for L2capChannelReader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -246,10 +346,33 @@ impl fmt::Debug for L2capChannelReader {
 /// Writer half of a L2CAP Connection-oriented Channel (CoC).
 pub struct L2capChannelWriter {
     stream: piper::Writer,
-    _closer: Arc<L2capCloser>,
+    closer: Arc<L2capCloser>,
 }
 
-derive_async_write!(L2capChannelWriter, stream);
+impl AsyncWrite for L2capChannelWriter {
+    fn poll_write(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let writer = pin::pin!(&mut self.stream);
+        match writer.poll_write(cx, buf) {
+            // the pipe is closed when the write thread has stopped.
+            Poll::Ready(Ok(0)) if !buf.is_empty() => Poll::Ready(Err(self.closer.io_error())),
+            poll => poll,
+        }
+    }
+
+    fn poll_flush(mut self: pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let writer = pin::pin!(&mut self.stream);
+        writer.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let writer = pin::pin!(&mut self.stream);
+        writer.poll_close(cx)
+    }
+}
 
 impl fmt::Debug for L2capChannelWriter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -4,20 +4,56 @@
 //! Anything incompatible with `bluest` in the API may be reported as a bug.
 //!
 //! This crate uses `ndk_context::AndroidContext`, which is automatically initialized by `android_activity`.
+//! Otherwise, call [init_with] before using this crate.
 //! The basic Android test template is provided in the crate page.
+//!
+//! All public types are `Send` and `Sync` (except borrowing types like [Transaction], which
+//! follow what they borrow), and all returned streams and futures are `Send`, so they can be
+//! moved into spawned tasks; JNI calls attach the current thread to the JVM when needed.
+//! [Adapter], [Device], [Service], [Characteristic] and [Descriptor] are cheap to clone.
 
 pub use adapter::{Adapter, AdapterConfig};
 pub use btuuid::BluetoothUuidExt;
+pub use cancellation::CancellationToken;
 pub use characteristic::Characteristic;
 pub use descriptor::Descriptor;
 pub use device::{Device, ServicesChanged};
 pub use error::Error;
+pub use gatt_snapshot::{
+    CharacteristicSnapshot, CharacteristicValues, DescriptorValue, DeviceSnapshot, GattSnapshot,
+    ServiceSnapshot, ServiceValues,
+};
 pub use l2cap_channel::{L2capChannel, L2capChannelReader, L2capChannelWriter};
+pub use observer::{OperationKind, OperationObserver, OperationOutcome};
+pub use reconnecting::{ReconnectState, ReconnectingDevice};
+pub use resilient::{NotifyEvent, RetryPolicy};
+pub use scan::{ScanCallbackType, ScanEvent, ScanEvents, ScanFilter, ScanSettings};
 pub use service::Service;
+pub use transaction::{Transaction, TransactionResult};
 
 /// Convenience alias for a result with [`Error`].
 pub type Result<T, E = Error> = core::result::Result<T, E>;
 
+/// Provides the JNI `JavaVM` pointer and the Android context for this crate explicitly, for
+/// applications in which `ndk_context` is not initialized (i.e. `android_activity` is not used).
+///
+/// It may be called more than once with the same values; an error is returned if different
+/// values are given, or if they were already obtained from `ndk_context`.
+///
+/// # Safety
+///
+/// - `vm` must be a valid JNI `JavaVM` pointer to a VM that will stay alive for the current
+///   native library's lifetime.
+/// - `context` must be a valid local or global reference to an `android.content.Context`
+///   instance from that VM, usable in the current thread. A new global reference is created
+///   for it, so the caller keeps the ownership of `context`.
+pub unsafe fn init_with(
+    vm: *mut java_spaghetti::sys::JavaVM,
+    context: java_spaghetti::sys::jobject,
+) -> Result<()> {
+    vm_context::android_init_with(vm, context)
+}
+
 // These are migrated from `bluest` for maintaining API compatibility with that library.
 pub use uuid::Uuid;
 pub mod btuuid;
@@ -27,19 +63,61 @@ pub use types::*;
 
 mod adapter;
 mod async_util;
+mod callback_log;
+mod cancellation;
 mod characteristic;
 mod descriptor;
 mod device;
 mod event_receiver;
+mod gatt_snapshot;
 mod gatt_tree;
 mod l2cap_channel;
+mod observer;
+mod reconnecting;
+mod resilient;
+mod scan;
 mod service;
+mod timer;
+mod trace;
+mod transaction;
 mod util;
 
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "traits")]
+pub mod traits;
+#[cfg(feature = "unstable-jni")]
+pub mod unstable_jni;
+
+// Checks the thread-safety guarantees documented above.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Adapter>();
+    assert_send_sync::<AdapterConfig>();
+    assert_send_sync::<Device>();
+    assert_send_sync::<Service>();
+    assert_send_sync::<Characteristic>();
+    assert_send_sync::<Descriptor>();
+    assert_send_sync::<L2capChannel>();
+    assert_send_sync::<L2capChannelReader>();
+    assert_send_sync::<L2capChannelWriter>();
+    assert_send_sync::<Error>();
+    assert_send_sync::<CancellationToken>();
+    assert_send_sync::<ServicesChanged>();
+    assert_send_sync::<GattSnapshot>();
+    assert_send_sync::<DeviceSnapshot>();
+    assert_send_sync::<ReconnectingDevice>();
+    assert_send_sync::<Transaction<'static>>();
+    assert_send_sync::<TransactionResult>();
+};
+
 // **NOTE**: it is important to use `jni_get_vm` or `jni_with_env` instead of `Global::vm`
 // so that a few bugs in `java-spaghetti` 0.2.0 may be avoided.
 #[allow(mismatched_lifetime_syntaxes)]
 mod bindings;
 mod callback;
+mod callback_thread;
 mod jni;
 mod vm_context;
@@ -0,0 +1,1042 @@
+//! In-process fake peripherals, for testing application logic off-device.
+//!
+//! [MockAdapter], [MockDevice], [MockServiceHandle], [MockCharacteristicHandle] and
+//! [MockDescriptorHandle] implement the [crate::traits] like [crate::Adapter] and other real
+//! types do, so application logic written against these traits can run with fake peripherals
+//! in host tests. They don't touch JNI and also work on non-Android targets. Code using the
+//! real types directly always calls the Android API, and it cannot be tested with this module.
+//!
+//! This feature enables the `traits` feature.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use android_ble::mock::*;
+//! use android_ble::traits::*;
+//! use android_ble::Uuid;
+//! use futures_lite::StreamExt;
+//!
+//! const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000181a_0000_1000_8000_00805f9b34fb);
+//! const TEMP_UUID: Uuid = Uuid::from_u128(0x00002a6e_0000_1000_8000_00805f9b34fb);
+//!
+//! /// Returns the current temperature value and the next notified one.
+//! async fn temperatures<A: AdapterApi>(adapter: &A) -> Result<[Vec<u8>; 2], A::Error> {
+//!     let services = [SERVICE_UUID];
+//!     let mut scan = adapter.scan(&services).await?;
+//!     let found = scan.next().await.ok_or(ErrorKind::NotFound)?;
+//!     let device = found.device().clone();
+//!     drop(scan);
+//!     adapter.connect_device(&device).await?;
+//!     let service = device.discover_services_with_uuid(SERVICE_UUID).await?;
+//!     let service = service.first().ok_or(ErrorKind::NotFound)?;
+//!     let temp = service.discover_characteristics_with_uuid(TEMP_UUID).await?;
+//!     let temp = temp.first().ok_or(ErrorKind::NotFound)?;
+//!     let current = temp.read().await?;
+//!     let mut notifications = temp.notify().await?;
+//!     let next = notifications.next().await.ok_or(ErrorKind::NotConnected)??;
+//!     Ok([current, next])
+//! }
+//!
+//! let peripheral = MockPeripheral::new("00:11:22:33:44:55")
+//!     .local_name("Thermometer")
+//!     .latency(Duration::from_millis(20))
+//!     .service(
+//!         MockService::new(SERVICE_UUID).characteristic(
+//!             MockCharacteristic::new(TEMP_UUID)
+//!                 .value(vec![0x10, 0x01])
+//!                 .notification(Duration::from_millis(100), Ok(vec![0x11, 0x01]))
+//!                 .notification(Duration::from_millis(100), Err(ErrorKind::NotConnected)),
+//!         ),
+//!     );
+//! let adapter = MockAdapter::new([peripheral]);
+//!
+//! let values = futures_lite::future::block_on(temperatures(&adapter)).unwrap();
+//! assert_eq!(values, [vec![0x10, 0x01], vec![0x11, 0x01]]);
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_core::Stream;
+use futures_lite::stream;
+
+use super::error::ErrorKind;
+use super::timer::Delay;
+use super::traits::{
+    AdapterApi, AdvertisingDeviceApi, CharacteristicApi, DescriptorApi, DeviceApi, ServiceApi,
+};
+use super::{AdvertisementData, CharacteristicProperties, DeviceId, Result, Uuid};
+
+/// Declares a fake peripheral for [MockAdapter].
+#[derive(Debug, Clone)]
+pub struct MockPeripheral {
+    id: DeviceId,
+    adv_data: AdvertisementData,
+    rssi: Option<i16>,
+    latency: Duration,
+    connect_error: Option<ErrorKind>,
+    services: Vec<MockService>,
+}
+
+/// Declares a service of [MockPeripheral].
+#[derive(Debug, Clone)]
+pub struct MockService {
+    uuid: Uuid,
+    characteristics: Vec<MockCharacteristic>,
+}
+
+/// Declares a characteristic of [MockService] with its initial value and scripted behaviors.
+#[derive(Debug, Clone)]
+pub struct MockCharacteristic {
+    uuid: Uuid,
+    properties: CharacteristicProperties,
+    value: Vec<u8>,
+    read_error: Option<ErrorKind>,
+    write_error: Option<ErrorKind>,
+    notifications: Vec<(Duration, Result<Vec<u8>, ErrorKind>)>,
+    descriptors: Vec<MockDescriptor>,
+}
+
+/// Declares a descriptor of [MockCharacteristic] with its initial value.
+#[derive(Debug, Clone)]
+pub struct MockDescriptor {
+    uuid: Uuid,
+    value: Vec<u8>,
+}
+
+impl MockPeripheral {
+    /// Creates a connectable peripheral with the given address and no services.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            id: DeviceId(address.into()),
+            adv_data: AdvertisementData {
+                local_name: None,
+                manufacturer_data: None,
+                services: Vec::new(),
+                service_data: HashMap::new(),
+                tx_power_level: None,
+                is_connectable: true,
+            },
+            rssi: Some(-60),
+            latency: Duration::ZERO,
+            connect_error: None,
+            services: Vec::new(),
+        }
+    }
+
+    /// Sets the local name in the advertisement, which is also the device name.
+    pub fn local_name(mut self, name: impl Into<String>) -> Self {
+        self.adv_data.local_name = Some(name.into());
+        self
+    }
+
+    /// Replaces the whole advertisement data.
+    pub fn adv_data(mut self, adv_data: AdvertisementData) -> Self {
+        self.adv_data = adv_data;
+        self
+    }
+
+    /// Sets the RSSI of the advertisement and the connection.
+    pub fn rssi(mut self, rssi: i16) -> Self {
+        self.rssi = Some(rssi);
+        self
+    }
+
+    /// Sets the delay of every operation, including connection and reads/writes.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Makes [MockAdapter::connect_device] fail with the given error kind.
+    pub fn connect_error(mut self, kind: ErrorKind) -> Self {
+        self.connect_error = Some(kind);
+        self
+    }
+
+    /// Adds a service; its UUID is also included in the advertisement.
+    pub fn service(mut self, service: MockService) -> Self {
+        self.adv_data.services.push(service.uuid);
+        self.services.push(service);
+        self
+    }
+}
+
+impl MockService {
+    /// Creates a service without characteristics.
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            characteristics: Vec::new(),
+        }
+    }
+
+    /// Adds a characteristic.
+    pub fn characteristic(mut self, characteristic: MockCharacteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+}
+
+impl MockCharacteristic {
+    /// Creates a characteristic supporting read, write and notify with an empty value.
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            properties: CharacteristicProperties {
+                read: true,
+                write: true,
+                notify: true,
+                ..Default::default()
+            },
+            value: Vec::new(),
+            read_error: None,
+            write_error: None,
+            notifications: Vec::new(),
+            descriptors: Vec::new(),
+        }
+    }
+
+    /// Overrides the characteristic properties.
+    pub fn properties(mut self, properties: CharacteristicProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// Sets the initial value.
+    pub fn value(mut self, value: Vec<u8>) -> Self {
+        self.value = value;
+        self
+    }
+
+    /// Makes every read fail with the given error kind.
+    pub fn read_error(mut self, kind: ErrorKind) -> Self {
+        self.read_error = Some(kind);
+        self
+    }
+
+    /// Makes every write fail with the given error kind.
+    pub fn write_error(mut self, kind: ErrorKind) -> Self {
+        self.write_error = Some(kind);
+        self
+    }
+
+    /// Appends an item to the notification script: it is delivered `delay` after the previous
+    /// item (or the subscription). A delivered value also becomes the characteristic value.
+    pub fn notification(mut self, delay: Duration, item: Result<Vec<u8>, ErrorKind>) -> Self {
+        self.notifications.push((delay, item));
+        self
+    }
+
+    /// Adds a descriptor.
+    pub fn descriptor(mut self, descriptor: MockDescriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+}
+
+impl MockDescriptor {
+    /// Creates a descriptor with an empty value.
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            value: Vec::new(),
+        }
+    }
+
+    /// Sets the initial value.
+    pub fn value(mut self, value: Vec<u8>) -> Self {
+        self.value = value;
+        self
+    }
+}
+
+/// A fake adapter serving [MockPeripheral]s.
+#[derive(Debug, Clone)]
+pub struct MockAdapter {
+    devices: Arc<Vec<MockDevice>>,
+}
+
+/// An advertisement yielded by [MockAdapter::scan].
+#[derive(Debug, Clone)]
+pub struct MockAdvertisingDevice {
+    /// The source of the advertisement
+    pub device: MockDevice,
+    /// The advertisement data
+    pub adv_data: AdvertisementData,
+    /// The signal strength in dBm
+    pub rssi: Option<i16>,
+}
+
+/// A [MockPeripheral] served by [MockAdapter]. Clones share the connection state.
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    inner: Arc<MockDeviceInner>,
+}
+
+#[derive(Debug)]
+struct MockDeviceInner {
+    decl: MockPeripheral,
+    paired: AtomicBool,
+    connection: Mutex<Option<MockConnection>>,
+}
+
+#[derive(Debug)]
+struct MockConnection {
+    /// Shared with all handles of this connection, cleared on disconnection.
+    alive: Arc<AtomicBool>,
+    services: Vec<MockServiceHandle>,
+}
+
+/// A service of a connected [MockDevice].
+#[derive(Debug, Clone)]
+pub struct MockServiceHandle {
+    uuid: Uuid,
+    characteristics: Vec<MockCharacteristicHandle>,
+    latency: Duration,
+    alive: Arc<AtomicBool>,
+}
+
+/// A characteristic of a connected [MockDevice], keeping its value across reads and writes.
+#[derive(Debug, Clone)]
+pub struct MockCharacteristicHandle {
+    decl: Arc<MockCharacteristic>,
+    value: Arc<Mutex<Vec<u8>>>,
+    subscribers: Arc<AtomicUsize>,
+    descriptors: Vec<MockDescriptorHandle>,
+    latency: Duration,
+    alive: Arc<AtomicBool>,
+}
+
+/// A descriptor of a connected [MockDevice], keeping its value across reads and writes.
+#[derive(Debug, Clone)]
+pub struct MockDescriptorHandle {
+    uuid: Uuid,
+    value: Arc<Mutex<Vec<u8>>>,
+    latency: Duration,
+    alive: Arc<AtomicBool>,
+}
+
+impl MockAdapter {
+    /// Creates the fake adapter.
+    pub fn new(peripherals: impl IntoIterator<Item = MockPeripheral>) -> Self {
+        let devices = peripherals
+            .into_iter()
+            .map(|decl| MockDevice {
+                inner: Arc::new(MockDeviceInner {
+                    decl,
+                    paired: AtomicBool::new(false),
+                    connection: Mutex::new(None),
+                }),
+            })
+            .collect();
+        Self {
+            devices: Arc::new(devices),
+        }
+    }
+
+    /// Returns immediately, the fake adapter is always available.
+    pub async fn wait_available(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Always returns true.
+    pub async fn is_available(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Returns the peripheral with the given address.
+    pub async fn open_device(&self, id: &DeviceId) -> Result<MockDevice> {
+        self.devices
+            .iter()
+            .find(|dev| &dev.inner.decl.id == id)
+            .cloned()
+            .ok_or_else(|| {
+                crate::Error::new(
+                    ErrorKind::NotFound,
+                    None,
+                    "no mock peripheral with this address",
+                )
+            })
+    }
+
+    /// Returns the connected peripherals.
+    pub async fn connected_devices(&self) -> Result<Vec<MockDevice>> {
+        Ok(self
+            .devices
+            .iter()
+            .filter(|dev| dev.inner.connection.lock().unwrap().is_some())
+            .cloned()
+            .collect())
+    }
+
+    /// Yields one advertisement of each peripheral, filtered by `services` like
+    /// [crate::Adapter::scan]; unlike a real scan, the stream ends after that.
+    pub async fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> Result<impl Stream<Item = MockAdvertisingDevice> + Send + Unpin + 'a> {
+        let found: Vec<_> = self
+            .devices
+            .iter()
+            .filter(|dev| {
+                let adv_services = &dev.inner.decl.adv_data.services;
+                services.is_empty() || services.iter().any(|s| adv_services.contains(s))
+            })
+            .map(|dev| MockAdvertisingDevice {
+                device: dev.clone(),
+                adv_data: dev.inner.decl.adv_data.clone(),
+                rssi: dev.inner.decl.rssi,
+            })
+            .collect();
+        Ok(stream::iter(found))
+    }
+
+    /// Connects to the peripheral after the configured latency, or fails with the injected
+    /// error. Every connection starts with the declared characteristic and descriptor values.
+    pub async fn connect_device(&self, device: &MockDevice) -> Result<()> {
+        let decl = &device.inner.decl;
+        Delay::new(decl.latency).await;
+        if let Some(kind) = decl.connect_error {
+            return Err(mock_error(kind));
+        }
+        let mut connection = device.inner.connection.lock().unwrap();
+        if connection.is_none() {
+            connection.replace(MockConnection::new(decl));
+        }
+        Ok(())
+    }
+
+    /// Disconnects the peripheral; handles of the connection fail with
+    /// [ErrorKind::NotConnected] afterwards, and notification streams end.
+    pub async fn disconnect_device(&self, device: &MockDevice) -> Result<()> {
+        if let Some(connection) = device.inner.connection.lock().unwrap().take() {
+            connection.alive.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+impl MockConnection {
+    fn new(decl: &MockPeripheral) -> Self {
+        let alive = Arc::new(AtomicBool::new(true));
+        let latency = decl.latency;
+        let services = decl
+            .services
+            .iter()
+            .map(|serv| MockServiceHandle {
+                uuid: serv.uuid,
+                characteristics: serv
+                    .characteristics
+                    .iter()
+                    .map(|ch| MockCharacteristicHandle {
+                        value: Arc::new(Mutex::new(ch.value.clone())),
+                        subscribers: Arc::new(AtomicUsize::new(0)),
+                        descriptors: ch
+                            .descriptors
+                            .iter()
+                            .map(|desc| MockDescriptorHandle {
+                                uuid: desc.uuid,
+                                value: Arc::new(Mutex::new(desc.value.clone())),
+                                latency,
+                                alive: alive.clone(),
+                            })
+                            .collect(),
+                        decl: Arc::new(ch.clone()),
+                        latency,
+                        alive: alive.clone(),
+                    })
+                    .collect(),
+                latency,
+                alive: alive.clone(),
+            })
+            .collect();
+        Self { alive, services }
+    }
+}
+
+impl MockDevice {
+    /// The address of the peripheral.
+    pub fn id(&self) -> DeviceId {
+        self.inner.decl.id.clone()
+    }
+
+    /// The local name in the advertisement.
+    pub fn name(&self) -> Result<String> {
+        self.inner
+            .decl
+            .adv_data
+            .local_name
+            .clone()
+            .ok_or_else(|| crate::Error::new(ErrorKind::NotFound, None, "the name is not set"))
+    }
+
+    /// Checks if the peripheral is connected.
+    pub async fn is_connected(&self) -> bool {
+        self.inner.connection.lock().unwrap().is_some()
+    }
+
+    /// Checks if [MockDevice::pair] has been called.
+    pub async fn is_paired(&self) -> Result<bool> {
+        Ok(self.inner.paired.load(Ordering::SeqCst))
+    }
+
+    /// Pairs with the connected peripheral after the configured latency.
+    pub async fn pair(&self) -> Result<()> {
+        Delay::new(self.inner.decl.latency).await;
+        self.services_now()?;
+        self.inner.paired.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns all declared services after the configured latency.
+    pub async fn discover_services(&self) -> Result<Vec<MockServiceHandle>> {
+        Delay::new(self.inner.decl.latency).await;
+        self.services_now()
+    }
+
+    /// Returns declared services with the given UUID after the configured latency.
+    pub async fn discover_services_with_uuid(&self, uuid: Uuid) -> Result<Vec<MockServiceHandle>> {
+        let mut services = self.discover_services().await?;
+        services.retain(|serv| serv.uuid == uuid);
+        Ok(services)
+    }
+
+    /// Returns all declared services without the configured latency.
+    pub async fn services(&self) -> Result<Vec<MockServiceHandle>> {
+        self.services_now()
+    }
+
+    /// Returns the declared RSSI of the connected peripheral.
+    pub async fn rssi(&self) -> Result<i16> {
+        self.services_now()?;
+        self.inner
+            .decl
+            .rssi
+            .ok_or_else(|| crate::Error::new(ErrorKind::NotSupported, None, "the RSSI is not set"))
+    }
+
+    /// Finds the first characteristic with `char_uuid` in the service with `service_uuid`.
+    pub fn characteristic(
+        &self,
+        service_uuid: Uuid,
+        char_uuid: Uuid,
+    ) -> Result<MockCharacteristicHandle> {
+        self.services_now()?
+            .iter()
+            .filter(|serv| serv.uuid == service_uuid)
+            .flat_map(|serv| serv.characteristics.iter())
+            .find(|ch| ch.uuid() == char_uuid)
+            .cloned()
+            .ok_or_else(|| {
+                crate::Error::new(ErrorKind::NotFound, None, "no such mock characteristic")
+            })
+    }
+
+    fn services_now(&self) -> Result<Vec<MockServiceHandle>> {
+        self.inner
+            .connection
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|conn| conn.services.clone())
+            .ok_or_else(not_connected)
+    }
+}
+
+impl MockServiceHandle {
+    /// The [Uuid] identifying the type of this service.
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// Returns all declared characteristics after the configured latency.
+    pub async fn discover_characteristics(&self) -> Result<Vec<MockCharacteristicHandle>> {
+        check_alive(self.latency, &self.alive).await?;
+        Ok(self.characteristics.clone())
+    }
+
+    /// Returns declared characteristics with the given UUID after the configured latency.
+    pub async fn discover_characteristics_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> Result<Vec<MockCharacteristicHandle>> {
+        let mut characteristics = self.discover_characteristics().await?;
+        characteristics.retain(|ch| ch.uuid() == uuid);
+        Ok(characteristics)
+    }
+
+    /// Returns all declared characteristics without the configured latency.
+    pub async fn characteristics(&self) -> Result<Vec<MockCharacteristicHandle>> {
+        check_alive(Duration::ZERO, &self.alive).await?;
+        Ok(self.characteristics.clone())
+    }
+}
+
+impl MockCharacteristicHandle {
+    /// The [Uuid] identifying the type of this characteristic.
+    pub fn uuid(&self) -> Uuid {
+        self.decl.uuid
+    }
+
+    /// The declared properties of this characteristic.
+    pub async fn properties(&self) -> Result<CharacteristicProperties> {
+        Ok(self.decl.properties)
+    }
+
+    /// The current value, without the configured latency.
+    pub fn value(&self) -> Vec<u8> {
+        self.value.lock().unwrap().clone()
+    }
+
+    /// Reads the current value after the configured latency, or fails with the injected error.
+    pub async fn read(&self) -> Result<Vec<u8>> {
+        check_alive(self.latency, &self.alive).await?;
+        if let Some(kind) = self.decl.read_error {
+            return Err(mock_error(kind));
+        }
+        Ok(self.value())
+    }
+
+    /// Replaces the current value after the configured latency, or fails with the injected error.
+    pub async fn write(&self, value: &[u8]) -> Result<()> {
+        check_alive(self.latency, &self.alive).await?;
+        if let Some(kind) = self.decl.write_error {
+            return Err(mock_error(kind));
+        }
+        *self.value.lock().unwrap() = value.to_vec();
+        Ok(())
+    }
+
+    /// Same as [MockCharacteristicHandle::write].
+    pub async fn write_without_response(&self, value: &[u8]) -> Result<()> {
+        self.write(value).await
+    }
+
+    /// Returns 20, the maximum under the default ATT MTU.
+    pub fn max_write_len(&self) -> Result<usize> {
+        Ok(20)
+    }
+
+    /// Plays the notification script; the stream ends after the last scripted item, or on
+    /// disconnection.
+    pub async fn notify(
+        &self,
+    ) -> Result<impl Stream<Item = Result<Vec<u8>>> + Send + Unpin + 'static> {
+        check_alive(Duration::ZERO, &self.alive).await?;
+        self.subscribers.fetch_add(1, Ordering::SeqCst);
+        let subscribed = Subscribed(self.subscribers.clone());
+        let (value, alive) = (self.value.clone(), self.alive.clone());
+        Ok(Box::pin(stream::unfold(
+            (self.decl.notifications.clone().into_iter(), subscribed),
+            move |(mut script, subscribed)| {
+                let (value, alive) = (value.clone(), alive.clone());
+                async move {
+                    let (delay, item) = script.next()?;
+                    Delay::new(delay).await;
+                    if !alive.load(Ordering::SeqCst) {
+                        return None;
+                    }
+                    if let Ok(new_value) = &item {
+                        *value.lock().unwrap() = new_value.clone();
+                    }
+                    Some((item.map_err(mock_error), (script, subscribed)))
+                }
+            },
+        )))
+    }
+
+    /// Checks if any notification stream of this characteristic is alive.
+    pub async fn is_notifying(&self) -> Result<bool> {
+        Ok(self.subscribers.load(Ordering::SeqCst) > 0)
+    }
+
+    /// Returns all declared descriptors after the configured latency.
+    pub async fn discover_descriptors(&self) -> Result<Vec<MockDescriptorHandle>> {
+        check_alive(self.latency, &self.alive).await?;
+        Ok(self.descriptors.clone())
+    }
+}
+
+impl MockDescriptorHandle {
+    /// The [Uuid] identifying the type of this descriptor.
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// Reads the current value after the configured latency.
+    pub async fn read(&self) -> Result<Vec<u8>> {
+        check_alive(self.latency, &self.alive).await?;
+        Ok(self.value.lock().unwrap().clone())
+    }
+
+    /// Replaces the current value after the configured latency.
+    pub async fn write(&self, value: &[u8]) -> Result<()> {
+        check_alive(self.latency, &self.alive).await?;
+        *self.value.lock().unwrap() = value.to_vec();
+        Ok(())
+    }
+}
+
+/// Counts a notification stream in [MockCharacteristicHandle::is_notifying] until it is dropped.
+struct Subscribed(Arc<AtomicUsize>);
+
+impl Drop for Subscribed {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn check_alive(latency: Duration, alive: &AtomicBool) -> Result<()> {
+    Delay::new(latency).await;
+    if alive.load(Ordering::SeqCst) {
+        Ok(())
+    } else {
+        Err(not_connected())
+    }
+}
+
+fn not_connected() -> crate::Error {
+    crate::Error::new(
+        ErrorKind::NotConnected,
+        None,
+        "the mock peripheral is not connected",
+    )
+}
+
+fn mock_error(kind: ErrorKind) -> crate::Error {
+    crate::Error::new(kind, None, "injected by the mock peripheral")
+}
+
+impl AdapterApi for MockAdapter {
+    type Error = crate::Error;
+    type Device = MockDevice;
+    type AdvertisingDevice = MockAdvertisingDevice;
+
+    fn wait_available(&self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockAdapter::wait_available(self)
+    }
+
+    fn is_available(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        MockAdapter::is_available(self)
+    }
+
+    fn connected_devices(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Device>, Self::Error>> + Send {
+        MockAdapter::connected_devices(self)
+    }
+
+    fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> impl Future<
+        Output = Result<
+            impl Stream<Item = Self::AdvertisingDevice> + Send + Unpin + 'a,
+            Self::Error,
+        >,
+    > + Send {
+        MockAdapter::scan(self, services)
+    }
+
+    fn connect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockAdapter::connect_device(self, device)
+    }
+
+    fn disconnect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockAdapter::disconnect_device(self, device)
+    }
+}
+
+impl AdvertisingDeviceApi for MockAdvertisingDevice {
+    type Device = MockDevice;
+
+    fn device(&self) -> &Self::Device {
+        &self.device
+    }
+
+    fn adv_data(&self) -> &AdvertisementData {
+        &self.adv_data
+    }
+
+    fn rssi(&self) -> Option<i16> {
+        self.rssi
+    }
+}
+
+impl DeviceApi for MockDevice {
+    type Error = crate::Error;
+    type Service = MockServiceHandle;
+
+    fn name(&self) -> Result<String, Self::Error> {
+        MockDevice::name(self)
+    }
+
+    fn is_connected(&self) -> impl Future<Output = bool> + Send {
+        MockDevice::is_connected(self)
+    }
+
+    fn is_paired(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        MockDevice::is_paired(self)
+    }
+
+    fn pair(&self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockDevice::pair(self)
+    }
+
+    fn discover_services(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send {
+        MockDevice::discover_services(self)
+    }
+
+    fn discover_services_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send {
+        MockDevice::discover_services_with_uuid(self, uuid)
+    }
+
+    fn services(&self) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send {
+        MockDevice::services(self)
+    }
+
+    fn rssi(&self) -> impl Future<Output = Result<i16, Self::Error>> + Send {
+        MockDevice::rssi(self)
+    }
+}
+
+impl ServiceApi for MockServiceHandle {
+    type Error = crate::Error;
+    type Characteristic = MockCharacteristicHandle;
+
+    fn uuid(&self) -> Uuid {
+        MockServiceHandle::uuid(self)
+    }
+
+    fn discover_characteristics(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send {
+        MockServiceHandle::discover_characteristics(self)
+    }
+
+    fn discover_characteristics_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send {
+        MockServiceHandle::discover_characteristics_with_uuid(self, uuid)
+    }
+
+    fn characteristics(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send {
+        MockServiceHandle::characteristics(self)
+    }
+}
+
+impl CharacteristicApi for MockCharacteristicHandle {
+    type Error = crate::Error;
+    type Descriptor = MockDescriptorHandle;
+
+    fn uuid(&self) -> Uuid {
+        MockCharacteristicHandle::uuid(self)
+    }
+
+    fn properties(
+        &self,
+    ) -> impl Future<Output = Result<CharacteristicProperties, Self::Error>> + Send {
+        MockCharacteristicHandle::properties(self)
+    }
+
+    fn read(&self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send {
+        MockCharacteristicHandle::read(self)
+    }
+
+    fn write(&self, value: &[u8]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockCharacteristicHandle::write(self, value)
+    }
+
+    fn write_without_response(
+        &self,
+        value: &[u8],
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockCharacteristicHandle::write_without_response(self, value)
+    }
+
+    fn max_write_len(&self) -> Result<usize, Self::Error> {
+        MockCharacteristicHandle::max_write_len(self)
+    }
+
+    fn notify(
+        &self,
+    ) -> impl Future<
+        Output = Result<
+            impl Stream<Item = Result<Vec<u8>, Self::Error>> + Send + Unpin + '_,
+            Self::Error,
+        >,
+    > + Send {
+        MockCharacteristicHandle::notify(self)
+    }
+
+    fn is_notifying(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        MockCharacteristicHandle::is_notifying(self)
+    }
+
+    fn discover_descriptors(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Descriptor>, Self::Error>> + Send {
+        MockCharacteristicHandle::discover_descriptors(self)
+    }
+}
+
+impl DescriptorApi for MockDescriptorHandle {
+    type Error = crate::Error;
+
+    fn uuid(&self) -> Uuid {
+        MockDescriptorHandle::uuid(self)
+    }
+
+    fn read(&self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send {
+        MockDescriptorHandle::read(self)
+    }
+
+    fn write(&self, value: &[u8]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        MockDescriptorHandle::write(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+    use futures_lite::StreamExt;
+
+    use super::*;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+    const OTHER_SERVICE: Uuid = Uuid::from_u128(0x0000180a_0000_1000_8000_00805f9b34fb);
+    const CHAR: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+
+    fn adapter() -> MockAdapter {
+        MockAdapter::new([
+            MockPeripheral::new("00:00:00:00:00:01").service(
+                MockService::new(SERVICE)
+                    .characteristic(MockCharacteristic::new(CHAR).value(vec![1])),
+            ),
+            MockPeripheral::new("00:00:00:00:00:02")
+                .service(MockService::new(OTHER_SERVICE))
+                .connect_error(ErrorKind::NotAuthorized),
+        ])
+    }
+
+    fn connected(adapter: &MockAdapter, address: &str) -> Result<MockDevice> {
+        block_on(async {
+            let device = adapter.open_device(&DeviceId(address.into())).await?;
+            adapter.connect_device(&device).await?;
+            Ok(device)
+        })
+    }
+
+    #[test]
+    fn scan_filters_by_service() {
+        let adapter = adapter();
+        let found: Vec<_> =
+            block_on(async { adapter.scan(&[SERVICE]).await.unwrap().collect().await });
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].device.id(), DeviceId("00:00:00:00:00:01".into()));
+        let count = block_on(async { adapter.scan(&[]).await.unwrap().count().await });
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn connect_error_is_injected() {
+        let adapter = adapter();
+        let err = connected(&adapter, "00:00:00:00:00:02").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotAuthorized);
+        let err = connected(&adapter, "00:00:00:00:00:03").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(block_on(adapter.connected_devices()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn values_are_kept_per_connection() {
+        let adapter = adapter();
+        let device = connected(&adapter, "00:00:00:00:00:01").unwrap();
+        block_on(async {
+            let char = device.characteristic(SERVICE, CHAR).unwrap();
+            assert_eq!(char.read().await.unwrap(), vec![1]);
+            char.write(&[2, 3]).await.unwrap();
+            assert_eq!(char.read().await.unwrap(), vec![2, 3]);
+
+            adapter.disconnect_device(&device).await.unwrap();
+            let err = char.read().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotConnected);
+            assert!(device.characteristic(SERVICE, CHAR).is_err());
+
+            adapter.connect_device(&device).await.unwrap();
+            let char = device.characteristic(SERVICE, CHAR).unwrap();
+            assert_eq!(char.read().await.unwrap(), vec![1]);
+        });
+    }
+
+    #[test]
+    fn notification_script_updates_value() {
+        let adapter = MockAdapter::new([MockPeripheral::new("00:00:00:00:00:01").service(
+            MockService::new(SERVICE).characteristic(
+                MockCharacteristic::new(CHAR)
+                    .read_error(ErrorKind::NotAuthorized)
+                    .notification(Duration::ZERO, Ok(vec![5]))
+                    .notification(Duration::ZERO, Err(ErrorKind::Timeout)),
+            ),
+        )]);
+        let device = connected(&adapter, "00:00:00:00:00:01").unwrap();
+        block_on(async {
+            let char = device.characteristic(SERVICE, CHAR).unwrap();
+            assert_eq!(
+                char.read().await.unwrap_err().kind(),
+                ErrorKind::NotAuthorized
+            );
+            let notifications = char.notify().await.unwrap();
+            assert!(char.is_notifying().await.unwrap());
+            let items: Vec<_> = notifications.collect().await;
+            assert_eq!(items.len(), 2);
+            assert_eq!(items[0].as_ref().unwrap(), &vec![5]);
+            assert_eq!(items[1].as_ref().unwrap_err().kind(), ErrorKind::Timeout);
+            assert_eq!(char.value(), vec![5]);
+            assert!(!char.is_notifying().await.unwrap());
+        });
+    }
+
+    // Application logic generic over the traits runs with the mock types.
+    #[test]
+    fn traits_are_implemented() {
+        async fn read_first<D: DeviceApi>(
+            device: &D,
+            char_uuid: Uuid,
+        ) -> Result<Vec<u8>, D::Error> {
+            for service in device.discover_services().await? {
+                let chars = service
+                    .discover_characteristics_with_uuid(char_uuid)
+                    .await?;
+                if let Some(char) = chars.first() {
+                    return char.read().await;
+                }
+            }
+            Err(ErrorKind::NotFound.into())
+        }
+
+        let adapter = adapter();
+        let device = connected(&adapter, "00:00:00:00:00:01").unwrap();
+        assert_eq!(block_on(read_first(&device, CHAR)).unwrap(), vec![1]);
+        let err = block_on(read_first(&device, OTHER_SERVICE)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
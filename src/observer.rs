@@ -0,0 +1,107 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::ConnectionStats;
+
+/// Receives timing data of GATT operations, set by [crate::AdapterConfig::operation_observer].
+///
+/// It is called from the task doing the operation right after the result is received, or the
+/// operation fails because of a timeout or a disconnection; it is not called for operations
+/// cancelled by dropping their futures. Implementations should return quickly.
+pub trait OperationObserver: Send + Sync {
+    /// - `queued`: how long the operation waited for previous operations of the same kind
+    ///   on the same item to be finished.
+    /// - `executed`: how long it took from issuing the request to the Android Bluetooth stack
+    ///   until the callback is received (or the operation fails).
+    fn on_operation(
+        &self,
+        op: OperationKind,
+        queued: Duration,
+        executed: Duration,
+        outcome: OperationOutcome,
+    );
+}
+
+/// Kinds of operations reported to [OperationObserver].
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum OperationKind {
+    Connect,
+    DiscoverServices,
+    ReadCharacteristic,
+    WriteCharacteristic,
+    ReadDescriptor,
+    WriteDescriptor,
+    ReadRssi,
+    ReadPhy,
+    RequestMtu,
+    ConnectionUpdate,
+}
+
+/// How an operation reported to [OperationObserver] ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationOutcome {
+    /// The callback is received; it may still carry an error status.
+    Completed,
+    /// No callback is received before the timeout or the deadline.
+    TimedOut,
+    /// The connection is closed while waiting.
+    Aborted,
+}
+
+/// Records latencies of completed reads and writes for `Device::stats`, and forwards all
+/// operations to the observer set by the user.
+pub(crate) struct LatencyRecorder {
+    stats: Mutex<ConnectionStats>,
+    /// Sums of recorded latencies of reads and writes, for calculating the averages.
+    sums: Mutex<(Duration, Duration)>,
+    inner: Option<Arc<dyn OperationObserver>>,
+}
+
+impl LatencyRecorder {
+    pub fn new(inner: Option<Arc<dyn OperationObserver>>) -> Self {
+        Self {
+            stats: Mutex::new(ConnectionStats::default()),
+            sums: Mutex::new((Duration::ZERO, Duration::ZERO)),
+            inner,
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+impl OperationObserver for LatencyRecorder {
+    fn on_operation(
+        &self,
+        op: OperationKind,
+        queued: Duration,
+        executed: Duration,
+        outcome: OperationOutcome,
+    ) {
+        if outcome == OperationOutcome::Completed {
+            let mut stats = self.stats.lock().unwrap();
+            let mut sums = self.sums.lock().unwrap();
+            match op {
+                OperationKind::ReadCharacteristic | OperationKind::ReadDescriptor => {
+                    stats.read_count += 1;
+                    sums.0 += executed;
+                    stats.last_read_latency = Some(executed);
+                    stats.average_read_latency = Some(sums.0 / stats.read_count);
+                }
+                OperationKind::WriteCharacteristic | OperationKind::WriteDescriptor => {
+                    stats.write_count += 1;
+                    sums.1 += executed;
+                    stats.last_write_latency = Some(executed);
+                    stats.average_write_latency = Some(sums.1 / stats.write_count);
+                }
+                _ => (),
+            }
+        }
+        if let Some(inner) = &self.inner {
+            inner.on_operation(op, queued, executed, outcome);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! A supervisor keeping a device connected, built only on the public API of this crate, so it
+//! also serves as an example of handling disconnections.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use async_broadcast::{InactiveReceiver, Sender};
+use futures_core::Stream;
+use log::{info, warn};
+use uuid::Uuid;
+
+use super::error::ErrorKind;
+use super::resilient::find_characteristic;
+use super::timer::Delay;
+use super::{Adapter, Characteristic, Device, DeviceId, NotifyEvent, Result, RetryPolicy};
+
+/// Health state of a [ReconnectingDevice].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReconnectState {
+    /// The device is connected, and the required services are discovered.
+    Connected,
+    /// The connection is being restored; `attempt` starts from 1.
+    Reconnecting {
+        /// Number of the current attempt in this round of retries.
+        attempt: u32,
+    },
+    /// All attempts of the last round of retries have failed. The next operation or
+    /// [ReconnectingDevice::ensure_connected] call starts another round.
+    GaveUp,
+}
+
+/// Wraps a [Device] to restore its connection transparently.
+///
+/// Operations of this type connect to the device if it is disconnected, retrying according to
+/// the [RetryPolicy]; operations issued while reconnecting are queued until the connection is
+/// restored, or fail with the last error if all attempts fail. Run [ReconnectingDevice::supervise]
+/// in a task to reconnect as soon as the connection is dropped, instead of on the next operation.
+///
+/// ```no_run
+/// # use android_ble::{Adapter, Device, ReconnectingDevice, RetryPolicy, Uuid};
+/// # const SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+/// # const CHAR_UUID: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+/// # async fn example(adapter: Adapter, device: Device) -> android_ble::Result<()> {
+/// let policy = RetryPolicy::default();
+/// let device = ReconnectingDevice::new(&adapter, device, policy, vec![SERVICE_UUID]).await;
+/// let supervisor = device.clone();
+/// std::thread::spawn(move || futures_lite::future::block_on(supervisor.supervise_forever()));
+/// let value = device.read(SERVICE_UUID, CHAR_UUID).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ReconnectingDevice {
+    inner: Arc<ReconnectingInner>,
+}
+
+struct ReconnectingInner {
+    adapter: Adapter,
+    device: Device,
+    policy: RetryPolicy,
+    required_services: Vec<Uuid>,
+    /// Held while reconnecting, which queues other operations.
+    reconnecting: async_lock::Mutex<()>,
+    state: Mutex<ReconnectState>,
+    state_sender: Sender<ReconnectState>,
+    #[allow(unused)]
+    state_keeper: InactiveReceiver<ReconnectState>,
+}
+
+impl ReconnectingDevice {
+    /// Creates the wrapper; `adapter` is used for reconnecting. `required_services` must be
+    /// found after each connection made by the wrapper, otherwise the attempt fails with an
+    /// [ErrorKind::NotFound] error.
+    ///
+    /// The initial state is [ReconnectState::Connected] if the device is connected, otherwise
+    /// [ReconnectState::GaveUp] until the first connection attempt.
+    pub async fn new(
+        adapter: &Adapter,
+        device: Device,
+        policy: RetryPolicy,
+        required_services: Vec<Uuid>,
+    ) -> Self {
+        let (mut state_sender, receiver) = async_broadcast::broadcast(16);
+        state_sender.set_overflow(true);
+        let state = if device.is_connected().await {
+            ReconnectState::Connected
+        } else {
+            ReconnectState::GaveUp
+        };
+        Self {
+            inner: Arc::new(ReconnectingInner {
+                adapter: adapter.clone(),
+                device,
+                policy,
+                required_services,
+                reconnecting: async_lock::Mutex::new(()),
+                state: Mutex::new(state),
+                state_sender,
+                state_keeper: receiver.deactivate(),
+            }),
+        }
+    }
+
+    /// The wrapped device.
+    pub fn device(&self) -> &Device {
+        &self.inner.device
+    }
+
+    /// The current health state.
+    pub fn state(&self) -> ReconnectState {
+        *self.inner.state.lock().unwrap()
+    }
+
+    /// A stream of changes of the health state, see [ReconnectingDevice::state].
+    ///
+    /// If the stream is not polled fast enough, its oldest states are dropped.
+    pub fn state_changes(&self) -> impl Stream<Item = ReconnectState> + Send + Unpin + 'static {
+        self.inner.state_keeper.activate_cloned()
+    }
+
+    fn set_state(&self, state: ReconnectState) {
+        let mut current = self.inner.state.lock().unwrap();
+        if *current != state {
+            *current = state;
+            let _ = self.inner.state_sender.try_broadcast(state);
+        }
+    }
+
+    /// Returns immediately if the device is connected; otherwise connects to it and discovers
+    /// services, retrying according to the policy. If another task is reconnecting, this waits
+    /// for its result.
+    pub async fn ensure_connected(&self) -> Result<()> {
+        let inner = &self.inner;
+        if inner.device.is_connected().await && self.state() == ReconnectState::Connected {
+            return Ok(());
+        }
+        let _reconnecting = inner.reconnecting.lock().await;
+        if inner.device.is_connected().await && self.state() == ReconnectState::Connected {
+            return Ok(()); // restored by the previous holder of the lock
+        }
+        retry_connect(
+            &inner.policy,
+            inner.device.id(),
+            || self.connect_once(),
+            |state| self.set_state(state),
+        )
+        .await
+    }
+
+    async fn connect_once(&self) -> Result<()> {
+        let inner = &self.inner;
+        if !inner.device.is_connected().await {
+            inner.adapter.connect_device(&inner.device).await?;
+        }
+        let services = inner.device.discover_services().await?;
+        for required in &inner.required_services {
+            if !services.iter().any(|serv| serv.uuid() == *required) {
+                return Err(crate::Error::new(
+                    ErrorKind::NotFound,
+                    None,
+                    format!("required service {required} is not found"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the connection to be dropped and restores it, until all attempts of a round of
+    /// retries fail; then the last error is returned.
+    pub async fn supervise(&self) -> Result<()> {
+        loop {
+            self.ensure_connected().await?;
+            let reason = self.inner.device.wait_for_disconnect().await;
+            info!("{} is disconnected: {reason:?}", self.inner.device.id());
+        }
+    }
+
+    /// Like [ReconnectingDevice::supervise], but starts another round of retries after
+    /// giving up, with the maximum backoff of the policy in between. It never returns.
+    pub async fn supervise_forever(self) {
+        loop {
+            if let Err(e) = self.supervise().await {
+                warn!(
+                    "supervision of {} is interrupted: {e}",
+                    self.inner.device.id()
+                );
+            }
+            Delay::new(self.inner.policy.max_backoff).await;
+        }
+    }
+
+    /// Finds the characteristic specified by UUIDs after making sure the device is connected.
+    ///
+    /// It is resolved from the services discovered after the last connection, so that no
+    /// discovery is done unless the connection has been restored (or services are changed).
+    pub async fn characteristic(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+    ) -> Result<Characteristic> {
+        self.ensure_connected().await?;
+        find_characteristic(&self.inner.device, service, characteristic).await
+    }
+
+    /// Runs `operation` on the characteristic; if it fails because the device is disconnected,
+    /// reconnects and runs it again once.
+    async fn with_characteristic<T, F, Fut>(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+        operation: F,
+    ) -> Result<T>
+    where
+        F: Fn(Characteristic) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let found = self.characteristic(service, characteristic).await?;
+        match operation(found).await {
+            Err(e) if e.kind() == ErrorKind::NotConnected => {
+                let found = self.characteristic(service, characteristic).await?;
+                operation(found).await
+            }
+            result => result,
+        }
+    }
+
+    /// Reads the characteristic, see [Characteristic::read].
+    pub async fn read(&self, service: Uuid, characteristic: Uuid) -> Result<Vec<u8>> {
+        self.with_characteristic(service, characteristic, |ch| async move { ch.read().await })
+            .await
+    }
+
+    /// Writes the characteristic with response, see [Characteristic::write].
+    pub async fn write(&self, service: Uuid, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        self.with_characteristic(service, characteristic, |ch| async move {
+            ch.write(value).await
+        })
+        .await
+    }
+
+    /// Writes the characteristic without response, see [Characteristic::write_without_response].
+    pub async fn write_without_response(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+        value: &[u8],
+    ) -> Result<()> {
+        self.with_characteristic(service, characteristic, |ch| async move {
+            ch.write_without_response(value).await
+        })
+        .await
+    }
+
+    /// Subscribes to the characteristic with [Characteristic::notify_persistent], so that the
+    /// subscription is restored whenever the connection is restored by this wrapper.
+    pub async fn notify(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+    ) -> Result<impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static> {
+        self.with_characteristic(service, characteristic, |ch| async move {
+            ch.notify_persistent().await
+        })
+        .await
+    }
+}
+
+/// Calls `connect` until it succeeds, or until `policy.max_retries` retries have failed, in
+/// which case the last error is returned. State changes are reported to `set_state`.
+async fn retry_connect<F, Fut>(
+    policy: &RetryPolicy,
+    id: DeviceId,
+    mut connect: F,
+    mut set_state: impl FnMut(ReconnectState),
+) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        set_state(ReconnectState::Reconnecting { attempt });
+        match connect().await {
+            Ok(()) => {
+                info!("connection with {id} is restored");
+                set_state(ReconnectState::Connected);
+                return Ok(());
+            }
+            Err(e) if attempt > policy.max_retries => {
+                warn!("gave up reconnecting {id}: {e}");
+                set_state(ReconnectState::GaveUp);
+                return Err(e);
+            }
+            Err(e) => {
+                warn!("failed to reconnect {id}: {e}");
+                Delay::new(policy.backoff(attempt - 1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[test]
+    fn connected_after_failed_attempts() {
+        let mut failures = 2;
+        let mut states = Vec::new();
+        let result = block_on(retry_connect(
+            &policy(5),
+            DeviceId("00:00:00:00:00:01".into()),
+            || {
+                let result: Result<()> = if failures > 0 {
+                    failures -= 1;
+                    Err(ErrorKind::NotConnected.into())
+                } else {
+                    Ok(())
+                };
+                async move { result }
+            },
+            |state| states.push(state),
+        ));
+        assert!(result.is_ok());
+        assert_eq!(
+            states,
+            [
+                ReconnectState::Reconnecting { attempt: 1 },
+                ReconnectState::Reconnecting { attempt: 2 },
+                ReconnectState::Reconnecting { attempt: 3 },
+                ReconnectState::Connected,
+            ]
+        );
+    }
+
+    #[test]
+    fn gives_up_with_last_error() {
+        let mut attempts = 0;
+        let mut states = Vec::new();
+        let result = block_on(retry_connect(
+            &policy(2),
+            DeviceId("00:00:00:00:00:01".into()),
+            || {
+                attempts += 1;
+                let kind = if attempts < 3 {
+                    ErrorKind::Timeout
+                } else {
+                    ErrorKind::NotFound
+                };
+                async move { Err(crate::Error::from(kind)) }
+            },
+            |state| states.push(state),
+        ));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 3);
+        assert_eq!(states.last(), Some(&ReconnectState::GaveUp));
+    }
+}
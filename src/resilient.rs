@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::Duration;
+
+use futures_core::Stream;
+use futures_lite::{future, stream, StreamExt};
+use log::{info, warn};
+use uuid::Uuid;
+
+use super::async_util::NotifierReceiver;
+use super::error::ErrorKind;
+use super::timer::Delay;
+use super::{Adapter, Characteristic, ConnectionEvent, Device, DeviceId, Result};
+
+/// Controls how reconnection is retried by utilities like [Device::resilient_notify].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Amount of consecutive failed attempts before the error is reported to the caller.
+    pub max_retries: u32,
+    /// Delay before the first retry; it is doubled for each following retry.
+    pub initial_backoff: Duration,
+    /// Upper limit of the delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the retry numbered `retry` (starting from 0).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(1 << retry.min(16))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Items yielded by [Device::resilient_notify], [Characteristic::notify_persistent] and
+/// [Characteristic::notify_with_status].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyEvent {
+    /// A value sent from the device.
+    Value(Vec<u8>),
+    /// Notifications are subscribed again after the connection has been restored.
+    /// Values sent by the device during the interruption are lost.
+    Reconnected,
+    /// The connection is dropped, and notifications are interrupted until
+    /// [NotifyEvent::Reconnected]. Only yielded by [Characteristic::notify_with_status].
+    Lost,
+}
+
+type NotifyReceiver = NotifierReceiver<Result<Vec<u8>>>;
+
+/// Device operations of [resilient_notify], separated from its retry loop so that the loop can
+/// be tested off-device.
+trait NotifySource {
+    /// Connects to the device if it is not connected.
+    async fn ensure_connected(&self) -> Result<()>;
+
+    /// Finds the characteristic and subscribes with the CCCD written, which is needed after
+    /// reconnecting.
+    async fn subscribe_with_cccd(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+    ) -> Result<NotifyReceiver>;
+}
+
+struct DeviceSource {
+    adapter: Adapter,
+    device: Device,
+}
+
+impl NotifySource for DeviceSource {
+    async fn ensure_connected(&self) -> Result<()> {
+        if !self.device.is_connected().await {
+            self.adapter.connect_device(&self.device).await?;
+        }
+        Ok(())
+    }
+
+    async fn subscribe_with_cccd(
+        &self,
+        service: Uuid,
+        characteristic: Uuid,
+    ) -> Result<NotifyReceiver> {
+        find_characteristic(&self.device, service, characteristic)
+            .await?
+            .notify_receiver_with_cccd()
+            .await
+    }
+}
+
+struct ResilientNotifyState<S> {
+    source: S,
+    service: Uuid,
+    characteristic: Uuid,
+    policy: RetryPolicy,
+    receiver: Option<NotifyReceiver>,
+    subscribed_once: bool,
+    failures: u32,
+}
+
+impl<S: NotifySource> ResilientNotifyState<S> {
+    async fn subscribe(&self) -> Result<NotifyReceiver> {
+        self.source.ensure_connected().await?;
+        self.source
+            .subscribe_with_cccd(self.service, self.characteristic)
+            .await
+    }
+}
+
+/// Finds the characteristic by UUIDs, discovering services if they are not discovered yet.
+pub(crate) async fn find_characteristic(
+    device: &Device,
+    service: Uuid,
+    characteristic: Uuid,
+) -> Result<Characteristic> {
+    let service_found = device
+        .services()
+        .await?
+        .into_iter()
+        .find(|serv| serv.uuid() == service)
+        .ok_or_else(|| {
+            crate::Error::new(
+                ErrorKind::NotFound,
+                None,
+                format!("service {service} is not found"),
+            )
+        })?;
+    service_found
+        .characteristics()
+        .await?
+        .into_iter()
+        .find(|ch| ch.uuid() == characteristic)
+        .ok_or_else(|| {
+            crate::Error::new(
+                ErrorKind::NotFound,
+                None,
+                format!("characteristic {characteristic} is not found"),
+            )
+        })
+}
+
+/// Finds the characteristic by UUIDs and instance IDs, discovering services if they are not
+/// discovered yet.
+async fn find_characteristic_instance(
+    device: &Device,
+    key: &PersistentKey,
+) -> Result<Characteristic> {
+    for service in device.services().await? {
+        if service.uuid() != key.service || service.instance_id() != key.service_instance {
+            continue;
+        }
+        let found = service
+            .characteristics()
+            .await?
+            .into_iter()
+            .find(|ch| ch.uuid() == key.characteristic && ch.instance_id() == key.instance_id);
+        if let Some(characteristic) = found {
+            return Ok(characteristic);
+        }
+    }
+    Err(crate::Error::new(
+        ErrorKind::NotFound,
+        None,
+        format!(
+            "characteristic {} (instance {}) of service {} (instance {}) is not found",
+            key.characteristic, key.instance_id, key.service, key.service_instance
+        ),
+    ))
+}
+
+pub(crate) fn resilient_notify(
+    adapter: &Adapter,
+    device: &Device,
+    service: Uuid,
+    characteristic: Uuid,
+    policy: RetryPolicy,
+) -> impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static {
+    let source = DeviceSource {
+        adapter: adapter.clone(),
+        device: device.clone(),
+    };
+    resilient_stream(source, service, characteristic, policy)
+}
+
+fn resilient_stream<S: NotifySource + 'static>(
+    source: S,
+    service: Uuid,
+    characteristic: Uuid,
+    policy: RetryPolicy,
+) -> impl Stream<Item = Result<NotifyEvent>> + Unpin + 'static {
+    let state = ResilientNotifyState {
+        source,
+        service,
+        characteristic,
+        policy,
+        receiver: None,
+        subscribed_once: false,
+        failures: 0,
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(receiver) = state.receiver.as_mut() {
+                match receiver.next().await {
+                    Some(Ok(value)) => return Some((Ok(NotifyEvent::Value(value)), state)),
+                    Some(Err(e)) => return Some((Err(e), state)),
+                    None => {
+                        info!("notification of {} interrupted", state.characteristic);
+                        state.receiver = None;
+                    }
+                }
+            }
+            if state.failures > 0 {
+                Delay::new(state.policy.backoff(state.failures - 1)).await;
+            }
+            match state.subscribe().await {
+                Ok(receiver) => {
+                    state.receiver.replace(receiver);
+                    state.failures = 0;
+                    if state.subscribed_once {
+                        return Some((Ok(NotifyEvent::Reconnected), state));
+                    }
+                    state.subscribed_once = true;
+                }
+                Err(e) => {
+                    warn!("failed to subscribe {}: {e}", state.characteristic);
+                    state.failures += 1;
+                    if state.failures > state.policy.max_retries {
+                        // reports the error, then starts another round of retries.
+                        state.failures = 0;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+        }
+    }))
+}
+
+// Subscriptions made by `Characteristic::notify_persistent`, grouped by devices. The entry of a
+// device is removed when its last persistent stream is dropped.
+static PERSISTENT_SUBSCRIPTIONS: LazyLock<Mutex<HashMap<DeviceId, Arc<PersistentSubscriptions>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct PersistentSubscriptions {
+    /// Held while restoring, so that subscriptions are restored once and in order.
+    restoring: async_lock::Mutex<()>,
+    /// In the order of subscribing.
+    slots: Mutex<Vec<Weak<PersistentSlot>>>,
+}
+
+/// Identifies the characteristic of a persistent subscription; characteristics of the same UUID
+/// are told apart by instance IDs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PersistentKey {
+    pub service: Uuid,
+    pub service_instance: i32,
+    pub characteristic: Uuid,
+    pub instance_id: i32,
+}
+
+struct PersistentSlot {
+    key: PersistentKey,
+    /// Set while the stream owning this slot has a working receiver.
+    active: AtomicBool,
+    /// Result of the last restoration, taken by the stream owning this slot.
+    restored: Mutex<Option<Result<NotifyReceiver>>>,
+}
+
+impl PersistentSubscriptions {
+    fn register(dev_id: &DeviceId, key: PersistentKey) -> (Arc<Self>, Arc<PersistentSlot>) {
+        // the map is kept locked, so that the entry can't be removed before the slot is added.
+        let mut map = PERSISTENT_SUBSCRIPTIONS.lock().unwrap();
+        let subs = map.entry(dev_id.clone()).or_default().clone();
+        let slot = Arc::new(PersistentSlot {
+            key,
+            active: AtomicBool::new(true),
+            restored: Mutex::new(None),
+        });
+        let mut slots = subs.slots.lock().unwrap();
+        slots.retain(|slot| slot.strong_count() > 0);
+        slots.push(Arc::downgrade(&slot));
+        drop((slots, map));
+        (subs, slot)
+    }
+
+    /// Removes `slot`, and removes the entry of the device if no slot is left.
+    fn unregister(self: &Arc<Self>, dev_id: &DeviceId, slot: &Arc<PersistentSlot>) {
+        let mut map = PERSISTENT_SUBSCRIPTIONS.lock().unwrap();
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|weak| {
+            weak.strong_count() > 0 && !std::ptr::eq(weak.as_ptr(), Arc::as_ptr(slot))
+        });
+        if slots.is_empty() && map.get(dev_id).is_some_and(|subs| Arc::ptr_eq(subs, self)) {
+            map.remove(dev_id);
+        }
+    }
+
+    /// Restores all inactive subscriptions which are not restored yet, in order. The first
+    /// stream noticing the reconnection does this for other streams of the device.
+    async fn restore(&self, device: &Device) {
+        let _restoring = self.restoring.lock().await;
+        let slots: Vec<_> = self
+            .slots
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for slot in slots {
+            if slot.active.load(Ordering::SeqCst) || slot.restored.lock().unwrap().is_some() {
+                continue;
+            }
+            let result = async {
+                find_characteristic_instance(device, &slot.key)
+                    .await?
+                    .notify_receiver_with_cccd()
+                    .await
+            }
+            .await;
+            if let Err(e) = &result {
+                warn!(
+                    "failed to restore the subscription of {}: {e}",
+                    slot.key.characteristic
+                );
+            }
+            slot.restored.lock().unwrap().replace(result);
+        }
+    }
+}
+
+struct PersistentNotifyState<E> {
+    device: Device,
+    subs: Arc<PersistentSubscriptions>,
+    slot: Arc<PersistentSlot>,
+    receiver: Option<NotifyReceiver>,
+    events: E,
+    /// Set after a failed restoration; the next attempt is made after the next reconnection.
+    wait_reconnect: bool,
+    /// Yields [NotifyEvent::Lost] on disconnection.
+    report_lost: bool,
+}
+
+impl<E> Drop for PersistentNotifyState<E> {
+    fn drop(&mut self) {
+        self.subs.unregister(&self.device.id(), &self.slot);
+    }
+}
+
+impl<E: Stream<Item = (DeviceId, ConnectionEvent)> + Unpin> PersistentNotifyState<E> {
+    /// Returns false if connection events are no longer available.
+    async fn wait_connected(&mut self) -> bool {
+        if !self.wait_reconnect && self.device.is_connected().await {
+            return true;
+        }
+        while let Some((dev_id, event)) = self.events.next().await {
+            if dev_id == self.device.id() && event == ConnectionEvent::Connected {
+                self.wait_reconnect = false;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+pub(crate) fn persistent_notify<E>(
+    device: Device,
+    key: PersistentKey,
+    receiver: NotifyReceiver,
+    events: E,
+    report_lost: bool,
+) -> impl Stream<Item = Result<NotifyEvent>> + Send + Unpin + 'static
+where
+    E: Stream<Item = (DeviceId, ConnectionEvent)> + Send + Unpin + 'static,
+{
+    let (subs, slot) = PersistentSubscriptions::register(&device.id(), key);
+    let state = PersistentNotifyState {
+        device,
+        subs,
+        slot,
+        receiver: Some(receiver),
+        events,
+        wait_reconnect: false,
+        report_lost,
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(receiver) = state.receiver.as_mut() {
+                match receiver.next().await {
+                    Some(Ok(value)) => return Some((Ok(NotifyEvent::Value(value)), state)),
+                    Some(Err(e)) => return Some((Err(e), state)),
+                    None => {
+                        info!(
+                            "persistent notification of {} interrupted",
+                            state.slot.key.characteristic
+                        );
+                        state.receiver = None;
+                        state.slot.active.store(false, Ordering::SeqCst);
+                        if state.report_lost {
+                            return Some((Ok(NotifyEvent::Lost), state));
+                        }
+                    }
+                }
+            }
+            if !state.wait_connected().await {
+                return None;
+            }
+            // drops connection events received before this point.
+            while let Some(Some(_)) = future::poll_once(state.events.next()).await {}
+            state.subs.restore(&state.device).await;
+            let restored = state.slot.restored.lock().unwrap().take();
+            match restored {
+                Some(Ok(receiver)) => {
+                    state.receiver.replace(receiver);
+                    state.slot.active.store(true, Ordering::SeqCst);
+                    return Some((Ok(NotifyEvent::Reconnected), state));
+                }
+                Some(Err(e)) => {
+                    state.wait_reconnect = true;
+                    return Some((Err(e), state));
+                }
+                None => state.wait_reconnect = true,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+    use crate::async_util::Notifier;
+    use crate::OverflowPolicy;
+
+    /// Simulates a device whose subscription ends on disconnection.
+    struct FakeSource {
+        notifier: Notifier<Result<Vec<u8>>>,
+        connected: AtomicBool,
+        calls: Mutex<Vec<&'static str>>,
+    }
+
+    impl NotifySource for Arc<FakeSource> {
+        async fn ensure_connected(&self) -> Result<()> {
+            if !self.connected.swap(true, Ordering::SeqCst) {
+                self.calls.lock().unwrap().push("connect");
+            }
+            Ok(())
+        }
+
+        async fn subscribe_with_cccd(&self, _: Uuid, _: Uuid) -> Result<NotifyReceiver> {
+            self.calls.lock().unwrap().push("subscribe with CCCD");
+            self.notifier
+                .subscribe(|| Ok::<_, crate::Error>(()), || ())
+                .await
+        }
+    }
+
+    #[test]
+    fn resilient_resubscribe_writes_cccd() {
+        let source = Arc::new(FakeSource {
+            notifier: Notifier::new(8, OverflowPolicy::DropOldest),
+            connected: AtomicBool::new(true),
+            calls: Mutex::new(Vec::new()),
+        });
+        let (service, characteristic) = (Uuid::from_u128(0x180d), Uuid::from_u128(0x2a37));
+        let mut stream = resilient_stream(
+            source.clone(),
+            service,
+            characteristic,
+            RetryPolicy::default(),
+        );
+        block_on(async {
+            assert!(future::poll_once(stream.next()).await.is_none());
+            source.notifier.notify(Ok(vec![1]));
+            assert_eq!(
+                stream.next().await.unwrap().unwrap(),
+                NotifyEvent::Value(vec![1])
+            );
+
+            source.connected.store(false, Ordering::SeqCst);
+            source.notifier.close();
+            let event = stream.next().await.unwrap().unwrap();
+            assert_eq!(event, NotifyEvent::Reconnected);
+            assert_eq!(
+                *source.calls.lock().unwrap(),
+                ["subscribe with CCCD", "connect", "subscribe with CCCD"]
+            );
+            source.notifier.notify(Ok(vec![2]));
+            assert_eq!(
+                stream.next().await.unwrap().unwrap(),
+                NotifyEvent::Value(vec![2])
+            );
+        });
+    }
+
+    fn key(instance_id: i32) -> PersistentKey {
+        PersistentKey {
+            service: Uuid::from_u128(0x180d),
+            service_instance: 1,
+            characteristic: Uuid::from_u128(0x2a37),
+            instance_id,
+        }
+    }
+
+    #[test]
+    fn persistent_entry_removed_with_last_slot() {
+        let dev_id = DeviceId("00:00:00:00:00:0A".into());
+        let (subs, slot_1) = PersistentSubscriptions::register(&dev_id, key(2));
+        let (subs_2, slot_2) = PersistentSubscriptions::register(&dev_id, key(5));
+        assert!(Arc::ptr_eq(&subs, &subs_2));
+        assert_ne!(slot_1.key, slot_2.key);
+
+        subs.unregister(&dev_id, &slot_1);
+        assert!(PERSISTENT_SUBSCRIPTIONS
+            .lock()
+            .unwrap()
+            .contains_key(&dev_id));
+        subs_2.unregister(&dev_id, &slot_2);
+        assert!(!PERSISTENT_SUBSCRIPTIONS
+            .lock()
+            .unwrap()
+            .contains_key(&dev_id));
+    }
+}
@@ -0,0 +1,460 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use java_spaghetti::{Env, Local, Ref};
+use log::warn;
+use uuid::Uuid;
+
+use super::adapter::ActiveScan;
+use super::bindings::android::bluetooth::le::{
+    ScanFilter as JScanFilter, ScanFilter_Builder, ScanSettings as JScanSettings,
+    ScanSettings_Builder,
+};
+use super::bindings::android::bluetooth::{BluetoothAdapter, BluetoothDevice};
+use super::bindings::android::os::ParcelUuid;
+use super::bindings::java::lang::String as JString;
+use super::error::ErrorKind;
+use super::util::OptionExt;
+use super::vm_context::{android_api_level, jni_with_env};
+use crate::{AdvertisementData, AdvertisingDevice, DeviceId, PhyMask, Result};
+
+/// Events generated by [crate::Adapter::scan_with_settings].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ScanEvent {
+    /// An advertisement matching the filters is received.
+    Found(AdvertisingDevice),
+    /// The device stopped advertising within the range; this is reported only with
+    /// [ScanCallbackType::MatchLost] or [ScanCallbackType::FirstMatchAndMatchLost].
+    Lost(DeviceId),
+    /// The scan is paused by [crate::Adapter::pause_scanning] or
+    /// [crate::AdapterConfig::pause_scans_while_connecting].
+    Paused,
+    /// The scan is resumed after being paused.
+    Resumed,
+}
+
+/// The stream of [ScanEvent] returned by [crate::Adapter::scan_with_settings]. The scan is
+/// stopped when it is dropped.
+pub struct ScanEvents<'a> {
+    stream: Pin<Box<dyn Stream<Item = ScanEvent> + Send + 'a>>,
+    scan: Arc<ActiveScan>,
+}
+
+impl<'a> ScanEvents<'a> {
+    pub(crate) fn new(
+        stream: Pin<Box<dyn Stream<Item = ScanEvent> + Send + 'a>>,
+        scan: Arc<ActiveScan>,
+    ) -> Self {
+        Self { stream, scan }
+    }
+
+    /// Changes the RSSI threshold (see [ScanSettings::min_rssi]) of this scan while it is running;
+    /// `None` removes the threshold.
+    pub fn set_min_rssi(&self, min_rssi: Option<i16>) {
+        jni_with_env(|env| self.scan.set_min_rssi(env, min_rssi))
+    }
+}
+
+impl Stream for ScanEvents<'_> {
+    type Item = ScanEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ScanEvent>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+impl std::fmt::Debug for ScanEvents<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScanEvents").finish_non_exhaustive()
+    }
+}
+
+/// When to report advertisements, corresponding to `CALLBACK_TYPE_*` constants of
+/// `android.bluetooth.le.ScanSettings`.
+///
+/// Types other than [ScanCallbackType::AllMatches] require at least one [ScanFilter]
+/// and hardware filtering support; they are unavailable below API level 23.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ScanCallbackType {
+    /// Report every received advertisement matching the filters.
+    #[default]
+    AllMatches,
+    /// Report only the first advertisement received from each device.
+    FirstMatch,
+    /// Report only when advertisements are no longer received from a previously matched device.
+    MatchLost,
+    /// Combination of [ScanCallbackType::FirstMatch] and [ScanCallbackType::MatchLost] for
+    /// tracking whether devices are within the range.
+    FirstMatchAndMatchLost,
+}
+
+impl ScanCallbackType {
+    fn to_java(self) -> i32 {
+        match self {
+            Self::AllMatches => JScanSettings::CALLBACK_TYPE_ALL_MATCHES,
+            Self::FirstMatch => JScanSettings::CALLBACK_TYPE_FIRST_MATCH,
+            Self::MatchLost => JScanSettings::CALLBACK_TYPE_MATCH_LOST,
+            Self::FirstMatchAndMatchLost => {
+                JScanSettings::CALLBACK_TYPE_FIRST_MATCH | JScanSettings::CALLBACK_TYPE_MATCH_LOST
+            }
+        }
+    }
+}
+
+/// Settings for [crate::Adapter::scan_with_settings].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ScanSettings {
+    callback_type: ScanCallbackType,
+    phy: Option<PhyMask>,
+    deduplicate: bool,
+    min_rssi: Option<i16>,
+}
+
+impl ScanSettings {
+    /// Creates the default settings used by [crate::Adapter::scan].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets when to report advertisements. [ScanCallbackType::AllMatches] is the default.
+    pub fn callback_type(mut self, callback_type: ScanCallbackType) -> Self {
+        self.callback_type = callback_type;
+        self
+    }
+
+    /// Sets the PHYs used for scanning on API level 26 and above. The primary advertising channel
+    /// only uses LE 1M or LE Coded PHY, so [PhyMask::le_2m] doesn't make a difference.
+    ///
+    /// Scanning on the LE Coded PHY (long range) requires `BluetoothAdapter.isLeCodedPhySupported()`;
+    /// if it is unsupported, or the API level is below 26, the default LE 1M PHY is used with a
+    /// warning. Extended advertisements are also reported when LE Coded PHY is used.
+    pub fn phy(mut self, phy: PhyMask) -> Self {
+        self.phy = Some(phy);
+        self
+    }
+
+    /// If set, a device is reported again only if its advertisement changed according to
+    /// [crate::AdvertisementData::significant_eq]; it is reported again after a
+    /// [ScanEvent::Lost] event as well. Default: false.
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    /// Drops advertisements received with a signal weaker than `min_rssi` (in dBm), before they
+    /// are passed to native code, so that they cost nothing. Client-side filters and
+    /// deduplication only see advertisements above the threshold; [ScanEvent::Lost] events are
+    /// not affected. See also [ScanEvents::set_min_rssi]. No threshold is set by default.
+    pub fn min_rssi(mut self, min_rssi: i16) -> Self {
+        self.min_rssi = Some(min_rssi);
+        self
+    }
+
+    pub(crate) fn min_rssi_value(&self) -> Option<i16> {
+        self.min_rssi
+    }
+
+    pub(crate) fn is_deduplicated(&self) -> bool {
+        self.deduplicate
+    }
+
+    pub(crate) fn to_java<'env>(
+        &self,
+        env: Env<'env>,
+        adapter: &Ref<'env, BluetoothAdapter>,
+    ) -> Result<Local<'env, JScanSettings>> {
+        let builder = ScanSettings_Builder::new(env)?;
+        builder.setScanMode(JScanSettings::SCAN_MODE_LOW_LATENCY)?;
+        if self.callback_type != ScanCallbackType::AllMatches {
+            if android_api_level() < 23 {
+                return Err(crate::Error::new(
+                    ErrorKind::NotSupported,
+                    None,
+                    "scan callback types other than `AllMatches` require API level 23",
+                ));
+            }
+            builder.setCallbackType(self.callback_type.to_java())?;
+        }
+        if let Some(phy) = self.phy.filter(|phy| phy.le_coded) {
+            if android_api_level() < 26 {
+                warn!("scanning on LE Coded PHY requires API level 26, using LE 1M PHY");
+            } else if !adapter.isLeCodedPhySupported()? {
+                warn!("LE Coded PHY is not supported by the adapter, using LE 1M PHY");
+            } else {
+                builder.setLegacy(false)?;
+                builder.setPhy(if phy.le_1m {
+                    JScanSettings::PHY_LE_ALL_SUPPORTED
+                } else {
+                    BluetoothDevice::PHY_LE_CODED
+                })?;
+            }
+        }
+        Ok(builder.build()?.non_null()?)
+    }
+}
+
+/// A filter of advertisements for [crate::Adapter::scan_with_filters].
+///
+/// Criteria set in the same filter must all be satisfied; an advertisement is reported if it matches
+/// any of the filters passed to the scan.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ScanFilter {
+    service: Option<Uuid>,
+    service_mask: Option<Uuid>,
+    /// The solicitation UUID and its mask.
+    solicitation: Option<(Uuid, Option<Uuid>)>,
+    device_name: Option<String>,
+    device_name_contains: Option<String>,
+    device_id: Option<DeviceId>,
+}
+
+impl ScanFilter {
+    /// Creates a filter that matches all advertisements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches advertisements including a GATT service with the given UUID.
+    pub fn service(mut self, uuid: Uuid) -> Self {
+        self.service = Some(uuid);
+        self.service_mask = None;
+        self
+    }
+
+    /// Matches advertisements including a GATT service whose UUID equals `uuid` in the bits set
+    /// in `mask`, so that a family of UUIDs can be matched by a single filter of the Android
+    /// Bluetooth stack. Bits of `uuid` cleared in `mask` are ignored (and cleared).
+    pub fn service_masked(mut self, uuid: Uuid, mask: Uuid) -> Self {
+        self.service = Some(masked_uuid(uuid, mask));
+        self.service_mask = Some(mask);
+        self
+    }
+
+    /// Matches advertisements soliciting a GATT service with the given UUID. This requires
+    /// Android API level 29 or higher; otherwise starting the scan fails with an
+    /// [ErrorKind::NotSupported] error.
+    ///
+    /// Solicitation UUIDs are not included in [crate::AdvertisementData], so this is only
+    /// checked by the Android Bluetooth stack.
+    pub fn service_solicitation(mut self, uuid: Uuid) -> Self {
+        self.solicitation = Some((uuid, None));
+        self
+    }
+
+    /// Matches advertisements soliciting a GATT service like [ScanFilter::service_solicitation],
+    /// with a mask like [ScanFilter::service_masked].
+    pub fn service_solicitation_masked(mut self, uuid: Uuid, mask: Uuid) -> Self {
+        self.solicitation = Some((masked_uuid(uuid, mask), Some(mask)));
+        self
+    }
+
+    /// Matches advertisements whose local name equals `name`. This is done by the Android Bluetooth stack
+    /// via `ScanFilter.Builder.setDeviceName()`.
+    ///
+    /// Note that Android compares the *complete* local name case-sensitively; a shortened local name
+    /// in the advertisement doesn't match. Use [ScanFilter::device_name_contains] for partial matching.
+    pub fn device_name(mut self, name: impl Into<String>) -> Self {
+        self.device_name = Some(name.into());
+        self
+    }
+
+    /// Matches advertisements whose local name contains `pattern` (case-sensitive).
+    ///
+    /// Android doesn't support this, so it is checked by this crate against
+    /// [crate::AdvertisementData::local_name] of every received advertisement.
+    pub fn device_name_contains(mut self, pattern: impl Into<String>) -> Self {
+        self.device_name_contains = Some(pattern.into());
+        self
+    }
+
+    /// Matches advertisements sent by the device with the given address.
+    pub(crate) fn device_id(mut self, id: DeviceId) -> Self {
+        self.device_id = Some(id);
+        self
+    }
+
+    /// Checks if some criteria of the filter cannot be handled by the Android Bluetooth stack.
+    pub(crate) fn needs_client_side(&self) -> bool {
+        self.device_name_contains.is_some()
+    }
+
+    /// Checks all criteria of the filter on this side.
+    pub(crate) fn matches(&self, adv_dev: &AdvertisingDevice) -> bool {
+        self.matches_data(&adv_dev.device.id, &adv_dev.adv_data)
+    }
+
+    fn matches_data(&self, dev_id: &DeviceId, adv_data: &AdvertisementData) -> bool {
+        if let Some(id) = &self.device_id {
+            if dev_id != id {
+                return false;
+            }
+        }
+        if let Some(uuid) = self.service {
+            let mask = self.service_mask.unwrap_or(Uuid::max());
+            if !adv_data
+                .services
+                .iter()
+                .any(|&service| masked_uuid(service, mask) == uuid)
+            {
+                return false;
+            }
+        }
+        if let Some(name) = &self.device_name {
+            if adv_data.local_name.as_ref() != Some(name) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.device_name_contains {
+            if !adv_data
+                .local_name
+                .as_ref()
+                .is_some_and(|name| name.contains(pattern.as_str()))
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Builds the `android.bluetooth.le.ScanFilter` with criteria supported by Android.
+    pub(crate) fn to_java<'env>(&self, env: Env<'env>) -> Result<Local<'env, JScanFilter>> {
+        let builder = ScanFilter_Builder::new(env)?;
+        let parcel_uuid = |uuid: Uuid| {
+            let uuid_string = JString::from_env_str(env, uuid.to_string());
+            ParcelUuid::fromString(env, uuid_string)
+        };
+        if let Some(uuid) = self.service {
+            match self.service_mask {
+                Some(mask) => builder
+                    .setServiceUuid_ParcelUuid_ParcelUuid(parcel_uuid(uuid)?, parcel_uuid(mask)?)?,
+                None => builder.setServiceUuid_ParcelUuid(parcel_uuid(uuid)?)?,
+            };
+        }
+        if let Some((uuid, mask)) = self.solicitation {
+            if android_api_level() < 29 {
+                return Err(crate::Error::new(
+                    ErrorKind::NotSupported,
+                    None,
+                    "filtering by service solicitation UUIDs requires API level 29",
+                ));
+            }
+            match mask {
+                Some(mask) => builder.setServiceSolicitationUuid_ParcelUuid_ParcelUuid(
+                    parcel_uuid(uuid)?,
+                    parcel_uuid(mask)?,
+                )?,
+                None => builder.setServiceSolicitationUuid_ParcelUuid(parcel_uuid(uuid)?)?,
+            };
+        }
+        if let Some(name) = &self.device_name {
+            builder.setDeviceName(JString::from_env_str(env, name))?;
+        }
+        if let Some(id) = &self.device_id {
+            builder.setDeviceAddress(JString::from_env_str(env, &id.0))?;
+        }
+        Ok(builder.build()?.non_null()?)
+    }
+}
+
+/// Clears bits of `uuid` which are cleared in `mask`.
+fn masked_uuid(uuid: Uuid, mask: Uuid) -> Uuid {
+    Uuid::from_u128(uuid.as_u128() & mask.as_u128())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adv_data(local_name: Option<&str>, services: &[Uuid]) -> AdvertisementData {
+        AdvertisementData {
+            local_name: local_name.map(str::to_string),
+            manufacturer_data: None,
+            services: services.to_vec(),
+            service_data: Default::default(),
+            tx_power_level: None,
+            is_connectable: true,
+        }
+    }
+
+    const BATTERY: Uuid = uuid::uuid!("0000180f-0000-1000-8000-00805f9b34fb");
+    const HEART_RATE: Uuid = uuid::uuid!("0000180d-0000-1000-8000-00805f9b34fb");
+    const NUS: Uuid = uuid::uuid!("6e400001-b5a3-f393-e0a9-e50e24dcca9e");
+    // Matches the 16-bit UUIDs based on the Bluetooth base UUID.
+    const BASE_MASK: Uuid = uuid::uuid!("ffff0000-ffff-ffff-ffff-ffffffffffff");
+
+    #[test]
+    fn masked_uuid_clears_bits() {
+        assert_eq!(masked_uuid(BATTERY, Uuid::max()), BATTERY);
+        assert!(masked_uuid(BATTERY, Uuid::nil()).is_nil());
+        assert_eq!(
+            masked_uuid(BATTERY, BASE_MASK),
+            uuid::uuid!("00000000-0000-1000-8000-00805f9b34fb")
+        );
+        assert_eq!(
+            masked_uuid(NUS, uuid::uuid!("ffffff00-ffff-ffff-ffff-ffffffffffff")),
+            uuid::uuid!("6e400000-b5a3-f393-e0a9-e50e24dcca9e")
+        );
+        assert_eq!(
+            masked_uuid(BATTERY, BASE_MASK),
+            masked_uuid(HEART_RATE, BASE_MASK)
+        );
+    }
+
+    #[test]
+    fn filter_matches_services() {
+        let id = DeviceId("AB:CD:EF:01:23:45".to_string());
+        let data = adv_data(None, &[HEART_RATE, NUS]);
+        assert!(ScanFilter::new().matches_data(&id, &data));
+        assert!(ScanFilter::new().service(NUS).matches_data(&id, &data));
+        assert!(!ScanFilter::new().service(BATTERY).matches_data(&id, &data));
+        assert!(!ScanFilter::new()
+            .service(BATTERY)
+            .matches_data(&id, &adv_data(None, &[])));
+
+        let base_family = ScanFilter::new().service_masked(BATTERY, BASE_MASK);
+        assert!(base_family.matches_data(&id, &data));
+        assert!(!base_family.matches_data(&id, &adv_data(None, &[NUS])));
+        // a later unmasked `service` replaces the mask.
+        let exact = base_family.service(BATTERY);
+        assert!(!exact.matches_data(&id, &data));
+
+        // solicitation UUIDs are only checked by Android.
+        let solicit = ScanFilter::new().service_solicitation_masked(BATTERY, BASE_MASK);
+        assert!(solicit.matches_data(&id, &adv_data(None, &[])));
+    }
+
+    #[test]
+    fn filter_matches_names_and_id() {
+        let id = DeviceId("AB:CD:EF:01:23:45".to_string());
+        let other_id = DeviceId("AB:CD:EF:01:23:46".to_string());
+        let data = adv_data(Some("Sensor 42"), &[BATTERY]);
+        let unnamed = adv_data(None, &[BATTERY]);
+
+        let name = ScanFilter::new().device_name("Sensor 42");
+        assert!(name.matches_data(&id, &data));
+        assert!(!name.matches_data(&id, &unnamed));
+        assert!(!ScanFilter::new()
+            .device_name("Sensor")
+            .matches_data(&id, &data));
+
+        let contains = ScanFilter::new().device_name_contains("sor 4");
+        assert!(contains.needs_client_side());
+        assert!(contains.matches_data(&id, &data));
+        assert!(!contains.matches_data(&id, &unnamed));
+        assert!(!ScanFilter::new()
+            .device_name_contains("sensor")
+            .matches_data(&id, &data));
+
+        let by_id = ScanFilter::new().device_id(id.clone()).service(BATTERY);
+        assert!(by_id.matches_data(&id, &data));
+        assert!(!by_id.matches_data(&other_id, &data));
+        assert!(!by_id.service(NUS).matches_data(&id, &data));
+    }
+}
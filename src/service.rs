@@ -1,11 +1,10 @@
 use std::sync::Arc;
 
-use super::bindings::android::bluetooth::BluetoothGattService;
 use super::characteristic::Characteristic;
+use super::descriptor::Descriptor;
+use super::device::Device;
 use super::gatt_tree::{CachedWeak, GattTree, ServiceInner};
 use super::util::OptionExt;
-use super::util::{JavaIterator, UuidExt};
-use super::vm_context::jni_with_env;
 use super::DeviceId;
 
 use crate::{Result, Uuid};
@@ -15,12 +14,15 @@ use crate::{Result, Uuid};
 pub struct Service {
     dev_id: DeviceId,
     service_id: Uuid,
+    instance_id: i32,
     inner: CachedWeak<ServiceInner>,
 }
 
 impl PartialEq for Service {
     fn eq(&self, other: &Self) -> bool {
-        self.dev_id == other.dev_id && self.service_id == other.service_id
+        self.dev_id == other.dev_id
+            && self.service_id == other.service_id
+            && self.instance_id == other.instance_id
     }
 }
 
@@ -30,14 +32,16 @@ impl std::hash::Hash for Service {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.dev_id.hash(state);
         self.service_id.hash(state);
+        self.instance_id.hash(state);
     }
 }
 
 impl Service {
-    pub(crate) fn new(dev_id: DeviceId, service_id: Uuid) -> Self {
+    pub(crate) fn new(dev_id: DeviceId, service_id: Uuid, instance_id: i32) -> Self {
         Self {
             dev_id,
             service_id,
+            instance_id,
             inner: CachedWeak::new(),
         }
     }
@@ -52,12 +56,24 @@ impl Service {
         Ok(self.service_id)
     }
 
+    /// The instance ID of this service, from `BluetoothGattService.getInstanceId()`. It tells
+    /// apart services of the same UUID on the device.
+    pub fn instance_id(&self) -> i32 {
+        self.instance_id
+    }
+
+    /// The device of this service. It is available even if the device is disconnected, so
+    /// device-level operations like [Device::is_connected] can be called on it.
+    pub fn device(&self) -> Result<Device> {
+        Device::from_known_id(&self.dev_id)
+    }
+
     /// Whether this is a primary service of the device.
+    ///
+    /// Secondary services are not returned by [crate::Device::services]; they can be
+    /// reached from [Service::included_services] of the including service.
     pub async fn is_primary(&self) -> Result<bool> {
-        jni_with_env(|env| {
-            Ok(self.get_inner()?.service.as_ref(env).getType()?
-                == BluetoothGattService::SERVICE_TYPE_PRIMARY)
-        })
+        Ok(self.get_inner()?.is_primary)
     }
 
     /// Returns all characteristics associated with this service.
@@ -84,11 +100,44 @@ impl Service {
 
     /// Get previously discovered characteristics.
     pub async fn characteristics(&self) -> Result<Vec<Characteristic>> {
-        Ok(self
-            .get_inner()?
-            .chars
-            .keys()
-            .map(|id| Characteristic::new(self.dev_id.clone(), self.service_id, *id))
+        let inner = self.get_inner()?;
+        let mut chars: Vec<_> = inner.chars.values().collect();
+        chars.sort_by_key(|ch| ch.instance_id);
+        Ok(chars
+            .into_iter()
+            .map(|ch| {
+                Characteristic::new(
+                    self.dev_id.clone(),
+                    self.service_id,
+                    self.instance_id,
+                    ch.uuid,
+                    ch.instance_id,
+                )
+            })
+            .collect())
+    }
+
+    /// Get previously discovered descriptors of all characteristics in this service,
+    /// ordered by characteristics.
+    pub async fn all_descriptors(&self) -> Result<Vec<Descriptor>> {
+        let inner = self.get_inner()?;
+        let mut chars: Vec<_> = inner.chars.values().collect();
+        chars.sort_by_key(|ch| ch.instance_id);
+        Ok(chars
+            .into_iter()
+            .flat_map(|ch| {
+                ch.descs.iter().enumerate().map(|(index, desc)| {
+                    Descriptor::new(
+                        self.dev_id.clone(),
+                        self.service_id,
+                        self.instance_id,
+                        ch.uuid,
+                        ch.instance_id,
+                        desc.uuid,
+                        index,
+                    )
+                })
+            })
             .collect())
     }
 
@@ -110,26 +159,46 @@ impl Service {
     }
 
     /// Returns the included services of this service.
+    ///
+    /// The include relationships are captured during service discovery.
     pub async fn included_services(&self) -> Result<Vec<Service>> {
-        jni_with_env(|env| {
-            let inner = self.get_inner()?;
-            let service = inner.service.as_ref(env);
-            let includes = service.getIncludedServices()?.non_null()?;
-            let vec = JavaIterator(includes.iterator()?.non_null()?)
-                .filter_map(|serv| {
-                    serv.cast::<BluetoothGattService>()
-                        .ok()
-                        .and_then(|serv| Uuid::from_java(serv.getUuid().ok()??.as_ref()).ok())
-                })
-                .map(|uuid| Service::new(self.dev_id.clone(), uuid))
-                .collect();
-            Ok(vec)
-        })
+        Ok(self
+            .get_inner()?
+            .includes
+            .iter()
+            .map(|&(uuid, instance_id)| Service::new(self.dev_id.clone(), uuid, instance_id))
+            .collect())
     }
 
     fn get_inner(&self) -> Result<Arc<ServiceInner>, crate::Error> {
-        self.inner.get_or_find(|| {
-            GattTree::find_service(&self.dev_id, self.service_id).ok_or_check_conn(&self.dev_id)
+        self.inner.get_or_find(&self.dev_id, || {
+            GattTree::find_service(&self.dev_id, self.instance_id)
+                .filter(|serv| serv.uuid == self.service_id)
+                .ok_or_check_conn(&self.dev_id)
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn services_of_same_uuid_are_distinct() {
+        let dev_id = DeviceId("00:00:00:00:00:01".into());
+        let uuid = Uuid::from_u128(0x180f);
+        let first = Service::new(dev_id.clone(), uuid, 10);
+        let second = Service::new(dev_id.clone(), uuid, 20);
+        assert_ne!(first, second);
+        assert_eq!(first, Service::new(dev_id.clone(), uuid, 10));
+        assert_ne!(
+            first,
+            Service::new(DeviceId("00:00:00:00:00:02".into()), uuid, 10)
+        );
+
+        let set: HashSet<_> = [first.clone(), second, first].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+}
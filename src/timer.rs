@@ -0,0 +1,81 @@
+//! Timer used by all timeouts and delays of this crate, selected by cargo features: `tokio`
+//! uses the timer of the Tokio runtime (which must be available wherever the futures of this
+//! crate are polled); otherwise `futures-timer` (the default) uses its own background thread.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+#[cfg(not(any(feature = "futures-timer", feature = "tokio")))]
+compile_error!("either the `futures-timer` or the `tokio` feature must be enabled");
+
+trait Timer {
+    type Sleep: Future<Output = ()> + Send + Sync + 'static;
+    fn sleep(duration: Duration) -> Self::Sleep;
+    fn reset(sleep: Pin<&mut Self::Sleep>, duration: Duration);
+}
+
+#[cfg(all(feature = "futures-timer", not(feature = "tokio")))]
+struct FuturesTimer;
+
+#[cfg(all(feature = "futures-timer", not(feature = "tokio")))]
+impl Timer for FuturesTimer {
+    type Sleep = futures_timer::Delay;
+
+    fn sleep(duration: Duration) -> Self::Sleep {
+        futures_timer::Delay::new(duration)
+    }
+
+    fn reset(sleep: Pin<&mut Self::Sleep>, duration: Duration) {
+        sleep.get_mut().reset(duration)
+    }
+}
+
+#[cfg(feature = "tokio")]
+struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+    type Sleep = tokio::time::Sleep;
+
+    fn sleep(duration: Duration) -> Self::Sleep {
+        tokio::time::sleep(duration)
+    }
+
+    fn reset(sleep: Pin<&mut Self::Sleep>, duration: Duration) {
+        sleep.reset(tokio::time::Instant::now() + duration)
+    }
+}
+
+#[cfg(feature = "tokio")]
+type ActiveTimer = TokioTimer;
+
+#[cfg(all(feature = "futures-timer", not(feature = "tokio")))]
+type ActiveTimer = FuturesTimer;
+
+/// A future which completes after the given duration.
+pub struct Delay {
+    sleep: Pin<Box<<ActiveTimer as Timer>::Sleep>>,
+}
+
+impl Delay {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            sleep: Box::pin(ActiveTimer::sleep(duration)),
+        }
+    }
+
+    /// Restarts the delay with a new duration from now.
+    pub fn reset(&mut self, duration: Duration) {
+        ActiveTimer::reset(self.sleep.as_mut(), duration)
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.sleep.as_mut().poll(cx)
+    }
+}
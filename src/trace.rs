@@ -0,0 +1,51 @@
+//! Spans around GATT operations, emitted through `tracing` if the `tracing` feature is enabled.
+
+use std::future::Future;
+
+use super::{DeviceId, Result, Uuid};
+
+/// Runs `fut` inside a `gatt` span with the operation name, the device ID and the attribute
+/// UUID, and records the outcome (`ok`, `att_error`, `timeout` or `error`) in the span.
+/// Without the `tracing` feature, it simply awaits `fut`.
+pub(crate) async fn traced<T>(
+    op: &'static str,
+    dev_id: &DeviceId,
+    uuid: Option<Uuid>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::{field, Instrument};
+        let span = tracing::debug_span!(
+            "gatt",
+            op,
+            device = %dev_id,
+            uuid = field::Empty,
+            outcome = field::Empty
+        );
+        if let Some(uuid) = uuid {
+            span.record("uuid", field::display(uuid));
+        }
+        let result = fut.instrument(span.clone()).await;
+        span.record("outcome", outcome(&result));
+        result
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (op, dev_id, uuid);
+        fut.await
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn outcome<T>(result: &Result<T>) -> &'static str {
+    use crate::error::ErrorKind;
+    match result {
+        Ok(_) => "ok",
+        Err(e) => match e.kind() {
+            ErrorKind::Protocol(_) => "att_error",
+            ErrorKind::Timeout => "timeout",
+            _ => "error",
+        },
+    }
+}
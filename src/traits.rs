@@ -0,0 +1,446 @@
+//! Traits covering the API surface shared with `bluest`, for writing application code which is
+//! generic over the Bluetooth backend.
+//!
+//! They are implemented by this crate's types. On other platforms, implement them for thin
+//! newtype wrappers of `bluest` types (the orphan rule forbids implementing them for `bluest`
+//! types directly); most methods just forward to the wrapped value. [ErrorKind] is the common
+//! error category, `bluest::error::ErrorKind` can be mapped to it variant by variant.
+//!
+//! ```no_run
+//! use android_ble::btuuid::characteristics::BATTERY_LEVEL;
+//! use android_ble::btuuid::services::BATTERY as BATTERY_SERVICE;
+//! use android_ble::traits::*;
+//! use futures_lite::StreamExt;
+//!
+//! /// Reads the battery level of the first device advertising the battery service.
+//! async fn battery_level<A: AdapterApi>(adapter: &A) -> Result<u8, A::Error> {
+//!     let services = [BATTERY_SERVICE];
+//!     let mut scan = adapter.scan(&services).await?;
+//!     let Some(found) = scan.next().await else {
+//!         return Err(A::Error::from(ErrorKind::NotFound));
+//!     };
+//!     let device = found.device().clone();
+//!     drop(scan);
+//!     adapter.connect_device(&device).await?;
+//!     for service in device.discover_services_with_uuid(BATTERY_SERVICE).await? {
+//!         let chars = service.discover_characteristics_with_uuid(BATTERY_LEVEL).await?;
+//!         if let Some(char) = chars.first() {
+//!             return Ok(char.read().await?.first().copied().unwrap_or(0));
+//!         }
+//!     }
+//!     Err(A::Error::from(ErrorKind::NotFound))
+//! }
+//! ```
+
+use std::future::Future;
+
+use futures_core::Stream;
+
+use crate::{
+    Adapter, AdvertisementData, AdvertisingDevice, Characteristic, CharacteristicProperties,
+    Descriptor, Device, Service, Uuid,
+};
+
+pub use crate::error::ErrorKind;
+
+/// Error type of a backend.
+pub trait ApiError: std::error::Error + From<ErrorKind> + Send + Sync + 'static {
+    /// Returns the category of this error.
+    fn kind(&self) -> ErrorKind;
+}
+
+/// The shared API of `Adapter`.
+pub trait AdapterApi: Send + Sync {
+    /// Error type of all traits of the backend.
+    type Error: ApiError;
+    /// Device type of the backend.
+    type Device: DeviceApi<Error = Self::Error>;
+    /// Item type of scan streams.
+    type AdvertisingDevice: AdvertisingDeviceApi<Device = Self::Device>;
+
+    /// See [Adapter::wait_available].
+    fn wait_available(&self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// See [Adapter::is_available].
+    fn is_available(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// See [Adapter::connected_devices].
+    fn connected_devices(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Device>, Self::Error>> + Send;
+
+    /// See [Adapter::scan].
+    fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> impl Future<
+        Output = Result<
+            impl Stream<Item = Self::AdvertisingDevice> + Send + Unpin + 'a,
+            Self::Error,
+        >,
+    > + Send;
+
+    /// See [Adapter::connect_device].
+    fn connect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// See [Adapter::disconnect_device].
+    fn disconnect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// The shared API of `AdvertisingDevice`.
+pub trait AdvertisingDeviceApi: Send {
+    /// Device type of the backend.
+    type Device: DeviceApi;
+
+    /// Returns the source of the advertisement.
+    fn device(&self) -> &Self::Device;
+
+    /// Returns the advertisement data.
+    fn adv_data(&self) -> &AdvertisementData;
+
+    /// Returns the signal strength in dBm.
+    fn rssi(&self) -> Option<i16>;
+}
+
+/// The shared API of `Device`.
+pub trait DeviceApi: Clone + Send + Sync {
+    /// Error type of all traits of the backend.
+    type Error: ApiError;
+    /// Service type of the backend.
+    type Service: ServiceApi<Error = Self::Error>;
+
+    /// See [Device::name].
+    fn name(&self) -> Result<String, Self::Error>;
+
+    /// See [Device::is_connected].
+    fn is_connected(&self) -> impl Future<Output = bool> + Send;
+
+    /// See [Device::is_paired].
+    fn is_paired(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// See [Device::pair].
+    fn pair(&self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// See [Device::discover_services].
+    fn discover_services(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send;
+
+    /// See [Device::discover_services_with_uuid].
+    fn discover_services_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send;
+
+    /// See [Device::services].
+    fn services(&self) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send;
+
+    /// See [Device::rssi].
+    fn rssi(&self) -> impl Future<Output = Result<i16, Self::Error>> + Send;
+}
+
+/// The shared API of `Service`.
+pub trait ServiceApi: Clone + Send + Sync {
+    /// Error type of all traits of the backend.
+    type Error: ApiError;
+    /// Characteristic type of the backend.
+    type Characteristic: CharacteristicApi<Error = Self::Error>;
+
+    /// See [Service::uuid].
+    fn uuid(&self) -> Uuid;
+
+    /// See [Service::discover_characteristics].
+    fn discover_characteristics(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send;
+
+    /// See [Service::discover_characteristics_with_uuid].
+    fn discover_characteristics_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send;
+
+    /// See [Service::characteristics].
+    fn characteristics(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send;
+}
+
+/// The shared API of `Characteristic`.
+pub trait CharacteristicApi: Clone + Send + Sync {
+    /// Error type of all traits of the backend.
+    type Error: ApiError;
+    /// Descriptor type of the backend.
+    type Descriptor: DescriptorApi<Error = Self::Error>;
+
+    /// See [Characteristic::uuid].
+    fn uuid(&self) -> Uuid;
+
+    /// See [Characteristic::properties].
+    fn properties(
+        &self,
+    ) -> impl Future<Output = Result<CharacteristicProperties, Self::Error>> + Send;
+
+    /// See [Characteristic::read].
+    fn read(&self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send;
+
+    /// See [Characteristic::write].
+    fn write(&self, value: &[u8]) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// See [Characteristic::write_without_response].
+    fn write_without_response(
+        &self,
+        value: &[u8],
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// See [Characteristic::max_write_len].
+    fn max_write_len(&self) -> Result<usize, Self::Error>;
+
+    /// See [Characteristic::notify].
+    fn notify(
+        &self,
+    ) -> impl Future<
+        Output = Result<
+            impl Stream<Item = Result<Vec<u8>, Self::Error>> + Send + Unpin + '_,
+            Self::Error,
+        >,
+    > + Send;
+
+    /// See [Characteristic::is_notifying].
+    fn is_notifying(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// See [Characteristic::discover_descriptors].
+    fn discover_descriptors(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Descriptor>, Self::Error>> + Send;
+}
+
+/// The shared API of `Descriptor`.
+pub trait DescriptorApi: Clone + Send + Sync {
+    /// Error type of all traits of the backend.
+    type Error: ApiError;
+
+    /// See [Descriptor::uuid].
+    fn uuid(&self) -> Uuid;
+
+    /// See [Descriptor::read].
+    fn read(&self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send;
+
+    /// See [Descriptor::write].
+    fn write(&self, value: &[u8]) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+impl ApiError for crate::Error {
+    fn kind(&self) -> ErrorKind {
+        crate::Error::kind(self)
+    }
+}
+
+impl AdapterApi for Adapter {
+    type Error = crate::Error;
+    type Device = Device;
+    type AdvertisingDevice = AdvertisingDevice;
+
+    fn wait_available(&self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Adapter::wait_available(self)
+    }
+
+    fn is_available(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        Adapter::is_available(self)
+    }
+
+    fn connected_devices(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Device>, Self::Error>> + Send {
+        Adapter::connected_devices(self)
+    }
+
+    fn scan<'a>(
+        &'a self,
+        services: &'a [Uuid],
+    ) -> impl Future<
+        Output = Result<
+            impl Stream<Item = Self::AdvertisingDevice> + Send + Unpin + 'a,
+            Self::Error,
+        >,
+    > + Send {
+        Adapter::scan(self, services)
+    }
+
+    fn connect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Adapter::connect_device(self, device)
+    }
+
+    fn disconnect_device(
+        &self,
+        device: &Self::Device,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Adapter::disconnect_device(self, device)
+    }
+}
+
+impl AdvertisingDeviceApi for AdvertisingDevice {
+    type Device = Device;
+
+    fn device(&self) -> &Self::Device {
+        &self.device
+    }
+
+    fn adv_data(&self) -> &AdvertisementData {
+        &self.adv_data
+    }
+
+    fn rssi(&self) -> Option<i16> {
+        self.rssi
+    }
+}
+
+impl DeviceApi for Device {
+    type Error = crate::Error;
+    type Service = Service;
+
+    fn name(&self) -> Result<String, Self::Error> {
+        Device::name(self)
+    }
+
+    fn is_connected(&self) -> impl Future<Output = bool> + Send {
+        Device::is_connected(self)
+    }
+
+    fn is_paired(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        Device::is_paired(self)
+    }
+
+    fn pair(&self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Device::pair(self)
+    }
+
+    fn discover_services(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send {
+        Device::discover_services(self)
+    }
+
+    fn discover_services_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send {
+        Device::discover_services_with_uuid(self, uuid)
+    }
+
+    fn services(&self) -> impl Future<Output = Result<Vec<Self::Service>, Self::Error>> + Send {
+        Device::services(self)
+    }
+
+    fn rssi(&self) -> impl Future<Output = Result<i16, Self::Error>> + Send {
+        Device::rssi(self)
+    }
+}
+
+impl ServiceApi for Service {
+    type Error = crate::Error;
+    type Characteristic = Characteristic;
+
+    fn uuid(&self) -> Uuid {
+        Service::uuid(self)
+    }
+
+    fn discover_characteristics(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send {
+        Service::discover_characteristics(self)
+    }
+
+    fn discover_characteristics_with_uuid(
+        &self,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send {
+        Service::discover_characteristics_with_uuid(self, uuid)
+    }
+
+    fn characteristics(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Characteristic>, Self::Error>> + Send {
+        Service::characteristics(self)
+    }
+}
+
+impl CharacteristicApi for Characteristic {
+    type Error = crate::Error;
+    type Descriptor = Descriptor;
+
+    fn uuid(&self) -> Uuid {
+        Characteristic::uuid(self)
+    }
+
+    fn properties(
+        &self,
+    ) -> impl Future<Output = Result<CharacteristicProperties, Self::Error>> + Send {
+        Characteristic::properties(self)
+    }
+
+    fn read(&self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send {
+        Characteristic::read(self)
+    }
+
+    fn write(&self, value: &[u8]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Characteristic::write(self, value)
+    }
+
+    fn write_without_response(
+        &self,
+        value: &[u8],
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Characteristic::write_without_response(self, value)
+    }
+
+    fn max_write_len(&self) -> Result<usize, Self::Error> {
+        Characteristic::max_write_len(self)
+    }
+
+    fn notify(
+        &self,
+    ) -> impl Future<
+        Output = Result<
+            impl Stream<Item = Result<Vec<u8>, Self::Error>> + Send + Unpin + '_,
+            Self::Error,
+        >,
+    > + Send {
+        Characteristic::notify(self)
+    }
+
+    fn is_notifying(&self) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        Characteristic::is_notifying(self)
+    }
+
+    fn discover_descriptors(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Self::Descriptor>, Self::Error>> + Send {
+        Characteristic::discover_descriptors(self)
+    }
+}
+
+impl DescriptorApi for Descriptor {
+    type Error = crate::Error;
+
+    fn uuid(&self) -> Uuid {
+        Descriptor::uuid(self)
+    }
+
+    fn read(&self) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send {
+        Descriptor::read(self)
+    }
+
+    fn write(&self, value: &[u8]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        Descriptor::write(self, value)
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use super::gatt_tree::GattTree;
+use super::timer::Delay;
+use super::{Characteristic, Descriptor, Device, Result};
+
+/// A sequence of GATT operations executed without being interleaved by other operations of the
+/// same connection, created by [Device::transaction].
+///
+/// All characteristics and descriptors given to the builder must belong to the device.
+#[derive(Debug)]
+#[must_use = "the transaction does nothing unless `execute` is called"]
+pub struct Transaction<'a> {
+    device: &'a Device,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Read(Characteristic),
+    Write(Characteristic, Vec<u8>),
+    WriteDescriptor(Descriptor, Vec<u8>),
+    Delay(Duration),
+}
+
+/// Result of a step in [Transaction].
+#[derive(Debug)]
+pub enum TransactionResult {
+    /// Result of [Transaction::read].
+    Read(Result<Vec<u8>>),
+    /// Result of [Transaction::write] or [Transaction::write_descriptor].
+    Write(Result<()>),
+    /// [Transaction::delay] is done.
+    Delay,
+}
+
+impl TransactionResult {
+    /// Checks if the step failed.
+    pub fn is_err(&self) -> bool {
+        matches!(self, Self::Read(Err(_)) | Self::Write(Err(_)))
+    }
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(device: &'a Device) -> Self {
+        Self {
+            device,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Reads the characteristic.
+    pub fn read(mut self, characteristic: &Characteristic) -> Self {
+        self.steps.push(Step::Read(characteristic.clone()));
+        self
+    }
+
+    /// Writes `value` to the characteristic with response.
+    pub fn write(mut self, characteristic: &Characteristic, value: &[u8]) -> Self {
+        self.steps
+            .push(Step::Write(characteristic.clone(), value.to_vec()));
+        self
+    }
+
+    /// Writes `value` to the descriptor.
+    pub fn write_descriptor(mut self, descriptor: &Descriptor, value: &[u8]) -> Self {
+        self.steps
+            .push(Step::WriteDescriptor(descriptor.clone(), value.to_vec()));
+        self
+    }
+
+    /// Waits for `duration` while still blocking other operations.
+    pub fn delay(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Delay(duration));
+        self
+    }
+
+    /// Executes the steps in order. Execution stops after the first failed step, so the
+    /// returned list may be shorter than the amount of steps; its last item is the error.
+    pub async fn execute(self) -> Result<Vec<TransactionResult>> {
+        let _sequence_guard = GattTree::check_connection(&self.device.id())?
+            .exclusive_sequence_guard()
+            .await;
+        let mut results = Vec::with_capacity(self.steps.len());
+        for step in self.steps {
+            let result = match step {
+                Step::Read(ch) => TransactionResult::Read(ch.read_in_sequence(None).await),
+                Step::Write(ch, value) => {
+                    TransactionResult::Write(ch.write_in_sequence(&value, true, None).await)
+                }
+                Step::WriteDescriptor(desc, value) => {
+                    TransactionResult::Write(desc.write_in_sequence(&value).await)
+                }
+                Step::Delay(duration) => {
+                    Delay::new(duration).await;
+                    TransactionResult::Delay
+                }
+            };
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        Ok(results)
+    }
+}
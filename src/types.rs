@@ -2,6 +2,7 @@
 use crate::Adapter;
 use crate::Uuid;
 use std::collections::HashMap;
+use std::time::Duration;
 
 /// A platform-specific device identifier.
 /// On Android it contains the Bluetooth address in the format `AB:CD:EF:01:23:45`.
@@ -24,6 +25,29 @@ pub enum AdapterEvent {
     Unavailable,
 }
 
+/// Result of [`Adapter::availability`], telling whether Bluetooth can be used by this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AdapterAvailability {
+    /// The adapter exists and is enabled.
+    Available,
+    /// The adapter exists but is turned off.
+    Disabled,
+    /// The system has no Bluetooth adapter.
+    NoAdapter,
+    /// The `BluetoothManager` system service is unavailable.
+    NoManager,
+    /// The Android context is not initialized, see [`crate::init_with`].
+    NoContext,
+}
+
+impl AdapterAvailability {
+    /// Checks if it is [`AdapterAvailability::Available`].
+    pub fn is_available(&self) -> bool {
+        *self == Self::Available
+    }
+}
+
 /// Events generated by [`Adapter::device_connection_events`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ConnectionEvent {
@@ -33,8 +57,320 @@ pub enum ConnectionEvent {
     Connected,
 }
 
+/// Power state of the local adapter, carried by [SystemEvent::AdapterStateChanged].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AdapterState {
+    /// The adapter is off.
+    Off,
+    /// The adapter is turning on.
+    TurningOn,
+    /// The adapter is on and ready to use.
+    On,
+    /// The adapter is turning off.
+    TurningOff,
+}
+
+impl AdapterState {
+    /// Converts from `BluetoothAdapter.STATE_*` values.
+    pub(crate) fn from_java(value: i32) -> Option<Self> {
+        match value {
+            10 => Some(Self::Off),
+            11 => Some(Self::TurningOn),
+            12 => Some(Self::On),
+            13 => Some(Self::TurningOff),
+            _ => None,
+        }
+    }
+}
+
+/// Bond (pairing) state of a remote device, carried by [SystemEvent::BondStateChanged].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BondState {
+    /// The device is not bonded.
+    None,
+    /// Bonding is in progress.
+    Bonding,
+    /// The device is bonded.
+    Bonded,
+}
+
+impl BondState {
+    /// Converts from `BluetoothDevice.BOND_*` values.
+    pub(crate) fn from_java(value: i32) -> Option<Self> {
+        match value {
+            10 => Some(Self::None),
+            11 => Some(Self::Bonding),
+            12 => Some(Self::Bonded),
+            _ => None,
+        }
+    }
+}
+
+/// System-wide Bluetooth broadcasts generated by [`Adapter::system_events`].
+///
+/// Events may concern devices that are not connected by this crate, including Bluetooth
+/// Classic devices for bond states and pairing requests.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// The power state of the local adapter has changed.
+    AdapterStateChanged(AdapterState),
+    /// An ACL link with the device is established over the LE transport.
+    ///
+    /// Android sends the same broadcast for BR/EDR (Classic) links; these are told apart by
+    /// `BluetoothDevice.EXTRA_TRANSPORT` and are not reported. A broadcast without that extra
+    /// (it is documented since API level 33) is not reported either.
+    AclConnected(DeviceId),
+    /// The ACL link with the device over the LE transport is lost. BR/EDR links are filtered
+    /// out like [SystemEvent::AclConnected].
+    AclDisconnected(DeviceId),
+    /// The bond state of the device has changed.
+    BondStateChanged {
+        /// The remote device.
+        device: DeviceId,
+        /// The previous bond state.
+        previous: BondState,
+        /// The new bond state.
+        current: BondState,
+    },
+    /// The device requests pairing.
+    PairingRequest {
+        /// The remote device.
+        device: DeviceId,
+        /// The pairing method, one of `BluetoothDevice.PAIRING_VARIANT_*` values.
+        variant: i32,
+        /// The passkey or PIN to be confirmed or displayed, if provided.
+        key: Option<i32>,
+    },
+}
+
+/// Why a connection is dropped, returned by [`crate::Device::wait_for_disconnect`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectReason {
+    /// The supervision timeout has elapsed without receiving packets from the device,
+    /// which usually means the device went out of range or lost power.
+    SupervisionTimeout,
+    /// The device has terminated the connection.
+    RemoteTerminated,
+    /// The Bluetooth stack of this side has terminated the connection.
+    LocalTerminated,
+    /// The connection failed to be established.
+    FailedToEstablish,
+    /// The connection is closed by this crate, e.g. by [`crate::Adapter::disconnect_device`]
+    /// or because the adapter is turned off.
+    Closed,
+    /// Other status code received in `onConnectionStateChange`.
+    Other(i32),
+    /// The device was not connected when waiting started.
+    Unknown,
+}
+
+impl DisconnectReason {
+    pub(crate) fn from_status(status: i32) -> Self {
+        // HCI error codes passed by `BluetoothGattCallback.onConnectionStateChange`.
+        match status {
+            0x08 => Self::SupervisionTimeout,
+            0x13 => Self::RemoteTerminated,
+            0x16 => Self::LocalTerminated,
+            0x3E => Self::FailedToEstablish,
+            status => Self::Other(status),
+        }
+    }
+}
+
+/// A snapshot of state values of a connection, returned by [`crate::Device::connection_info`].
+///
+/// Items that have never been populated are `None`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The negotiated ATT MTU.
+    pub mtu: Option<usize>,
+    /// The signal strength in dBm got by the last successful [`crate::Device::rssi`] call.
+    pub rssi: Option<i16>,
+    /// The time when the connection was established.
+    pub connected_at: Option<std::time::SystemTime>,
+    /// The current transmitter and receiver PHYs; see [`crate::Device::current_phy`].
+    pub phy: Option<(Phy, Phy)>,
+}
+
+/// Latencies of GATT operations on a connection, returned by [`crate::Device::stats`].
+///
+/// A latency is the time from issuing the request to the Android Bluetooth stack until the
+/// callback is received; operations that failed because of a timeout or a disconnection are
+/// not counted. Descriptor operations are counted together with characteristic operations.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Count of completed reads.
+    pub read_count: u32,
+    /// Latency of the last completed read.
+    pub last_read_latency: Option<Duration>,
+    /// Average latency of all completed reads.
+    pub average_read_latency: Option<Duration>,
+    /// Count of completed writes.
+    pub write_count: u32,
+    /// Latency of the last completed write.
+    pub last_write_latency: Option<Duration>,
+    /// Average latency of all completed writes.
+    pub average_write_latency: Option<Duration>,
+}
+
+/// Parameters of a Bluetooth LE connection, used by [`crate::Device::request_connection_update`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionParameters {
+    /// Minimum connection interval in units of 1.25 ms.
+    pub min_interval: u16,
+    /// Maximum connection interval in units of 1.25 ms.
+    pub max_interval: u16,
+    /// Peripheral latency in number of connection events.
+    pub latency: u16,
+    /// Supervision timeout in units of 10 ms.
+    pub supervision_timeout: u16,
+}
+
+impl ConnectionParameters {
+    /// Creates connection parameters from raw values; see the fields for their units.
+    pub fn new(
+        min_interval: u16,
+        max_interval: u16,
+        latency: u16,
+        supervision_timeout: u16,
+    ) -> Self {
+        Self {
+            min_interval,
+            max_interval,
+            latency,
+            supervision_timeout,
+        }
+    }
+
+    /// The minimum connection interval.
+    pub fn min_interval_duration(&self) -> Duration {
+        Duration::from_micros(self.min_interval as u64 * 1250)
+    }
+
+    /// The maximum connection interval.
+    pub fn max_interval_duration(&self) -> Duration {
+        Duration::from_micros(self.max_interval as u64 * 1250)
+    }
+
+    /// The supervision timeout.
+    pub fn supervision_timeout_duration(&self) -> Duration {
+        Duration::from_millis(self.supervision_timeout as u64 * 10)
+    }
+}
+
+/// Bluetooth Classic scan mode of the local adapter, which decides whether remote devices can
+/// connect to it or discover it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScanMode {
+    /// Neither connectable nor discoverable.
+    None,
+    /// Connectable from remote devices which know its address, but not discoverable.
+    Connectable,
+    /// Both connectable and discoverable.
+    ConnectableDiscoverable,
+}
+
+impl ScanMode {
+    /// Converts from `BluetoothAdapter.SCAN_MODE_*` values.
+    pub(crate) fn from_java(value: i32) -> Self {
+        match value {
+            21 => Self::Connectable,
+            23 => Self::ConnectableDiscoverable,
+            _ => Self::None,
+        }
+    }
+}
+
+/// A physical layer (PHY) of Bluetooth LE connections.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phy {
+    /// LE 1M PHY
+    Le1M,
+    /// LE 2M PHY
+    Le2M,
+    /// LE Coded PHY
+    LeCoded,
+}
+
+impl Phy {
+    /// Converts from `BluetoothDevice.PHY_LE_*` values.
+    pub(crate) fn from_java(value: i32) -> Option<Self> {
+        match value {
+            1 => Some(Self::Le1M),
+            2 => Some(Self::Le2M),
+            3 => Some(Self::LeCoded),
+            _ => None,
+        }
+    }
+
+    /// Converts to `BluetoothDevice.PHY_LE_*_MASK` values.
+    pub(crate) fn to_java_mask(self) -> i32 {
+        match self {
+            Self::Le1M => 1,
+            Self::Le2M => 2,
+            Self::LeCoded => 4,
+        }
+    }
+}
+
+/// A set of [Phy]s.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PhyMask {
+    /// Includes [Phy::Le1M].
+    pub le_1m: bool,
+    /// Includes [Phy::Le2M].
+    pub le_2m: bool,
+    /// Includes [Phy::LeCoded].
+    pub le_coded: bool,
+}
+
+impl PhyMask {
+    /// Only the LE 1M PHY.
+    pub const LE_1M: Self = Self {
+        le_1m: true,
+        le_2m: false,
+        le_coded: false,
+    };
+
+    /// All PHYs.
+    pub const ALL: Self = Self {
+        le_1m: true,
+        le_2m: true,
+        le_coded: true,
+    };
+
+    /// Checks if `phy` is in the set.
+    pub fn contains(self, phy: Phy) -> bool {
+        match phy {
+            Phy::Le1M => self.le_1m,
+            Phy::Le2M => self.le_2m,
+            Phy::LeCoded => self.le_coded,
+        }
+    }
+}
+
+impl From<Phy> for PhyMask {
+    fn from(phy: Phy) -> Self {
+        Self {
+            le_1m: phy == Phy::Le1M,
+            le_2m: phy == Phy::Le2M,
+            le_coded: phy == Phy::LeCoded,
+        }
+    }
+}
+
 /// Represents a device discovered during a scan operation.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct AdvertisingDevice {
     /// The source of the advertisement
     pub device: crate::Device,
@@ -42,6 +378,38 @@ pub struct AdvertisingDevice {
     pub adv_data: AdvertisementData,
     /// The signal strength in dBm of the received advertisement packet
     pub rssi: Option<i16>,
+    /// Extended advertising details; this is `None` below API level 26.
+    pub extended: Option<ExtendedAdvertisingInfo>,
+}
+
+impl AdvertisingDevice {
+    /// Checks if the device accepts connections according to the advertisement, so that
+    /// broadcast-only beacons can be excluded before calling [crate::Adapter::connect_device].
+    ///
+    /// This is taken from `ScanResult.isConnectable()` on API level 26 or higher. On older
+    /// systems the PDU type is unavailable and `None` is returned, like for advertisements
+    /// without [AdvertisingDevice::extended] information.
+    pub fn is_connectable(&self) -> Option<bool> {
+        self.extended.map(|_| self.adv_data.is_connectable)
+    }
+}
+
+/// Details of a received advertisement related to extended advertising (Bluetooth 5.0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct ExtendedAdvertisingInfo {
+    /// Set for legacy advertisements, for which the fields below are meaningless.
+    pub is_legacy: bool,
+    /// The PHY of the primary advertising channel.
+    pub primary_phy: Option<Phy>,
+    /// The PHY of the secondary advertising channel, `None` if it is unused.
+    pub secondary_phy: Option<Phy>,
+    /// The advertising set ID, `None` if it is not present.
+    pub advertising_sid: Option<u8>,
+    /// The periodic advertising interval, `None` if there is no periodic advertising.
+    pub periodic_advertising_interval: Option<Duration>,
+    /// Set if the advertising data is incomplete because it is truncated by the controller.
+    pub data_truncated: bool,
 }
 
 /// Data included in a Bluetooth advertisement or scan reponse.
@@ -57,10 +425,32 @@ pub struct AdvertisementData {
     pub service_data: HashMap<Uuid, Vec<u8>>,
     /// Transmitted power level (CSS §A.1.5)
     pub tx_power_level: Option<i16>,
-    /// Set to true for connectable advertising packets
+    /// Set to true for connectable advertising packets. Below API level 26, this is unknown and
+    /// always true, see [AdvertisingDevice::is_connectable].
     pub is_connectable: bool,
 }
 
+impl AdvertisementData {
+    /// Compares the stable payload of two advertisements: the local name, the manufacturer data,
+    /// the service UUIDs (regardless of their order, but counting duplicates) and the service
+    /// data. The transmitted power level and the connectable flag are ignored, like the RSSI
+    /// which is not included here.
+    ///
+    /// This is used by [crate::ScanSettings::deduplicate] to decide whether to report a device again.
+    pub fn significant_eq(&self, other: &Self) -> bool {
+        self.local_name == other.local_name
+            && self.manufacturer_data == other.manufacturer_data
+            && sorted(&self.services) == sorted(&other.services)
+            && self.service_data == other.service_data
+    }
+}
+
+fn sorted(uuids: &[Uuid]) -> Vec<Uuid> {
+    let mut uuids = uuids.to_vec();
+    uuids.sort_unstable();
+    uuids
+}
+
 /// Manufacturer specific data included in Bluetooth advertisements.
 ///
 /// See the Bluetooth Core Specification Supplement §A.1.4 for details.
@@ -76,6 +466,7 @@ pub struct ManufacturerData {
 ///
 /// Extended properties are also included as defined in §3.3.3.1.
 #[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CharacteristicProperties {
@@ -126,3 +517,70 @@ impl CharacteristicProperties {
             | (u32::from(self.writable_auxiliaries) << 9)
     }
 }
+
+/// Value of the Characteristic Extended Properties descriptor as defined in the Bluetooth Core
+/// Specification, Vol 3, Part G, §3.3.3.1.
+#[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtendedProperties {
+    pub reliable_write: bool,
+    pub writable_auxiliaries: bool,
+}
+
+impl ExtendedProperties {
+    /// Raw transmutation from the 16-bit descriptor value.
+    pub fn from_bits(bits: u16) -> Self {
+        ExtendedProperties {
+            reliable_write: (bits & (1 << 0)) != 0,
+            writable_auxiliaries: (bits & (1 << 1)) != 0,
+        }
+    }
+
+    /// Raw transmutation to the 16-bit descriptor value.
+    pub fn to_bits(self) -> u16 {
+        u16::from(self.reliable_write) | (u16::from(self.writable_auxiliaries) << 1)
+    }
+}
+
+/// What to do with new characteristic values when the receiver of [crate::Characteristic::notify]
+/// cannot keep up with the peripheral and its buffer is full.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Discard the oldest buffered value to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Discard the new value.
+    DropNewest,
+    /// Discard the new value, then deliver an error of [crate::error::ErrorKind::Other] before
+    /// the next value that fits into the buffer, so that the consumer knows data was lost.
+    Error,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adv_data(services: &[u16]) -> AdvertisementData {
+        AdvertisementData {
+            local_name: None,
+            manufacturer_data: None,
+            services: services
+                .iter()
+                .map(|&n| Uuid::from_u128(n.into()))
+                .collect(),
+            service_data: HashMap::new(),
+            tx_power_level: None,
+            is_connectable: true,
+        }
+    }
+
+    #[test]
+    fn significant_eq_compares_services_as_multisets() {
+        assert!(adv_data(&[1, 2, 3]).significant_eq(&adv_data(&[3, 1, 2])));
+        assert!(!adv_data(&[1, 1, 2]).significant_eq(&adv_data(&[1, 2, 2])));
+        assert!(!adv_data(&[1, 2]).significant_eq(&adv_data(&[1, 2, 2])));
+    }
+}
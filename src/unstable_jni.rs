@@ -0,0 +1,428 @@
+//! **(Unstable)** Helpers for interoperating with raw `java-spaghetti` objects.
+//!
+//! This module is available with the `unstable-jni` feature. Items here depend on the
+//! `java-spaghetti` version used by this crate, and may change in any release.
+
+use std::sync::Arc;
+
+use java_spaghetti::{ByteArray, Env, Local, Ref, ReferenceType};
+
+use super::callback::BluetoothGattCallbackProxy;
+use super::gatt_tree::BluetoothGattCallbackProxy as GattCallbackHandler;
+use super::jni::ByteArrayExt;
+
+pub use super::bindings::android::bluetooth::{
+    BluetoothGatt, BluetoothGattCharacteristic, BluetoothGattDescriptor,
+};
+
+/// Copies the content of a Java `byte[]` array into a new vector.
+pub fn byte_array_to_vec(array: &ByteArray) -> Vec<u8> {
+    array.as_vec_u8()
+}
+
+/// Creates a Java `byte[]` array with the content of `data`.
+pub fn byte_array_from_slice<'env>(env: Env<'env>, data: &[u8]) -> Local<'env, ByteArray> {
+    ByteArray::from_slice(env, data)
+}
+
+/// Receives raw `BluetoothGattCallback` calls of all connections made by an adapter, set by
+/// [crate::AdapterConfig::raw_gatt_callback_hook]. This is an escape hatch for callbacks or
+/// arguments not surfaced by this crate.
+///
+/// Each method is called in the Android Binder thread right after this crate has handled the
+/// callback, so implementations must not block or do long work; otherwise all GATT operations
+/// of the connection are delayed. Java objects must not be modified. All methods do nothing
+/// by default.
+#[allow(unused_variables)]
+pub trait RawGattCallbackHook: Send + Sync {
+    /// `onConnectionStateChange`.
+    fn on_connection_state_change<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
+        new_state: i32,
+    ) {
+    }
+
+    /// `onServicesDiscovered`.
+    fn on_services_discovered<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
+    ) {
+    }
+
+    /// Both overloads of `onCharacteristicRead`; `value` is `None` below API level 33, in which
+    /// case it is held by `characteristic`.
+    fn on_characteristic_read<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        value: Option<Ref<'env, ByteArray>>,
+        status: i32,
+    ) {
+    }
+
+    /// `onCharacteristicWrite`.
+    fn on_characteristic_write<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        status: i32,
+    ) {
+    }
+
+    /// Both overloads of `onCharacteristicChanged`; `value` is `None` below API level 33, in
+    /// which case it is held by `characteristic`.
+    fn on_characteristic_changed<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        value: Option<Ref<'env, ByteArray>>,
+    ) {
+    }
+
+    /// Both overloads of `onDescriptorRead`; `value` is `None` below API level 33, in which
+    /// case it is held by `descriptor`.
+    fn on_descriptor_read<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        descriptor: Option<Ref<'env, BluetoothGattDescriptor>>,
+        status: i32,
+        value: Option<Ref<'env, ByteArray>>,
+    ) {
+    }
+
+    /// `onDescriptorWrite`.
+    fn on_descriptor_write<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        descriptor: Option<Ref<'env, BluetoothGattDescriptor>>,
+        status: i32,
+    ) {
+    }
+
+    /// `onReliableWriteCompleted`.
+    fn on_reliable_write_completed<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
+    ) {
+    }
+
+    /// `onReadRemoteRssi`.
+    fn on_read_remote_rssi<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        rssi: i32,
+        status: i32,
+    ) {
+    }
+
+    /// `onMtuChanged`.
+    fn on_mtu_changed<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        mtu: i32,
+        status: i32,
+    ) {
+    }
+
+    /// `onPhyRead`.
+    fn on_phy_read<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    ) {
+    }
+
+    /// `onPhyUpdate`.
+    fn on_phy_update<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    ) {
+    }
+
+    /// `onServiceChanged`.
+    fn on_service_changed<'env>(&self, env: Env<'env>, gatt: Option<Ref<'env, BluetoothGatt>>) {}
+
+    /// The hidden `onConnectionUpdated` (API level 26 and above).
+    fn on_connection_updated<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    ) {
+    }
+}
+
+/// Passes callbacks to the crate's own handler, then to the user's hook. It is only used when
+/// a hook is set, so that nothing changes otherwise.
+pub(crate) struct HookedGattCallback {
+    pub(crate) inner: Arc<GattCallbackHandler>,
+    pub(crate) hook: Arc<dyn RawGattCallbackHook>,
+}
+
+/// Duplicates a borrowed reference, which stays valid during the callback.
+fn dup<'env, T: ReferenceType>(obj: &Option<Ref<'env, T>>) -> Option<Ref<'env, T>> {
+    obj.as_ref()
+        .map(|obj| unsafe { Ref::from_raw(obj.env(), obj.as_raw()) })
+}
+
+impl BluetoothGattCallbackProxy for HookedGattCallback {
+    fn onPhyUpdate<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    ) {
+        self.inner
+            .onPhyUpdate(env, dup(&gatt), tx_phy, rx_phy, status);
+        self.hook.on_phy_update(env, gatt, tx_phy, rx_phy, status);
+    }
+
+    fn onPhyRead<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        tx_phy: i32,
+        rx_phy: i32,
+        status: i32,
+    ) {
+        self.inner
+            .onPhyRead(env, dup(&gatt), tx_phy, rx_phy, status);
+        self.hook.on_phy_read(env, gatt, tx_phy, rx_phy, status);
+    }
+
+    fn onConnectionStateChange<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
+        new_state: i32,
+    ) {
+        self.inner
+            .onConnectionStateChange(env, dup(&gatt), status, new_state);
+        self.hook
+            .on_connection_state_change(env, gatt, status, new_state);
+    }
+
+    fn onServicesDiscovered<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
+    ) {
+        self.inner.onServicesDiscovered(env, dup(&gatt), status);
+        self.hook.on_services_discovered(env, gatt, status);
+    }
+
+    fn onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_int<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        status: i32,
+    ) {
+        self.inner
+            .onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_int(
+                env,
+                dup(&gatt),
+                dup(&characteristic),
+                status,
+            );
+        self.hook
+            .on_characteristic_read(env, gatt, characteristic, None, status);
+    }
+
+    fn onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_byte_array_int<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        value: Option<Ref<'env, ByteArray>>,
+        status: i32,
+    ) {
+        self.inner
+            .onCharacteristicRead_BluetoothGatt_BluetoothGattCharacteristic_byte_array_int(
+                env,
+                dup(&gatt),
+                dup(&characteristic),
+                dup(&value),
+                status,
+            );
+        self.hook
+            .on_characteristic_read(env, gatt, characteristic, value, status);
+    }
+
+    fn onCharacteristicWrite<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        status: i32,
+    ) {
+        self.inner
+            .onCharacteristicWrite(env, dup(&gatt), dup(&characteristic), status);
+        self.hook
+            .on_characteristic_write(env, gatt, characteristic, status);
+    }
+
+    fn onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+    ) {
+        self.inner
+            .onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic(
+                env,
+                dup(&gatt),
+                dup(&characteristic),
+            );
+        self.hook
+            .on_characteristic_changed(env, gatt, characteristic, None);
+    }
+
+    fn onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic_byte_array<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        characteristic: Option<Ref<'env, BluetoothGattCharacteristic>>,
+        value: Option<Ref<'env, ByteArray>>,
+    ) {
+        self.inner
+            .onCharacteristicChanged_BluetoothGatt_BluetoothGattCharacteristic_byte_array(
+                env,
+                dup(&gatt),
+                dup(&characteristic),
+                dup(&value),
+            );
+        self.hook
+            .on_characteristic_changed(env, gatt, characteristic, value);
+    }
+
+    fn onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        descriptor: Option<Ref<'env, BluetoothGattDescriptor>>,
+        status: i32,
+    ) {
+        self.inner
+            .onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int(
+                env,
+                dup(&gatt),
+                dup(&descriptor),
+                status,
+            );
+        self.hook
+            .on_descriptor_read(env, gatt, descriptor, status, None);
+    }
+
+    fn onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int_byte_array<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        descriptor: Option<Ref<'env, BluetoothGattDescriptor>>,
+        status: i32,
+        value: Option<Ref<'env, ByteArray>>,
+    ) {
+        self.inner
+            .onDescriptorRead_BluetoothGatt_BluetoothGattDescriptor_int_byte_array(
+                env,
+                dup(&gatt),
+                dup(&descriptor),
+                status,
+                dup(&value),
+            );
+        self.hook
+            .on_descriptor_read(env, gatt, descriptor, status, value);
+    }
+
+    fn onDescriptorWrite<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        descriptor: Option<Ref<'env, BluetoothGattDescriptor>>,
+        status: i32,
+    ) {
+        self.inner
+            .onDescriptorWrite(env, dup(&gatt), dup(&descriptor), status);
+        self.hook.on_descriptor_write(env, gatt, descriptor, status);
+    }
+
+    fn onReliableWriteCompleted<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        status: i32,
+    ) {
+        self.inner.onReliableWriteCompleted(env, dup(&gatt), status);
+        self.hook.on_reliable_write_completed(env, gatt, status);
+    }
+
+    fn onReadRemoteRssi<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        rssi: i32,
+        status: i32,
+    ) {
+        self.inner.onReadRemoteRssi(env, dup(&gatt), rssi, status);
+        self.hook.on_read_remote_rssi(env, gatt, rssi, status);
+    }
+
+    fn onMtuChanged<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        mtu: i32,
+        status: i32,
+    ) {
+        self.inner.onMtuChanged(env, dup(&gatt), mtu, status);
+        self.hook.on_mtu_changed(env, gatt, mtu, status);
+    }
+
+    fn onServiceChanged<'env>(&self, env: Env<'env>, gatt: Option<Ref<'env, BluetoothGatt>>) {
+        self.inner.onServiceChanged(env, dup(&gatt));
+        self.hook.on_service_changed(env, gatt);
+    }
+
+    fn onConnectionUpdated<'env>(
+        &self,
+        env: Env<'env>,
+        gatt: Option<Ref<'env, BluetoothGatt>>,
+        interval: i32,
+        latency: i32,
+        timeout: i32,
+        status: i32,
+    ) {
+        self.inner
+            .onConnectionUpdated(env, dup(&gatt), interval, latency, timeout, status);
+        self.hook
+            .on_connection_updated(env, gatt, interval, latency, timeout, status);
+    }
+}
@@ -1,85 +1,124 @@
+use crate::async_util::WaitError;
 use crate::bindings;
 use crate::error::{BluetoothStatusCode, ErrorKind, NativeError};
 use crate::{gatt_tree::GattTree, DeviceId};
 use bindings::android::os::ParcelUuid;
 use java_spaghetti::Local;
 
-use std::mem::ManuallyDrop;
 use std::num::NonZeroI32;
 
-pub struct ScopeGuard<F: FnOnce()> {
-    dropfn: ManuallyDrop<F>,
-}
+pub trait UuidExt {
+    fn from_java(
+        value: java_spaghetti::Ref<'_, bindings::java::util::UUID>,
+    ) -> Result<uuid::Uuid, crate::Error>;
+    fn from_android_parcel(uuid: Local<'_, ParcelUuid>) -> Result<uuid::Uuid, crate::Error>;
 
-impl<F: FnOnce()> ScopeGuard<F> {
+    #[deprecated(note = "use `from_android_parcel` instead")]
     #[allow(unused)]
-    pub fn defuse(mut self) {
-        unsafe { ManuallyDrop::drop(&mut self.dropfn) }
-        std::mem::forget(self)
+    fn from_andriod_parcel(uuid: Local<'_, ParcelUuid>) -> Result<uuid::Uuid, crate::Error> {
+        Self::from_android_parcel(uuid)
     }
 }
 
-impl<F: FnOnce()> Drop for ScopeGuard<F> {
-    fn drop(&mut self) {
-        // SAFETY: This is OK because `dropfn` is `ManuallyDrop` which will not be dropped by the compiler.
-        let dropfn = unsafe { ManuallyDrop::take(&mut self.dropfn) };
-        dropfn();
+impl UuidExt for uuid::Uuid {
+    fn from_java(
+        value: java_spaghetti::Ref<'_, bindings::java::util::UUID>,
+    ) -> Result<Self, crate::Error> {
+        // two primitive JNI calls without allocation, cheaper than `toString()` and parsing.
+        match (
+            value.getMostSignificantBits(),
+            value.getLeastSignificantBits(),
+        ) {
+            (Ok(msb), Ok(lsb)) => Ok(uuid_from_java_bits(msb, lsb)),
+            _ => parse_java_string(value.toString()?.non_null()?),
+        }
     }
-}
 
-pub fn defer<F: FnOnce()>(dropfn: F) -> ScopeGuard<F> {
-    ScopeGuard {
-        dropfn: ManuallyDrop::new(dropfn),
+    fn from_android_parcel(uuid: Local<'_, ParcelUuid>) -> Result<Self, crate::Error> {
+        match uuid.getUuid() {
+            Ok(Some(value)) => Self::from_java(value.as_ref()),
+            _ => parse_java_string(uuid.toString()?.non_null()?),
+        }
     }
 }
 
-pub trait UuidExt {
-    fn from_java(
-        value: java_spaghetti::Ref<'_, bindings::java::util::UUID>,
-    ) -> Result<uuid::Uuid, crate::Error>;
-    fn from_andriod_parcel(uuid: Local<'_, ParcelUuid>) -> Result<uuid::Uuid, crate::Error>;
+/// Builds the UUID from the signed halves given by `java.util.UUID`.
+fn uuid_from_java_bits(msb: i64, lsb: i64) -> uuid::Uuid {
+    uuid::Uuid::from_u64_pair(msb as u64, lsb as u64)
 }
 
-impl UuidExt for uuid::Uuid {
-    fn from_java(
-        value: java_spaghetti::Ref<'_, bindings::java::util::UUID>,
-    ) -> Result<Self, crate::Error> {
-        uuid::Uuid::parse_str(value.toString()?.non_null()?.to_string_lossy().trim()).map_err(|e| {
-            crate::Error::new(
-                ErrorKind::Internal,
-                None,
-                format!("`Uuid::parse_str` failed: {e:?}"),
-            )
-        })
+fn parse_java_string(
+    value: Local<'_, bindings::java::lang::String>,
+) -> Result<uuid::Uuid, crate::Error> {
+    parse_uuid_str(&value.to_string_lossy())
+}
+
+fn parse_uuid_str(value: &str) -> Result<uuid::Uuid, crate::Error> {
+    uuid::Uuid::parse_str(value.trim()).map_err(|e| {
+        crate::Error::new(
+            ErrorKind::Internal,
+            None,
+            format!("`Uuid::parse_str` failed: {e:?}"),
+        )
+    })
+}
+
+/// Iterates over a `java.util.Iterator`. A Java exception thrown by `hasNext()` or `next()`
+/// (e.g. if the collection is modified while the Bluetooth stack restarts) and a null item are
+/// yielded as errors; the iteration ends after an error.
+pub struct JavaIterator<'env> {
+    iter: Local<'env, bindings::java::util::Iterator>,
+    done: bool,
+}
+
+impl<'env> JavaIterator<'env> {
+    pub fn new(iter: Local<'env, bindings::java::util::Iterator>) -> Self {
+        Self { iter, done: false }
     }
 
-    fn from_andriod_parcel(uuid: Local<'_, ParcelUuid>) -> Result<Self, crate::Error> {
-        // doing 1 JNI method call, probably faster than 3 method calls:
-        // getUuid(), getLeastSignificantBits(), getMostSignificantBits()
-        uuid::Uuid::parse_str(uuid.toString()?.non_null()?.to_string_lossy().trim()).map_err(|e| {
-            crate::Error::new(
-                ErrorKind::Internal,
-                None,
-                format!("`Uuid::parse_str` failed: {e:?}"),
-            )
-        })
+    fn next_item(&self) -> Result<Option<Local<'env, bindings::java::lang::Object>>, crate::Error> {
+        if !self.iter.hasNext()? {
+            return Ok(None);
+        }
+        let obj = self.iter.next()?.non_null()?;
+        // SAFETY: `obj` is a new local reference created in the JNI frame of `self.iter`, so it
+        // stays valid for `'env`; `into_raw` gives up its ownership, so it is deleted only once.
+        let obj = unsafe { Local::from_raw(self.iter.env(), obj.into_raw()) };
+        Ok(Some(obj))
     }
 }
 
-pub struct JavaIterator<'env>(pub Local<'env, bindings::java::util::Iterator>);
-
 impl<'env> Iterator for JavaIterator<'env> {
-    type Item = Local<'env, bindings::java::lang::Object>;
+    type Item = Result<Local<'env, bindings::java::lang::Object>, crate::Error>;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.0.hasNext().unwrap() {
-            let obj = self.0.next().unwrap().unwrap();
-            // upgrade lifetime to the original env.
-            let obj = unsafe { Local::from_raw(self.0.env(), obj.into_raw()) };
-            Some(obj)
-        } else {
-            None
-        }
+        let mut done = self.done;
+        let item = fused_next(&mut done, || self.next_item());
+        self.done = done;
+        item
+    }
+}
+
+/// One step of a fallible iteration which ends after the first error or `Ok(None)`;
+/// `next_item` is not called again once `done` is set.
+fn fused_next<T>(
+    done: &mut bool,
+    next_item: impl FnOnce() -> Result<Option<T>, crate::Error>,
+) -> Option<Result<T, crate::Error>> {
+    if *done {
+        return None;
     }
+    let item = next_item().transpose();
+    *done = !matches!(item, Some(Ok(_)));
+    item
+}
+
+/// Copies the items of a `java.util.List` with a single `toArray()` call, which is cheaper than
+/// calling `hasNext()` and `next()` for each item through [JavaIterator].
+pub fn java_list_items<'env>(
+    list: &java_spaghetti::Ref<'env, bindings::java::util::List>,
+) -> Result<Vec<Local<'env, bindings::java::lang::Object>>, crate::Error> {
+    let array = list.toArray()?.non_null()?;
+    Ok(array.iter().flatten().collect())
 }
 
 // TODO: make use of the caller information in these track caller methods.
@@ -107,6 +146,38 @@ impl<T> OptionExt<T> for Option<T> {
     }
 }
 
+pub(crate) trait WaitResultExt<T> {
+    fn check_wait(self, dev_id: &DeviceId, operation: &str) -> Result<T, crate::Error>;
+}
+
+impl<T> WaitResultExt<T> for Result<T, WaitError> {
+    /// Maps the error returned by `ResultWaiter::wait_unlock` for `operation`: it is
+    /// `ErrorKind::NotConnected` if the connection is closed, otherwise `ErrorKind::Timeout`.
+    #[track_caller]
+    fn check_wait(self, dev_id: &DeviceId, operation: &str) -> Result<T, crate::Error> {
+        self.map_err(|e| {
+            if e == WaitError::Aborted || GattTree::find_connection(dev_id).is_none() {
+                ErrorKind::NotConnected.into()
+            } else {
+                crate::Error::new(
+                    ErrorKind::Timeout,
+                    None,
+                    format!("timed out waiting for the result of {operation}"),
+                )
+            }
+        })
+    }
+}
+
+/// Returns an `ErrorKind::Timeout` error for `operation` whose deadline is reached.
+pub(crate) fn deadline_error(operation: &str) -> crate::Error {
+    crate::Error::new(
+        ErrorKind::Timeout,
+        None,
+        format!("deadline reached during {operation}"),
+    )
+}
+
 pub(crate) trait BoolExt {
     fn non_false(self) -> Result<(), crate::Error>;
 }
@@ -132,3 +203,101 @@ impl IntExt for i32 {
         Err(NativeError::BluetoothStatusCode(BluetoothStatusCode::from(code)).into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID_CORPUS: &[&str] = &[
+        "00000000-0000-0000-0000-000000000000",
+        "ffffffff-ffff-ffff-ffff-ffffffffffff",
+        "00000000-0000-1000-8000-00805f9b34fb",
+        "0000180f-0000-1000-8000-00805f9b34fb",
+        "00002a19-0000-1000-8000-00805f9b34fb",
+        "6e400001-b5a3-f393-e0a9-e50e24dcca9e",
+        "80000000-0000-0000-8000-000000000000",
+        "7fffffff-ffff-ffff-7fff-ffffffffffff",
+        "00000000-0000-0001-0000-000000000001",
+        "f000aa00-0451-4000-b000-000000000000",
+    ];
+
+    // `java.util.UUID.toString()` gives the lowercase hyphenated form, and its halves are the
+    // signed big-endian values of the bytes.
+    #[test]
+    fn uuid_bits_and_string_paths_agree() {
+        for &text in UUID_CORPUS {
+            let expected = uuid::Uuid::parse_str(text).unwrap();
+            let (msb, lsb) = expected.as_u64_pair();
+            let from_bits = uuid_from_java_bits(msb as i64, lsb as i64);
+            assert_eq!(from_bits, expected, "{text}");
+            assert_eq!(from_bits.to_string(), text);
+            assert_eq!(parse_uuid_str(text).unwrap(), expected, "{text}");
+            assert_eq!(parse_uuid_str(&format!(" {text}\n")).unwrap(), expected);
+            assert_eq!(parse_uuid_str(&text.to_uppercase()).unwrap(), expected);
+        }
+        assert!(uuid_from_java_bits(0, 0).is_nil());
+        assert!(uuid_from_java_bits(-1, -1).is_max());
+    }
+
+    // Mimics a `java.util.Iterator` whose `next()` throws on the third item.
+    #[test]
+    fn fused_next_ends_after_exception() {
+        let mut calls = 0;
+        let mut done = false;
+        let items: Vec<_> = std::iter::from_fn(|| {
+            fused_next(&mut done, || {
+                calls += 1;
+                match calls {
+                    1 | 2 => Ok(Some(calls)),
+                    3 => Err(crate::Error::new(
+                        ErrorKind::Internal,
+                        None,
+                        "java.util.ConcurrentModificationException",
+                    )),
+                    _ => Ok(Some(calls)),
+                }
+            })
+        })
+        .collect();
+        assert_eq!(items.len(), 3);
+        assert!(matches!(items[..2], [Ok(1), Ok(2)]));
+        assert_eq!(items[2].as_ref().unwrap_err().kind(), ErrorKind::Internal);
+        assert!(done);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn fused_next_ends_after_null_or_exhaustion() {
+        let mut source = vec![Some(1), None, Some(3)].into_iter();
+        let mut done = false;
+        let mut next = || {
+            fused_next(&mut done, || {
+                source.next().map(|item| item.non_null()).transpose()
+            })
+        };
+        assert!(matches!(next(), Some(Ok(1))));
+        assert!(matches!(next(), Some(Err(_))));
+        assert!(next().is_none());
+        assert_eq!(source.len(), 1);
+
+        let mut source = vec![1].into_iter();
+        let mut done = false;
+        let mut next = || fused_next(&mut done, || Ok(source.next()));
+        assert!(matches!(next(), Some(Ok(1))));
+        assert!(next().is_none());
+        assert!(next().is_none());
+    }
+
+    #[test]
+    fn invalid_uuid_strings() {
+        for text in [
+            "",
+            "180f",
+            "0000180f-0000-1000-8000-00805f9b34f",
+            "not a uuid",
+        ] {
+            let error = parse_uuid_str(text).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::Internal, "{text:?}");
+        }
+    }
+}
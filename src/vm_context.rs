@@ -3,7 +3,8 @@
 use std::ptr::null;
 use std::sync::OnceLock;
 
-use java_spaghetti::{Env, Global, Ref};
+use java_spaghetti::sys::{jmethodID, jvalue};
+use java_spaghetti::{Env, Global, Ref, ReferenceType};
 use log::warn;
 
 use super::bindings::android::content::Context;
@@ -11,7 +12,8 @@ use super::bindings::android::os::Build_VERSION;
 use super::bindings::dalvik::system::{DexClassLoader, InMemoryDexClassLoader};
 use super::bindings::java::lang::{Class, ClassLoader, Object, String as JString, Throwable};
 use super::bindings::java::nio;
-use super::jni::{ByteArrayExt, VM};
+use super::error::ErrorKind;
+use super::jni::{is_same_object, ByteArrayExt, VM};
 
 static JAVA_VM: OnceLock<VM> = OnceLock::new();
 static ANDROID_CONTEXT: OnceLock<Global<Context>> = OnceLock::new();
@@ -37,6 +39,64 @@ where
     jni_get_vm().with_env(callback)
 }
 
+/// Sets the JVM and the Android context explicitly. It can be called again with the same values.
+///
+/// # Safety
+///
+/// `vm` must be a valid JNI `JavaVM` pointer; `context` must be a valid reference to an
+/// `android.content.Context` instance from that VM, usable in the current thread.
+pub unsafe fn android_init_with(
+    vm: *mut java_spaghetti::sys::JavaVM,
+    context: java_spaghetti::sys::jobject,
+) -> Result<(), crate::Error> {
+    if vm.is_null() || context.is_null() {
+        return Err(crate::Error::new(
+            ErrorKind::InvalidParameter,
+            None,
+            "null `JavaVM` or context pointer",
+        ));
+    }
+    let vm = VM::from_raw(vm);
+    if JAVA_VM.get_or_init(|| vm).as_raw() != vm.as_raw() {
+        return Err(crate::Error::new(
+            ErrorKind::InvalidParameter,
+            None,
+            "already initialized with a different `JavaVM`",
+        ));
+    }
+    vm.with_env(|env| {
+        let context = Ref::<'_, Context>::from_raw(env, context.cast());
+        let stored = ANDROID_CONTEXT.get_or_init(|| context.as_global());
+        if !is_same_object(&stored.as_ref(env), &context) {
+            return Err(crate::Error::new(
+                ErrorKind::InvalidParameter,
+                None,
+                "already initialized with a different Android context",
+            ));
+        }
+        Ok(())
+    })
+}
+
+/// Checks if the JVM and the Android context are available, either set by [android_init_with]
+/// or provided by `ndk_context`.
+pub fn android_check_initialized() -> Result<(), crate::Error> {
+    if JAVA_VM.get().is_some() && ANDROID_CONTEXT.get().is_some() {
+        return Ok(());
+    }
+    // `ndk_context::android_context()` panics if it is not initialized.
+    let ndk_ctx = std::panic::catch_unwind(ndk_context::android_context).ok();
+    if ndk_ctx.is_some_and(|ctx| !ctx.vm().is_null()) {
+        Ok(())
+    } else {
+        Err(crate::Error::new(
+            ErrorKind::NotReady,
+            None,
+            "Android context is unavailable; call `android_ble::init_with` or use `android_activity`",
+        ))
+    }
+}
+
 pub fn android_context() -> Global<Context> {
     ANDROID_CONTEXT
         .get_or_init(|| {
@@ -135,6 +195,93 @@ pub fn android_load_dex(dex_data: &[u8]) -> Global<ClassLoader> {
     })
 }
 
+/// Looks up an instance method of the object's class, which may be hidden or unavailable on the
+/// current system. Returns `None` (with the pending exception cleared) if it cannot be found.
+///
+/// Note: `name` and `signature` must be null-terminated.
+pub fn jni_find_method<'env, T: ReferenceType>(
+    object: &Ref<'env, T>,
+    name: &str,
+    signature: &str,
+) -> Option<jmethodID> {
+    assert!(name.ends_with('\0') && signature.ends_with('\0'));
+    let jnienv = object.env().as_raw();
+    unsafe {
+        let class = ((**jnienv).v1_2.GetObjectClass)(jnienv, object.as_raw());
+        let method = ((**jnienv).v1_2.GetMethodID)(
+            jnienv,
+            class,
+            name.as_ptr().cast(),
+            signature.as_ptr().cast(),
+        );
+        ((**jnienv).v1_2.DeleteLocalRef)(jnienv, class);
+        if method.is_null() {
+            ((**jnienv).v1_2.ExceptionClear)(jnienv);
+            None
+        } else {
+            Some(method)
+        }
+    }
+}
+
+/// Sets an `int` field of the object, which may be unavailable in an outdated copy of the proxy
+/// class. Returns `false` (with the pending exception cleared) if it cannot be found.
+///
+/// Note: `name` must be null-terminated.
+pub fn jni_set_int_field<T: ReferenceType>(object: &Ref<'_, T>, name: &str, value: i32) -> bool {
+    assert!(name.ends_with('\0'));
+    let jnienv = object.env().as_raw();
+    unsafe {
+        let class = ((**jnienv).v1_2.GetObjectClass)(jnienv, object.as_raw());
+        let field =
+            ((**jnienv).v1_2.GetFieldID)(jnienv, class, name.as_ptr().cast(), c"I".as_ptr());
+        ((**jnienv).v1_2.DeleteLocalRef)(jnienv, class);
+        if field.is_null() {
+            ((**jnienv).v1_2.ExceptionClear)(jnienv);
+            false
+        } else {
+            ((**jnienv).v1_2.SetIntField)(jnienv, object.as_raw(), field, value);
+            true
+        }
+    }
+}
+
+/// Reads an integer system property through the hidden `android.os.SystemProperties.getInt()`.
+/// Returns `None` if it is not set or the hidden method is inaccessible.
+///
+/// Note: `key` doesn't need to be null-terminated.
+pub fn android_system_property_int(key: &str) -> Option<i32> {
+    jni_with_env(|env| {
+        let jnienv = env.as_raw();
+        unsafe {
+            let class =
+                ((**jnienv).v1_2.FindClass)(jnienv, c"android/os/SystemProperties".as_ptr());
+            if class.is_null() {
+                ((**jnienv).v1_2.ExceptionClear)(jnienv);
+                return None;
+            }
+            let method = ((**jnienv).v1_2.GetStaticMethodID)(
+                jnienv,
+                class,
+                c"getInt".as_ptr(),
+                c"(Ljava/lang/String;I)I".as_ptr(),
+            );
+            let value = if method.is_null() {
+                ((**jnienv).v1_2.ExceptionClear)(jnienv);
+                None
+            } else {
+                let key = JString::from_env_str(env, key);
+                let args = [jvalue { l: key.as_raw() }, jvalue { i: -1 }];
+                env.call_static_int_method_a::<Throwable>(class, method, args.as_ptr())
+                    .ok()
+                    .filter(|&value| value >= 0)
+            };
+            ((**jnienv).v1_2.DeleteLocalRef)(jnienv, class);
+            value
+        }
+    })
+}
+
 pub fn jni_load_class_with<'env>(
     loader: Ref<'env, ClassLoader>,
     bin_name: &str,